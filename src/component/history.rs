@@ -0,0 +1,49 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::library::Store;
+use crate::rekordbox::{read_history, sessions, render_setlist, Session, SetlistFormat, SetlistOptions};
+
+fn load_sessions(data_dir: &Path) -> io::Result<Vec<Session>> {
+    Ok(sessions(&read_history(&Store::new(data_dir))?))
+}
+
+/// Print the recorded sessions, the latest last.
+pub fn list(data_dir: &Path) -> io::Result<()> {
+    for session in load_sessions(data_dir)? {
+        println!("{}\t{} tracks", session.name, session.tracks().len());
+    }
+
+    Ok(())
+}
+
+/// Write a session as a setlist to `output`, or stdout without one.
+/// The latest session is exported when no session name is given.
+pub fn export(
+    data_dir: &Path,
+    session_name: Option<&str>,
+    format: SetlistFormat,
+    options: &SetlistOptions,
+    output: Option<&Path>,
+) -> io::Result<()> {
+    let sessions = load_sessions(data_dir)?;
+    let session = match session_name {
+        Some(name) => sessions.iter().find(|session| session.name == name),
+        None => sessions.last(),
+    };
+
+    let session = match session {
+        Some(session) => session,
+        None => return Err(io::Error::new(ErrorKind::NotFound, "No such history session")),
+    };
+
+    let setlist = render_setlist(session, format, options);
+    match output {
+        Some(path) => fs::write(path, setlist),
+        None => {
+            print!("{}", setlist);
+            Ok(())
+        },
+    }
+}
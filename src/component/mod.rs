@@ -1,7 +1,10 @@
+pub mod history;
+pub mod playlist;
+
 use std::thread;
 use std::sync::mpsc::{channel, Receiver};
-use crate::rekordbox::{Server, Database, Event};
-use std::path::Path;
+use crate::rekordbox::{Server, Database, DatabaseOptions, Event};
+use ipnetwork::IpNetwork;
 
 pub struct App {
     rekordbox_server: Server,
@@ -9,13 +12,14 @@ pub struct App {
 }
 
 impl App {
-    pub fn new<T: AsRef<Path>>(path: T) -> Self {
+    pub fn new(options: DatabaseOptions, allowed_hosts: Vec<IpNetwork>) -> Self {
         let (tx, rx) = channel::<Event>();
-        let database = Database::new(path);
+        let database = Database::with_options(options);
 
         let rekordbox_server = Server::new(
             database,
             tx,
+            allowed_hosts,
         );
 
         App {
@@ -0,0 +1,44 @@
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::rekordbox::{Database, DatabaseError, PlaylistSource};
+
+fn print_folder(database: &Database, folder_id: u32, depth: usize) {
+    for playlist in database.playlist_folder(folder_id) {
+        let details = match &playlist.source {
+            PlaylistSource::Folder => String::from("folder"),
+            PlaylistSource::Tracks(entries) => format!("{} tracks", entries.len()),
+            PlaylistSource::File(_) => format!("{} tracks, file", database.playlist_tracks(playlist.id).len()),
+            PlaylistSource::Smart(_) => format!("{} tracks, smart", database.playlist_tracks(playlist.id).len()),
+        };
+        println!("{:>4}  {}{} ({})", playlist.id, "  ".repeat(depth), playlist.name, details);
+
+        if playlist.is_folder() {
+            print_folder(database, playlist.id, depth + 1);
+        }
+    }
+}
+
+/// Print the playlist tree with the ids used by the other playlist commands.
+pub fn list(database: &Database, folder_id: u32) {
+    print_folder(database, folder_id, 0);
+}
+
+/// Print the entries of a playlist with their positions, counting from 1.
+pub fn show(database: &Database, playlist_id: u32) {
+    for (index, path) in database.playlist_paths(playlist_id).iter().enumerate() {
+        println!("{:>3}. {}", index + 1, path.display());
+    }
+}
+
+/// Add files of the library to the end of a playlist, in the given order.
+pub fn add_tracks(database: &Database, playlist_id: u32, paths: &[&Path]) -> io::Result<()> {
+    for path in paths {
+        database.add_playlist_path(playlist_id, path).map_err(|err| match err {
+            DatabaseError::Store(err) => err,
+            err => io::Error::new(ErrorKind::InvalidInput, err.to_string()),
+        })?;
+    }
+
+    Ok(())
+}
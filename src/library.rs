@@ -1,14 +1,50 @@
+mod analysis;
+mod audio;
+mod content_hash;
+mod decoder;
+mod device;
+mod key;
+mod loudness;
+mod m3u;
+mod phrase;
+mod rating;
+mod rekordbox_xml;
+mod serato;
+mod sidecar;
+mod store;
+mod transcode;
+
+pub use analysis::{analyze_file, Beat, TrackAnalysis};
+pub use content_hash::{normalize_tag, ContentHashes};
+pub use device::{is_device, read_device, DevicePlaylist, PDB_PATH};
+pub use key::{camelot_key, compatible_keys, key_distance, key_id, key_of_id};
+pub use loudness::Loudness;
+pub use m3u::FilePlaylist;
+pub use phrase::{Phrase, PhraseKind};
+pub use rating::MAX_RATING;
+pub use rekordbox_xml::{read_rekordbox_xml, ImportedTrack};
+pub use store::{path_from_field, path_to_field, Store};
+pub use transcode::{TranscodeCache, TranscodedAudio, Transcoder};
+
 use walkdir::{DirEntry, WalkDir};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use id3::{Tag, v1 as id3v1};
 use std::fs::{File, metadata};
 use std::os::unix::fs::MetadataExt;
 use std::ffi::OsStr;
 use std::io;
 use crate::rekordbox::{
+    Cue,
+    CueSource,
     Metadata,
     MetadataTrack as Track,
+    MAX_WIRE_FILE_SIZE,
 };
+use serato::serato_cues;
+use rating::tag_rating;
+use loudness::{flac_loudness, tag_loudness};
+use sidecar::read_sidecar;
+use m3u::read_m3u;
 
 fn is_hidden(entry: &DirEntry) -> bool {
     entry.file_name()
@@ -21,17 +57,44 @@ fn is_regular_file(entry: &DirEntry) -> bool {
     entry.file_type().is_file()
 }
 
-fn has_mp3_extension(entry: &DirEntry) -> bool {
-    entry.path().extension() == Some(OsStr::new("mp3"))
+const SUPPORTED_EXTENSIONS: [&str; 6] = ["mp3", "wav", "aif", "aiff", "aifc", "flac"];
+
+const PLAYLIST_EXTENSIONS: [&str; 2] = ["m3u", "m3u8"];
+
+fn has_extension(entry: &DirEntry, extension: &str) -> bool {
+    path_has_extension(entry.path(), extension)
+}
+
+fn path_has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(|value| value.eq_ignore_ascii_case(extension))
+        .unwrap_or(false)
+}
+
+fn has_supported_extension(entry: &DirEntry) -> bool {
+    SUPPORTED_EXTENSIONS.iter().any(|extension| has_extension(entry, extension))
+}
+
+/// Whether a scan would pick up a file as a track, going by its name.
+pub fn is_track_file<T: AsRef<Path>>(path: T) -> bool {
+    SUPPORTED_EXTENSIONS.iter().any(|extension| path_has_extension(path.as_ref(), extension))
 }
 
-fn mp3_files_iterator<T: AsRef<Path>>(t: T) -> impl Iterator<Item = DirEntry> {
+fn is_playlist(entry: &DirEntry) -> bool {
+    PLAYLIST_EXTENSIONS.iter().any(|extension| has_extension(entry, extension))
+}
+
+fn files_iterator<T: AsRef<Path>>(t: T) -> impl Iterator<Item = DirEntry> {
     WalkDir::new(t)
         .into_iter()
         .filter_entry(|e| !is_hidden(e))
         .filter_map(|e| e.ok())
         .filter(is_regular_file)
-        .filter(has_mp3_extension)
+}
+
+fn audio_files_iterator<T: AsRef<Path>>(t: T) -> impl Iterator<Item = DirEntry> {
+    files_iterator(t).filter(has_supported_extension)
 }
 
 fn extract_bpm(tag: &Tag) -> Option<u32> {
@@ -51,12 +114,23 @@ fn extract_bpm(tag: &Tag) -> Option<u32> {
     }
 }
 
+fn extract_key(tag: &Tag) -> Option<String> {
+    match tag.get("TKEY").map(|frame| frame.content()) {
+        Some(id3::Content::Text(text)) => camelot_key(text),
+        _ => None,
+    }
+}
+
 fn extract_id3v2(tag: Tag) -> Metadata {
     Metadata {
         artist: tag.artist().unwrap_or("").to_string(),
         title: tag.title().unwrap_or("").to_string(),
         bpm: extract_bpm(&tag),
         album: tag.album().unwrap_or("").to_string(),
+        rating: tag_rating(&tag).unwrap_or(0),
+        genre: tag.genre().unwrap_or("").trim().to_string(),
+        comment: tag.comments().next().map(|comment| comment.text.clone()).unwrap_or_default(),
+        key: extract_key(&tag),
     }
 }
 
@@ -66,6 +140,10 @@ fn extract_id3v1<'a>(tag: id3v1::Tag) -> Metadata {
         title: tag.title,
         bpm: None,
         album: tag.album,
+        rating: 0,
+        genre: String::new(),
+        comment: tag.comment,
+        key: None,
     }
 }
 
@@ -91,14 +169,42 @@ impl From<std::io::Error> for LibraryError {
     }
 }
 
-fn metadata_extractor(entry: DirEntry) -> Option<(Metadata, PathBuf, u32)> {
-    let extracted_metadata = match Tag::read_from_path(entry.path()) {
-        Ok(tag) => extract_id3v2(tag),
+/// Metadata for untagged recordings, "Artist - Title.wav" or just "Title.wav".
+fn extract_file_name(path: &Path) -> Metadata {
+    let stem = path.file_stem()
+        .and_then(OsStr::to_str)
+        .unwrap_or("");
+
+    let (artist, title) = match stem.find(" - ") {
+        Some(index) => (&stem[..index], &stem[index + 3..]),
+        None => ("", stem),
+    };
+
+    Metadata {
+        artist: artist.to_string(),
+        title: title.to_string(),
+        bpm: None,
+        album: String::new(),
+        rating: 0,
+        genre: String::new(),
+        comment: String::new(),
+        key: None,
+    }
+}
+
+fn metadata_extractor(entry: DirEntry) -> Option<Track> {
+    let (extracted_metadata, cues, loudness): (Metadata, Vec<Cue>, Option<Loudness>) = match Tag::read_from_path(entry.path()) {
+        Ok(tag) => {
+            let cues = serato_cues(&tag);
+            let loudness = tag_loudness(&tag);
+            (extract_id3v2(tag), cues, loudness)
+        },
         Err(_) => {
             match File::open(entry.path()) {
                 Ok(file) => {
                     match id3v1::Tag::read_from(file) {
-                        Ok(tag) => extract_id3v1(tag),
+                        Ok(tag) => (extract_id3v1(tag), vec![], None),
+                        Err(_err) if !has_extension(&entry, "mp3") => (extract_file_name(entry.path()), vec![], None),
                         Err(_err) => return None,
                     }
                 },
@@ -107,21 +213,65 @@ fn metadata_extractor(entry: DirEntry) -> Option<(Metadata, PathBuf, u32)> {
         },
     };
 
+    // FLAC files keep ReplayGain in Vorbis comments instead of ID3 frames
+    let loudness = match loudness {
+        None if has_extension(&entry, "flac") => flac_loudness(entry.path()),
+        loudness => loudness,
+    };
+
     match metadata(entry.path()) {
         Ok(attributes) => {
-            Some((
+            if attributes.size() > MAX_WIRE_FILE_SIZE {
+                eprintln!(
+                    "{:?} is {} bytes, players can only address the first {} bytes of it",
+                    entry.path(),
+                    attributes.size(),
+                    MAX_WIRE_FILE_SIZE,
+                );
+            }
+
+            let mut track = Track::new(
                 extracted_metadata,
                 entry.path().to_path_buf(),
-                attributes.size() as u32,
-            ))
+                attributes.size(),
+            );
+            track.cues = cues;
+            track.loudness = loudness;
+
+            // Hand maintained sidecars win over cues embedded by other software
+            if let Some(sidecar) = read_sidecar(entry.path()) {
+                if !sidecar.cues.is_empty() {
+                    track.cues = sidecar.cues;
+                    track.cue_source = CueSource::Sidecar;
+                }
+                track.color = sidecar.color;
+            }
+
+            Some(track)
         },
         _ => None,
     }
 }
 
 pub fn scan_folder<T: AsRef<Path>>(path: T) -> Vec<Track> {
-    mp3_files_iterator(path)
+    audio_files_iterator(path)
         .filter_map(metadata_extractor)
-        .map(|(metadata, path, file_size)| Track::new(metadata, path, file_size))
         .collect()
 }
+
+/// M3U playlists kept in a library folder, ordered by path.
+pub fn scan_playlists<T: AsRef<Path>>(path: T) -> Vec<FilePlaylist> {
+    let mut playlists: Vec<FilePlaylist> = files_iterator(path)
+        .filter(is_playlist)
+        .filter_map(|entry| match read_m3u(entry.path()) {
+            Ok(playlist) => Some(playlist),
+            Err(err) => {
+                eprintln!("Failed reading playlist {:?}; error = {}", entry.path(), err);
+                None
+            },
+        })
+        .collect();
+    playlists.sort_by(|a, b| a.path.cmp(&b.path));
+
+    playlists
+}
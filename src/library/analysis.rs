@@ -0,0 +1,187 @@
+use std::io;
+use std::path::Path;
+
+use super::decoder::{decode_pcm, PcmFormat};
+use super::loudness::{Loudness, LoudnessMeter};
+use super::phrase::{detect_phrases, Phrase};
+
+/// Columns of the waveform players show above the jog wheel.
+pub const PREVIEW_COLUMNS: usize = 400;
+
+/// Columns per second of the scrolling waveform.
+pub const DETAIL_COLUMNS_PER_SECOND: usize = 150;
+
+/// Samples quieter than this don't count as the start of the music.
+const SILENCE_THRESHOLD: f32 = 0.01;
+
+/// Brightness players use for the waveform columns, out of 7.
+const WAVEFORM_WHITENESS: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Beat {
+    /// Position in the bar, 1 to 4.
+    pub number: u8,
+    /// BPM times 100.
+    pub tempo: u32,
+    pub time_ms: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackAnalysis {
+    pub duration_ms: u32,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    /// One byte per column, the height in the low 5 bits and the whiteness in the high 3.
+    pub preview: Vec<u8>,
+    pub detail: Vec<u8>,
+    pub beats: Vec<Beat>,
+    pub loudness: Option<Loudness>,
+    pub phrases: Vec<Phrase>,
+}
+
+fn waveform_column(peaks: &[f32]) -> u8 {
+    let peak = peaks.iter().fold(0f32, |peak, sample| peak.max(*sample)).min(1.0);
+    (WAVEFORM_WHITENESS << 5) | (peak * 31.0).round() as u8
+}
+
+fn waveform(peaks: &[f32], columns: usize) -> Vec<u8> {
+    (0..columns)
+        .map(|column| {
+            let start = peaks.len() * column / columns;
+            let end = (peaks.len() * (column + 1) / columns).max(start);
+            waveform_column(&peaks[start..end])
+        })
+        .collect()
+}
+
+/// Beats at a constant tempo from the first sound to the end of the track.
+fn beat_grid(first_sound: u64, sample_rate: u32, duration_ms: u32, bpm: Option<u32>) -> Vec<Beat> {
+    let tempo = match bpm {
+        Some(tempo) if tempo > 0 && sample_rate > 0 => tempo,
+        _ => return vec![],
+    };
+    let start_ms = first_sound as f64 * 1000.0 / sample_rate as f64;
+    let beat_ms = 60_000.0 * 100.0 / tempo as f64;
+
+    (0..)
+        .map(|index: u32| (index, start_ms + index as f64 * beat_ms))
+        .take_while(|(_index, time_ms)| *time_ms < duration_ms as f64)
+        .map(|(index, time_ms)| Beat {
+            number: (index % 4) as u8 + 1,
+            tempo,
+            time_ms: time_ms.round() as u32,
+        })
+        .collect()
+}
+
+/// Peak and energy of the mono signal over a column of the scrolling waveform.
+#[derive(Debug, Clone, Copy, Default)]
+struct Column {
+    peak: f32,
+    energy: f32,
+    frames: u32,
+}
+
+/// Collects what the analysis needs while a track is decoded, so only a
+/// column per 150th of a second is kept instead of every sample.
+#[derive(Default)]
+struct Analyzer {
+    format: PcmFormat,
+    frames: u64,
+    first_sound: Option<u64>,
+    columns: Vec<Column>,
+    loudness: Option<LoudnessMeter>,
+}
+
+impl Analyzer {
+    /// Samples of all channels interleaved, starting with a whole frame.
+    fn push(&mut self, format: PcmFormat, samples: &[f32]) {
+        if self.loudness.is_none() {
+            self.format = format;
+            self.loudness = Some(LoudnessMeter::new(format.sample_rate, format.channels));
+        }
+        if let Some(loudness) = &mut self.loudness {
+            loudness.push(samples);
+        }
+
+        let channels = self.format.channels.max(1) as usize;
+        let sample_rate = self.format.sample_rate.max(1) as u64;
+        for frame in samples.chunks_exact(channels) {
+            let sample = frame.iter().sum::<f32>() / channels as f32;
+            if self.first_sound.is_none() && sample.abs() > SILENCE_THRESHOLD {
+                self.first_sound = Some(self.frames);
+            }
+
+            let index = (self.frames * DETAIL_COLUMNS_PER_SECOND as u64 / sample_rate) as usize;
+            if index >= self.columns.len() {
+                self.columns.resize(index + 1, Column::default());
+            }
+            let column = &mut self.columns[index];
+            column.peak = column.peak.max(sample.abs());
+            column.energy += sample * sample;
+            column.frames += 1;
+            self.frames += 1;
+        }
+    }
+
+    /// Waveforms, loudness, and a beat grid and phrases for the tempo found in the tags, if any.
+    fn finish(self, bpm: Option<u32>) -> TrackAnalysis {
+        let duration_ms = match self.format.sample_rate {
+            0 => 0,
+            sample_rate => (self.frames * 1000 / sample_rate as u64) as u32,
+        };
+        let detail_columns = duration_ms as usize * DETAIL_COLUMNS_PER_SECOND / 1000;
+        let peaks: Vec<f32> = self.columns.iter().map(|column| column.peak).collect();
+        // The RMS of every column, phrases take the RMS over whole blocks of them
+        let envelope: Vec<f32> = self.columns.iter()
+            .map(|column| (column.energy / column.frames.max(1) as f32).sqrt())
+            .collect();
+        let beats = beat_grid(self.first_sound.unwrap_or(0), self.format.sample_rate, duration_ms, bpm);
+
+        TrackAnalysis {
+            duration_ms,
+            sample_rate: self.format.sample_rate,
+            bits_per_sample: self.format.bits_per_sample,
+            preview: waveform(&peaks, PREVIEW_COLUMNS),
+            detail: waveform(&peaks, detail_columns),
+            phrases: detect_phrases(&envelope, DETAIL_COLUMNS_PER_SECOND as u32, &beats),
+            beats,
+            loudness: self.loudness.and_then(LoudnessMeter::finish),
+        }
+    }
+}
+
+/// Decode a track and analyze it on the way.
+pub fn analyze_file<T: AsRef<Path>>(path: T, bpm: Option<u32>) -> io::Result<TrackAnalysis> {
+    let mut analyzer = Analyzer::default();
+    decode_pcm(path, |format, samples| analyzer.push(format, samples))?;
+
+    Ok(analyzer.finish(bpm))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_analyzes_waveforms_and_beats() {
+        // Two seconds of silence followed by two seconds at full scale, 1000 frames per second
+        let mut samples = vec![0f32; 2000];
+        samples.extend(vec![1f32; 2000]);
+        let mut analyzer = Analyzer::default();
+        for chunk in samples.chunks(300) {
+            analyzer.push(PcmFormat { sample_rate: 1000, channels: 1, bits_per_sample: 16 }, chunk);
+        }
+
+        let analysis = analyzer.finish(Some(12000));
+        assert_eq!(4000, analysis.duration_ms);
+        assert_eq!(PREVIEW_COLUMNS, analysis.preview.len());
+        assert_eq!((0xa0, 0xbf), (analysis.preview[0], analysis.preview[399]));
+        assert_eq!(600, analysis.detail.len());
+        assert_eq!(
+            vec![(1, 2000), (2, 2500), (3, 3000), (4, 3500)],
+            analysis.beats.iter().map(|beat| (beat.number, beat.time_ms)).collect::<Vec<(u8, u32)>>(),
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Byte range of the encoded audio inside a file, tags and headers excluded.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AudioPayload {
+    pub offset: u64,
+    pub length: u64,
+}
+
+pub(super) fn read_exact_at(file: &mut File, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buffer)
+}
+
+fn syncsafe_u32(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0u64, |acc, byte| (acc << 7) | (*byte as u64 & 0x7f))
+}
+
+/// MPEG audio framed by an optional ID3v2 header and an optional ID3v1 trailer.
+fn mp3_payload(file: &mut File, file_size: u64) -> io::Result<AudioPayload> {
+    let mut start = 0u64;
+    let mut end = file_size;
+
+    let mut header = [0u8; 10];
+    if file_size >= 10 {
+        read_exact_at(file, 0, &mut header)?;
+        if &header[0..3] == b"ID3" {
+            let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+            start = (10 + syncsafe_u32(&header[6..10]) + footer).min(file_size);
+        }
+    }
+
+    let mut trailer = [0u8; 3];
+    if end >= start + 128 {
+        read_exact_at(file, end - 128, &mut trailer)?;
+        if &trailer == b"TAG" {
+            end -= 128;
+        }
+    }
+
+    Ok(AudioPayload {
+        offset: start,
+        length: end - start,
+    })
+}
+
+/// Walk the chunks of a RIFF (little endian) or IFF (big endian) container
+/// looking for the chunk holding the sample data.
+pub(super) fn find_chunk(
+    file: &mut File,
+    file_size: u64,
+    wanted: &[u8; 4],
+    little_endian: bool,
+) -> io::Result<Option<AudioPayload>> {
+    let mut offset = 12u64;
+    let mut header = [0u8; 8];
+
+    while offset + 8 <= file_size {
+        read_exact_at(file, offset, &mut header)?;
+        let size = if little_endian {
+            u32::from_le_bytes([header[4], header[5], header[6], header[7]])
+        } else {
+            u32::from_be_bytes([header[4], header[5], header[6], header[7]])
+        } as u64;
+
+        if &header[0..4] == wanted {
+            let length = size.min(file_size - offset - 8);
+            return Ok(Some(AudioPayload {
+                offset: offset + 8,
+                length,
+            }));
+        }
+
+        // Chunks are padded to an even number of bytes
+        offset += 8 + size + (size & 1);
+    }
+
+    Ok(None)
+}
+
+fn whole_file(file_size: u64) -> AudioPayload {
+    AudioPayload {
+        offset: 0,
+        length: file_size,
+    }
+}
+
+/// Locate the encoded audio of the file, skipping tags that are edited
+/// independently of the audio itself.
+pub fn audio_payload<T: AsRef<Path>>(path: T) -> io::Result<AudioPayload> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+
+    let mut magic = [0u8; 12];
+    if file_size < 12 {
+        return Ok(whole_file(file_size));
+    }
+    read_exact_at(&mut file, 0, &mut magic)?;
+
+    match (&magic[0..4], &magic[8..12]) {
+        (b"RIFF", b"WAVE") => {
+            Ok(find_chunk(&mut file, file_size, b"data", true)?
+                .unwrap_or_else(|| whole_file(file_size)))
+        },
+        (b"FORM", b"AIFF") | (b"FORM", b"AIFC") => {
+            // SSND starts with 8 bytes of offset and block size before the samples
+            Ok(find_chunk(&mut file, file_size, b"SSND", false)?
+                .map(|chunk| AudioPayload {
+                    offset: chunk.offset + 8,
+                    length: chunk.length.saturating_sub(8),
+                })
+                .unwrap_or_else(|| whole_file(file_size)))
+        },
+        _ => mp3_payload(&mut file, file_size),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_fixture(temp: &tempfile::TempDir, name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = temp.path().join(name);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn it_skips_id3_tags_around_mp3_audio() {
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x05tags!".to_vec();
+        data.extend(vec![0xffu8; 200]);
+        data.extend(b"TAG");
+        data.extend(vec![0x00u8; 125]);
+        let temp = crate::utils::test_dir("audio-payload");
+        let path = write_fixture(&temp, "Track.mp3", &data);
+
+        assert_eq!(
+            AudioPayload { offset: 15, length: 200 },
+            audio_payload(&path).unwrap(),
+        );
+    }
+
+    #[test]
+    fn it_finds_the_data_chunk_of_wave_files() {
+        let mut data = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        data.extend(b"fmt \x03\x00\x00\x00abc\x00");
+        data.extend(b"data\x04\x00\x00\x00\x01\x02\x03\x04");
+        let temp = crate::utils::test_dir("audio-payload");
+        let path = write_fixture(&temp, "Track.wav", &data);
+
+        assert_eq!(
+            AudioPayload { offset: 32, length: 4 },
+            audio_payload(&path).unwrap(),
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::audio::audio_payload;
+use super::store::{path_from_field, path_to_field};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Number of bytes sampled from the start, middle and end of the audio.
+const WINDOW_SIZE: u64 = 64 * 1024;
+
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Hash of the encoded audio of a file.
+///
+/// This is not an acoustic fingerprint: only byte identical audio hashes
+/// equal, a re-encode of the same song does not. Tags are left out, so two
+/// copies that only differ in their metadata get the same hash. Only three
+/// windows of the audio are hashed together with its length to keep indexing
+/// of large libraries fast.
+pub fn content_hash<T: AsRef<Path>>(path: T) -> io::Result<u64> {
+    let payload = audio_payload(&path)?;
+    let mut file = File::open(&path)?;
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, &payload.length.to_be_bytes());
+
+    let last_window = payload.length.saturating_sub(WINDOW_SIZE);
+    let mut windows = vec![0, last_window / 2, last_window];
+    windows.dedup();
+
+    let mut buffer = Vec::with_capacity(WINDOW_SIZE as usize);
+    for window in windows {
+        buffer.clear();
+        file.seek(SeekFrom::Start(payload.offset + window))?;
+        (&mut file).take(WINDOW_SIZE.min(payload.length)).read_to_end(&mut buffer)?;
+        hash = fnv1a(hash, &buffer);
+    }
+
+    Ok(hash)
+}
+
+/// Content hashes computed earlier, so files that didn't change since are not read again.
+///
+/// Entries are keyed by path and only used while the size and modification
+/// time of the file are the ones they were computed for.
+#[derive(Debug, Default)]
+pub struct ContentHashes {
+    entries: HashMap<PathBuf, (u64, u128, u64)>,
+    changed: bool,
+}
+
+impl ContentHashes {
+    /// Hashes from records written by `to_records`, malformed records are skipped.
+    pub fn from_records(records: &[Vec<String>]) -> Self {
+        let mut entries = HashMap::new();
+        for record in records {
+            if let [path, size, modified, hash] = record.as_slice() {
+                if let (Ok(size), Ok(modified), Ok(hash)) = (size.parse(), modified.parse(), u64::from_str_radix(hash, 16)) {
+                    entries.insert(path_from_field(path), (size, modified, hash));
+                }
+            }
+        }
+
+        Self {
+            entries,
+            changed: false,
+        }
+    }
+
+    pub fn to_records(&self) -> Vec<Vec<String>> {
+        let mut records: Vec<Vec<String>> = self.entries.iter()
+            .map(|(path, (size, modified, hash))| vec![
+                path_to_field(path),
+                size.to_string(),
+                modified.to_string(),
+                format!("{:016x}", hash),
+            ])
+            .collect();
+        records.sort();
+        records
+    }
+
+    /// Whether hashes were computed since the records were read.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Content hash of a file, only read when it changed since it was last hashed.
+    pub fn hash<T: AsRef<Path>>(&mut self, path: T) -> io::Result<u64> {
+        let path = path.as_ref();
+        let metadata = path.metadata()?;
+        let modified = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+
+        match self.entries.get(path) {
+            Some((size, cached_modified, hash)) if *size == metadata.len() && *cached_modified == modified => Ok(*hash),
+            _ => {
+                let hash = content_hash(path)?;
+                self.entries.insert(path.to_path_buf(), (metadata.len(), modified, hash));
+                self.changed = true;
+                Ok(hash)
+            },
+        }
+    }
+}
+
+/// Lowercased alphanumeric form of a tag value, so "The Track (Original Mix)"
+/// and "the track - original mix" compare equal.
+pub fn normalize_tag(value: &str) -> String {
+    value.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_ignores_tags_when_hashing() {
+        let audio = vec![0x55u8; 4096];
+        let temp = crate::utils::test_dir("content-hash");
+        let untagged = temp.path().join("Untagged.mp3");
+        let tagged = temp.path().join("Tagged.mp3");
+
+        std::fs::write(&untagged, &audio).unwrap();
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x03abc".to_vec();
+        data.extend(&audio);
+        std::fs::write(&tagged, &data).unwrap();
+
+        assert_eq!(
+            content_hash(&untagged).unwrap(),
+            content_hash(&tagged).unwrap(),
+        );
+    }
+
+    #[test]
+    fn it_only_hashes_files_again_when_they_change() {
+        let temp = crate::utils::test_dir("content-hash-cached");
+        let path = temp.path().join("Track.mp3");
+        std::fs::write(&path, vec![0x55u8; 4096]).unwrap();
+
+        let mut hashes = ContentHashes::default();
+        let hash = hashes.hash(&path).unwrap();
+        assert!(hashes.changed());
+
+        // a stale hash is served as long as size and modification time match
+        let mut records = hashes.to_records();
+        records[0][3] = String::from("0000000000000001");
+        let mut hashes = ContentHashes::from_records(&records);
+        assert_eq!(1, hashes.hash(&path).unwrap());
+        assert!(!hashes.changed());
+
+        std::fs::write(&path, vec![0x55u8; 2048]).unwrap();
+        assert_ne!(hash, hashes.hash(&path).unwrap());
+        assert!(hashes.changed());
+    }
+
+    #[test]
+    fn it_normalizes_tag_values() {
+        assert_eq!(normalize_tag("The Track (Original Mix)"), normalize_tag("the track - original mix"));
+    }
+}
@@ -0,0 +1,303 @@
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::audio::{find_chunk, read_exact_at, AudioPayload};
+
+/// Frames handed out at a time, so a whole track is never held in memory.
+const CHUNK_FRAMES: usize = 4096;
+
+/// Format of decoded audio.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Bits per sample of the source, 16 for MP3.
+    pub bits_per_sample: u16,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// Bytes per sample of `bits` bits, 32 bit floats when `float` is set.
+fn sample_width(bits: u16, float: bool) -> io::Result<usize> {
+    let width = (bits as usize).div_ceil(8);
+    if width == 0 || width > 4 || (float && width != 4) {
+        return Err(invalid("Unsupported sample format"));
+    }
+
+    Ok(width)
+}
+
+/// Integer samples of `bits` bits, or 32 bit floats when `float` is set.
+fn decode_samples(data: &[u8], bits: u16, little_endian: bool, float: bool) -> io::Result<Vec<f32>> {
+    let width = sample_width(bits, float)?;
+
+    Ok(data.chunks_exact(width).map(|bytes| {
+        let value = match little_endian {
+            true => bytes.iter().rev().fold(0u32, |acc, byte| (acc << 8) | *byte as u32),
+            false => bytes.iter().fold(0u32, |acc, byte| (acc << 8) | *byte as u32),
+        };
+        // Move the sample to the most significant bits so the sign is kept
+        let value = value << (32 - 8 * width);
+
+        match (float, width) {
+            (true, _) => f32::from_bits(value),
+            // 8 bit WAV samples are unsigned
+            (false, 1) if little_endian => ((value >> 24) as f32 - 128.0) / 128.0,
+            (false, _) => value as i32 as f32 / 2147483648.0,
+        }
+    }).collect())
+}
+
+fn read_chunk(file: &mut File, file_size: u64, id: &[u8; 4], little_endian: bool) -> io::Result<Vec<u8>> {
+    let chunk = find_chunk(file, file_size, id, little_endian)?
+        .ok_or_else(|| invalid("Missing chunk"))?;
+    let mut data = vec![0u8; chunk.length as usize];
+    read_exact_at(file, chunk.offset, &mut data)?;
+
+    Ok(data)
+}
+
+/// Plain samples stored in the file, read and decoded a chunk at a time.
+fn decode_data<F>(file: &mut File, data: AudioPayload, format: PcmFormat, little_endian: bool, float: bool, chunk: &mut F) -> io::Result<()>
+where
+    F: FnMut(PcmFormat, &[f32])
+{
+    let frame_size = sample_width(format.bits_per_sample, float)? * format.channels.max(1) as usize;
+    let mut buffer = vec![0u8; CHUNK_FRAMES * frame_size];
+    let mut position = 0u64;
+    while position < data.length {
+        let length = (data.length - position).min(buffer.len() as u64) as usize;
+        read_exact_at(file, data.offset + position, &mut buffer[..length])?;
+        chunk(format, &decode_samples(&buffer[..length], format.bits_per_sample, little_endian, float)?);
+        position += length as u64;
+    }
+
+    Ok(())
+}
+
+fn decode_wav<F>(file: &mut File, file_size: u64, chunk: &mut F) -> io::Result<()>
+where
+    F: FnMut(PcmFormat, &[f32])
+{
+    let format = read_chunk(file, file_size, b"fmt ", true)?;
+    if format.len() < 16 {
+        return Err(invalid("Short fmt chunk"));
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([format[offset], format[offset + 1]]);
+
+    // WAVE_FORMAT_EXTENSIBLE keeps the actual format in the first bytes of its sub format
+    let tag = match u16_at(0) {
+        0xfffe if format.len() >= 26 => u16_at(24),
+        tag => tag,
+    };
+    let pcm_format = PcmFormat {
+        sample_rate: u32::from_le_bytes([format[4], format[5], format[6], format[7]]),
+        channels: u16_at(2),
+        bits_per_sample: u16_at(14),
+    };
+
+    let data = find_chunk(file, file_size, b"data", true)?
+        .ok_or_else(|| invalid("Missing chunk"))?;
+    match tag {
+        1 => decode_data(file, data, pcm_format, true, false, chunk),
+        3 => decode_data(file, data, pcm_format, true, true, chunk),
+        _ => Err(invalid("Compressed WAV files are not supported")),
+    }
+}
+
+/// The sample rate of an AIFF file is an 80 bit extended precision float.
+pub(super) fn extended_to_u32(bytes: &[u8]) -> u32 {
+    let exponent = (u16::from_be_bytes([bytes[0], bytes[1]]) & 0x7fff) as i32 - 16383;
+    let mantissa = u64::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8], bytes[9]]);
+
+    (mantissa as f64 * 2f64.powi(exponent - 63)).round() as u32
+}
+
+fn decode_aiff<F>(file: &mut File, file_size: u64, compressed: bool, chunk: &mut F) -> io::Result<()>
+where
+    F: FnMut(PcmFormat, &[f32])
+{
+    let common = read_chunk(file, file_size, b"COMM", false)?;
+    if common.len() < 18 {
+        return Err(invalid("Short COMM chunk"));
+    }
+    let format = PcmFormat {
+        sample_rate: extended_to_u32(&common[8..18]),
+        channels: u16::from_be_bytes([common[0], common[1]]),
+        bits_per_sample: u16::from_be_bytes([common[6], common[7]]),
+    };
+
+    // AIFC files are only read when they hold plain big or little endian samples
+    let little_endian = match (compressed, common.get(18..22)) {
+        (false, _) | (true, Some(b"NONE")) | (true, Some(b"twos")) => false,
+        (true, Some(b"sowt")) => true,
+        _ => return Err(invalid("Compressed AIFF files are not supported")),
+    };
+
+    // The samples follow an offset and a block size
+    let sound = find_chunk(file, file_size, b"SSND", false)?
+        .ok_or_else(|| invalid("Missing chunk"))?;
+    let mut header = [0u8; 4];
+    let skip = match sound.length >= 8 {
+        true => {
+            read_exact_at(file, sound.offset, &mut header)?;
+            (u32::from_be_bytes(header) as u64 + 8).min(sound.length)
+        },
+        false => sound.length,
+    };
+    let data = AudioPayload {
+        offset: sound.offset + skip,
+        length: sound.length - skip,
+    };
+
+    decode_data(file, data, format, little_endian, false, chunk)
+}
+
+fn decode_flac<F>(file: File, chunk: &mut F) -> io::Result<()>
+where
+    F: FnMut(PcmFormat, &[f32])
+{
+    let mut reader = claxon::FlacReader::new(file)
+        .map_err(|err| invalid(&err.to_string()))?;
+    let info = reader.streaminfo();
+    let format = PcmFormat {
+        sample_rate: info.sample_rate,
+        channels: info.channels as u16,
+        bits_per_sample: info.bits_per_sample as u16,
+    };
+    let scale = (1u64 << (info.bits_per_sample - 1)) as f32;
+    let chunk_samples = CHUNK_FRAMES * format.channels as usize;
+
+    let mut samples = Vec::with_capacity(chunk_samples);
+    for sample in reader.samples() {
+        samples.push(sample.map_err(|err| invalid(&err.to_string()))? as f32 / scale);
+        if samples.len() == chunk_samples {
+            chunk(format, &samples);
+            samples.clear();
+        }
+    }
+    if !samples.is_empty() {
+        chunk(format, &samples);
+    }
+
+    Ok(())
+}
+
+fn decode_mp3<F>(file: File, chunk: &mut F) -> io::Result<()>
+where
+    F: FnMut(PcmFormat, &[f32])
+{
+    let mut decoder = minimp3::Decoder::new(file);
+    let mut frames = 0;
+
+    loop {
+        match decoder.next_frame() {
+            Ok(frame) => {
+                let format = PcmFormat {
+                    sample_rate: frame.sample_rate as u32,
+                    channels: frame.channels as u16,
+                    bits_per_sample: 16,
+                };
+                let samples: Vec<f32> = frame.data.iter().map(|sample| *sample as f32 / 32768.0).collect();
+                chunk(format, &samples);
+                frames += 1;
+            },
+            Err(minimp3::Error::Eof) => break,
+            Err(minimp3::Error::Io(err)) => return Err(err),
+            Err(minimp3::Error::InsufficientData) | Err(minimp3::Error::SkippedData) => continue,
+        }
+    }
+
+    match frames {
+        0 => Err(invalid("No MPEG audio frames found")),
+        _ => Ok(()),
+    }
+}
+
+/// Decode an audio file, handing the samples to `chunk` a few thousand
+/// frames at a time: all channels interleaved and scaled to -1.0..1.0.
+///
+/// WAV and AIFF files need to hold plain PCM samples, FLAC files are
+/// recognized by their marker and everything else is decoded as MP3.
+pub fn decode_pcm<T, F>(path: T, mut chunk: F) -> io::Result<()>
+where
+    T: AsRef<Path>,
+    F: FnMut(PcmFormat, &[f32])
+{
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+
+    let mut magic = [0u8; 12];
+    if file_size >= 12 {
+        read_exact_at(&mut file, 0, &mut magic)?;
+    }
+
+    match (&magic[0..4], &magic[8..12]) {
+        (b"RIFF", b"WAVE") => decode_wav(&mut file, file_size, &mut chunk),
+        (b"FORM", b"AIFF") => decode_aiff(&mut file, file_size, false, &mut chunk),
+        (b"FORM", b"AIFC") => decode_aiff(&mut file, file_size, true, &mut chunk),
+        (b"fLaC", _) => decode_flac(file, &mut chunk),
+        _ => decode_mp3(file, &mut chunk),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_scales_pcm_samples() {
+        assert_eq!(vec![0.5, -1.0], decode_samples(&[0x00, 0x40, 0x00, 0x80], 16, true, false).unwrap());
+        assert_eq!(vec![0.5, -0.5], decode_samples(&[0x40, 0x00, 0x00, 0xc0, 0x00, 0x00], 24, false, false).unwrap());
+        assert_eq!(vec![0.0, 0.5], decode_samples(&[128, 192], 8, true, false).unwrap());
+    }
+
+    #[test]
+    fn it_decodes_wave_files() {
+        let mut data = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        data.extend(b"fmt \x10\x00\x00\x00\x01\x00\x02\x00\x44\xac\x00\x00\x10\xb1\x02\x00\x04\x00\x10\x00");
+        data.extend(b"data\x08\x00\x00\x00\x00\x40\x00\xc0\x00\x20\x00\xe0");
+        let temp = crate::utils::test_dir("decoder");
+        let path = temp.path().join("Track.wav");
+        std::fs::write(&path, &data).unwrap();
+
+        let mut formats = vec![];
+        let mut samples = vec![];
+        decode_pcm(&path, |format, chunk| {
+            formats.push(format);
+            samples.extend_from_slice(chunk);
+        }).unwrap();
+        assert_eq!(vec![PcmFormat { sample_rate: 44100, channels: 2, bits_per_sample: 16 }], formats);
+        assert_eq!(vec![0.5, -0.5, 0.25, -0.25], samples);
+    }
+
+    #[test]
+    fn it_decodes_long_files_a_chunk_at_a_time() {
+        let mut data = b"FORM\x00\x00\x00\x00AIFF".to_vec();
+        data.extend(b"COMM\x00\x00\x00\x12\x00\x01\x00\x00\x00\x00\x00\x10\x40\x0e\xac\x44\x00\x00\x00\x00\x00\x00");
+        let frames = 2 * CHUNK_FRAMES + 1;
+        data.extend(b"SSND");
+        data.extend(&(8 + 2 * frames as u32).to_be_bytes());
+        data.extend(&[0u8; 8]);
+        data.extend([0x40, 0x00].iter().cycle().take(2 * frames));
+        let temp = crate::utils::test_dir("decoder-chunks");
+        let path = temp.path().join("Track.aiff");
+        std::fs::write(&path, &data).unwrap();
+
+        let mut chunks = vec![];
+        decode_pcm(&path, |_format, chunk| {
+            assert!(chunk.iter().all(|sample| *sample == 0.5));
+            chunks.push(chunk.len());
+        }).unwrap();
+        assert_eq!(vec![CHUNK_FRAMES, CHUNK_FRAMES, 1], chunks);
+    }
+
+    #[test]
+    fn it_reads_aiff_sample_rates() {
+        assert_eq!(44100, extended_to_u32(&[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]));
+    }
+}
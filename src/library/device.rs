@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::rekordbox::{Cue, CueSource, Metadata, MetadataTrack as Track, TrackColor};
+use super::{camelot_key, TrackAnalysis};
+use anlz::parse_anlz;
+use pdb::{parse_pdb, PdbDatabase, PdbTrack};
+
+mod anlz;
+mod pdb;
+
+/// Where players look for the database on a device.
+pub const PDB_PATH: &str = "PIONEER/rekordbox/export.pdb";
+
+/// A folder or playlist of an export, listed after the folder it is in.
+#[derive(Debug, PartialEq)]
+pub struct DevicePlaylist {
+    pub id: u32,
+    pub parent_id: u32,
+    pub name: String,
+    pub is_folder: bool,
+    pub entries: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct DeviceLibrary {
+    pub tracks: Vec<Track>,
+    pub playlists: Vec<DevicePlaylist>,
+}
+
+/// Whether a folder is the root of a USB stick or SD card prepared by rekordbox.
+pub fn is_device<T: AsRef<Path>>(root: T) -> bool {
+    root.as_ref().join(PDB_PATH).is_file()
+}
+
+fn device_file(root: &Path, device_path: &str) -> PathBuf {
+    root.join(device_path.trim_start_matches('/'))
+}
+
+fn read_analysis(root: &Path, track: &PdbTrack) -> Option<(TrackAnalysis, Vec<Cue>)> {
+    if track.analyze_path.is_empty() {
+        return None;
+    }
+    let dat = fs::read(device_file(root, &track.analyze_path)).map(|data| parse_anlz(&data)).ok()?;
+    let ext = fs::read(device_file(root, &track.analyze_path.replace(".DAT", ".EXT")))
+        .map(|data| parse_anlz(&data))
+        .unwrap_or_default();
+
+    let cues = match ext.cues.is_empty() {
+        true => dat.cues,
+        false => ext.cues,
+    };
+    let analysis = TrackAnalysis {
+        duration_ms: track.duration as u32 * 1000,
+        sample_rate: track.sample_rate,
+        bits_per_sample: track.sample_depth,
+        preview: dat.preview,
+        detail: ext.detail,
+        beats: dat.beats,
+        loudness: None,
+        phrases: ext.phrases,
+    };
+
+    Some((analysis, cues))
+}
+
+fn device_track(root: &Path, pdb: &PdbDatabase, track: &PdbTrack) -> Option<Track> {
+    let path = device_file(root, &track.file_path);
+    let size = match fs::metadata(&path) {
+        Ok(attributes) => attributes.len(),
+        Err(err) => {
+            eprintln!("Failed reading {:?} from the export; error = {}", path, err);
+            return None;
+        },
+    };
+
+    let name = |names: &HashMap<u32, String>, id: u32| names.get(&id).cloned().unwrap_or_default();
+    let metadata = Metadata {
+        artist: name(&pdb.artists, track.artist_id),
+        title: track.title.clone(),
+        bpm: Some(track.tempo).filter(|tempo| *tempo > 0),
+        album: name(&pdb.albums, track.album_id),
+        rating: track.rating.min(super::MAX_RATING),
+        genre: name(&pdb.genres, track.genre_id),
+        comment: track.comment.clone(),
+        key: pdb.keys.get(&track.key_id).and_then(|key| camelot_key(key)),
+    };
+
+    let mut device_track = Track::new(metadata, path, size);
+    device_track.color = TrackColor::from_id(track.color_id).filter(|color| *color != TrackColor::None);
+    if let Some((analysis, cues)) = read_analysis(root, track) {
+        device_track.cues = cues;
+        device_track.cue_source = CueSource::Import;
+        // Exports of tracks that were never analyzed only carry cues
+        device_track.analysis = Some(analysis)
+            .filter(|analysis| !analysis.beats.is_empty() || !analysis.preview.is_empty());
+    }
+
+    Some(device_track)
+}
+
+/// Folders before their contents, every folder in the order of its sort field.
+fn playlist_tree(pdb: &PdbDatabase, paths: &HashMap<u32, PathBuf>, parent_id: u32, playlists: &mut Vec<DevicePlaylist>) {
+    for playlist in pdb.playlists.iter().filter(|playlist| playlist.parent_id == parent_id) {
+        // A folder listing itself would never end
+        if playlist.id == parent_id {
+            continue;
+        }
+        let entries = pdb.entries.get(&playlist.id)
+            .map(|track_ids| track_ids.iter().filter_map(|track_id| paths.get(track_id).cloned()).collect())
+            .unwrap_or_default();
+        playlists.push(DevicePlaylist {
+            id: playlist.id,
+            parent_id: playlist.parent_id,
+            name: playlist.name.clone(),
+            is_folder: playlist.is_folder,
+            entries,
+        });
+
+        if playlist.is_folder {
+            playlist_tree(pdb, paths, playlist.id, playlists);
+        }
+    }
+}
+
+/// Tracks and playlists of a rekordbox export, with the cues, beat grids
+/// and waveforms of its ANLZ files. Tracks missing on the device are skipped.
+pub fn read_device<T: AsRef<Path>>(root: T) -> io::Result<DeviceLibrary> {
+    let root = root.as_ref();
+    let pdb = parse_pdb(&fs::read(root.join(PDB_PATH))?)?;
+
+    let mut tracks = vec![];
+    let mut paths = HashMap::new();
+    for pdb_track in &pdb.tracks {
+        if let Some(track) = device_track(root, &pdb, pdb_track) {
+            paths.insert(pdb_track.id, track.path.clone());
+            tracks.push(track);
+        }
+    }
+
+    let mut playlists = vec![];
+    playlist_tree(&pdb, &paths, 0, &mut playlists);
+
+    Ok(DeviceLibrary { tracks, playlists })
+}
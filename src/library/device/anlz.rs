@@ -0,0 +1,219 @@
+//! Reader for the ANLZ files rekordbox writes next to every exported track.
+//! Only the beat grid, the waveforms, the cues and the phrases are picked
+//! up, all other sections are skipped.
+
+use crate::library::{Beat, Phrase, PhraseKind};
+use crate::rekordbox::{Cue, CueKind};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct AnlzData {
+    pub beats: Vec<Beat>,
+    pub preview: Vec<u8>,
+    pub detail: Vec<u8>,
+    pub cues: Vec<Cue>,
+    pub phrases: Vec<Phrase>,
+}
+
+/// Key newer versions of rekordbox mask the phrase section with from its mood on.
+const PHRASE_MASK: [u8; 19] = [
+    0xcb, 0xe1, 0xee, 0xfa, 0xe5, 0xee, 0xad, 0xee, 0xe9, 0xd2, 0xe9, 0xeb, 0xe1, 0xe9, 0xf3, 0xe8, 0xe9, 0xf4, 0xe1,
+];
+const PHRASE_MOOD: usize = 0x12;
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Tagged sections following each other from `offset`, with their header length.
+fn sections(data: &[u8], offset: usize) -> Vec<(&[u8], usize)> {
+    let mut sections = vec![];
+    let mut offset = offset;
+
+    while let (Some(header_length), Some(length)) = (u32_at(data, offset + 4), u32_at(data, offset + 8)) {
+        let section = match data.get(offset..offset + length as usize) {
+            Some(section) if length >= 12 => section,
+            _ => break,
+        };
+        sections.push((section, header_length as usize));
+        offset += length as usize;
+    }
+
+    sections
+}
+
+fn cue(hot_cue: u32, cue_type: u8, position_ms: u32, loop_end_ms: u32) -> Cue {
+    let kind = match hot_cue {
+        0 => CueKind::Memory,
+        pad => CueKind::Hot(pad as u8),
+    };
+    let loop_end_ms = match (cue_type, loop_end_ms) {
+        (2, end) if end != u32::MAX => Some(end),
+        _ => None,
+    };
+
+    Cue::new(kind, position_ms, loop_end_ms)
+}
+
+fn beat_grid(section: &[u8], header_length: usize) -> Vec<Beat> {
+    let count = u32_at(section, 0x14).unwrap_or(0) as usize;
+
+    (0..count)
+        .map(|index| header_length + index * 8)
+        .map_while(|offset| Some(Beat {
+            number: u16_at(section, offset)? as u8,
+            tempo: u16_at(section, offset + 2)? as u32,
+            time_ms: u32_at(section, offset + 4)?,
+        }))
+        .collect()
+}
+
+fn waveform(section: &[u8], header_length: usize, length_offset: usize) -> Vec<u8> {
+    let length = u32_at(section, length_offset).unwrap_or(0) as usize;
+    section.get(header_length..header_length + length)
+        .unwrap_or_default()
+        .to_vec()
+}
+
+/// PCOB lists of the DAT file.
+fn cue_list(section: &[u8], header_length: usize) -> Vec<Cue> {
+    sections(section, header_length).into_iter()
+        .filter(|(entry, _header_length)| entry.starts_with(b"PCPT"))
+        .filter_map(|(entry, header_length)| Some(cue(
+            u32_at(entry, 0x0c)?,
+            *entry.get(header_length)?,
+            u32_at(entry, header_length + 4)?,
+            u32_at(entry, header_length + 8)?,
+        )))
+        .collect()
+}
+
+/// PCO2 lists of the EXT file, which newer versions of rekordbox write
+/// with colors and comments. Entries have no header of their own.
+fn extended_cue_list(section: &[u8], header_length: usize) -> Vec<Cue> {
+    sections(section, header_length).into_iter()
+        .filter(|(entry, _header_length)| entry.starts_with(b"PCP2"))
+        .filter_map(|(entry, _header_length)| Some(cue(
+            u32_at(entry, 0x0c)?,
+            *entry.get(0x10)?,
+            u32_at(entry, 0x14)?,
+            u32_at(entry, 0x18)?,
+        )))
+        .collect()
+}
+
+/// Phrase kinds of the high, mid and low moods.
+fn phrase_kind(mood: u16, kind: u16) -> Option<PhraseKind> {
+    match (mood, kind) {
+        (_, 1) => Some(PhraseKind::Intro),
+        (1, 2) => Some(PhraseKind::Verse),
+        (1, 3) => Some(PhraseKind::Breakdown),
+        (1, 5) => Some(PhraseKind::Chorus),
+        (1, 6) => Some(PhraseKind::Outro),
+        (2, 2..=7) | (3, 2..=7) => Some(PhraseKind::Verse),
+        (2, 8) | (3, 8) => Some(PhraseKind::Breakdown),
+        (2, 9) | (3, 9) => Some(PhraseKind::Chorus),
+        (2, 10) | (3, 10) => Some(PhraseKind::Outro),
+        _ => None,
+    }
+}
+
+/// PSSI section of the EXT file. Each phrase runs to the start of the next,
+/// the last one to the end beat in the header.
+fn phrases(section: &[u8], header_length: usize) -> Vec<Phrase> {
+    let mut section = section.to_vec();
+    let count = u16_at(&section, 0x10).unwrap_or(0);
+    if u16_at(&section, PHRASE_MOOD).unwrap_or(0) > 20 {
+        for (index, byte) in section.iter_mut().skip(PHRASE_MOOD).enumerate() {
+            *byte ^= PHRASE_MASK[index % PHRASE_MASK.len()].wrapping_add(count as u8);
+        }
+    }
+    let mood = u16_at(&section, PHRASE_MOOD).unwrap_or(0);
+    let end_beat = u16_at(&section, 0x1a).unwrap_or(0) as u32;
+    let entry_length = u32_at(&section, 0x0c).unwrap_or(0) as usize;
+
+    let entries: Vec<(u32, Option<PhraseKind>)> = (0..count as usize)
+        .map(|index| header_length + index * entry_length)
+        .map_while(|offset| Some((u16_at(&section, offset + 2)? as u32, u16_at(&section, offset + 4)?)))
+        .map(|(start_beat, kind)| (start_beat, phrase_kind(mood, kind)))
+        .collect();
+
+    entries.iter().enumerate()
+        .filter_map(|(index, (start_beat, kind))| Some(Phrase {
+            kind: (*kind)?,
+            start_beat: *start_beat,
+            end_beat: entries.get(index + 1).map(|(start_beat, _kind)| *start_beat).unwrap_or(end_beat),
+        }))
+        .collect()
+}
+
+/// Read an ANLZ0000.DAT or ANLZ0000.EXT file. When a file holds both kinds
+/// of cue lists the extended ones win.
+pub fn parse_anlz(data: &[u8]) -> AnlzData {
+    let mut anlz = AnlzData::default();
+    if !data.starts_with(b"PMAI") {
+        return anlz;
+    }
+
+    let mut cues = vec![];
+    let mut extended_cues = vec![];
+    for (section, header_length) in sections(data, u32_at(data, 4).unwrap_or(0) as usize) {
+        match &section[..4] {
+            b"PQTZ" => anlz.beats = beat_grid(section, header_length),
+            b"PWAV" => anlz.preview = waveform(section, header_length, 0x0c),
+            b"PWV3" => anlz.detail = waveform(section, header_length, 0x10),
+            b"PCOB" => cues.extend(cue_list(section, header_length)),
+            b"PSSI" => anlz.phrases = phrases(section, header_length),
+            b"PCO2" => extended_cues.extend(extended_cue_list(section, header_length)),
+            _ => {},
+        }
+    }
+    anlz.cues = match extended_cues.is_empty() {
+        true => cues,
+        false => extended_cues,
+    };
+
+    anlz
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_reads_masked_phrases() {
+        let mut section = b"PSSI\x00\x00\x00\x20\x00\x00\x00\x50\x00\x00\x00\x18\x00\x02\x00\x01".to_vec();
+        section.extend(&[0, 0, 0, 0, 0, 0, 0x00, 0x61, 0, 0, 0, 0]);
+        section.extend(&[0x00, 0x01, 0x00, 0x01, 0x00, 0x01]);
+        section.extend(&[0u8; 18]);
+        section.extend(&[0x00, 0x02, 0x00, 0x21, 0x00, 0x05]);
+        section.extend(&[0u8; 18]);
+        let expected = vec![
+            Phrase { kind: PhraseKind::Intro, start_beat: 1, end_beat: 33 },
+            Phrase { kind: PhraseKind::Chorus, start_beat: 33, end_beat: 97 },
+        ];
+        assert_eq!(expected, phrases(&section, 0x20));
+
+        for (index, byte) in section.iter_mut().skip(PHRASE_MOOD).enumerate() {
+            *byte ^= PHRASE_MASK[index % PHRASE_MASK.len()].wrapping_add(2);
+        }
+        assert_eq!(expected, phrases(&section, 0x20));
+    }
+
+    #[test]
+    fn it_reads_extended_cues() {
+        let mut entry = b"PCP2\x00\x00\x00\x10\x00\x00\x00\x1c\x00\x00\x00\x03\x02\x00\x00\x00".to_vec();
+        entry.extend(&[0x00, 0x00, 0x07, 0xd0, 0x00, 0x00, 0x0f, 0xa0]);
+        let mut list = b"PCO2\x00\x00\x00\x14\x00\x00\x00\x30\x00\x00\x00\x01\x00\x01\x00\x00".to_vec();
+        list.extend(entry);
+        let mut file = b"PMAI\x00\x00\x00\x1c\x00\x00\x00\x4c".to_vec();
+        file.extend(&[0u8; 16]);
+        file.extend(list);
+
+        assert_eq!(vec![Cue::new(CueKind::Hot(3), 2000, Some(4000))], parse_anlz(&file).cues);
+    }
+}
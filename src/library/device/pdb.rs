@@ -0,0 +1,306 @@
+//! Reader for the DeviceSQL database rekordbox writes to `PIONEER/rekordbox/export.pdb`.
+//!
+//! The file header lists the first and last page of every table. Pages of a
+//! table are chained through their next page field, data pages keep their
+//! rows in a heap and the row offsets in groups of 16 at the end of the page.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+
+const PAGE_HEADER_SIZE: usize = 0x28;
+const ROW_GROUP_SIZE: usize = 0x24;
+const ROWS_PER_GROUP: usize = 16;
+
+/// Header pages and index pages have this flag set, data pages don't.
+const INDEX_PAGE_FLAG: u8 = 0x40;
+
+const TRACKS: u32 = 0;
+const GENRES: u32 = 1;
+const ARTISTS: u32 = 2;
+const ALBUMS: u32 = 3;
+const KEYS: u32 = 5;
+const PLAYLIST_TREE: u32 = 7;
+const PLAYLIST_ENTRIES: u32 = 8;
+
+/// Offsets of the strings in a track row.
+const TRACK_STRINGS: usize = 0x5e;
+const ANALYZE_PATH: usize = 14;
+const COMMENT: usize = 16;
+const TITLE: usize = 17;
+const FILE_PATH: usize = 20;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct PdbTrack {
+    pub id: u32,
+    pub title: String,
+    pub comment: String,
+    pub artist_id: u32,
+    pub album_id: u32,
+    pub genre_id: u32,
+    pub key_id: u32,
+    pub color_id: u8,
+    pub rating: u8,
+    /// BPM times 100.
+    pub tempo: u32,
+    /// Seconds.
+    pub duration: u16,
+    pub sample_rate: u32,
+    pub sample_depth: u16,
+    /// Path on the device, "/Contents/Artist/Title.mp3".
+    pub file_path: String,
+    /// Path of the ANLZ0000.DAT file on the device.
+    pub analyze_path: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PdbPlaylist {
+    pub id: u32,
+    pub parent_id: u32,
+    pub sort_order: u32,
+    pub is_folder: bool,
+    pub name: String,
+}
+
+#[derive(Debug, Default)]
+pub struct PdbDatabase {
+    pub tracks: Vec<PdbTrack>,
+    pub artists: HashMap<u32, String>,
+    pub albums: HashMap<u32, String>,
+    pub genres: HashMap<u32, String>,
+    pub keys: HashMap<u32, String>,
+    pub playlists: Vec<PdbPlaylist>,
+    /// Track ids of every playlist, in playlist order.
+    pub entries: HashMap<u32, Vec<u32>>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// DeviceSQL string: short ASCII strings carry their length in the first
+/// byte, longer ones a 4 byte header with ASCII or UTF-16LE data.
+pub fn device_sql_string(data: &[u8], offset: usize) -> Option<String> {
+    let flags = *data.get(offset)?;
+    if flags & 1 == 1 {
+        let bytes = data.get(offset + 1..offset + (flags >> 1) as usize)?;
+        return Some(String::from_utf8_lossy(bytes).into_owned());
+    }
+
+    let length = u16_at(data, offset + 1)? as usize;
+    let bytes = data.get(offset + 4..offset + length)?;
+    match flags {
+        0x40 => Some(String::from_utf8_lossy(bytes).into_owned()),
+        0x90 => {
+            let units: Vec<u16> = bytes.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+            Some(String::from_utf16_lossy(&units))
+        },
+        _ => None,
+    }
+}
+
+/// Rows present on a data page, each running to the end of the page.
+fn page_rows(page: &[u8]) -> Vec<&[u8]> {
+    let counts = u32::from_le_bytes([page[0x18], page[0x19], page[0x1a], 0]);
+    let row_offsets = (counts & 0x1fff) as usize;
+
+    (0..row_offsets)
+        .filter_map(|index| {
+            let group_end = page.len().checked_sub((index / ROWS_PER_GROUP) * ROW_GROUP_SIZE)?;
+            let slot = index % ROWS_PER_GROUP;
+            let present = u16_at(page, group_end.checked_sub(4)?)?;
+            if present & (1 << slot) == 0 {
+                return None;
+            }
+            let offset = u16_at(page, group_end.checked_sub(6 + 2 * slot)?)? as usize;
+            page.get(PAGE_HEADER_SIZE + offset..)
+        })
+        .collect()
+}
+
+struct Pdb<'a> {
+    data: &'a [u8],
+    page_size: usize,
+    /// First and last page of every table.
+    tables: HashMap<u32, (u32, u32)>,
+}
+
+impl<'a> Pdb<'a> {
+    fn new(data: &'a [u8]) -> io::Result<Self> {
+        let page_size = u32_at(data, 0x04).ok_or_else(|| invalid("Missing file header"))? as usize;
+        let table_count = u32_at(data, 0x08).unwrap_or(0) as usize;
+        if page_size < PAGE_HEADER_SIZE + ROW_GROUP_SIZE {
+            return Err(invalid("Invalid page size"));
+        }
+
+        let mut tables = HashMap::new();
+        for index in 0..table_count {
+            let pointer = 0x1c + index * 16;
+            match (u32_at(data, pointer), u32_at(data, pointer + 8), u32_at(data, pointer + 12)) {
+                (Some(table_type), Some(first_page), Some(last_page)) => {
+                    tables.insert(table_type, (first_page, last_page));
+                },
+                _ => return Err(invalid("Short table list")),
+            }
+        }
+
+        Ok(Self { data, page_size, tables })
+    }
+
+    fn rows(&self, table_type: u32) -> Vec<&'a [u8]> {
+        let (first_page, last_page) = match self.tables.get(&table_type) {
+            Some(pages) => *pages,
+            None => return vec![],
+        };
+
+        let mut rows = vec![];
+        let mut page_index = first_page as usize;
+        // Guards against page chains running in circles
+        for _ in 0..self.data.len() / self.page_size {
+            let page = match self.data.get(page_index * self.page_size..(page_index + 1) * self.page_size) {
+                Some(page) => page,
+                None => break,
+            };
+            if page[0x1b] & INDEX_PAGE_FLAG == 0 {
+                rows.extend(page_rows(page));
+            }
+            if page_index == last_page as usize {
+                break;
+            }
+            page_index = u32_at(page, 0x0c).unwrap_or(0) as usize;
+        }
+
+        rows
+    }
+
+    /// Names of the genre, artist, album or key tables by id.
+    fn names(&self, table_type: u32, parse: fn(&[u8]) -> Option<(u32, String)>) -> HashMap<u32, String> {
+        self.rows(table_type).into_iter()
+            .filter_map(parse)
+            .collect()
+    }
+}
+
+fn parse_track(row: &[u8]) -> Option<PdbTrack> {
+    let string = |index: usize| -> String {
+        u16_at(row, TRACK_STRINGS + 2 * index)
+            .and_then(|offset| device_sql_string(row, offset as usize))
+            .unwrap_or_default()
+    };
+
+    Some(PdbTrack {
+        id: u32_at(row, 0x48)?,
+        title: string(TITLE),
+        comment: string(COMMENT),
+        artist_id: u32_at(row, 0x44)?,
+        album_id: u32_at(row, 0x40)?,
+        genre_id: u32_at(row, 0x3c)?,
+        key_id: u32_at(row, 0x20)?,
+        color_id: *row.get(0x58)?,
+        rating: *row.get(0x59)?,
+        tempo: u32_at(row, 0x38)?,
+        duration: u16_at(row, 0x54)?,
+        sample_rate: u32_at(row, 0x08)?,
+        sample_depth: u16_at(row, 0x52)?,
+        file_path: string(FILE_PATH),
+        analyze_path: string(ANALYZE_PATH),
+    })
+}
+
+/// Artist and album rows with a subtype ending in 4 keep their name at a 16 bit offset.
+fn name_offset(row: &[u8], near: usize) -> Option<usize> {
+    match u16_at(row, 0)? & 0x04 {
+        0 => row.get(near).map(|offset| *offset as usize),
+        _ => u16_at(row, near + 1).map(|offset| offset as usize),
+    }
+}
+
+fn parse_artist(row: &[u8]) -> Option<(u32, String)> {
+    Some((u32_at(row, 0x04)?, device_sql_string(row, name_offset(row, 0x09)?)?))
+}
+
+fn parse_album(row: &[u8]) -> Option<(u32, String)> {
+    Some((u32_at(row, 0x0c)?, device_sql_string(row, name_offset(row, 0x15)?)?))
+}
+
+fn parse_genre(row: &[u8]) -> Option<(u32, String)> {
+    Some((u32_at(row, 0)?, device_sql_string(row, 0x04)?))
+}
+
+fn parse_key(row: &[u8]) -> Option<(u32, String)> {
+    Some((u32_at(row, 0)?, device_sql_string(row, 0x08)?))
+}
+
+fn parse_playlist(row: &[u8]) -> Option<PdbPlaylist> {
+    Some(PdbPlaylist {
+        id: u32_at(row, 0x0c)?,
+        parent_id: u32_at(row, 0)?,
+        sort_order: u32_at(row, 0x08)?,
+        is_folder: u32_at(row, 0x10)? != 0,
+        name: device_sql_string(row, 0x14)?,
+    })
+}
+
+pub fn parse_pdb(data: &[u8]) -> io::Result<PdbDatabase> {
+    let pdb = Pdb::new(data)?;
+
+    let mut entries: Vec<(u32, u32, u32)> = pdb.rows(PLAYLIST_ENTRIES).into_iter()
+        .filter_map(|row| Some((u32_at(row, 8)?, u32_at(row, 0)?, u32_at(row, 4)?)))
+        .collect();
+    entries.sort();
+    let mut playlist_entries: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (playlist_id, _index, track_id) in entries {
+        playlist_entries.entry(playlist_id).or_default().push(track_id);
+    }
+
+    let mut playlists: Vec<PdbPlaylist> = pdb.rows(PLAYLIST_TREE).into_iter().filter_map(parse_playlist).collect();
+    playlists.sort_by_key(|playlist| (playlist.parent_id, playlist.sort_order));
+
+    Ok(PdbDatabase {
+        tracks: pdb.rows(TRACKS).into_iter().filter_map(parse_track).collect(),
+        artists: pdb.names(ARTISTS, parse_artist),
+        albums: pdb.names(ALBUMS, parse_album),
+        genres: pdb.names(GENRES, parse_genre),
+        keys: pdb.names(KEYS, parse_key),
+        playlists,
+        entries: playlist_entries,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_decodes_device_sql_strings() {
+        assert_eq!(Some(String::from("Dub")), device_sql_string(b"\x09Dub", 0));
+        assert_eq!(Some(String::from("ét")), device_sql_string(&[0x90, 0x08, 0x00, 0x00, 0xe9, 0x00, 0x74, 0x00], 0));
+        assert_eq!(Some(String::from("Long")), device_sql_string(b"\x40\x08\x00\x00Long", 0));
+        assert_eq!(None, device_sql_string(b"\x0fDub", 0));
+    }
+
+    #[test]
+    fn it_reads_artist_rows_with_far_names() {
+        let mut row = vec![0x64, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0x00, 0x0c, 0x00];
+        row.extend(b"\x0fArtist");
+
+        assert_eq!(Some((7, String::from("Artist"))), parse_artist(&row));
+    }
+
+    #[test]
+    fn it_reads_album_rows() {
+        let mut row = vec![0x80, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00];
+        row.extend(&[0x00, 0x00, 0x00, 0x00, 0x03, 0x16]);
+        row.extend(b"\x0dAlbum");
+
+        assert_eq!(Some((2, String::from("Album"))), parse_album(&row));
+    }
+}
@@ -0,0 +1,140 @@
+/// Camelot numbers of the minor keys, indexed by pitch class starting at C.
+const MINOR_CAMELOT: [u8; 12] = [5, 12, 7, 2, 9, 4, 11, 6, 1, 8, 3, 10];
+
+/// Camelot numbers of the major keys, indexed by pitch class starting at C.
+const MAJOR_CAMELOT: [u8; 12] = [8, 3, 10, 5, 12, 7, 2, 9, 4, 11, 6, 1];
+
+fn pitch_class(note: char) -> Option<i32> {
+    match note.to_ascii_uppercase() {
+        'C' => Some(0),
+        'D' => Some(2),
+        'E' => Some(4),
+        'F' => Some(5),
+        'G' => Some(7),
+        'A' => Some(9),
+        'B' => Some(11),
+        _ => None,
+    }
+}
+
+/// Camelot notation ("8A") of a key written as Camelot or as a musical key
+/// ("Am", "F#", "Bbm", "Ebmin"), as found in TKEY frames and DJ software.
+pub fn camelot_key(key: &str) -> Option<String> {
+    let key = key.trim();
+
+    let (number, letter) = key.split_at(key.len().saturating_sub(1));
+    if let (Ok(number), Some(letter)) = (number.parse::<u8>(), letter.chars().next()) {
+        return match (number, letter.to_ascii_uppercase()) {
+            (1..=12, letter @ 'A') | (1..=12, letter @ 'B') => Some(format!("{}{}", number, letter)),
+            _ => None,
+        };
+    }
+
+    let mut characters = key.chars();
+    let mut pitch = pitch_class(characters.next()?)?;
+    let rest: String = characters.collect();
+
+    let mode = match rest.chars().next() {
+        Some('#') | Some('♯') => {
+            pitch += 1;
+            &rest[rest.chars().next().unwrap().len_utf8()..]
+        },
+        Some('b') | Some('♭') => {
+            pitch -= 1;
+            &rest[rest.chars().next().unwrap().len_utf8()..]
+        },
+        _ => &rest[..],
+    };
+    let pitch = pitch.rem_euclid(12) as usize;
+
+    match mode.trim().to_lowercase().as_str() {
+        "" | "maj" | "major" => Some(format!("{}B", MAJOR_CAMELOT[pitch])),
+        "m" | "min" | "minor" => Some(format!("{}A", MINOR_CAMELOT[pitch])),
+        _ => None,
+    }
+}
+
+fn camelot_parts(key: &str) -> Option<(u8, char)> {
+    let (number, letter) = key.split_at(key.len().checked_sub(1)?);
+    Some((number.parse().ok()?, letter.chars().next()?))
+}
+
+/// Id of a Camelot key as players send it when browsing by key: 1A is 1,
+/// 1B is 2 and so on up to 24 for 12B.
+pub fn key_id(key: &str) -> Option<u32> {
+    match camelot_parts(key)? {
+        (number @ 1..=12, 'A') => Some(number as u32 * 2 - 1),
+        (number @ 1..=12, 'B') => Some(number as u32 * 2),
+        _ => None,
+    }
+}
+
+/// Camelot key of a key id.
+pub fn key_of_id(id: u32) -> Option<String> {
+    match id {
+        1..=24 => Some(format!("{}{}", id.div_ceil(2), if id % 2 == 1 { 'A' } else { 'B' })),
+        _ => None,
+    }
+}
+
+/// Steps between two Camelot keys on the wheel, where a neighbouring number
+/// and the relative major or minor are one step each.
+pub fn key_distance(a: &str, b: &str) -> Option<u32> {
+    let ((number_a, letter_a), (number_b, letter_b)) = (camelot_parts(a)?, camelot_parts(b)?);
+    let around = (number_a as u32 + 12 - number_b as u32 % 12) % 12;
+    Some(around.min(12 - around) + (letter_a != letter_b) as u32)
+}
+
+/// Whether two Camelot keys mix harmonically: the same key, a neighbour on
+/// the wheel or the relative major or minor.
+pub fn compatible_keys(a: &str, b: &str) -> bool {
+    matches!(key_distance(a, b), Some(0..=1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_converts_keys_to_camelot() {
+        let keys: Vec<Option<String>> = ["8a", "12B", "Am", "C", "F#m", "Bbm", "Ebmin", "Db major", "13A", "H"].iter()
+            .map(|key| camelot_key(key))
+            .collect();
+
+        assert_eq!(vec![
+            Some(String::from("8A")),
+            Some(String::from("12B")),
+            Some(String::from("8A")),
+            Some(String::from("8B")),
+            Some(String::from("11A")),
+            Some(String::from("3A")),
+            Some(String::from("2A")),
+            Some(String::from("3B")),
+            None,
+            None,
+        ], keys);
+    }
+
+    #[test]
+    fn it_finds_compatible_keys() {
+        assert!(compatible_keys("8A", "8A"));
+        assert!(compatible_keys("12A", "1A"));
+        assert!(compatible_keys("8A", "8B"));
+        assert!(!compatible_keys("8A", "9B"));
+        assert!(!compatible_keys("8A", "10A"));
+    }
+
+    #[test]
+    fn it_numbers_keys_around_the_wheel() {
+        assert_eq!(Some(1), key_id("1A"));
+        assert_eq!(Some(24), key_id("12B"));
+        assert_eq!(None, key_id("13A"));
+        assert_eq!(Some(String::from("8A")), key_of_id(15));
+        assert_eq!(Some(String::from("12B")), key_of_id(24));
+        assert_eq!(None, key_of_id(0));
+        assert_eq!(Some(2), key_distance("1A", "11A"));
+        assert_eq!(Some(3), key_distance("8A", "10B"));
+        assert_eq!(None, key_distance("8A", "C"));
+    }
+}
@@ -0,0 +1,250 @@
+use id3::Tag;
+use std::path::Path;
+use std::f64::consts::PI;
+
+/// Loudness ReplayGain 2.0 normalizes tracks to.
+pub const REFERENCE_LUFS: f32 = -18.0;
+
+/// Blocks are 400 ms long and start every 100 ms.
+const SEGMENT_MS: u32 = 100;
+const SEGMENTS_PER_BLOCK: usize = 4;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Integrated loudness after EBU R128, in LUFS.
+    pub lufs: f32,
+    /// Sample peak, 1.0 is full scale.
+    pub peak: f32,
+}
+
+impl Loudness {
+    /// Gain in dB that brings the track to the ReplayGain reference level.
+    pub fn replay_gain(&self) -> f32 {
+        REFERENCE_LUFS - self.lufs
+    }
+
+    pub fn from_replay_gain(gain: f32, peak: f32) -> Self {
+        Self {
+            lufs: REFERENCE_LUFS - gain,
+            peak,
+        }
+    }
+}
+
+/// Second order IIR filter section.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    state: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.state[0];
+        self.state[0] = self.b[1] * input - self.a[1] * output + self.state[1];
+        self.state[1] = self.b[2] * input - self.a[2] * output;
+        output
+    }
+}
+
+/// The K-weighting filter of ITU-R BS.1770 for any sample rate: a high
+/// shelf modelling the head followed by a high pass.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let k = (PI * 1681.974450955533 / rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    let k = (PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        state: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+fn block_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// Integrated loudness and peak measured while audio is decoded, chunk by
+/// chunk. All channels are weighted equally.
+pub struct LoudnessMeter {
+    channels: usize,
+    segment_frames: usize,
+    filters: Vec<[Biquad; 2]>,
+    /// Weighted power of every segment, the last one may still be filling.
+    segments: Vec<f64>,
+    frames: usize,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            channels,
+            segment_frames: (sample_rate * SEGMENT_MS / 1000) as usize,
+            filters: (0..channels).map(|_channel| k_weighting(sample_rate)).collect(),
+            segments: vec![],
+            frames: 0,
+            peak: 0.0,
+        }
+    }
+
+    /// Samples of all channels interleaved, starting with a whole frame.
+    pub fn push(&mut self, samples: &[f32]) {
+        if self.segment_frames == 0 {
+            return;
+        }
+
+        for frame in samples.chunks_exact(self.channels) {
+            let segment = self.frames / self.segment_frames;
+            if segment == self.segments.len() {
+                self.segments.push(0.0);
+            }
+            for (channel, sample) in frame.iter().enumerate() {
+                self.peak = self.peak.max(sample.abs());
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(*sample as f64));
+                self.segments[segment] += weighted * weighted;
+            }
+            self.frames += 1;
+        }
+    }
+
+    /// `None` when the audio was too short for a single block or silent.
+    pub fn finish(self) -> Option<Loudness> {
+        if self.segment_frames == 0 {
+            return None;
+        }
+
+        // A segment that didn't fill up doesn't count
+        let segments = &self.segments[..self.frames / self.segment_frames];
+        let block_frames = (self.segment_frames * SEGMENTS_PER_BLOCK) as f64;
+        let blocks: Vec<f64> = segments.windows(SEGMENTS_PER_BLOCK)
+            .map(|window| window.iter().sum::<f64>() / block_frames)
+            .filter(|power| *power > 0.0 && block_loudness(*power) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+
+        let relative_gate = block_loudness(blocks.iter().sum::<f64>() / blocks.len() as f64) + RELATIVE_GATE_LU;
+        let gated: Vec<f64> = blocks.into_iter()
+            .filter(|power| block_loudness(*power) > relative_gate)
+            .collect();
+
+        Some(Loudness {
+            lufs: block_loudness(gated.iter().sum::<f64>() / gated.len() as f64) as f32,
+            peak: self.peak,
+        })
+    }
+}
+
+/// "-6.48 dB" as written by ReplayGain taggers.
+fn parse_gain(value: &str) -> Option<f32> {
+    value.trim().trim_end_matches("dB").trim_end_matches("db").trim().parse().ok()
+}
+
+/// Track gain and peak from REPLAYGAIN_TRACK_* values, a missing peak counts as full scale.
+fn replay_gain_loudness<F: Fn(&str) -> Option<String>>(text: F) -> Option<Loudness> {
+    let gain = text("REPLAYGAIN_TRACK_GAIN").and_then(|value| parse_gain(&value))?;
+    let peak = text("REPLAYGAIN_TRACK_PEAK")
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(1.0);
+
+    Some(Loudness::from_replay_gain(gain, peak))
+}
+
+/// Loudness from the ReplayGain text frames of an ID3 tag.
+pub fn tag_loudness(tag: &Tag) -> Option<Loudness> {
+    replay_gain_loudness(|description| {
+        tag.extended_texts()
+            .find(|text| text.description.eq_ignore_ascii_case(description))
+            .map(|text| text.value.clone())
+    })
+}
+
+/// Loudness from the ReplayGain Vorbis comments of a FLAC file.
+pub fn flac_loudness(path: &Path) -> Option<Loudness> {
+    let reader = claxon::FlacReader::open(path).ok()?;
+    replay_gain_loudness(|name| reader.get_tag(name).next().map(str::to_string))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Stereo sine at 48 kHz, measured in chunks of a tenth of a second.
+    fn sine_loudness(frequency: f32, amplitude: f32, seconds: u32) -> Option<Loudness> {
+        let samples: Vec<f32> = (0..48000 * seconds)
+            .flat_map(|frame| {
+                let sample = amplitude * (2.0 * std::f32::consts::PI * frequency * frame as f32 / 48000.0).sin();
+                vec![sample, sample]
+            })
+            .collect();
+
+        let mut meter = LoudnessMeter::new(48000, 2);
+        for chunk in samples.chunks(9600) {
+            meter.push(chunk);
+        }
+        meter.finish()
+    }
+
+    #[test]
+    fn it_measures_the_loudness_of_a_reference_tone() {
+        // EBU Tech 3341: a 1 kHz stereo sine at -23 dBFS reads -23 LUFS
+        let loudness = sine_loudness(1000.0, 10f32.powf(-23.0 / 20.0), 5).unwrap();
+
+        assert!((loudness.lufs + 23.0).abs() < 0.1, "{}", loudness.lufs);
+        assert!((loudness.peak - 0.0708).abs() < 0.001);
+        assert_eq!(None, sine_loudness(1000.0, 0.0, 5));
+    }
+
+    #[test]
+    fn it_reads_replay_gain_values() {
+        assert_eq!(Some(-6.48), parse_gain("-6.48 dB"));
+        assert_eq!(Some(2.0), parse_gain("+2.00 dB"));
+        assert!((Loudness::from_replay_gain(-6.48, 1.0).lufs + 11.52).abs() < 0.001);
+    }
+
+    #[test]
+    fn it_reads_replay_gain_from_flac_comments() {
+        let temp = crate::utils::test_dir("flac-loudness");
+        let path = temp.path().join("Track.flac");
+        // Stream info of a 44.1 kHz 16 bit stereo stream without frames
+        let mut flac = b"fLaC\x00\x00\x00\x22\x10\x00\x10\x00\x00\x00\x00\x00\x00\x00\x0a\xc4\x42\xf0\x00\x00\x00\x00".to_vec();
+        flac.extend([0u8; 16].iter());
+        let comments = ["REPLAYGAIN_TRACK_GAIN=-6.48 dB", "REPLAYGAIN_TRACK_PEAK=0.988"];
+        let mut block = vec![0u8; 4];
+        block.extend((comments.len() as u32).to_le_bytes().iter());
+        for comment in comments.iter() {
+            block.extend((comment.len() as u32).to_le_bytes().iter());
+            block.extend(comment.as_bytes());
+        }
+        flac.extend([0x84, 0x00, 0x00, block.len() as u8].iter());
+        flac.extend(block);
+        std::fs::write(&path, flac).unwrap();
+
+        let loudness = flac_loudness(&path).unwrap();
+        assert!((loudness.replay_gain() + 6.48).abs() < 0.001);
+        assert!((loudness.peak - 0.988).abs() < 0.001);
+    }
+}
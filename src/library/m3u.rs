@@ -0,0 +1,74 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use super::rekordbox_xml::location_to_path;
+
+/// A playlist file found in a library folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePlaylist {
+    /// File name without the extension.
+    pub name: String,
+    pub path: PathBuf,
+    /// Entries in playlist order, relative entries resolved against the playlist folder.
+    pub entries: Vec<PathBuf>,
+}
+
+/// Drop "." and resolve ".." without touching the file system, so entries
+/// compare equal to the paths found while scanning.
+fn normalize(path: PathBuf) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn parse_entries(document: &str, base: &Path) -> Vec<PathBuf> {
+    document.lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| location_to_path(line).unwrap_or_else(|| base.join(line)))
+        .map(normalize)
+        .collect()
+}
+
+/// Read an M3U or M3U8 playlist, only the track paths are used.
+pub fn read_m3u<T: AsRef<Path>>(path: T) -> io::Result<FilePlaylist> {
+    let path = path.as_ref();
+    let document = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+    Ok(FilePlaylist {
+        name: path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default(),
+        path: path.to_path_buf(),
+        entries: parse_entries(&document, base),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_resolves_playlist_entries() {
+        let document = "#EXTM3U\n#EXTINF:-1,Artist - One\nOne.mp3\n\n../music/./Four.mp3\n/music/Two.mp3\r\nfile://localhost/music/Three%20Dub.mp3\n";
+
+        assert_eq!(
+            vec![
+                PathBuf::from("/playlists/One.mp3"),
+                PathBuf::from("/music/Four.mp3"),
+                PathBuf::from("/music/Two.mp3"),
+                PathBuf::from("/music/Three Dub.mp3"),
+            ],
+            parse_entries(document, Path::new("/playlists")),
+        );
+    }
+}
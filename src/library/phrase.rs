@@ -0,0 +1,153 @@
+use super::analysis::Beat;
+
+pub const BEATS_PER_BAR: u32 = 4;
+
+/// Phrases are found in blocks of this many bars, the usual length of a phrase in dance music.
+const BARS_PER_BLOCK: usize = 8;
+
+/// Energy of a block relative to the loudest one below which it counts as quiet.
+const QUIET_ENERGY: f32 = 0.6;
+
+/// Energy of a block relative to the loudest one from which it counts as a chorus.
+const CHORUS_ENERGY: f32 = 0.85;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhraseKind {
+    Intro,
+    Verse,
+    Chorus,
+    Breakdown,
+    Outro,
+}
+
+impl PhraseKind {
+    pub const ALL: [PhraseKind; 5] = [
+        PhraseKind::Intro,
+        PhraseKind::Verse,
+        PhraseKind::Chorus,
+        PhraseKind::Breakdown,
+        PhraseKind::Outro,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PhraseKind::Intro => "intro",
+            PhraseKind::Verse => "verse",
+            PhraseKind::Chorus => "chorus",
+            PhraseKind::Breakdown => "breakdown",
+            PhraseKind::Outro => "outro",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        PhraseKind::ALL.iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(name))
+            .cloned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phrase {
+    pub kind: PhraseKind,
+    /// Beat the phrase starts on, counting from 1 like the beat grid.
+    pub start_beat: u32,
+    /// Beat the next phrase starts on.
+    pub end_beat: u32,
+}
+
+impl Phrase {
+    pub fn bars(&self) -> u32 {
+        self.end_beat.saturating_sub(self.start_beat) / BEATS_PER_BAR
+    }
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    match samples.len() {
+        0 => 0.0,
+        length => (samples.iter().map(|sample| sample * sample).sum::<f32>() / length as f32).sqrt(),
+    }
+}
+
+/// Split a track into intro, verses, choruses, breakdowns and outro. The
+/// energy of every block of 8 bars, taken from the beat grid, decides:
+/// quiet blocks at the edges are intro and outro, quiet blocks in between
+/// breakdowns and the loudest blocks choruses.
+pub fn detect_phrases(mono: &[f32], sample_rate: u32, beats: &[Beat]) -> Vec<Phrase> {
+    let first_downbeat = match beats.iter().position(|beat| beat.number == 1) {
+        Some(index) => index,
+        None => return vec![],
+    };
+    let beats_per_block = BARS_PER_BLOCK * BEATS_PER_BAR as usize;
+    let sample_at = |time_ms: u32| ((time_ms as u64 * sample_rate as u64 / 1000) as usize).min(mono.len());
+
+    // Blocks as first beat, end beat and energy, a trailing block shorter than a bar is dropped
+    let mut blocks: Vec<(usize, usize, f32)> = vec![];
+    let mut start = first_downbeat;
+    while beats.len() - start >= BEATS_PER_BAR as usize {
+        let end = (start + beats_per_block).min(beats.len());
+        let end_sample = beats.get(end).map(|beat| sample_at(beat.time_ms)).unwrap_or(mono.len());
+        let start_sample = sample_at(beats[start].time_ms).min(end_sample);
+        blocks.push((start, end, rms(&mono[start_sample..end_sample])));
+        start = end;
+    }
+
+    let loudest = blocks.iter().fold(0f32, |loudest, (_start, _end, energy)| loudest.max(*energy));
+    if blocks.is_empty() || loudest == 0.0 {
+        return vec![];
+    }
+    let quiet = |energy: f32| energy / loudest < QUIET_ENERGY;
+
+    // Tracks always start with an intro and end with an outro, even when they start loud
+    let intro_end = blocks.iter().position(|(_start, _end, energy)| !quiet(*energy)).unwrap_or(blocks.len()).max(1);
+    let outro_start = blocks.iter().rposition(|(_start, _end, energy)| !quiet(*energy))
+        .map(|index| index + 1)
+        .unwrap_or(0)
+        .min(blocks.len() - 1)
+        .max(intro_end);
+
+    let mut phrases: Vec<Phrase> = vec![];
+    for (index, (start, end, energy)) in blocks.iter().enumerate() {
+        let kind = match index {
+            index if index < intro_end => PhraseKind::Intro,
+            index if index >= outro_start => PhraseKind::Outro,
+            _ if quiet(*energy) => PhraseKind::Breakdown,
+            _ if energy / loudest >= CHORUS_ENERGY => PhraseKind::Chorus,
+            _ => PhraseKind::Verse,
+        };
+
+        match phrases.last_mut() {
+            Some(phrase) if phrase.kind == kind => phrase.end_beat = *end as u32 + 1,
+            _ => phrases.push(Phrase { kind, start_beat: *start as u32 + 1, end_beat: *end as u32 + 1 }),
+        }
+    }
+
+    phrases
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_labels_blocks_by_their_energy() {
+        // 1000 samples per second at 120 BPM, every block of 8 bars lasts 16 seconds
+        let levels = [0.2, 0.2, 1.0, 1.0, 0.7, 0.3, 1.0, 0.2];
+        let mono: Vec<f32> = levels.iter().flat_map(|level| vec![*level; 16000]).collect();
+        let beats: Vec<Beat> = (0..256)
+            .map(|index| Beat { number: (index % 4) as u8 + 1, tempo: 12000, time_ms: index * 500 })
+            .collect();
+
+        let phrases: Vec<(PhraseKind, u32, u32)> = detect_phrases(&mono, 1000, &beats).iter()
+            .map(|phrase| (phrase.kind, phrase.start_beat, phrase.bars()))
+            .collect();
+        assert_eq!(vec![
+            (PhraseKind::Intro, 1, 16),
+            (PhraseKind::Chorus, 65, 16),
+            (PhraseKind::Verse, 129, 8),
+            (PhraseKind::Breakdown, 161, 8),
+            (PhraseKind::Chorus, 193, 8),
+            (PhraseKind::Outro, 225, 8),
+        ], phrases);
+    }
+}
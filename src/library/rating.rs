@@ -0,0 +1,68 @@
+use id3::{Tag, Content};
+
+/// Highest rating, players show ratings as up to five stars.
+pub const MAX_RATING: u8 = 5;
+
+/// Stars for a POPM rating byte, using the ranges most taggers write:
+/// 1, 64, 128, 196 and 255 for one to five stars.
+fn popm_to_stars(rating: u8) -> u8 {
+    match rating {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    }
+}
+
+/// Stars for an FMPS_Rating value, a fraction between 0.0 and 1.0.
+fn fmps_to_stars(value: &str) -> Option<u8> {
+    value.trim().parse::<f64>().ok()
+        .filter(|fraction| *fraction >= 0.0 && *fraction <= 1.0)
+        .map(|fraction| (fraction * MAX_RATING as f64).round() as u8)
+}
+
+fn popm_rating(data: &[u8]) -> Option<u8> {
+    let email_end = data.iter().position(|c| *c == 0)?;
+    data.get(email_end + 1).map(|rating| popm_to_stars(*rating))
+}
+
+/// Rating from a POPM frame, or a FMPS_Rating text frame when there is none.
+pub fn tag_rating(tag: &Tag) -> Option<u8> {
+    let popm = tag.frames()
+        .filter(|frame| frame.id() == "POPM")
+        .filter_map(|frame| match frame.content() {
+            Content::Unknown(data) => popm_rating(data),
+            _ => None,
+        })
+        .max();
+
+    popm.or_else(|| {
+        tag.extended_texts()
+            .find(|text| text.description == "FMPS_Rating")
+            .and_then(|text| fmps_to_stars(&text.value))
+    })
+}
+
+/// Stars for a rekordbox XML Rating attribute, which counts in steps of 51.
+pub fn rekordbox_to_stars(rating: u8) -> u8 {
+    (rating / 51).min(MAX_RATING)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_maps_popm_and_fmps_ratings_to_stars() {
+        let stars: Vec<u8> = [0u8, 1, 64, 128, 196, 255].iter().map(|rating| popm_to_stars(*rating)).collect();
+        assert_eq!(vec![0, 1, 2, 3, 4, 5], stars);
+
+        assert_eq!(Some(3), popm_rating(b"Windows Media Player 9 Series\0\x80"));
+        assert_eq!(Some(4), fmps_to_stars("0.8"));
+        assert_eq!(None, fmps_to_stars("1.5"));
+        assert_eq!(4, rekordbox_to_stars(204));
+    }
+}
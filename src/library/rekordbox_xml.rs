@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::rekordbox::{Cue, CueKind, TrackColor};
+use super::rating::rekordbox_to_stars;
+
+/// A track of the COLLECTION in a rekordbox XML export.
+#[derive(Debug, PartialEq)]
+pub struct ImportedTrack {
+    pub path: PathBuf,
+    pub cues: Vec<Cue>,
+    pub rating: Option<u8>,
+    pub color: Option<TrackColor>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    closing: bool,
+    self_closing: bool,
+}
+
+fn decode_entities(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(std::char::from_u32),
+            entity if entity.starts_with('#') => entity[1..].parse::<u32>().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+
+        match decoded {
+            Some(character) => {
+                output.push(character);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    output.push_str(rest);
+
+    output
+}
+
+fn parse_attributes(input: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = input;
+
+    while let Some(equals) = rest.find('=') {
+        let name = rest[..equals].trim().to_string();
+        let value = rest[equals + 1..].trim_start();
+
+        let quote = match value.chars().next() {
+            Some(quote @ '"') | Some(quote @ '\'') => quote,
+            _ => break,
+        };
+        let end = match value[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+
+        attributes.insert(name, decode_entities(&value[1..end]));
+        rest = &value[end + 1..];
+    }
+
+    attributes
+}
+
+/// Split a document into its tags, text content is of no interest for rekordbox exports.
+fn elements(document: &str) -> Vec<Element> {
+    let mut elements = vec![];
+    let mut rest = document;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+
+        if rest.starts_with("!--") {
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+            continue;
+        }
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_start_matches('/').trim_end_matches('/');
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+
+        elements.push(Element {
+            name: tag[..name_end].to_string(),
+            attributes: parse_attributes(&tag[name_end..]),
+            closing,
+            self_closing,
+        });
+    }
+
+    elements
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let decoded = match bytes[index] {
+            b'%' if index + 2 < bytes.len() => {
+                std::str::from_utf8(&bytes[index + 1..index + 3]).ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            },
+            _ => None,
+        };
+
+        match decoded {
+            Some(byte) => {
+                output.push(byte);
+                index += 3;
+            },
+            None => {
+                output.push(bytes[index]);
+                index += 1;
+            },
+        }
+    }
+
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// rekordbox stores locations as "file://localhost/path/to/Song.mp3".
+pub(crate) fn location_to_path(location: &str) -> Option<PathBuf> {
+    let path = location.strip_prefix("file://localhost")
+        .or_else(|| location.strip_prefix("file://"))?;
+
+    Some(PathBuf::from(percent_decode(path)))
+}
+
+/// rekordbox writes the color of a track as an RGB value in the Colour attribute.
+fn colour(value: &str) -> Option<TrackColor> {
+    let rgb = u32::from_str_radix(value.trim_start_matches("0x").trim_start_matches("0X"), 16).ok()?;
+
+    match rgb {
+        0xff007f => Some(TrackColor::Pink),
+        0xff0000 => Some(TrackColor::Red),
+        0xffa500 => Some(TrackColor::Orange),
+        0xffff00 => Some(TrackColor::Yellow),
+        0x00ff00 => Some(TrackColor::Green),
+        0x25fde9 => Some(TrackColor::Aqua),
+        0x0000ff => Some(TrackColor::Blue),
+        0x660099 => Some(TrackColor::Purple),
+        _ => None,
+    }
+}
+
+fn seconds_to_ms(value: Option<&String>) -> Option<u32> {
+    value.and_then(|value| value.parse::<f64>().ok())
+        .filter(|seconds| *seconds >= 0.0)
+        .map(|seconds| (seconds * 1000.0).round() as u32)
+}
+
+/// POSITION_MARK Type 0 is a cue and Type 4 a loop, Num -1 is a memory point
+/// and 0 to 7 are the hot cue pads. Fades and load points are skipped.
+fn position_mark(attributes: &HashMap<String, String>) -> Option<Cue> {
+    let kind = match attributes.get("Num").and_then(|num| num.parse::<i32>().ok()) {
+        Some(num) if (0..8).contains(&num) => CueKind::Hot(num as u8 + 1),
+        Some(-1) => CueKind::Memory,
+        _ => return None,
+    };
+    let start = seconds_to_ms(attributes.get("Start"))?;
+
+    match attributes.get("Type").map(String::as_str) {
+        Some("0") => Some(Cue::new(kind, start, None)),
+        Some("4") => Some(Cue::new(kind, start, Some(seconds_to_ms(attributes.get("End"))?))),
+        _ => None,
+    }
+}
+
+pub fn parse_rekordbox_xml(document: &str) -> Vec<ImportedTrack> {
+    let mut tracks = vec![];
+    let mut current: Option<ImportedTrack> = None;
+
+    for element in elements(document) {
+        match (element.name.as_str(), element.closing) {
+            // Playlist entries are TRACK elements too, but without a Location
+            ("TRACK", false) => {
+                let track = element.attributes.get("Location")
+                    .and_then(|location| location_to_path(location))
+                    .map(|path| ImportedTrack {
+                        path,
+                        cues: vec![],
+                        rating: element.attributes.get("Rating")
+                            .and_then(|rating| rating.parse::<u8>().ok())
+                            .map(rekordbox_to_stars),
+                        color: element.attributes.get("Colour").and_then(|value| colour(value)),
+                    });
+
+                match (track, element.self_closing) {
+                    (Some(track), true) => tracks.push(track),
+                    (track, false) => current = track,
+                    _ => {},
+                }
+            },
+            ("TRACK", true) => tracks.extend(current.take()),
+            ("POSITION_MARK", false) => {
+                if let Some(track) = current.as_mut() {
+                    track.cues.extend(position_mark(&element.attributes));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    tracks
+}
+
+pub fn read_rekordbox_xml<T: AsRef<Path>>(path: T) -> io::Result<Vec<ImportedTrack>> {
+    Ok(parse_rekordbox_xml(&fs::read_to_string(path)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const COLLECTION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<DJ_PLAYLISTS Version="1.0.0">
+  <COLLECTION Entries="2">
+    <TRACK TrackID="1" Name="Song &amp; Dance" Rating="153" Colour="0x25FDE9" Location="file://localhost/music/Song%20%26%20Dance.mp3">
+      <TEMPO Inizio="0.025" Bpm="128.00" Metro="4/4" Battito="1"/>
+      <POSITION_MARK Name="" Type="0" Start="0.025" Num="-1"/>
+      <POSITION_MARK Name="Drop" Type="0" Start="30.5" Num="0"/>
+      <POSITION_MARK Name="" Type="4" Start="60.0" End="67.5" Num="2"/>
+      <POSITION_MARK Name="" Type="1" Start="1.0" Num="-1"/>
+    </TRACK>
+    <TRACK TrackID="2" Location="file://localhost/music/Empty.mp3"/>
+  </COLLECTION>
+  <PLAYLISTS>
+    <NODE Type="0" Name="ROOT" Count="1">
+      <NODE Name="Set" Type="1" KeyType="0" Entries="1">
+        <TRACK Key="1"/>
+      </NODE>
+    </NODE>
+  </PLAYLISTS>
+</DJ_PLAYLISTS>"#;
+
+    #[test]
+    fn it_imports_collection_tracks_with_position_marks() {
+        assert_eq!(vec![
+            ImportedTrack {
+                path: PathBuf::from("/music/Song & Dance.mp3"),
+                cues: vec![
+                    Cue::new(CueKind::Memory, 25, None),
+                    Cue::new(CueKind::Hot(1), 30500, None),
+                    Cue::new(CueKind::Hot(3), 60000, Some(67500)),
+                ],
+                rating: Some(3),
+                color: Some(TrackColor::Aqua),
+            },
+            ImportedTrack {
+                path: PathBuf::from("/music/Empty.mp3"),
+                cues: vec![],
+                rating: None,
+                color: None,
+            },
+        ], parse_rekordbox_xml(COLLECTION));
+    }
+}
@@ -0,0 +1,159 @@
+use std::convert::TryInto;
+
+use id3::{Tag, Content};
+use crate::rekordbox::{Cue, CueKind};
+
+const MARKERS2_DESCRIPTION: &str = "Serato Markers2";
+
+fn base64_value(character: u8) -> Option<u8> {
+    match character {
+        b'A'..=b'Z' => Some(character - b'A'),
+        b'a'..=b'z' => Some(character - b'a' + 26),
+        b'0'..=b'9' => Some(character - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decode base64 leniently, Serato leaves out padding and wraps lines.
+fn decode_base64(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0u32;
+
+    for value in input.iter().take_while(|c| **c != 0 && **c != b'=').filter_map(|c| base64_value(*c)) {
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    output
+}
+
+/// Split a null terminated latin1/utf-8 string off the front of `input`.
+fn take_cstring(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = input.iter().position(|c| *c == 0)?;
+    Some((&input[..end], &input[end + 1..]))
+}
+
+fn be_u32_at(input: &[u8], offset: usize) -> Option<u32> {
+    input.get(offset..offset + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_be_bytes)
+}
+
+fn parse_entry(name: &[u8], data: &[u8]) -> Option<Cue> {
+    match name {
+        b"CUE" => {
+            let index = *data.get(1)?;
+            Some(Cue::new(CueKind::Hot(index + 1), be_u32_at(data, 2)?, None))
+        },
+        // Serato's saved loops don't map onto hot cue pads, keep them as memory loops
+        b"LOOP" => Some(Cue::new(CueKind::Memory, be_u32_at(data, 2)?, Some(be_u32_at(data, 6)?))),
+        _ => None,
+    }
+}
+
+/// Decode the base64 payload of a "Serato Markers2" object.
+fn parse_markers2(object: &[u8]) -> Vec<Cue> {
+    let mut cues = vec![];
+    let payload = decode_base64(object.get(2..).unwrap_or(&[]));
+    let mut input = payload.get(2..).unwrap_or(&[]);
+
+    while let Some((name, rest)) = take_cstring(input) {
+        if name.is_empty() {
+            break;
+        }
+
+        let length = match be_u32_at(rest, 0) {
+            Some(length) => length as usize,
+            None => break,
+        };
+        let data = match rest.get(4..4 + length) {
+            Some(data) => data,
+            None => break,
+        };
+
+        cues.extend(parse_entry(name, data));
+        input = &rest[4 + length..];
+    }
+
+    cues
+}
+
+/// Cues stored by Serato DJ in the GEOB frames of the ID3 tag.
+pub fn serato_cues(tag: &Tag) -> Vec<Cue> {
+    tag.frames()
+        .filter(|frame| frame.id() == "GEOB")
+        .filter_map(|frame| match frame.content() {
+            Content::Unknown(data) => Some(data),
+            _ => None,
+        })
+        .filter_map(|data| {
+            // Only latin1 and utf-8 encoded frames, which is what Serato writes
+            if data.first() != Some(&0) && data.first() != Some(&3) {
+                return None;
+            }
+            let (_mime_type, rest) = take_cstring(&data[1..])?;
+            let (_file_name, rest) = take_cstring(rest)?;
+            let (description, object) = take_cstring(rest)?;
+
+            if description == MARKERS2_DESCRIPTION.as_bytes() {
+                Some(parse_markers2(object))
+            } else {
+                None
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_decodes_unpadded_base64() {
+        assert_eq!(b"Serato".to_vec(), decode_base64(b"U2VyYXRv"));
+        assert_eq!(b"Sera".to_vec(), decode_base64(b"U2Vy\nYQ"));
+    }
+
+    #[test]
+    fn it_parses_markers2_cues_and_loops() {
+        let mut payload = vec![0x01, 0x01];
+        payload.extend(b"CUE\0");
+        payload.extend(&13u32.to_be_bytes());
+        payload.extend(&[0x00, 0x02, 0x00, 0x00, 0x75, 0x30, 0x00, 0xcc, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        payload.extend(b"LOOP\0");
+        payload.extend(&10u32.to_be_bytes());
+        payload.extend(&[0x00, 0x00, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x07, 0xd0]);
+        payload.push(0x00);
+
+        let encoded = base64_encode(&payload);
+        let mut object = vec![0x01, 0x01];
+        object.extend(encoded.as_bytes());
+
+        assert_eq!(vec![
+            Cue::new(CueKind::Hot(3), 30000, None),
+            Cue::new(CueKind::Memory, 1000, Some(2000)),
+        ], parse_markers2(&object));
+    }
+
+    fn base64_encode(input: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut output = String::new();
+        for chunk in input.chunks(3) {
+            let value = chunk.iter().enumerate()
+                .fold(0u32, |acc, (index, byte)| acc | (*byte as u32) << (16 - index * 8));
+            for index in 0..=chunk.len() {
+                output.push(ALPHABET[(value >> (18 - index * 6)) as usize & 0x3f] as char);
+            }
+        }
+        output
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::rekordbox::{Cue, CueKind, TrackColor};
+
+/// Sidecar files sit next to the track, "Song.mp3" gets "Song.mp3.termdj".
+pub const SIDECAR_EXTENSION: &str = "termdj";
+
+/// Per track data maintained by hand, in a line based format:
+///
+/// ```text
+/// # Times are in seconds, hot cue pads are named A to H
+/// cue memory 12.5
+/// cue hot A 30.0
+/// loop memory 60.0 64.0
+/// loop hot B 92.0 96.0
+/// color aqua
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct Sidecar {
+    pub cues: Vec<Cue>,
+    pub color: Option<TrackColor>,
+}
+
+pub fn sidecar_path(track: &Path) -> PathBuf {
+    let mut file_name = track.file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    file_name.push(".");
+    file_name.push(SIDECAR_EXTENSION);
+
+    track.with_file_name(file_name)
+}
+
+fn seconds_to_ms(value: &str) -> Option<u32> {
+    value.parse::<f64>().ok()
+        .filter(|seconds| *seconds >= 0.0)
+        .map(|seconds| (seconds * 1000.0).round() as u32)
+}
+
+fn hot_cue_pad(value: &str) -> Option<u8> {
+    match value.as_bytes() {
+        [pad @ b'A'..=b'H'] | [pad @ b'a'..=b'h'] => Some(pad.to_ascii_uppercase() - b'A' + 1),
+        _ => None,
+    }
+}
+
+fn parse_cue(fields: &[&str]) -> Option<Cue> {
+    let (kind, times) = match fields {
+        [_, "memory", times @ ..] => (CueKind::Memory, times),
+        [_, "hot", pad, times @ ..] => (CueKind::Hot(hot_cue_pad(pad)?), times),
+        _ => return None,
+    };
+
+    match (fields[0], times) {
+        ("cue", [position]) => Some(Cue::new(kind, seconds_to_ms(position)?, None)),
+        ("loop", [start, end]) => Some(Cue::new(kind, seconds_to_ms(start)?, Some(seconds_to_ms(end)?))),
+        _ => None,
+    }
+}
+
+impl Sidecar {
+    pub fn parse(contents: &str) -> Self {
+        let mut sidecar = Sidecar::default();
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[0] {
+                "cue" | "loop" => {
+                    match parse_cue(&fields) {
+                        Some(cue) => sidecar.cues.push(cue),
+                        None => eprintln!("Ignoring malformed sidecar line: {:?}", line),
+                    }
+                },
+                "color" => {
+                    match fields.get(1).and_then(|name| TrackColor::from_name(name)) {
+                        Some(color) => sidecar.color = Some(color),
+                        None => eprintln!("Ignoring malformed sidecar line: {:?}", line),
+                    }
+                },
+                _ => eprintln!("Ignoring unknown sidecar line: {:?}", line),
+            }
+        }
+
+        sidecar
+    }
+}
+
+/// Read the sidecar belonging to a track, if the track has one.
+pub fn read_sidecar(track: &Path) -> Option<Sidecar> {
+    fs::read_to_string(sidecar_path(track)).ok()
+        .map(|contents| Sidecar::parse(&contents))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_parses_cues_and_loops() {
+        let sidecar = Sidecar::parse("
+            # intro
+            cue memory 12.5
+            cue hot A 30.0
+            loop hot b 92 96.5
+            cue hot Z 1.0
+            color Aqua
+        ");
+
+        assert_eq!(vec![
+            Cue::new(CueKind::Memory, 12500, None),
+            Cue::new(CueKind::Hot(1), 30000, None),
+            Cue::new(CueKind::Hot(2), 92000, Some(96500)),
+        ], sidecar.cues);
+        assert_eq!(Some(TrackColor::Aqua), sidecar.color);
+    }
+
+    #[test]
+    fn it_names_sidecars_after_the_track() {
+        assert_eq!(
+            PathBuf::from("/music/Song.mp3.termdj"),
+            sidecar_path(Path::new("/music/Song.mp3")),
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// Directory holding the state TermDJ keeps between runs.
+///
+/// Every table is a tab separated file with one record per line. Tracks are
+/// referenced by path because track ids are assigned anew on every scan.
+#[derive(Debug, Clone)]
+pub struct Store {
+    dir: PathBuf,
+}
+
+fn escape(field: &str) -> String {
+    let mut output = String::with_capacity(field.len());
+    for character in field.chars() {
+        match character {
+            '\\' => output.push_str("\\\\"),
+            '\t' => output.push_str("\\t"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            character => output.push(character),
+        }
+    }
+    output
+}
+
+fn unescape(field: &str) -> String {
+    let mut output = String::with_capacity(field.len());
+    let mut characters = field.chars();
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            output.push(character);
+            continue;
+        }
+        match characters.next() {
+            Some('t') => output.push('\t'),
+            Some('n') => output.push('\n'),
+            Some('r') => output.push('\r'),
+            Some(other) => output.push(other),
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+/// Path as a field, byte for byte: bytes that aren't valid UTF-8 are
+/// written as `\xHH` and backslashes are doubled.
+pub fn path_to_field(path: &Path) -> String {
+    let mut bytes = path.as_os_str().as_bytes();
+    let mut output = String::with_capacity(bytes.len());
+    while !bytes.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(bytes) {
+            Ok(valid) => (valid, 0),
+            Err(err) => {
+                let valid = std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default();
+                (valid, err.error_len().unwrap_or(bytes.len() - valid.len()))
+            },
+        };
+        output.push_str(&valid.replace('\\', "\\\\"));
+        for byte in &bytes[valid.len()..valid.len() + invalid] {
+            output.push_str(&format!("\\x{:02x}", byte));
+        }
+        bytes = &bytes[valid.len() + invalid..];
+    }
+    output
+}
+
+/// Path written by `path_to_field`.
+pub fn path_from_field(field: &str) -> PathBuf {
+    let mut output = Vec::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(position) = rest.find('\\') {
+        output.extend_from_slice(&rest.as_bytes()[..position]);
+        rest = &rest[position + 1..];
+        let byte = rest.strip_prefix('x')
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match byte {
+            Some(byte) => {
+                output.push(byte);
+                rest = &rest[3..];
+            },
+            None if rest.starts_with('\\') => {
+                output.push(b'\\');
+                rest = &rest[1..];
+            },
+            None => output.push(b'\\'),
+        }
+    }
+    output.extend_from_slice(rest.as_bytes());
+    PathBuf::from(OsString::from_vec(output))
+}
+
+impl Store {
+    pub fn new<T: AsRef<Path>>(dir: T) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    fn table_path(&self, table: &str) -> PathBuf {
+        self.dir.join(format!("{}.tsv", table))
+    }
+
+    /// Records of a table, a table that was never written is empty.
+    pub fn read_table(&self, table: &str) -> io::Result<Vec<Vec<String>>> {
+        let contents = match fs::read_to_string(self.table_path(table)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+
+        Ok(contents.lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.split('\t').map(unescape).collect())
+            .collect())
+    }
+
+    /// Add a record to the end of a table, for tables that only ever grow.
+    pub fn append_record(&self, table: &str, record: &[String]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let fields: Vec<String> = record.iter().map(|field| escape(field)).collect();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.table_path(table))?;
+        writeln!(file, "{}", fields.join("\t"))
+    }
+
+    /// Replace all records of a table. The table is written next to the old
+    /// one and renamed over it, so a crash never leaves half a table behind.
+    pub fn write_table(&self, table: &str, records: &[Vec<String>]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let mut contents = String::new();
+        for record in records {
+            let fields: Vec<String> = record.iter().map(|field| escape(field)).collect();
+            contents.push_str(&fields.join("\t"));
+            contents.push('\n');
+        }
+
+        let path = self.table_path(table);
+        let partial = path.with_extension("tsv.partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, &path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_round_trips_records_with_separators() {
+        let temp = crate::utils::test_dir("store");
+        let store = Store::new(temp.path());
+        let records = vec![
+            vec![String::from("/music/A\tB.mp3"), String::from("line\nbreak")],
+            vec![String::from("C:\\music"), String::new()],
+        ];
+
+        store.write_table("records", &records).unwrap();
+        assert_eq!(records, store.read_table("records").unwrap());
+
+        store.append_record("records", &[String::from("appended")]).unwrap();
+        assert_eq!(vec![String::from("appended")], store.read_table("records").unwrap()[2]);
+        assert_eq!(Vec::<Vec<String>>::new(), store.read_table("missing").unwrap());
+    }
+
+    #[test]
+    fn it_round_trips_paths_that_are_not_utf8() {
+        let path = PathBuf::from(OsString::from_vec(b"/music/Caf\xe9 \\x41\\.mp3".to_vec()));
+        let field = path_to_field(&path);
+
+        assert_eq!("/music/Caf\\xe9 \\\\x41\\\\.mp3", field);
+        assert_eq!(path, path_from_field(&field));
+        assert_eq!(PathBuf::from("/music/Café.mp3"), path_from_field(&path_to_field(Path::new("/music/Café.mp3"))));
+    }
+}
@@ -0,0 +1,540 @@
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use super::audio::{find_chunk, read_exact_at};
+use super::decoder::extended_to_u32;
+
+/// Transcoded tracks are served next to their source, "Track.flac" as "Track.flac.wav".
+const TRANSCODED_EXTENSION: &str = "wav";
+const WAV_HEADER_SIZE: u64 = 44;
+/// Frames read at a time from PCM sources, FLAC is decoded a block at a time.
+const FRAMES_PER_READ: u64 = 4096;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Flac,
+    /// Integer samples starting at an offset of an AIFF file.
+    Pcm { offset: u64, little_endian: bool },
+}
+
+/// A track older players can't decode, served as a WAV file decoded from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscodedAudio {
+    source: PathBuf,
+    kind: Source,
+    sample_rate: u32,
+    channels: u16,
+    source_bits: u16,
+    frames: u64,
+}
+
+fn probe_flac(source: &Path) -> io::Result<Option<TranscodedAudio>> {
+    let reader = claxon::FlacReader::open(source).map_err(|err| invalid(&err.to_string()))?;
+    let info = reader.streaminfo();
+    let frames = info.samples.ok_or_else(|| invalid("FLAC stream of unknown length"))?;
+
+    Ok(Some(TranscodedAudio {
+        source: source.to_path_buf(),
+        kind: Source::Flac,
+        sample_rate: info.sample_rate,
+        channels: info.channels as u16,
+        source_bits: info.bits_per_sample as u16,
+        frames,
+    }))
+}
+
+/// Only AIFC files and sample sizes other than 16 and 24 bits are transcoded,
+/// players read plain AIFF files themselves.
+fn probe_aiff(source: &Path) -> io::Result<Option<TranscodedAudio>> {
+    let mut file = File::open(source)?;
+    let file_size = file.metadata()?.len();
+    let mut magic = [0u8; 12];
+    read_exact_at(&mut file, 0, &mut magic)?;
+    let compressed = match (&magic[0..4], &magic[8..12]) {
+        (b"FORM", b"AIFF") => false,
+        (b"FORM", b"AIFC") => true,
+        _ => return Err(invalid("Not an AIFF file")),
+    };
+
+    let common = find_chunk(&mut file, file_size, b"COMM", false)?
+        .ok_or_else(|| invalid("Missing COMM chunk"))?;
+    let mut header = vec![0u8; common.length.min(22) as usize];
+    read_exact_at(&mut file, common.offset, &mut header)?;
+    if header.len() < 18 {
+        return Err(invalid("Short COMM chunk"));
+    }
+    let channels = u16::from_be_bytes([header[0], header[1]]);
+    let frames = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as u64;
+    let bits = u16::from_be_bytes([header[6], header[7]]);
+    let little_endian = match (compressed, header.get(18..22)) {
+        (false, _) | (true, Some(b"NONE")) | (true, Some(b"twos")) => false,
+        (true, Some(b"sowt")) => true,
+        _ => return Err(invalid("Compressed AIFF files are not supported")),
+    };
+    if bits == 0 || bits > 32 {
+        return Err(invalid("Unsupported sample size"));
+    }
+    if !compressed && (bits == 16 || bits == 24) {
+        return Ok(None);
+    }
+
+    let sound = find_chunk(&mut file, file_size, b"SSND", false)?
+        .ok_or_else(|| invalid("Missing SSND chunk"))?;
+    let mut data_offset = [0u8; 4];
+    read_exact_at(&mut file, sound.offset, &mut data_offset)?;
+
+    Ok(Some(TranscodedAudio {
+        source: source.to_path_buf(),
+        kind: Source::Pcm {
+            offset: sound.offset + 8 + u32::from_be_bytes(data_offset) as u64,
+            little_endian,
+        },
+        sample_rate: extended_to_u32(&header[8..18]),
+        channels,
+        // Samples are kept left aligned in whole bytes
+        source_bits: bits.div_ceil(8) * 8,
+        frames,
+    }))
+}
+
+impl TranscodedAudio {
+    /// How a track is transcoded, `None` for tracks players decode
+    /// themselves and for those that can't be decoded here either.
+    pub fn probe(source: &Path) -> Option<TranscodedAudio> {
+        let extension = source.extension()?.to_str()?.to_ascii_lowercase();
+        let probed = match extension.as_str() {
+            "flac" => probe_flac(source),
+            "aif" | "aiff" | "aifc" => probe_aiff(source),
+            _ => return None,
+        };
+
+        probed.unwrap_or_else(|err| {
+            eprintln!("Not transcoding {:?}; error = {}", source, err);
+            None
+        })
+    }
+
+    /// Whether a track is of a kind that may be transcoded, told by its name alone.
+    pub fn may_transcode(source: &Path) -> bool {
+        match source.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => ["flac", "aif", "aiff", "aifc"].iter().any(|kind| extension.eq_ignore_ascii_case(kind)),
+            None => false,
+        }
+    }
+
+    /// The track a transcoded path would be served from, `None` for other
+    /// paths. Files that exist are never taken for transcoded ones.
+    pub fn source_of(path: &Path) -> Option<PathBuf> {
+        let extension = path.extension()?.to_str()?;
+        if !extension.eq_ignore_ascii_case(TRANSCODED_EXTENSION) || path.exists() {
+            return None;
+        }
+
+        Some(path.with_extension(""))
+    }
+
+    /// Name a track of the name is served as when it's transcoded.
+    pub fn served_name(name: &str) -> String {
+        format!("{}.{}", name, TRANSCODED_EXTENSION)
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// The same audio with symlinks in the path of the track resolved.
+    pub fn canonicalize(self) -> io::Result<TranscodedAudio> {
+        Ok(TranscodedAudio {
+            source: self.source.canonicalize()?,
+            ..self
+        })
+    }
+
+    /// Path the WAV file is served at.
+    pub fn path(&self) -> PathBuf {
+        let mut path = self.source.clone().into_os_string();
+        path.push(".");
+        path.push(TRANSCODED_EXTENSION);
+        PathBuf::from(path)
+    }
+
+    /// Sources of up to 16 bits become 16 bit files, anything more 24 bits.
+    fn output_bits(&self) -> u16 {
+        match self.source_bits {
+            0..=16 => 16,
+            _ => 24,
+        }
+    }
+
+    fn frame_size(&self) -> u64 {
+        self.channels as u64 * (self.output_bits() / 8) as u64
+    }
+
+    /// Size of the WAV file, known before anything is decoded.
+    pub fn size(&self) -> u64 {
+        WAV_HEADER_SIZE + self.frames * self.frame_size()
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let data_size = u32::try_from(self.frames * self.frame_size()).unwrap_or(u32::MAX);
+        let block_align = self.frame_size() as u16;
+
+        let mut header = Vec::with_capacity(WAV_HEADER_SIZE as usize);
+        header.extend(b"RIFF");
+        header.extend(&data_size.saturating_add(36).to_le_bytes());
+        header.extend(b"WAVEfmt ");
+        header.extend(&16u32.to_le_bytes());
+        header.extend(&1u16.to_le_bytes());
+        header.extend(&self.channels.to_le_bytes());
+        header.extend(&self.sample_rate.to_le_bytes());
+        header.extend(&(self.sample_rate * block_align as u32).to_le_bytes());
+        header.extend(&block_align.to_le_bytes());
+        header.extend(&self.output_bits().to_le_bytes());
+        header.extend(b"data");
+        header.extend(&data_size.to_le_bytes());
+        header
+    }
+}
+
+enum Decoder {
+    Flac(claxon::FlacReader<File>),
+    Pcm { file: File, position: u64, end: u64, little_endian: bool },
+}
+
+impl Decoder {
+    fn open(audio: &TranscodedAudio) -> io::Result<Decoder> {
+        Ok(match audio.kind {
+            Source::Flac => Decoder::Flac(
+                claxon::FlacReader::open(&audio.source).map_err(|err| invalid(&err.to_string()))?,
+            ),
+            Source::Pcm { offset, little_endian } => Decoder::Pcm {
+                file: File::open(&audio.source)?,
+                position: offset,
+                end: offset + audio.frames * audio.channels as u64 * (audio.source_bits / 8) as u64,
+                little_endian,
+            },
+        })
+    }
+
+    /// The next samples, channels interleaved, none at the end of the track.
+    fn next(&mut self, audio: &TranscodedAudio) -> io::Result<Vec<i32>> {
+        match self {
+            Decoder::Flac(reader) => {
+                let block = reader.blocks().read_next_or_eof(vec![])
+                    .map_err(|err| invalid(&err.to_string()))?;
+                Ok(match block {
+                    Some(block) => (0..block.duration())
+                        .flat_map(|index| (0..block.channels()).map(move |channel| (channel, index)))
+                        .map(|(channel, index)| block.sample(channel, index))
+                        .collect(),
+                    None => vec![],
+                })
+            },
+            Decoder::Pcm { file, position, end, little_endian } => {
+                let width = (audio.source_bits / 8) as usize;
+                let wanted = (*end - *position).min(FRAMES_PER_READ * audio.channels as u64 * width as u64);
+                let mut data = vec![];
+                file.seek(SeekFrom::Start(*position))?;
+                file.take(wanted).read_to_end(&mut data)?;
+                *position += data.len() as u64;
+
+                Ok(data.chunks_exact(width).map(|bytes| {
+                    let value = match little_endian {
+                        true => bytes.iter().rev().fold(0u32, |acc, byte| (acc << 8) | *byte as u32),
+                        false => bytes.iter().fold(0u32, |acc, byte| (acc << 8) | *byte as u32),
+                    };
+                    // Shift through the sign bit to keep the sign
+                    ((value << (32 - 8 * width)) as i32) >> (32 - 8 * width)
+                }).collect())
+            },
+        }
+    }
+}
+
+/// How far a track is decoded into its cache file.
+#[derive(Debug, Default)]
+struct Progress {
+    /// Bytes of the WAV file in the cache, counted from its start.
+    cached: u64,
+    /// Why decoding stopped before the end, if it did.
+    failed: Option<String>,
+}
+
+/// Progress of a transcoder, shared with the thread decoding for it.
+#[derive(Debug, Default)]
+struct Shared {
+    progress: Mutex<Progress>,
+    decoded: Condvar,
+    /// Set when the transcoder is dropped, decoding stops.
+    cancelled: AtomicBool,
+}
+
+impl Shared {
+    fn progress(&self) -> MutexGuard<'_, Progress> {
+        self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Progress)) {
+        update(&mut self.progress());
+        self.decoded.notify_all();
+    }
+}
+
+/// The WAV file of a transcoded track, decoded by a thread of its own into a
+/// cache file, so reads only wait for the part they ask for, reading back is
+/// cheap and finished files outlive the process.
+pub struct Transcoder {
+    audio: TranscodedAudio,
+    cache: File,
+    shared: Arc<Shared>,
+}
+
+/// Directory the WAV files of transcoded tracks are kept in, up to a size:
+/// the least recently opened files are removed to make room for others.
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeCache {
+    directory: PathBuf,
+    max_size: u64,
+}
+
+impl TranscodeCache {
+    pub fn new<T: AsRef<Path>>(directory: T, max_size: u64) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+            max_size,
+        }
+    }
+
+    /// Cache file of a track, named after its device and inode.
+    fn path(&self, source: &std::fs::Metadata) -> PathBuf {
+        self.directory.join(format!("{}-{}.wav", source.dev(), source.ino()))
+    }
+
+    /// Start transcoding, or pick up a finished cache file that is newer than the track.
+    pub fn open(&self, audio: TranscodedAudio) -> io::Result<Transcoder> {
+        std::fs::create_dir_all(&self.directory)?;
+        let source = std::fs::metadata(&audio.source)?;
+        let path = self.path(&source);
+        let cache = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let shared = Arc::new(Shared::default());
+
+        let cached = cache.metadata()?;
+        if cached.len() == audio.size() && cached.modified()? >= source.modified()? {
+            cache.set_modified(SystemTime::now())?;
+            shared.progress().cached = audio.size();
+            return Ok(Transcoder { audio, cache, shared });
+        }
+
+        self.make_room(&path, audio.size());
+        cache.set_len(0)?;
+        cache.write_all_at(&audio.header(), 0)?;
+        shared.progress().cached = WAV_HEADER_SIZE;
+        let decoder = Decoder::open(&audio)?;
+        let (output, progress) = (cache.try_clone()?, shared.clone());
+        let track = audio.clone();
+        std::thread::spawn(move || transcode(&track, decoder, &output, &progress));
+
+        Ok(Transcoder { audio, cache, shared })
+    }
+
+    /// Remove the files opened least recently until `size` more bytes fit,
+    /// besides the file at `keep`. Files still being read stay readable until closed.
+    fn make_room(&self, keep: &Path, size: u64) {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(err) => return eprintln!("Failed listing transcoded files; error = {}", err),
+        };
+        let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path() != keep)
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+            })
+            .collect();
+        files.sort();
+
+        let mut total = size + files.iter().map(|(_modified, length, _path)| length).sum::<u64>();
+        for (_modified, length, path) in files {
+            if total <= self.max_size {
+                break;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => total -= length,
+                Err(err) => eprintln!("Failed removing transcoded file {:?}; error = {}", path, err),
+            }
+        }
+    }
+}
+
+impl Transcoder {
+    pub fn size(&self) -> u64 {
+        self.audio.size()
+    }
+
+    /// Up to `count` bytes of the WAV file starting at `offset`, once they are decoded.
+    pub fn read_at(&self, offset: u64, count: u32) -> io::Result<Vec<u8>> {
+        let end = (offset + count as u64).min(self.size());
+        let mut progress = self.shared.progress();
+        while progress.cached < end {
+            if let Some(failed) = &progress.failed {
+                return Err(invalid(failed));
+            }
+            progress = self.shared.decoded.wait(progress).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        drop(progress);
+
+        let mut buffer = vec![0u8; end.saturating_sub(offset) as usize];
+        if !buffer.is_empty() {
+            self.cache.read_exact_at(&mut buffer, offset)?;
+        }
+        Ok(buffer)
+    }
+}
+
+impl Drop for Transcoder {
+    fn drop(&mut self) {
+        self.shared.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Decode a whole track into its cache file, unless its transcoder is dropped before.
+fn transcode(audio: &TranscodedAudio, mut decoder: Decoder, cache: &File, shared: &Shared) {
+    let mut cached = WAV_HEADER_SIZE;
+    while cached < audio.size() && !shared.cancelled.load(Ordering::Relaxed) {
+        match append(audio, &mut decoder, cache, cached) {
+            Ok(appended) => {
+                cached = appended;
+                shared.update(|progress| progress.cached = cached);
+            },
+            Err(err) => {
+                eprintln!("Failed transcoding {:?}; error = {}", audio.source(), err);
+                shared.update(|progress| progress.failed = Some(err.to_string()));
+                return;
+            },
+        }
+    }
+}
+
+/// Append the next samples to the cache, returning the bytes cached then.
+/// Tracks ending before their announced length are filled up with silence.
+fn append(audio: &TranscodedAudio, decoder: &mut Decoder, cache: &File, cached: u64) -> io::Result<u64> {
+    let samples = decoder.next(audio)?;
+    if samples.is_empty() {
+        cache.set_len(audio.size())?;
+        return Ok(audio.size());
+    }
+
+    let (from, to) = (audio.source_bits, audio.output_bits());
+    let width = (to / 8) as usize;
+    let mut data: Vec<u8> = samples.into_iter()
+        .map(|sample| if from > to { sample >> (from - to) } else { sample << (to - from) })
+        .flat_map(|sample| sample.to_le_bytes()[..width].to_vec())
+        .collect();
+    data.truncate((audio.size() - cached) as usize);
+
+    cache.write_all_at(&data, cached)?;
+    Ok(cached + data.len() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn write_aiff(path: &Path, form: &[u8; 4], compression: &[u8], bits: u16, samples: &[u8]) {
+        let mut common = vec![0, 2, 0, 0, 0, (samples.len() / 2 / (bits as usize / 8)) as u8];
+        common.extend(&bits.to_be_bytes());
+        common.extend(&[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+        common.extend(compression);
+
+        let mut data = b"FORM\0\0\0\0".to_vec();
+        data.extend(form);
+        data.extend(b"COMM");
+        data.extend(&(common.len() as u32).to_be_bytes());
+        data.extend(&common);
+        data.extend(b"SSND");
+        data.extend(&(samples.len() as u32 + 8).to_be_bytes());
+        data.extend(&[0u8; 8]);
+        data.extend(samples);
+        std::fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn it_serves_aifc_files_as_wave_files() {
+        let temp = crate::utils::test_dir("transcode");
+        let base = temp.path().to_path_buf();
+        std::fs::create_dir_all(&base).unwrap();
+        let track = base.join("Track.aifc");
+        // Two frames of little endian stereo samples
+        write_aiff(&track, b"AIFC", b"sowt", 16, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+        write_aiff(&base.join("Plain.aiff"), b"AIFF", b"", 16, &[0u8; 8]);
+
+        let audio = TranscodedAudio::probe(&track).unwrap();
+        assert_eq!(base.join("Track.aifc.wav"), audio.path());
+        assert_eq!(44 + 8, audio.size());
+        assert_eq!(Some(track.clone()), TranscodedAudio::source_of(&audio.path()));
+        assert_eq!(None, TranscodedAudio::probe(&base.join("Plain.aiff")));
+        assert_eq!(None, TranscodedAudio::source_of(&base.join("Plain.aiff")));
+
+        let cache = TranscodeCache::new(base.join("cache"), u64::MAX);
+        let transcoder = cache.open(audio.clone()).unwrap();
+        assert_eq!(vec![0x03, 0x04, 0x05, 0x06, 0x07, 0x08], transcoder.read_at(46, 100).unwrap());
+        assert_eq!(b"RIFF".to_vec(), transcoder.read_at(0, 4).unwrap());
+        assert_eq!(b"data\x08\0\0\0".to_vec(), transcoder.read_at(36, 8).unwrap());
+
+        // A finished file is read from the cache
+        let cached = cache.open(audio).unwrap();
+        assert_eq!(Arc::strong_count(&cached.shared), 1);
+        assert_eq!(vec![0x01, 0x02], cached.read_at(44, 2).unwrap());
+    }
+
+    #[test]
+    fn it_widens_8_bit_samples() {
+        let temp = crate::utils::test_dir("transcode-8-bit");
+        let base = temp.path().to_path_buf();
+        std::fs::create_dir_all(&base).unwrap();
+        let track = base.join("Track.aif");
+        write_aiff(&track, b"AIFF", b"", 8, &[0x40, 0xc0, 0x00, 0x7f]);
+
+        let cache = TranscodeCache::new(base.join("cache"), u64::MAX);
+        let transcoder = cache.open(TranscodedAudio::probe(&track).unwrap()).unwrap();
+        assert_eq!(vec![0x00, 0x40, 0x00, 0xc0, 0x00, 0x00, 0x00, 0x7f], transcoder.read_at(44, 8).unwrap());
+    }
+
+    #[test]
+    fn it_removes_the_files_opened_least_recently_beyond_its_size() {
+        let temp = crate::utils::test_dir("transcode-cache");
+        let base = temp.path().to_path_buf();
+        let tracks: Vec<PathBuf> = ["A", "B", "C"].iter().map(|name| base.join(format!("{}.aif", name))).collect();
+        for track in &tracks {
+            write_aiff(track, b"AIFF", b"", 8, &[0u8; 8]);
+        }
+        let size = TranscodedAudio::probe(&tracks[0]).unwrap().size();
+        let cache = TranscodeCache::new(base.join("cache"), 2 * size);
+        let transcode = |track: &Path| {
+            let transcoder = cache.open(TranscodedAudio::probe(track).unwrap()).unwrap();
+            transcoder.read_at(0, 100).unwrap();
+        };
+        let cached = |track: &Path| cache.path(&std::fs::metadata(track).unwrap()).exists();
+
+        transcode(&tracks[0]);
+        transcode(&tracks[1]);
+        transcode(&tracks[0]);
+        transcode(&tracks[2]);
+
+        assert!(cached(&tracks[0]));
+        assert!(!cached(&tracks[1]));
+        assert!(cached(&tracks[2]));
+    }
+}
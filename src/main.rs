@@ -7,16 +7,287 @@ mod component;
 mod rpc;
 mod library;
 
+use std::path::{Path, PathBuf};
 use component::App;
+use ipnetwork::IpNetwork;
+use library::PhraseKind;
+use rekordbox::{Database, DatabaseOptions, DuplicatePolicy, SetlistFormat, SetlistOptions, ROOT_FOLDER};
+
+/// The data dir given on the command line, or termdj in the XDG data dir of the user.
+fn data_dir(matches: &clap::ArgMatches) -> Option<PathBuf> {
+    if let Some(data_dir) = matches.value_of("DATA_DIR") {
+        return Some(PathBuf::from(data_dir));
+    }
+
+    // Relative paths in XDG_DATA_HOME are invalid and ignored, as the spec says
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|data_home| data_home.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("share")))?;
+
+    Some(data_home.join("termdj"))
+}
+
+/// Smart playlists given on the command line, or the definitions in the data dir if there are any.
+fn smart_playlists(matches: &clap::ArgMatches, data_dir: Option<&PathBuf>) -> Option<PathBuf> {
+    matches.value_of("SMART_PLAYLISTS")
+        .map(PathBuf::from)
+        .or_else(|| {
+            data_dir
+                .map(|data_dir| data_dir.join("smart-playlists.conf"))
+                .filter(|path| path.exists())
+        })
+}
+
+fn history(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = data_dir(matches).ok_or("Either $HOME or --data-dir is required")?;
+
+    match matches.subcommand() {
+        ("list", Some(_matches)) => component::history::list(&data_dir)?,
+        ("export", Some(matches)) => {
+            let format = matches.value_of("FORMAT")
+                .and_then(SetlistFormat::from_name)
+                .unwrap_or(SetlistFormat::Tracklist);
+            let mut options = SetlistOptions::default();
+            if let Some(recording) = matches.value_of("RECORDING") {
+                options.recording = recording.to_string();
+            }
+            if let Some(offset) = matches.value_of("OFFSET") {
+                options.recording_offset = offset.parse()?;
+            }
+
+            component::history::export(
+                &data_dir,
+                matches.value_of("SESSION"),
+                format,
+                &options,
+                matches.value_of("OUTPUT").map(Path::new),
+            )?;
+        },
+        _ => {},
+    }
+
+    Ok(())
+}
+
+/// Playlist positions on the command line count from 1.
+fn position(matches: &clap::ArgMatches, name: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    match matches.value_of(name).unwrap_or("").parse::<usize>()? {
+        0 => Err("Positions start at 1".into()),
+        position => Ok(position - 1),
+    }
+}
+
+fn playlist(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let database = Database::playlist_store(DatabaseOptions {
+        roots: matches.values_of("LIBRARY_PATH").unwrap().map(PathBuf::from).collect(),
+        data_dir: data_dir(matches),
+        ..Default::default()
+    });
+    let id = |matches: &clap::ArgMatches, name: &str| -> Result<u32, std::num::ParseIntError> {
+        matches.value_of(name).map(str::parse).unwrap_or(Ok(ROOT_FOLDER))
+    };
+
+    match matches.subcommand() {
+        ("list", Some(matches)) => component::playlist::list(&database, id(matches, "FOLDER")?),
+        ("show", Some(matches)) => component::playlist::show(&database, id(matches, "ID")?),
+        ("create", Some(matches)) => {
+            let name = matches.value_of("NAME").unwrap();
+            let created = match matches.is_present("IS_FOLDER") {
+                true => database.create_playlist_folder(id(matches, "FOLDER")?, name)?,
+                false => database.create_playlist(id(matches, "FOLDER")?, name)?,
+            };
+            println!("{}", created);
+        },
+        ("rename", Some(matches)) => database.rename_playlist(id(matches, "ID")?, matches.value_of("NAME").unwrap())?,
+        ("delete", Some(matches)) => database.delete_playlist(id(matches, "ID")?)?,
+        ("move", Some(matches)) => {
+            let position = match matches.is_present("POSITION") {
+                true => Some(position(matches, "POSITION")?),
+                false => None,
+            };
+            database.move_playlist(id(matches, "ID")?, id(matches, "FOLDER")?, position)?;
+        },
+        ("add", Some(matches)) => {
+            let paths: Vec<&Path> = matches.values_of("TRACK").unwrap().map(Path::new).collect();
+            component::playlist::add_tracks(&database, id(matches, "ID")?, &paths)?;
+        },
+        ("remove", Some(matches)) => database.remove_playlist_track(id(matches, "ID")?, position(matches, "POSITION")?)?,
+        ("reorder", Some(matches)) => database.move_playlist_track(
+            id(matches, "ID")?,
+            position(matches, "FROM")?,
+            position(matches, "TO")?,
+        )?,
+        _ => {},
+    }
+
+    Ok(())
+}
+
+fn export(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let data_dir = data_dir(matches);
+    let database = Database::with_options(DatabaseOptions {
+        roots: vec![PathBuf::from(matches.value_of("LIBRARY_PATH").unwrap())],
+        smart_playlists: smart_playlists(matches, data_dir.as_ref()),
+        data_dir,
+        ..Default::default()
+    });
+
+    let summary = rekordbox::export_device(&database, Path::new(matches.value_of("DESTINATION").unwrap()))?;
+    println!(
+        "Exported {} tracks ({} already on the device, {} without analysis) and {} playlists",
+        summary.tracks,
+        summary.unchanged,
+        summary.unanalyzed,
+        summary.playlists,
+    );
+
+    Ok(())
+}
+
+fn analyze(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let database = Database::with_options(DatabaseOptions {
+        roots: vec![PathBuf::from(matches.value_of("LIBRARY_PATH").unwrap())],
+        data_dir: data_dir(matches),
+        ..Default::default()
+    });
+
+    let analyzed = database.analyze_tracks();
+    println!("Analyzed {} tracks", analyzed);
+
+    if let Some(kind) = matches.value_of("FIND").and_then(PhraseKind::from_name) {
+        let bars = matches.value_of("BARS").map(str::parse).transpose()?.unwrap_or(1);
+        for track in database.tracks_with_phrase(kind, bars) {
+            println!("{}", track.path());
+        }
+    }
+
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = clap_app!(myapp => 
-        (@arg LIBRARY_PATH: +required "Path to music library to serve")
+    let matches = clap_app!(termdj =>
+        (@setting SubcommandsNegateReqs)
+        (@setting ArgsNegateSubcommands)
+        (@arg LIBRARY_PATH: +required +multiple "Paths to music libraries or rekordbox USB exports to serve")
+        (@arg DUPLICATES: --duplicates +takes_value possible_value[show hide] "Show or hide identical files and copies with the same tags in player menus")
+        (@arg DATA_DIR: --("data-dir") +takes_value "Where to keep changes made on the players, defaults to $XDG_DATA_HOME/termdj")
+        (@arg IMPORT: --import +takes_value +multiple number_of_values(1) "rekordbox XML collection to import cues from")
+        (@arg SMART_PLAYLISTS: --("smart-playlists") +takes_value "Smart playlist definitions, defaults to smart-playlists.conf in the data dir")
+        (@arg ALLOW: --allow +takes_value +multiple number_of_values(1) "Host or subnet allowed to mount the library, defaults to the network the players are on")
+        (@subcommand history =>
+            (about: "Lists and exports the tracks played from the library")
+            (@setting SubcommandRequiredElseHelp)
+            (@arg DATA_DIR: --("data-dir") +takes_value "Data dir the history was recorded in")
+            (@subcommand list =>
+                (about: "Lists the recorded sessions")
+            )
+            (@subcommand export =>
+                (about: "Writes the tracks of a session with their times and decks")
+                (@arg FORMAT: --format +takes_value possible_value[csv tracklist cue m3u] "Setlist format, defaults to tracklist")
+                (@arg SESSION: --session +takes_value "Name of the session to export, defaults to the latest")
+                (@arg OUTPUT: -o --output +takes_value "File to write to instead of stdout")
+                (@arg RECORDING: --recording +takes_value "Recording of the set the CUE sheet refers to")
+                (@arg OFFSET: --offset +takes_value "Seconds into the recording the first track started")
+            )
+        )
+        (@subcommand analyze =>
+            (about: "Measures loudness and detects intros, verses, choruses, breakdowns and outros")
+            (@arg LIBRARY_PATH: +required "Library to analyze")
+            (@arg DATA_DIR: --("data-dir") +takes_value "Data dir to keep the measurements in")
+            (@arg FIND: --find +takes_value possible_value[intro verse chorus breakdown outro] "List the tracks with this phrase afterwards")
+            (@arg BARS: --bars +takes_value "Shortest phrase to list, in bars, defaults to 1")
+        )
+        (@subcommand playlist =>
+            (about: "Builds the playlists shown on the players")
+            (@setting SubcommandRequiredElseHelp)
+            (@arg LIBRARY_PATH: +required +multiple "Libraries the tracks are in")
+            (@arg DATA_DIR: --("data-dir") +takes_value "Data dir the playlists are kept in")
+            (@subcommand list =>
+                (about: "Lists folders and playlists with their ids")
+                (@arg FOLDER: --folder +takes_value "Only list what is in this folder")
+            )
+            (@subcommand show =>
+                (about: "Lists the tracks of a playlist")
+                (@arg ID: +required "Playlist id")
+            )
+            (@subcommand create =>
+                (about: "Creates an empty playlist and prints its id")
+                (@arg NAME: +required "Name shown on the players")
+                (@arg FOLDER: --folder +takes_value "Folder to create it in, defaults to the top")
+                (@arg IS_FOLDER: --("is-folder") "Create a folder instead of a playlist")
+            )
+            (@subcommand rename =>
+                (about: "Renames a playlist or folder")
+                (@arg ID: +required "Playlist id")
+                (@arg NAME: +required "New name")
+            )
+            (@subcommand delete =>
+                (about: "Deletes a playlist, or a folder with everything in it")
+                (@arg ID: +required "Playlist id")
+            )
+            (@subcommand move =>
+                (about: "Moves a playlist or folder")
+                (@arg ID: +required "Playlist id")
+                (@arg FOLDER: --folder +takes_value "Folder to move it to, defaults to the top")
+                (@arg POSITION: --position +takes_value "Position within the folder, defaults to the end")
+            )
+            (@subcommand add =>
+                (about: "Adds tracks to the end of a playlist")
+                (@arg ID: +required "Playlist id")
+                (@arg TRACK: +required +multiple "Paths of the tracks")
+            )
+            (@subcommand remove =>
+                (about: "Removes a track from a playlist")
+                (@arg ID: +required "Playlist id")
+                (@arg POSITION: +required "Position of the track")
+            )
+            (@subcommand reorder =>
+                (about: "Moves a track within a playlist")
+                (@arg ID: +required "Playlist id")
+                (@arg FROM: +required "Position of the track")
+                (@arg TO: +required "Position to move it to")
+            )
+        )
+        (@subcommand export =>
+            (about: "Exports the library to a USB stick or SD card the players can read")
+            (@arg LIBRARY_PATH: +required "Library to export")
+            (@arg DESTINATION: +required "Root of the device")
+            (@arg DATA_DIR: --("data-dir") +takes_value "Data dir with cues, ratings and playlists")
+            (@arg SMART_PLAYLISTS: --("smart-playlists") +takes_value "Smart playlist definitions, defaults to smart-playlists.conf in the data dir")
+        )
     ).get_matches();
 
-    let library_path = matches.value_of("LIBRARY_PATH").unwrap();
-    let mut app = App::new(library_path);
+    match matches.subcommand() {
+        ("history", Some(matches)) => return history(matches),
+        ("playlist", Some(matches)) => return playlist(matches),
+        ("export", Some(matches)) => return export(matches),
+        ("analyze", Some(matches)) => return analyze(matches),
+        _ => {},
+    }
+
+    let roots: Vec<PathBuf> = matches.values_of("LIBRARY_PATH").unwrap().map(PathBuf::from).collect();
+
+    let data_dir = data_dir(&matches);
+    let smart_playlists = smart_playlists(&matches, data_dir.as_ref());
+
+    let options = DatabaseOptions {
+        data_dir,
+        smart_playlists,
+        roots,
+        imports: matches.values_of("IMPORT").map(|values| values.map(PathBuf::from).collect()).unwrap_or_default(),
+        duplicate_policy: match matches.value_of("DUPLICATES") {
+            Some("hide") => DuplicatePolicy::Hide,
+            _ => DuplicatePolicy::Show,
+        },
+    };
+    let allowed_hosts = matches.values_of("ALLOW")
+        .map(|values| values.map(str::parse).collect::<Result<Vec<IpNetwork>, _>>())
+        .transpose()?
+        .unwrap_or_default();
+
+    let mut app = App::new(options, allowed_hosts);
     app.run().await;
 
     Ok(())
@@ -5,25 +5,38 @@ pub enum DBRequestType {
     AlbumByArtistRequest,
     AlbumRequest,
     ArtistRequest,
+    ColorRequest,
+    CueList,
+    CueListRequest,
+    ExtendedCueList,
+    /// Cue list with colors and comments that nxs2 players ask for, answered with `ExtendedCueList`.
+    ExtendedCueListRequest,
     GenreRequest,
     HistoryRequest,
+    HistoryTracksRequest,
     KeyRequest,
     MenuFooter,
     MenuHeader,
     MenuItem,
     MetadataRequest,
     MountInfoRequest,
-    LoadTrackRequest,
-    LoadTrackSuccess,
     PlaylistRequest,
     PreviewWaveformRequest,
+    RatingRequest,
+    /// Tracks by key and distance, with the sort order, the key id and the
+    /// distance on the Camelot wheel as arguments.
+    TitleByKeyDistanceRequest,
     RenderRequest,
     RootMenuRequest,
     SearchQueryRequest,
     Setup,
     Success,
     TitleByArtistAlbumRequest,
+    TitleByColorRequest,
+    TitleByRatingRequest,
     TitleRequest,
+    /// Answer to requests for data the server doesn't have.
+    Unavailable,
     Unknown(u16),
 }
 
@@ -32,19 +45,29 @@ impl DBRequestType {
         Bytes::from(match self {
             DBRequestType::AlbumByArtistRequest => "\x11\x02",
             DBRequestType::ArtistRequest => "\x10\x02",
-            DBRequestType::LoadTrackRequest => "\x2b\x04",
+            DBRequestType::ColorRequest => "\x10\x0d",
+            DBRequestType::CueList => "\x47\x02",
+            DBRequestType::CueListRequest => "\x21\x04",
+            DBRequestType::ExtendedCueList => "\x4e\x02",
+            DBRequestType::ExtendedCueListRequest => "\x2b\x04",
+            DBRequestType::HistoryRequest => "\x10\x12",
+            DBRequestType::HistoryTracksRequest => "\x11\x12",
             DBRequestType::MenuFooter => "\x42\x01",
             DBRequestType::MenuHeader => "\x40\x01",
             DBRequestType::MenuItem => "\x41\x01",
-            DBRequestType::LoadTrackSuccess => "\x4e\x02",
             DBRequestType::MetadataRequest => "\x20\x02",
             DBRequestType::MountInfoRequest => "\x21\x02",
             DBRequestType::PreviewWaveformRequest => "\x20\x04",
+            DBRequestType::RatingRequest => "\x10\x07",
+            DBRequestType::TitleByKeyDistanceRequest => "\x12\x14",
             DBRequestType::RootMenuRequest => "\x10\x00",
             DBRequestType::RenderRequest => "\x30\x00",
             DBRequestType::Setup => "\x00\x00",
             DBRequestType::Success => "\x40\x00",
             DBRequestType::TitleByArtistAlbumRequest => "\x12\x02",
+            DBRequestType::TitleByColorRequest => "\x11\x0d",
+            DBRequestType::TitleByRatingRequest => "\x11\x07",
+            DBRequestType::Unavailable => "\x40\x03",
             _ => "\x00\x00",
         })
     }
@@ -57,21 +80,31 @@ impl DBRequestType {
             4098_u16 => DBRequestType::ArtistRequest,
             4099_u16 => DBRequestType::AlbumRequest,
             4100_u16 => DBRequestType::TitleRequest,
+            4103_u16 => DBRequestType::RatingRequest,
+            4109_u16 => DBRequestType::ColorRequest,
             4114_u16 => DBRequestType::HistoryRequest,
             4116_u16 => DBRequestType::KeyRequest,
             4354_u16 => DBRequestType::AlbumByArtistRequest,
             4357_u16 => DBRequestType::PlaylistRequest,
+            4359_u16 => DBRequestType::TitleByRatingRequest,
+            4365_u16 => DBRequestType::TitleByColorRequest,
+            4370_u16 => DBRequestType::HistoryTracksRequest,
             4610_u16 => DBRequestType::TitleByArtistAlbumRequest,
+            4628_u16 => DBRequestType::TitleByKeyDistanceRequest,
             4864_u16 => DBRequestType::SearchQueryRequest,
             8194_u16 => DBRequestType::MetadataRequest,
             8196_u16 => DBRequestType::PreviewWaveformRequest,
             8450_u16 => DBRequestType::MountInfoRequest,
-            11012_u16 => DBRequestType::LoadTrackRequest,
+            8452_u16 => DBRequestType::CueListRequest,
+            11012_u16 => DBRequestType::ExtendedCueListRequest,
             12288_u16 => DBRequestType::RenderRequest,
             16384_u16 => DBRequestType::Success,
             16385_u16 => DBRequestType::MenuHeader,
+            16387_u16 => DBRequestType::Unavailable,
             16641_u16 => DBRequestType::MenuItem,
             16897_u16 => DBRequestType::MenuFooter,
+            18178_u16 => DBRequestType::CueList,
+            19970_u16 => DBRequestType::ExtendedCueList,
             _ => DBRequestType::Unknown(value)
         }
     }
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::library::{analyze_file, TrackAnalysis, PDB_PATH};
+use crate::rekordbox::{Database, Record, Track, TrackColor};
+use crate::rekordbox::library::history::format_date;
+use pdb::{Table, TrackRow, track_string};
+
+mod anlz;
+pub mod pdb;
+
+/// What an export wrote to the device.
+#[derive(Debug, Default, PartialEq)]
+pub struct ExportSummary {
+    pub tracks: usize,
+    /// Audio files that were already on the device with the same size.
+    pub unchanged: usize,
+    pub playlists: usize,
+    /// Tracks exported without waveforms and beat grid because they could not be decoded.
+    pub unanalyzed: usize,
+}
+
+/// Characters FAT32 does not allow in file names.
+fn sanitize(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    name.trim().trim_end_matches('.').to_string()
+}
+
+/// Device path of a track, "/Contents/Artist/File.mp3", numbered when taken.
+fn content_path(track: &Track, artist: &str, taken: &mut HashMap<String, u32>) -> String {
+    let folder = match sanitize(artist) {
+        artist if artist.is_empty() => String::from("Unknown Artist"),
+        artist => artist,
+    };
+    let stem = track.path.file_stem().map(|stem| sanitize(&stem.to_string_lossy())).unwrap_or_default();
+    let extension = track.path.extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut path = format!("/Contents/{}/{}{}", folder, stem, extension);
+    let count = taken.entry(path.to_lowercase()).or_insert(0);
+    *count += 1;
+    if *count > 1 {
+        path = format!("/Contents/{}/{} ({}){}", folder, stem, count, extension);
+    }
+    path
+}
+
+fn device_file(destination: &Path, device_path: &str) -> PathBuf {
+    destination.join(device_path.trim_start_matches('/'))
+}
+
+fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, data)
+}
+
+/// Copy a track unless the device already holds a file of the same size.
+fn copy_track(track: &Track, target: &Path) -> io::Result<bool> {
+    if fs::metadata(target).map(|metadata| metadata.len() == track.size).unwrap_or(false) {
+        return Ok(false);
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(&track.path, target)?;
+    Ok(true)
+}
+
+/// Ids for distinct names, in the order they are first seen.
+#[derive(Default)]
+struct NameTable {
+    ids: HashMap<String, u32>,
+    names: Vec<String>,
+}
+
+impl NameTable {
+    fn id(&mut self, name: &str) -> u32 {
+        if name.is_empty() {
+            return 0;
+        }
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        self.names.push(name.to_string());
+        let id = self.names.len() as u32;
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+}
+
+/// Ids for distinct albums, albums of the same name by different artists are different albums.
+#[derive(Default)]
+struct AlbumTable {
+    ids: HashMap<(String, u32), u32>,
+    albums: Vec<(String, u32)>,
+}
+
+impl AlbumTable {
+    fn id(&mut self, name: &str, artist_id: u32) -> u32 {
+        if name.is_empty() {
+            return 0;
+        }
+        let album = (name.to_string(), artist_id);
+        if let Some(id) = self.ids.get(&album) {
+            return *id;
+        }
+        self.albums.push(album.clone());
+        let id = self.albums.len() as u32;
+        self.ids.insert(album, id);
+        id
+    }
+}
+
+fn track_row(track: &Track, device_path: &str, analyze_path: &str, analysis: Option<&TrackAnalysis>, date: &str) -> TrackRow {
+    let duration_ms = analysis.map(|analysis| analysis.duration_ms).unwrap_or(0);
+    let file_name = device_path.rsplit('/').next().unwrap_or("").to_string();
+
+    TrackRow {
+        id: *track.id(),
+        artist_id: track.artist_id,
+        color_id: track.color.unwrap_or(TrackColor::None).id(),
+        rating: track.rating,
+        sample_rate: analysis.map(|analysis| analysis.sample_rate).unwrap_or(0),
+        sample_depth: analysis.map(|analysis| analysis.bits_per_sample).unwrap_or(0),
+        file_size: track.wire_size(),
+        bitrate: match duration_ms {
+            0 => 0,
+            duration_ms => (track.size * 8 / duration_ms as u64) as u32,
+        },
+        tempo: track.bpm.unwrap_or(0),
+        duration: (duration_ms / 1000).min(u16::MAX as u32) as u16,
+        strings: vec![
+            (track_string::AUTOLOAD_HOTCUES, String::from("ON")),
+            (track_string::DATE_ADDED, date.to_string()),
+            (track_string::ANALYZE_PATH, analyze_path.to_string()),
+            (track_string::ANALYZE_DATE, date.to_string()),
+            (track_string::COMMENT, track.comment_with_replay_gain()),
+            (track_string::TITLE, track.name().clone()),
+            (track_string::FILENAME, file_name),
+            (track_string::FILE_PATH, device_path.to_string()),
+        ],
+        ..Default::default()
+    }
+}
+
+/// Write the library to a USB stick or SD card the way rekordbox exports
+/// it: the audio below `Contents`, an ANLZ file pair for every track and
+/// the database with tracks, artists, albums, genres, keys, colors and playlists.
+pub fn export_device(database: &Database, destination: &Path) -> io::Result<ExportSummary> {
+    let mut summary = ExportSummary::default();
+    let date = format_date(SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0));
+
+    let mut taken = HashMap::new();
+    let mut albums = AlbumTable::default();
+    let mut genres = NameTable::default();
+    let mut keys = NameTable::default();
+    let mut track_rows = vec![];
+    let mut exported = vec![];
+
+    for track in database.tracks() {
+        let artist = database.get_artist(track.artist_id).map(|artist| artist.name().clone()).unwrap_or_default();
+        let device_path = content_path(&track, &artist, &mut taken);
+        match copy_track(&track, &device_file(destination, &device_path)) {
+            Ok(true) => {},
+            Ok(false) => summary.unchanged += 1,
+            Err(err) => {
+                eprintln!("Failed copying {:?}; error = {}", track.path, err);
+                continue;
+            },
+        }
+
+        // Tracks read from another export keep the analysis they came with
+        let analysis = match database.track_analysis(*track.id()) {
+            Some(analysis) => Ok(analysis),
+            None => analyze_file(&track.path, track.bpm),
+        };
+        let analysis = match analysis {
+            Ok(analysis) => Some(analysis),
+            Err(err) => {
+                eprintln!("Failed analyzing {:?}; error = {}", track.path, err);
+                summary.unanalyzed += 1;
+                None
+            },
+        };
+
+        let analyze_path = format!("/PIONEER/USBANLZ/P000/{:08X}/ANLZ0000.DAT", track.id());
+        let cues = database.cues(*track.id());
+        write_file(&device_file(destination, &analyze_path), &anlz::dat_file(&device_path, analysis.as_ref(), &cues))?;
+        write_file(
+            &device_file(destination, &analyze_path.replace(".DAT", ".EXT")),
+            &anlz::ext_file(&device_path, analysis.as_ref()),
+        )?;
+
+        let mut row = track_row(&track, &device_path, &analyze_path, analysis.as_ref(), &date);
+        row.album_id = albums.id(&track.album, track.artist_id);
+        row.genre_id = genres.id(&track.genre);
+        row.key_id = keys.id(track.key.as_deref().unwrap_or(""));
+        track_rows.push(pdb::track_row(track_rows.len(), &row));
+        exported.push(*track.id());
+        summary.tracks += 1;
+    }
+
+    let mut artists = database.artists();
+    artists.sort_by_key(|artist| *artist.id());
+    let mut playlist_rows = vec![];
+    let mut entry_rows = vec![];
+    let playlists = database.playlists();
+    for playlist in &playlists {
+        let sort_order = playlists.iter()
+            .filter(|sibling| sibling.parent_id == playlist.parent_id)
+            .position(|sibling| sibling.id == playlist.id)
+            .unwrap_or(0);
+        playlist_rows.push(pdb::playlist_tree_row(
+            playlist.id,
+            playlist.parent_id,
+            sort_order as u32,
+            playlist.is_folder(),
+            &playlist.name,
+        ));
+
+        let tracks = database.playlist_tracks(playlist.id).into_iter()
+            .filter(|track| exported.contains(track.id()));
+        for (index, track) in tracks.enumerate() {
+            entry_rows.push(pdb::playlist_entry_row(index as u32 + 1, *track.id(), playlist.id));
+        }
+        summary.playlists += 1;
+    }
+
+    let tables = vec![
+        Table { table_type: pdb::TRACKS, rows: track_rows },
+        Table {
+            table_type: pdb::GENRES,
+            rows: genres.names.iter().enumerate().map(|(index, name)| pdb::named_row(index as u32 + 1, name)).collect(),
+        },
+        Table {
+            table_type: pdb::ARTISTS,
+            rows: artists.iter().enumerate().map(|(index, artist)| pdb::artist_row(index, *artist.id(), artist.name())).collect(),
+        },
+        Table {
+            table_type: pdb::ALBUMS,
+            rows: albums.albums.iter().enumerate()
+                .map(|(index, (name, artist_id))| pdb::album_row(index, index as u32 + 1, *artist_id, name))
+                .collect(),
+        },
+        Table {
+            table_type: pdb::KEYS,
+            rows: keys.names.iter().enumerate().map(|(index, name)| pdb::key_row(index as u32 + 1, name)).collect(),
+        },
+        Table {
+            table_type: pdb::COLORS,
+            rows: TrackColor::ALL.iter().skip(1).map(|color| pdb::color_row(color.id(), color.name())).collect(),
+        },
+        Table { table_type: pdb::PLAYLIST_TREE, rows: playlist_rows },
+        Table { table_type: pdb::PLAYLIST_ENTRIES, rows: entry_rows },
+    ];
+    write_file(&destination.join(PDB_PATH), &pdb::write_pdb(tables))?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_sanitizes_device_paths() {
+        assert_eq!("AC_DC", sanitize("AC/DC"));
+        assert_eq!("What_", sanitize(" What? "));
+        assert_eq!("Dots", sanitize("Dots..."));
+    }
+}
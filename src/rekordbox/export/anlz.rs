@@ -0,0 +1,182 @@
+//! Writer for the ANLZ analysis files players load next to a track:
+//! `ANLZ0000.DAT` with the beat grid, preview waveform and cues, and
+//! `ANLZ0000.EXT` with the scrolling waveform and phrases. Both are a PMAI
+//! header followed by tagged sections, all numbers big endian.
+
+use crate::library::{PhraseKind, TrackAnalysis};
+use crate::rekordbox::{Cue, CueKind};
+
+const FILE_HEADER_SIZE: u32 = 0x1c;
+
+/// Section with a 12 byte tag, header length and total length, followed
+/// by the rest of its header and the body.
+fn section(tag: &[u8; 4], header: &[u8], body: &[u8]) -> Vec<u8> {
+    let header_length = 12 + header.len();
+    let mut bytes = tag.to_vec();
+    bytes.extend(&(header_length as u32).to_be_bytes());
+    bytes.extend(&((header_length + body.len()) as u32).to_be_bytes());
+    bytes.extend(header);
+    bytes.extend(body);
+    bytes
+}
+
+fn file(sections: Vec<Vec<u8>>) -> Vec<u8> {
+    let body = sections.concat();
+    let mut bytes = b"PMAI".to_vec();
+    bytes.extend(&FILE_HEADER_SIZE.to_be_bytes());
+    bytes.extend(&(FILE_HEADER_SIZE + body.len() as u32).to_be_bytes());
+    bytes.extend(&1u32.to_be_bytes());
+    bytes.extend(&0x10000u32.to_be_bytes());
+    bytes.extend(&0x10000u32.to_be_bytes());
+    bytes.extend(&0u32.to_be_bytes());
+    bytes.extend(body);
+    bytes
+}
+
+/// Path of the track on the device, UTF-16BE with a terminating NUL.
+fn path_section(path: &str) -> Vec<u8> {
+    let mut body: Vec<u8> = path.encode_utf16().flat_map(|unit| unit.to_be_bytes().to_vec()).collect();
+    body.extend(&[0, 0]);
+    section(b"PPTH", &(body.len() as u32).to_be_bytes(), &body)
+}
+
+fn beat_grid_section(analysis: &TrackAnalysis) -> Vec<u8> {
+    let mut header = 0u32.to_be_bytes().to_vec();
+    header.extend(&0x80000u32.to_be_bytes());
+    header.extend(&(analysis.beats.len() as u32).to_be_bytes());
+
+    let mut body = vec![];
+    for beat in &analysis.beats {
+        body.extend(&(beat.number as u16).to_be_bytes());
+        body.extend(&(beat.tempo as u16).to_be_bytes());
+        body.extend(&beat.time_ms.to_be_bytes());
+    }
+    section(b"PQTZ", &header, &body)
+}
+
+fn preview_section(analysis: &TrackAnalysis) -> Vec<u8> {
+    let mut header = (analysis.preview.len() as u32).to_be_bytes().to_vec();
+    header.extend(&0x10000u32.to_be_bytes());
+    section(b"PWAV", &header, &analysis.preview)
+}
+
+fn detail_section(analysis: &TrackAnalysis) -> Vec<u8> {
+    let mut header = 1u32.to_be_bytes().to_vec();
+    header.extend(&(analysis.detail.len() as u32).to_be_bytes());
+    header.extend(&0x960000u32.to_be_bytes());
+    section(b"PWV3", &header, &analysis.detail)
+}
+
+/// Phrase kinds of the "high" mood, the one made for dance music.
+fn phrase_kind(kind: PhraseKind) -> u16 {
+    match kind {
+        PhraseKind::Intro => 1,
+        PhraseKind::Verse => 2,
+        PhraseKind::Breakdown => 3,
+        PhraseKind::Chorus => 5,
+        PhraseKind::Outro => 6,
+    }
+}
+
+/// Song structure, written without the masking newer rekordbox versions apply.
+fn phrase_section(analysis: &TrackAnalysis) -> Vec<u8> {
+    let end_beat = analysis.phrases.last().map(|phrase| phrase.end_beat).unwrap_or(0);
+    let mut header = 0x18u32.to_be_bytes().to_vec();
+    header.extend(&(analysis.phrases.len() as u16).to_be_bytes());
+    header.extend(&1u16.to_be_bytes());
+    header.extend(&[0u8; 6]);
+    header.extend(&(end_beat as u16).to_be_bytes());
+    header.extend(&[0u8; 4]);
+
+    let mut body = vec![];
+    for (index, phrase) in analysis.phrases.iter().enumerate() {
+        body.extend(&(index as u16 + 1).to_be_bytes());
+        body.extend(&(phrase.start_beat as u16).to_be_bytes());
+        body.extend(&phrase_kind(phrase.kind).to_be_bytes());
+        body.extend(&[0u8; 18]);
+    }
+    section(b"PSSI", &header, &body)
+}
+
+fn cue_entry(cue: &Cue) -> Vec<u8> {
+    let hot_cue = match cue.kind {
+        CueKind::Memory => 0u32,
+        CueKind::Hot(pad) => pad as u32,
+    };
+    let mut header = hot_cue.to_be_bytes().to_vec();
+    header.extend(&1u32.to_be_bytes());
+    header.extend(&0x10000u32.to_be_bytes());
+    header.extend(&0xffffu16.to_be_bytes());
+    header.extend(&0xffffu16.to_be_bytes());
+
+    let mut body = vec![if cue.loop_end_ms.is_some() { 2 } else { 1 }, 0];
+    body.extend(&0x3e8u16.to_be_bytes());
+    body.extend(&cue.position_ms.to_be_bytes());
+    body.extend(&cue.loop_end_ms.unwrap_or(u32::MAX).to_be_bytes());
+    body.extend(&[0u8; 16]);
+    section(b"PCPT", &header, &body)
+}
+
+/// Memory points go in a list of type 0, hot cues in a list of type 1.
+fn cue_list_section(cues: &[Cue], hot_cues: bool) -> Vec<u8> {
+    let cues: Vec<&Cue> = cues.iter()
+        .filter(|cue| (cue.kind != CueKind::Memory) == hot_cues)
+        .collect();
+
+    let mut header = (hot_cues as u32).to_be_bytes().to_vec();
+    header.extend(&0u16.to_be_bytes());
+    header.extend(&(cues.len() as u16).to_be_bytes());
+    header.extend(&u32::MAX.to_be_bytes());
+
+    let body: Vec<u8> = cues.iter().flat_map(|cue| cue_entry(cue)).collect();
+    section(b"PCOB", &header, &body)
+}
+
+pub fn dat_file(path: &str, analysis: Option<&TrackAnalysis>, cues: &[Cue]) -> Vec<u8> {
+    let mut sections = vec![path_section(path)];
+    if let Some(analysis) = analysis {
+        sections.push(beat_grid_section(analysis));
+        sections.push(preview_section(analysis));
+    }
+    sections.push(cue_list_section(cues, false));
+    sections.push(cue_list_section(cues, true));
+
+    file(sections)
+}
+
+pub fn ext_file(path: &str, analysis: Option<&TrackAnalysis>) -> Vec<u8> {
+    let mut sections = vec![path_section(path)];
+    if let Some(analysis) = analysis {
+        sections.push(detail_section(analysis));
+        if !analysis.phrases.is_empty() {
+            sections.push(phrase_section(analysis));
+        }
+    }
+
+    file(sections)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_writes_path_and_cue_sections() {
+        let cues = vec![
+            Cue::new(CueKind::Memory, 1000, None),
+            Cue::new(CueKind::Hot(2), 2000, Some(4000)),
+        ];
+        let dat = dat_file("/A", None, &cues);
+
+        assert_eq!(b"PMAI\x00\x00\x00\x1c", &dat[..8]);
+        assert_eq!(dat.len() as u32, u32::from_be_bytes([dat[8], dat[9], dat[10], dat[11]]));
+        assert_eq!(b"PPTH\x00\x00\x00\x10\x00\x00\x00\x16\x00\x00\x00\x06\x00/\x00A\x00\x00", &dat[0x1c..0x1c + 22]);
+
+        let hot_cues = &dat[dat.len() - (0x18 + 0x38)..];
+        assert_eq!(b"PCOB\x00\x00\x00\x18\x00\x00\x00\x50\x00\x00\x00\x01\x00\x00\x00\x01", &hot_cues[..20]);
+        let entry = &hot_cues[0x18..];
+        assert_eq!(b"PCPT\x00\x00\x00\x1c\x00\x00\x00\x38\x00\x00\x00\x02", &entry[..16]);
+        assert_eq!(&[2, 0, 0x03, 0xe8, 0, 0, 0x07, 0xd0, 0, 0, 0x0f, 0xa0], &entry[0x1c..0x28]);
+    }
+}
@@ -0,0 +1,375 @@
+//! Writer for the DeviceSQL database players read from `PIONEER/rekordbox/export.pdb`.
+//!
+//! The layout follows the analysis of the format by Deep Symmetry: a file
+//! header page listing the tables, then for every table a header page
+//! followed by a chain of data pages. Each data page holds a heap of rows
+//! growing from the start of the page and groups of row offsets growing
+//! from its end. All numbers are little endian.
+
+pub const PAGE_SIZE: usize = 4096;
+const PAGE_HEADER_SIZE: usize = 0x28;
+const ROW_GROUP_SIZE: usize = 0x24;
+const ROWS_PER_GROUP: usize = 16;
+
+const DATA_PAGE_FLAGS: u8 = 0x34;
+const HEADER_PAGE_FLAGS: u8 = 0x64;
+
+pub const TRACKS: u32 = 0;
+pub const GENRES: u32 = 1;
+pub const ARTISTS: u32 = 2;
+pub const ALBUMS: u32 = 3;
+pub const LABELS: u32 = 4;
+pub const KEYS: u32 = 5;
+pub const COLORS: u32 = 6;
+pub const PLAYLIST_TREE: u32 = 7;
+pub const PLAYLIST_ENTRIES: u32 = 8;
+
+/// rekordbox always lists all table types, including the ones we leave empty.
+const NUMBER_OF_TABLES: u32 = 20;
+
+/// Rows of a table that each get an index shift of 0x20 times their position.
+pub struct Table {
+    pub table_type: u32,
+    pub rows: Vec<Vec<u8>>,
+}
+
+/// Offsets of the strings in a track row, in the order they follow the fixed fields.
+pub mod track_string {
+    pub const AUTOLOAD_HOTCUES: usize = 7;
+    pub const DATE_ADDED: usize = 10;
+    pub const ANALYZE_PATH: usize = 14;
+    pub const ANALYZE_DATE: usize = 15;
+    pub const COMMENT: usize = 16;
+    pub const TITLE: usize = 17;
+    pub const FILENAME: usize = 19;
+    pub const FILE_PATH: usize = 20;
+    pub const COUNT: usize = 21;
+}
+
+const TRACK_ROW_FIXED_SIZE: usize = 0x5e + 2 * track_string::COUNT;
+
+#[derive(Debug, Default)]
+pub struct TrackRow {
+    pub id: u32,
+    pub artist_id: u32,
+    pub album_id: u32,
+    pub genre_id: u32,
+    pub key_id: u32,
+    pub label_id: u32,
+    pub color_id: u8,
+    pub rating: u8,
+    pub sample_rate: u32,
+    pub sample_depth: u16,
+    pub file_size: u32,
+    /// Kilobits per second.
+    pub bitrate: u32,
+    /// BPM times 100.
+    pub tempo: u32,
+    /// Seconds.
+    pub duration: u16,
+    pub strings: Vec<(usize, String)>,
+}
+
+/// DeviceSQL string: short ASCII strings carry their length in a single
+/// byte, longer ones and anything else get a 4 byte header, the latter
+/// encoded as UTF-16LE.
+pub fn device_sql_string(value: &str) -> Vec<u8> {
+    if value.is_ascii() && value.len() <= 126 {
+        let mut bytes = vec![(((value.len() + 1) << 1) | 1) as u8];
+        bytes.extend(value.as_bytes());
+        return bytes;
+    }
+
+    let (flags, data): (u8, Vec<u8>) = match value.is_ascii() {
+        true => (0x40, value.as_bytes().to_vec()),
+        false => (0x90, value.encode_utf16().flat_map(|unit| unit.to_le_bytes().to_vec()).collect()),
+    };
+    let mut bytes = vec![flags];
+    bytes.extend(&((data.len() + 4) as u16).to_le_bytes());
+    bytes.push(0);
+    bytes.extend(data);
+    bytes
+}
+
+fn index_shift(index: usize) -> u16 {
+    (index as u16).wrapping_mul(0x20)
+}
+
+pub fn track_row(index: usize, track: &TrackRow) -> Vec<u8> {
+    let mut row = Vec::with_capacity(TRACK_ROW_FIXED_SIZE + 256);
+    row.extend(&0x24u16.to_le_bytes());
+    row.extend(&index_shift(index).to_le_bytes());
+    row.extend(&0x000c_0700u32.to_le_bytes());
+    row.extend(&track.sample_rate.to_le_bytes());
+    row.extend(&0u32.to_le_bytes()); // composer
+    row.extend(&track.file_size.to_le_bytes());
+    row.extend(&0u32.to_le_bytes());
+    row.extend(&0u16.to_le_bytes());
+    row.extend(&0u16.to_le_bytes());
+    row.extend(&0u32.to_le_bytes()); // artwork
+    row.extend(&track.key_id.to_le_bytes());
+    row.extend(&0u32.to_le_bytes()); // original artist
+    row.extend(&track.label_id.to_le_bytes());
+    row.extend(&0u32.to_le_bytes()); // remixer
+    row.extend(&track.bitrate.to_le_bytes());
+    row.extend(&0u32.to_le_bytes()); // track number
+    row.extend(&track.tempo.to_le_bytes());
+    row.extend(&track.genre_id.to_le_bytes());
+    row.extend(&track.album_id.to_le_bytes());
+    row.extend(&track.artist_id.to_le_bytes());
+    row.extend(&track.id.to_le_bytes());
+    row.extend(&0u16.to_le_bytes()); // disc number
+    row.extend(&0u16.to_le_bytes()); // play count
+    row.extend(&0u16.to_le_bytes()); // year
+    row.extend(&track.sample_depth.to_le_bytes());
+    row.extend(&track.duration.to_le_bytes());
+    row.extend(&0x29u16.to_le_bytes());
+    row.push(track.color_id);
+    row.push(track.rating);
+    row.extend(&1u16.to_le_bytes());
+    row.extend(&3u16.to_le_bytes());
+
+    let mut strings = vec![String::new(); track_string::COUNT];
+    for (index, value) in &track.strings {
+        strings[*index] = value.clone();
+    }
+
+    let mut heap = vec![];
+    for value in &strings {
+        row.extend(&((TRACK_ROW_FIXED_SIZE + heap.len()) as u16).to_le_bytes());
+        heap.extend(device_sql_string(value));
+    }
+    row.extend(heap);
+    row
+}
+
+pub fn artist_row(index: usize, id: u32, name: &str) -> Vec<u8> {
+    let mut row = vec![];
+    row.extend(&0x60u16.to_le_bytes());
+    row.extend(&index_shift(index).to_le_bytes());
+    row.extend(&id.to_le_bytes());
+    row.push(0x03);
+    row.push(0x0a);
+    row.extend(device_sql_string(name));
+    row
+}
+
+pub fn album_row(index: usize, id: u32, artist_id: u32, name: &str) -> Vec<u8> {
+    let mut row = vec![];
+    row.extend(&0x80u16.to_le_bytes());
+    row.extend(&index_shift(index).to_le_bytes());
+    row.extend(&0u32.to_le_bytes());
+    row.extend(&artist_id.to_le_bytes());
+    row.extend(&id.to_le_bytes());
+    row.extend(&0u32.to_le_bytes());
+    row.push(0x03);
+    row.push(0x16);
+    row.extend(device_sql_string(name));
+    row
+}
+
+/// Rows of the genre and label tables.
+pub fn named_row(id: u32, name: &str) -> Vec<u8> {
+    let mut row = id.to_le_bytes().to_vec();
+    row.extend(device_sql_string(name));
+    row
+}
+
+pub fn key_row(id: u32, name: &str) -> Vec<u8> {
+    let mut row = id.to_le_bytes().to_vec();
+    row.extend(&id.to_le_bytes());
+    row.extend(device_sql_string(name));
+    row
+}
+
+pub fn color_row(id: u8, name: &str) -> Vec<u8> {
+    let mut row = vec![0u8; 5];
+    row.extend(&(id as u16).to_le_bytes());
+    row.push(0);
+    row.extend(device_sql_string(name));
+    row
+}
+
+pub fn playlist_tree_row(id: u32, parent_id: u32, sort_order: u32, is_folder: bool, name: &str) -> Vec<u8> {
+    let mut row = parent_id.to_le_bytes().to_vec();
+    row.extend(&0u32.to_le_bytes());
+    row.extend(&sort_order.to_le_bytes());
+    row.extend(&id.to_le_bytes());
+    row.extend(&(is_folder as u32).to_le_bytes());
+    row.extend(device_sql_string(name));
+    row
+}
+
+/// Entry indexes count from 1 within their playlist.
+pub fn playlist_entry_row(entry_index: u32, track_id: u32, playlist_id: u32) -> Vec<u8> {
+    let mut row = entry_index.to_le_bytes().to_vec();
+    row.extend(&track_id.to_le_bytes());
+    row.extend(&playlist_id.to_le_bytes());
+    row
+}
+
+fn put_u16(page: &mut [u8], offset: usize, value: u16) {
+    page[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(page: &mut [u8], offset: usize, value: u32) {
+    page[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn page_header(page: &mut [u8], page_index: u32, table_type: u32, next_page: u32, flags: u8) {
+    put_u32(page, 0x04, page_index);
+    put_u32(page, 0x08, table_type);
+    put_u32(page, 0x0c, next_page);
+    put_u32(page, 0x10, 1);
+    page[0x1b] = flags;
+}
+
+fn groups_for(rows: usize) -> usize {
+    rows.div_ceil(ROWS_PER_GROUP)
+}
+
+/// Rows are 4 byte aligned on the heap.
+fn padded_len(row: &[u8]) -> usize {
+    (row.len() + 3) & !3
+}
+
+/// Split rows into the groups of rows that fit a data page.
+fn paginate(rows: &[Vec<u8>]) -> Vec<&[Vec<u8>]> {
+    let capacity = PAGE_SIZE - PAGE_HEADER_SIZE;
+    let mut pages = vec![];
+    let mut start = 0;
+    let mut heap = 0;
+
+    for (index, row) in rows.iter().enumerate() {
+        let rows_on_page = index - start + 1;
+        if heap + padded_len(row) + groups_for(rows_on_page) * ROW_GROUP_SIZE > capacity && index > start {
+            pages.push(&rows[start..index]);
+            start = index;
+            heap = 0;
+        }
+        heap += padded_len(row);
+    }
+    pages.push(&rows[start..]);
+
+    pages
+}
+
+fn data_page(page_index: u32, table_type: u32, next_page: u32, rows: &[Vec<u8>]) -> Vec<u8> {
+    let mut page = vec![0u8; PAGE_SIZE];
+    page_header(&mut page, page_index, table_type, next_page, DATA_PAGE_FLAGS);
+
+    let mut heap = 0;
+    for (index, row) in rows.iter().enumerate() {
+        let start = PAGE_HEADER_SIZE + heap;
+        page[start..start + row.len()].copy_from_slice(row);
+
+        let group_end = PAGE_SIZE - (index / ROWS_PER_GROUP) * ROW_GROUP_SIZE;
+        let slot = index % ROWS_PER_GROUP;
+        put_u16(&mut page, group_end - 6 - 2 * slot, heap as u16);
+        let present = u16::from_le_bytes([page[group_end - 4], page[group_end - 3]]) | (1 << slot);
+        put_u16(&mut page, group_end - 4, present);
+
+        heap += padded_len(row);
+    }
+
+    // Row offsets and rows use 13 and 11 bits of a 24 bit field
+    let rows_field = rows.len() as u32 | ((rows.len() as u32) << 13);
+    page[0x18..0x1b].copy_from_slice(&rows_field.to_le_bytes()[..3]);
+    let free = PAGE_SIZE - PAGE_HEADER_SIZE - heap - groups_for(rows.len()) * ROW_GROUP_SIZE;
+    put_u16(&mut page, 0x1c, free as u16);
+    put_u16(&mut page, 0x1e, heap as u16);
+    put_u16(&mut page, 0x20, 1);
+
+    page
+}
+
+/// Build the database file from the given tables, missing table types are written empty.
+pub fn write_pdb(tables: Vec<Table>) -> Vec<u8> {
+    let mut pages: Vec<Vec<u8>> = vec![vec![0u8; PAGE_SIZE]];
+    // Pointers of each table: type, empty candidate, first page and last page
+    let mut pointers: Vec<[u32; 4]> = vec![];
+
+    for table_type in 0..NUMBER_OF_TABLES {
+        let rows: &[Vec<u8>] = tables.iter()
+            .find(|table| table.table_type == table_type)
+            .map(|table| table.rows.as_slice())
+            .unwrap_or(&[]);
+        let chunks = paginate(rows);
+
+        let first_page = pages.len() as u32;
+        let last_page = first_page + chunks.len() as u32;
+        // Like rekordbox, the last page links to a free page the table could grow into
+        let empty_candidate = last_page + 1;
+
+        let mut header = vec![0u8; PAGE_SIZE];
+        page_header(&mut header, first_page, table_type, first_page + 1, HEADER_PAGE_FLAGS);
+        pages.push(header);
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let page_index = first_page + 1 + index as u32;
+            let next_page = if page_index == last_page { empty_candidate } else { page_index + 1 };
+            pages.push(data_page(page_index, table_type, next_page, chunk));
+        }
+
+        pointers.push([table_type, empty_candidate, first_page, last_page]);
+        // Keep the candidate free by leaving an empty page for it
+        pages.push(vec![0u8; PAGE_SIZE]);
+    }
+
+    let file_header = &mut pages[0];
+    put_u32(file_header, 0x04, PAGE_SIZE as u32);
+    put_u32(file_header, 0x08, NUMBER_OF_TABLES);
+    put_u32(file_header, 0x10, 5);
+    put_u32(file_header, 0x14, 1);
+    for (index, pointer) in pointers.iter().enumerate() {
+        for (field, value) in pointer.iter().enumerate() {
+            put_u32(file_header, 0x1c + index * 16 + field * 4, *value);
+        }
+    }
+    let next_unused_page = pages.len() as u32;
+    put_u32(&mut pages[0], 0x0c, next_unused_page);
+
+    pages.concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_encodes_device_sql_strings() {
+        assert_eq!(b"\x09Dub".to_vec(), device_sql_string("Dub"));
+        assert_eq!(vec![0x90, 0x08, 0x00, 0x00, 0xe9, 0x00, 0x74, 0x00], device_sql_string("ét"));
+        let long = "a".repeat(200);
+        assert_eq!(vec![0x40, 204, 0, 0], device_sql_string(&long)[..4].to_vec());
+    }
+
+    #[test]
+    fn it_lays_out_rows_from_both_ends_of_a_page() {
+        let rows: Vec<Vec<u8>> = (0..17).map(|id| named_row(id, "Techno")).collect();
+        let page = data_page(3, GENRES, 4, &rows);
+
+        assert_eq!(&[17, 0x20, 0x02, DATA_PAGE_FLAGS], &page[0x18..0x1c]);
+        // First group at the end of the page with all 16 rows present, the second holding the last row
+        assert_eq!(&[0xff, 0xff, 0, 0], &page[PAGE_SIZE - 4..]);
+        assert_eq!(&[0, 0], &page[PAGE_SIZE - 6..PAGE_SIZE - 4]);
+        assert_eq!(&[12, 0], &page[PAGE_SIZE - 8..PAGE_SIZE - 6]);
+        assert_eq!(&[1, 0], &page[PAGE_SIZE - ROW_GROUP_SIZE - 4..PAGE_SIZE - ROW_GROUP_SIZE - 2]);
+        assert_eq!(&[16 * 12, 0], &page[PAGE_SIZE - ROW_GROUP_SIZE - 6..PAGE_SIZE - ROW_GROUP_SIZE - 4]);
+        assert_eq!(&named_row(1, "Techno")[..], &page[PAGE_HEADER_SIZE + 12..PAGE_HEADER_SIZE + 23]);
+    }
+
+    #[test]
+    fn it_lists_every_table_in_the_file_header() {
+        let file = write_pdb(vec![Table { table_type: ARTISTS, rows: vec![artist_row(0, 1, "Artist")] }]);
+
+        assert_eq!(0, file.len() % PAGE_SIZE);
+        assert_eq!(NUMBER_OF_TABLES, u32::from_le_bytes([file[8], file[9], file[10], file[11]]));
+        let artists = 0x1c + ARTISTS as usize * 16;
+        let first_page = u32::from_le_bytes([file[artists + 8], file[artists + 9], file[artists + 10], file[artists + 11]]) as usize;
+        let data = &file[(first_page + 1) * PAGE_SIZE..];
+        assert_eq!(DATA_PAGE_FLAGS, data[0x1b]);
+        assert_eq!(&artist_row(0, 1, "Artist")[..], &data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 17]);
+    }
+}
@@ -1,4 +1,5 @@
 use bytes::{Bytes, BytesMut};
+use std::convert::TryFrom;
 use std::net::{SocketAddr};
 use std::sync::{Arc, Mutex};
 use tokio::net::TcpListener;
@@ -10,34 +11,50 @@ use super::packets::{DBMessage, ManyDBMessages, Arguments};
 use super::db_field::{DBField, DBFieldType};
 use super::db_request_type::DBRequestType;
 use super::db_message_argument::ArgumentCollection;
-use crate::rekordbox::{Database, ServerState, Record};
+use crate::rekordbox::{Database, ServerState, Record, TrackColor};
+use crate::library::{key_id, key_of_id, MAX_RATING};
+use crate::rpc::NfsExport;
 use crate::utils::network::random_ipv4_socket_address;
 
 mod codec;
+mod cue;
 mod request;
 mod fixtures;
 mod helper;
 pub mod model;
 pub mod database;
 pub mod metadata_type;
+pub mod history;
+pub mod setlist;
+pub mod playlist;
+pub mod smart_playlist;
+mod related;
 
 pub use metadata_type::*;
 use request::{Controller, RequestWrapper, RequestHandler};
 use fixtures::PREVIEW_WAVEFORM_RESPONSE;
+use cue::{encode_cue_list, encode_extended_cue_list};
+use playlist::ROOT_FOLDER;
 use helper::*;
 
 pub struct ClientState {
     previous_request: Option<StatefulRequest>,
+    /// Track whose metadata was asked for last, the one selected on the player.
+    selected_track: Option<u32>,
     state: Arc<Mutex<ServerState>>,
     database: Arc<Database>,
+    /// The files served over NFS, to announce tracks where players find them.
+    export: Arc<NfsExport>,
 }
 
 impl ClientState {
-    pub fn new(state: Arc<Mutex<ServerState>>, database: Arc<Database>) -> Self {
+    pub fn new(state: Arc<Mutex<ServerState>>, database: Arc<Database>, export: Arc<NfsExport>) -> Self {
         Self {
             previous_request: None,
+            selected_track: None,
             state,
             database,
+            export,
         }
     }
 
@@ -70,6 +87,19 @@ impl Controller for SetupController {
     }
 }
 
+/// Entries of the root menu: MenuName, MetadataType, MenuId
+const ROOT_MENU: [(&str, MetadataType, u32); 9] = [
+    ("\u{fffa}ARTIST\u{fffb}", metadata_type::ROOT_ARTIST,      0x02),
+    ("\u{fffa}ALBUM\u{fffb}", metadata_type::ROOT_ALBUM,        0x03),
+    ("\u{fffa}TRACK\u{fffb}", metadata_type::ROOT_TRACK,        0x04),
+    ("\u{fffa}KEY\u{fffb}", metadata_type::ROOT_KEY,            0x0c),
+    ("\u{fffa}RATING\u{fffb}", metadata_type::ROOT_RATING,      0x07),
+    ("\u{fffa}COLOR\u{fffb}", metadata_type::ROOT_COLOR,        0x0d),
+    ("\u{fffa}PLAYLIST\u{fffb}", metadata_type::ROOT_PLAYLIST,  0x05),
+    ("\u{fffa}HISTORY\u{fffb}", metadata_type::ROOT_HISTORY,    0x16),
+    ("\u{fffa}SEARCH\u{fffb}", metadata_type::ROOT_SEARCH,      0x12),
+];
+
 struct RootMenuController;
 impl Controller for RootMenuController {
     fn to_response(&self, request: RequestWrapper, _context: &mut ClientState) -> Bytes {
@@ -79,7 +109,7 @@ impl Controller for RootMenuController {
         bytes.extend(Bytes::from(
             ArgumentCollection::new(vec![
                 DBField::from([0x00, 0x00, 0x10, 0x00]),
-                DBField::from([0x00, 0x00, 0x00, 0x08]),
+                DBField::from(ROOT_MENU.len() as u32),
             ]),
         ));
 
@@ -199,16 +229,7 @@ impl RenderController {
             build_message_header(&transaction_id),
         ]);
 
-        response.extend(vec![
-            // MenuName, MetadataType, MenuId
-            ("\u{fffa}ARTIST\u{fffb}", metadata_type::ROOT_ARTIST,      0x02),
-            ("\u{fffa}ALBUM\u{fffb}", metadata_type::ROOT_ALBUM,        0x03),
-            ("\u{fffa}TRACK\u{fffb}", metadata_type::ROOT_TRACK,        0x04),
-            ("\u{fffa}KEY\u{fffb}", metadata_type::ROOT_KEY,            0x0c),
-            ("\u{fffa}PLAYLIST\u{fffb}", metadata_type::ROOT_PLAYLIST,  0x05),
-            ("\u{fffa}HISTORY\u{fffb}", metadata_type::ROOT_HISTORY,    0x16),
-            ("\u{fffa}SEARCH\u{fffb}", metadata_type::ROOT_SEARCH,      0x12),
-        ].iter().map(|item| build_message_item(&transaction_id,
+        response.extend(ROOT_MENU.iter().map(|item| build_message_item(&transaction_id,
             item.0,
             item.1,
             item.2,
@@ -322,6 +343,7 @@ impl RenderController {
         let transaction_id = request.message.transaction_id;
         let track = context.database.get_track(track_id).unwrap();
         let artist = context.database.get_artist(track.artist_id).unwrap();
+        let comment = track.comment_with_replay_gain();
 
         ManyDBMessages::new(vec![
             build_message_header(&transaction_id),
@@ -380,7 +402,7 @@ impl RenderController {
                 DBRequestType::MenuItem,
                 Arguments {
                     entry_id2: 5,
-                    value1: "",
+                    value1: &comment,
                     _type: metadata_type::COMMENT,
                     ..Default::default()
                 },
@@ -389,7 +411,8 @@ impl RenderController {
                 transaction_id.clone(),
                 DBRequestType::MenuItem,
                 Arguments {
-                    entry_id1: 1,
+                    entry_id1: track.key.as_deref().and_then(key_id).unwrap_or(0),
+                    value1: track.key.as_deref().unwrap_or(""),
                     _type: metadata_type::KEY,
                     ..Default::default()
                 },
@@ -398,6 +421,7 @@ impl RenderController {
                 transaction_id.clone(),
                 DBRequestType::MenuItem,
                 Arguments {
+                    entry_id2: track.rating as u32,
                     _type: metadata_type::RATING,
                     ..Default::default()
                 },
@@ -406,7 +430,9 @@ impl RenderController {
                 transaction_id.clone(),
                 DBRequestType::MenuItem,
                 Arguments {
-                    _type: metadata_type::COLOR_NONE,
+                    entry_id2: track.color.unwrap_or(TrackColor::None).id() as u32,
+                    value1: track.color.unwrap_or(TrackColor::None).name(),
+                    _type: metadata_type::color_type(track.color.unwrap_or(TrackColor::None)),
                     ..Default::default()
                 },
             ),
@@ -414,6 +440,7 @@ impl RenderController {
                 transaction_id.clone(),
                 DBRequestType::MenuItem,
                 Arguments {
+                    value1: &track.genre,
                     _type: metadata_type::GENRE,
                     ..Default::default()
                 },
@@ -426,6 +453,205 @@ impl RenderController {
         ])
     }
 
+    fn render_color(&self, request: RequestWrapper, context: &ClientState) -> ManyDBMessages {
+        let transaction_id = request.message.transaction_id;
+        let mut response = ManyDBMessages::new(vec![
+            build_message_header(&transaction_id),
+        ]);
+
+        for (color, _count) in color_groups(&context.database) {
+            response.push(build_message_item(&transaction_id,
+                color.name(),
+                metadata_type::color_type(color),
+                color.id() as u32,
+            ));
+        }
+
+        response.push(DBMessage::new(
+            transaction_id,
+            DBRequestType::MenuFooter,
+            ArgumentCollection::new(vec![]),
+        ));
+
+        response
+    }
+
+    fn render_title_by_color(&self, request: RequestWrapper, context: &ClientState, color: TrackColor) -> ManyDBMessages {
+        let transaction_id = request.message.transaction_id;
+        let mut response = ManyDBMessages::new(vec![
+            build_message_header(&transaction_id),
+        ]);
+
+        for track in context.database.title_by_color(color) {
+            response.push(build_message_item(&transaction_id,
+                &track.name().clone(),
+                metadata_type::TITLE,
+                *track.id(),
+            ));
+        }
+
+        response.push(DBMessage::new(
+            transaction_id,
+            DBRequestType::MenuFooter,
+            ArgumentCollection::new(vec![]),
+        ));
+
+        response
+    }
+
+    fn render_title_by_key_distance(&self, request: RequestWrapper, context: &ClientState, key: &str, distance: u32) -> ManyDBMessages {
+        let transaction_id = request.message.transaction_id;
+        let mut response = ManyDBMessages::new(vec![
+            build_message_header(&transaction_id),
+        ]);
+
+        for track in context.database.title_by_key_distance(key, distance, context.selected_track) {
+            response.push(build_message_item(&transaction_id,
+                &track.name().clone(),
+                metadata_type::TITLE,
+                *track.id(),
+            ));
+        }
+
+        response.push(DBMessage::new(
+            transaction_id,
+            DBRequestType::MenuFooter,
+            ArgumentCollection::new(vec![]),
+        ));
+
+        response
+    }
+
+    fn render_rating(&self, request: RequestWrapper, context: &ClientState) -> ManyDBMessages {
+        let transaction_id = request.message.transaction_id;
+        let mut response = ManyDBMessages::new(vec![
+            build_message_header(&transaction_id),
+        ]);
+
+        // Players draw the stars from the number, the label stays empty
+        for (rating, _count) in rating_groups(&context.database) {
+            response.push(build_message_item(&transaction_id,
+                "",
+                metadata_type::RATING,
+                rating as u32,
+            ));
+        }
+
+        response.push(DBMessage::new(
+            transaction_id,
+            DBRequestType::MenuFooter,
+            ArgumentCollection::new(vec![]),
+        ));
+
+        response
+    }
+
+    fn render_title_by_rating(&self, request: RequestWrapper, context: &ClientState, rating: u8) -> ManyDBMessages {
+        let transaction_id = request.message.transaction_id;
+        let mut response = ManyDBMessages::new(vec![
+            build_message_header(&transaction_id),
+        ]);
+
+        for track in context.database.title_by_rating(rating) {
+            response.push(build_message_item(&transaction_id,
+                &track.name().clone(),
+                metadata_type::TITLE,
+                *track.id(),
+            ));
+        }
+
+        response.push(DBMessage::new(
+            transaction_id,
+            DBRequestType::MenuFooter,
+            ArgumentCollection::new(vec![]),
+        ));
+
+        response
+    }
+
+    fn render_history(&self, request: RequestWrapper, context: &ClientState) -> ManyDBMessages {
+        let transaction_id = request.message.transaction_id;
+        let mut response = ManyDBMessages::new(vec![
+            build_message_header(&transaction_id),
+        ]);
+
+        for session in context.database.history_sessions() {
+            response.push(build_message_item(&transaction_id,
+                &session.name,
+                metadata_type::HISTORY_PLAYLIST,
+                session.id,
+            ));
+        }
+
+        response.push(DBMessage::new(
+            transaction_id,
+            DBRequestType::MenuFooter,
+            ArgumentCollection::new(vec![]),
+        ));
+
+        response
+    }
+
+    fn render_history_tracks(&self, request: RequestWrapper, context: &ClientState, session_id: u32) -> ManyDBMessages {
+        let transaction_id = request.message.transaction_id;
+        let mut response = ManyDBMessages::new(vec![
+            build_message_header(&transaction_id),
+        ]);
+
+        for track in history_tracks(session_id, &context.database) {
+            response.push(build_message_item(&transaction_id,
+                &track.name().clone(),
+                metadata_type::TITLE,
+                *track.id(),
+            ));
+        }
+
+        response.push(DBMessage::new(
+            transaction_id,
+            DBRequestType::MenuFooter,
+            ArgumentCollection::new(vec![]),
+        ));
+
+        response
+    }
+
+    fn render_playlist(&self, request: RequestWrapper, context: &ClientState, playlist_id: u32, folder: bool) -> ManyDBMessages {
+        let transaction_id = request.message.transaction_id;
+        let mut response = ManyDBMessages::new(vec![
+            build_message_header(&transaction_id),
+        ]);
+
+        if folder {
+            for playlist in context.database.playlist_folder(playlist_id) {
+                let item_type = match playlist.is_folder() {
+                    true => metadata_type::FOLDER,
+                    false => metadata_type::PLAYLIST,
+                };
+                response.push(build_message_item(&transaction_id,
+                    &playlist.name,
+                    item_type,
+                    playlist.id,
+                ));
+            }
+        } else {
+            for track in context.database.playlist_tracks(playlist_id) {
+                response.push(build_message_item(&transaction_id,
+                    &track.name().clone(),
+                    metadata_type::TITLE,
+                    *track.id(),
+                ));
+            }
+        }
+
+        response.push(DBMessage::new(
+            transaction_id,
+            DBRequestType::MenuFooter,
+            ArgumentCollection::new(vec![]),
+        ));
+
+        response
+    }
+
     fn render_mount_info(&self, request: RequestWrapper, context: &ClientState, track_id: u32) -> ManyDBMessages {
         let transaction_id = request.message.transaction_id;
 
@@ -435,6 +661,15 @@ impl RenderController {
 
         match context.database.get_track(track_id) {
             Some(track) => {
+                // Players are pointed at the WAV file of tracks they can't decode
+                let (path, size) = match context.export.transcoding(&track.path) {
+                    Some(audio) => (
+                        audio.path().to_string_lossy().into_owned(),
+                        u32::try_from(audio.size()).unwrap_or(u32::MAX),
+                    ),
+                    None => (track.path().to_string(), track.wire_size()),
+                };
+
                 resp.push(DBMessage::new(
                     transaction_id.clone(),
                     DBRequestType::MenuItem,
@@ -477,9 +712,9 @@ impl RenderController {
                     DBRequestType::MenuItem,
                     Arguments {
                         _type: metadata_type::MOUNT_PATH,
-                        entry_id1: track.size,
+                        entry_id1: size,
                         entry_id2: 5,
-                        value1: track.path(),
+                        value1: &path,
                         ..Default::default()
                     },
                 ));
@@ -528,6 +763,19 @@ impl Controller for QueryMountInfoController {
     }
 }
 
+/// Reply to a request that lacks the arguments to answer it.
+fn unavailable(request: RequestWrapper) -> Bytes {
+    let request_type_value = request.message.request_type.value();
+
+    Bytes::from(DBMessage::new(
+        request.message.transaction_id,
+        DBRequestType::Unavailable,
+        ArgumentCollection::new(vec![
+            DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+        ]),
+    ))
+}
+
 fn dbfield_to_u32(input: &DBField) -> u32 {
     if input.kind != DBFieldType::U32 {
         panic!("Unsupported conversation");
@@ -570,6 +818,7 @@ impl Controller for MetadataController {
         let request_type_value = request.message.request_type.value();
         let track_id = dbfield_to_u32(&request.message.arguments[1]);
 
+        context.selected_track = Some(track_id);
         context.set_previous_request(StatefulRequest::MetadataRequest {
             track_id,
         });
@@ -585,20 +834,237 @@ impl Controller for MetadataController {
     }
 }
 
-struct LoadTrackController;
-impl Controller for LoadTrackController {
-    fn to_response(&self, request: RequestWrapper, _context: &mut ClientState) -> Bytes {
+struct ColorController;
+impl Controller for ColorController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let number_of_colors = color_groups(&context.database).len() as u32;
+
+        context.set_previous_request(StatefulRequest::ColorRequest);
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::Success,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+                DBField::from(number_of_colors),
+            ]),
+        ))
+    }
+}
+
+struct TitleByColorController;
+impl Controller for TitleByColorController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let color_id = dbfield_to_u32(&request.message.arguments[2]);
+        let color = TrackColor::from_id(color_id as u8).unwrap_or(TrackColor::None);
+        let number_of_tracks = context.database.title_by_color(color).len() as u32;
+
+        context.set_previous_request(StatefulRequest::TitleByColorRequest {
+            color,
+        });
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::Success,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+                DBField::from(number_of_tracks),
+            ]),
+        ))
+    }
+}
+
+struct TitleByKeyDistanceController;
+impl Controller for TitleByKeyDistanceController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let arguments: Vec<&DBField> = request.message.arguments.iter().collect();
+        let (key, distance) = match (arguments.get(2), arguments.get(3)) {
+            (Some(key_id), Some(distance)) if key_id.kind == DBFieldType::U32 && distance.kind == DBFieldType::U32 => {
+                match key_of_id(dbfield_to_u32(key_id)) {
+                    Some(key) => (key, dbfield_to_u32(distance)),
+                    None => return unavailable(request),
+                }
+            },
+            _ => return unavailable(request),
+        };
+        let number_of_tracks = context.database.title_by_key_distance(&key, distance, context.selected_track).len() as u32;
+
+        context.set_previous_request(StatefulRequest::TitleByKeyDistanceRequest {
+            key,
+            distance,
+        });
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::Success,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+                DBField::from(number_of_tracks),
+            ]),
+        ))
+    }
+}
+
+struct RatingController;
+impl Controller for RatingController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let number_of_ratings = rating_groups(&context.database).len() as u32;
+
+        context.set_previous_request(StatefulRequest::RatingRequest);
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::Success,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+                DBField::from(number_of_ratings),
+            ]),
+        ))
+    }
+}
+
+struct TitleByRatingController;
+impl Controller for TitleByRatingController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let rating = dbfield_to_u32(&request.message.arguments[2]).min(MAX_RATING as u32) as u8;
+        let number_of_tracks = context.database.title_by_rating(rating).len() as u32;
+
+        context.set_previous_request(StatefulRequest::TitleByRatingRequest {
+            rating,
+        });
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::Success,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+                DBField::from(number_of_tracks),
+            ]),
+        ))
+    }
+}
+
+struct HistoryController;
+impl Controller for HistoryController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let number_of_sessions = context.database.history_sessions().len() as u32;
+
+        context.set_previous_request(StatefulRequest::HistoryRequest);
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::Success,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+                DBField::from(number_of_sessions),
+            ]),
+        ))
+    }
+}
+
+struct HistoryTracksController;
+impl Controller for HistoryTracksController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let session_id = dbfield_to_u32(&request.message.arguments[2]);
+        let number_of_tracks = history_tracks(session_id, &context.database).len() as u32;
+
+        context.set_previous_request(StatefulRequest::HistoryTracksRequest {
+            session_id,
+        });
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::Success,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+                DBField::from(number_of_tracks),
+            ]),
+        ))
+    }
+}
+
+/// Opens a playlist or a playlist folder, the arguments after the sort order
+/// are the id and whether it is a folder. The root folder has id 0.
+struct PlaylistController;
+impl Controller for PlaylistController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let playlist_id = dbfield_to_u32(&request.message.arguments[2]);
+        let folder = playlist_id == ROOT_FOLDER || request.message.arguments.iter().nth(3)
+            .map(|field| dbfield_to_u32(field) != 0)
+            .unwrap_or(false);
+        let number_of_items = match folder {
+            true => context.database.playlist_folder(playlist_id).len(),
+            false => context.database.playlist_tracks(playlist_id).len(),
+        } as u32;
+
+        context.set_previous_request(StatefulRequest::PlaylistRequest {
+            playlist_id,
+            folder,
+        });
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::Success,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
+                DBField::from(number_of_items),
+            ]),
+        ))
+    }
+}
+
+struct ExtendedCueListController;
+impl Controller for ExtendedCueListController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
         let request_type_value = request.message.request_type.value();
+        let track_id = match request.message.arguments.iter().nth(1) {
+            Some(field) if field.kind == DBFieldType::U32 => dbfield_to_u32(field),
+            _ => return unavailable(request),
+        };
+        let cues = context.database.cues(track_id);
+        let blob = encode_extended_cue_list(&cues);
 
         Bytes::from(DBMessage::new(
             request.message.transaction_id,
-            DBRequestType::LoadTrackSuccess,
+            DBRequestType::ExtendedCueList,
             ArgumentCollection::new(vec![
                 DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
                 DBField::from(1u32),
+                DBField::from(blob.len() as u32),
+                DBField::new(DBFieldType::Binary, &blob),
+                DBField::from(cues.len() as u32),
+            ]),
+        ))
+    }
+}
+
+struct CueListController;
+impl Controller for CueListController {
+    fn to_response(&self, request: RequestWrapper, context: &mut ClientState) -> Bytes {
+        let request_type_value = request.message.request_type.value();
+        let track_id = dbfield_to_u32(&request.message.arguments[1]);
+        let cues = context.database.cues(track_id);
+        let blob = encode_cue_list(&cues);
+
+        Bytes::from(DBMessage::new(
+            request.message.transaction_id,
+            DBRequestType::CueList,
+            ArgumentCollection::new(vec![
+                DBField::from([0u8, 0u8, request_type_value[0], request_type_value[1]]),
                 DBField::from(0u32),
-                DBField::new(DBFieldType::Binary, &[]),
+                DBField::from(blob.len() as u32),
+                DBField::new(DBFieldType::Binary, &blob),
                 DBField::from(0u32),
+                DBField::from(cues.len() as u32),
+                DBField::from(0x24u32),
             ]),
         ))
     }
@@ -613,6 +1079,14 @@ enum StatefulRequest {
     TitleByArtistAlbumRequest { artist_id: u32 },
     MetadataRequest { track_id: u32 },
     MountInfoRequest { track_id: u32 },
+    HistoryRequest,
+    HistoryTracksRequest { session_id: u32 },
+    RatingRequest,
+    TitleByRatingRequest { rating: u8 },
+    ColorRequest,
+    TitleByColorRequest { color: TrackColor },
+    TitleByKeyDistanceRequest { key: String, distance: u32 },
+    PlaylistRequest { playlist_id: u32, folder: bool },
 }
 
 impl Controller for RenderController {
@@ -625,6 +1099,14 @@ impl Controller for RenderController {
             Some(StatefulRequest::TitleByArtistAlbumRequest { artist_id }) => self.render_title_by_artist_album(request, context, artist_id),
             Some(StatefulRequest::MetadataRequest { track_id }) => self.render_metadata(request, context, track_id),
             Some(StatefulRequest::MountInfoRequest { track_id }) => self.render_mount_info(request, context, track_id),
+            Some(StatefulRequest::ColorRequest) => self.render_color(request, context),
+            Some(StatefulRequest::TitleByColorRequest { color }) => self.render_title_by_color(request, context, color),
+            Some(StatefulRequest::TitleByKeyDistanceRequest { ref key, distance }) => self.render_title_by_key_distance(request, context, key, distance),
+            Some(StatefulRequest::RatingRequest) => self.render_rating(request, context),
+            Some(StatefulRequest::TitleByRatingRequest { rating }) => self.render_title_by_rating(request, context, rating),
+            Some(StatefulRequest::HistoryRequest) => self.render_history(request, context),
+            Some(StatefulRequest::HistoryTracksRequest { session_id }) => self.render_history_tracks(request, context, session_id),
+            Some(StatefulRequest::PlaylistRequest { playlist_id, folder }) => self.render_playlist(request, context, playlist_id, folder),
             _ => ManyDBMessages::new(vec![]),
         })
     }
@@ -634,14 +1116,23 @@ fn get_controller(request_type: &DBRequestType) -> Option<Box<dyn Controller>> {
     match request_type {
         DBRequestType::AlbumByArtistRequest => Some(Box::new(AlbumByArtistController)),
         DBRequestType::ArtistRequest => Some(Box::new(ArtistController)),
-        DBRequestType::LoadTrackRequest => Some(Box::new(LoadTrackController)),
+        DBRequestType::ColorRequest => Some(Box::new(ColorController)),
+        DBRequestType::CueListRequest => Some(Box::new(CueListController)),
+        DBRequestType::ExtendedCueListRequest => Some(Box::new(ExtendedCueListController)),
+        DBRequestType::HistoryRequest => Some(Box::new(HistoryController)),
+        DBRequestType::HistoryTracksRequest => Some(Box::new(HistoryTracksController)),
         DBRequestType::MetadataRequest => Some(Box::new(MetadataController)),
         DBRequestType::MountInfoRequest => Some(Box::new(QueryMountInfoController)),
+        DBRequestType::PlaylistRequest => Some(Box::new(PlaylistController)),
         DBRequestType::PreviewWaveformRequest => Some(Box::new(PreviewWaveformController)),
+        DBRequestType::RatingRequest => Some(Box::new(RatingController)),
+        DBRequestType::TitleByKeyDistanceRequest => Some(Box::new(TitleByKeyDistanceController)),
         DBRequestType::RenderRequest => Some(Box::new(RenderController)),
         DBRequestType::RootMenuRequest => Some(Box::new(RootMenuController)),
         DBRequestType::Setup => Some(Box::new(SetupController)),
         DBRequestType::TitleByArtistAlbumRequest => Some(Box::new(TitleByArtistAlbumController)),
+        DBRequestType::TitleByColorRequest => Some(Box::new(TitleByColorController)),
+        DBRequestType::TitleByRatingRequest => Some(Box::new(TitleByRatingController)),
         DBRequestType::TitleRequest => Some(Box::new(TitleController)),
         _ => None,
     }
@@ -708,12 +1199,13 @@ fn process(
 async fn spawn_library_client_handler(
     mut listener: TcpListener,
     state: &Arc<Mutex<ServerState>>,
-    database: &Arc<Database>
+    database: &Arc<Database>,
+    export: &Arc<NfsExport>,
 ) {
     match listener.accept().await {
         Ok((remote_client, address)) => {
             let mut remote_client = Framed::new(remote_client, BytesCodec::new());
-            let mut context = ClientState::new(state.clone(), database.clone());
+            let mut context = ClientState::new(state.clone(), database.clone(), export.clone());
 
             while let Some(result) = remote_client.next().await {
                 match result {
@@ -733,7 +1225,12 @@ async fn spawn_library_client_handler(
 
 pub struct DBLibraryServer;
 impl DBLibraryServer {
-    async fn spawn(address: &str, state: Arc<Mutex<ServerState>>, database: Arc<Database>) -> Result<(), std::io::Error> {
+    async fn spawn(
+        address: &str,
+        state: Arc<Mutex<ServerState>>,
+        database: Arc<Database>,
+        export: Arc<NfsExport>,
+    ) -> Result<(), std::io::Error> {
         let addr = address.parse::<SocketAddr>().unwrap();
         let mut listener = TcpListener::bind(&addr).await?;
 
@@ -742,6 +1239,7 @@ impl DBLibraryServer {
                 Ok((socket, _address)) => {
                     let state = state.clone();
                     let database = database.clone();
+                    let export = export.clone();
 
                     tokio::spawn(async move {
                         let mut socket = Framed::new(socket, BytesCodec::new());
@@ -751,11 +1249,12 @@ impl DBLibraryServer {
                                 Ok(_data) => {
                                     let state = state.clone();
                                     let database = database.clone();
+                                    let export = export.clone();
                                     let allocated_socket = TcpListener::bind(&random_ipv4_socket_address()).await.unwrap();
                                     let allocated_port = allocated_socket.local_addr().unwrap().port();
 
                                     tokio::spawn(async move {
-                                        spawn_library_client_handler(allocated_socket, &state, &database).await;
+                                        spawn_library_client_handler(allocated_socket, &state, &database, &export).await;
                                     });
                                     let message = Bytes::from(allocated_port.to_be_bytes().to_vec());
                                     match socket.send(message).await {
@@ -773,8 +1272,8 @@ impl DBLibraryServer {
         }
     }
 
-    pub async fn run(state: Arc<Mutex<ServerState>>, database: Arc<Database>) -> Result<(), std::io::Error> {
-        Self::spawn("0.0.0.0:12523", state, database).await
+    pub async fn run(state: Arc<Mutex<ServerState>>, database: Arc<Database>, export: Arc<NfsExport>) -> Result<(), std::io::Error> {
+        Self::spawn("0.0.0.0:12523", state, database, export).await
     }
 }
 
@@ -790,6 +1289,7 @@ mod test {
         ClientState::new(
             Arc::new(Mutex::new(ServerState::new())),
             Arc::new(Database::new("./test/music")),
+            Arc::new(NfsExport::new(&[], None)),
         )
     }
 
@@ -828,6 +1328,46 @@ mod test {
         assert_eq!(request_handler.respond_to(), fixtures::setup_response_packet());
     }
 
+    #[test]
+    fn it_answers_extended_cue_list_requests() {
+        let mut context = context();
+        let request = |arguments| DBMessage::new(
+            DBField::from(7u32),
+            DBRequestType::ExtendedCueListRequest,
+            ArgumentCollection::new(arguments),
+        );
+
+        let response = process(request(vec![DBField::from([2u8, 1, 3, 1]), DBField::from(1u32)]).into(), &mut context, &peer());
+        assert_eq!(DBRequestType::ExtendedCueList.value(), response.slice(11..13));
+
+        let response = process(request(vec![DBField::from([2u8, 1, 3, 1])]).into(), &mut context, &peer());
+        assert_eq!(DBRequestType::Unavailable, DBMessage::parse(&response).unwrap().1.request_type);
+    }
+
+    #[test]
+    fn it_honours_the_key_and_distance_of_key_requests() {
+        let mut context = context();
+        let request = |key_id: u32| DBMessage::new(
+            DBField::from(7u32),
+            DBRequestType::TitleByKeyDistanceRequest,
+            ArgumentCollection::new(vec![
+                DBField::from([2u8, 1, 3, 1]),
+                DBField::from(0u32),
+                DBField::from(key_id),
+                DBField::from(2u32),
+            ]),
+        );
+
+        process(request(15).into(), &mut context, &peer());
+        assert_eq!(
+            Some(StatefulRequest::TitleByKeyDistanceRequest { key: String::from("8A"), distance: 2 }),
+            context.previous_request,
+        );
+
+        let response = process(request(25).into(), &mut context, &peer());
+        assert_eq!(DBRequestType::Unavailable, DBMessage::parse(&response).unwrap().1.request_type);
+    }
+
     #[test]
     fn test_album_by_artist_dialog() {
         let dialog = fixtures::album_by_artist_dialog();
@@ -0,0 +1,106 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::rekordbox::{Cue, CueKind};
+
+/// Size of a single entry in the cue list blob.
+pub const CUE_ENTRY_SIZE: usize = 36;
+
+/// Size of an entry in the extended cue list blob, without a comment.
+pub const EXTENDED_CUE_ENTRY_SIZE: usize = 0x38;
+
+/// Players count positions in half frames, 150 of them per second.
+fn ms_to_half_frames(ms: u32) -> u32 {
+    (ms as u64 * 150 / 1000) as u32
+}
+
+/// Encode a cue list the way rekordbox returns it for a cue list request.
+///
+/// Every entry is 36 bytes: a loop flag, a cue flag, the hot cue pad (0 for
+/// memory points) and the little endian start and loop end positions.
+pub fn encode_cue_list(cues: &[Cue]) -> Bytes {
+    let mut bytes = BytesMut::with_capacity(cues.len() * CUE_ENTRY_SIZE);
+
+    for cue in cues {
+        let mut entry = [0u8; CUE_ENTRY_SIZE];
+        entry[0] = cue.loop_end_ms.is_some() as u8;
+        entry[1] = 1;
+        entry[2] = match cue.kind {
+            CueKind::Memory => 0,
+            CueKind::Hot(pad) => pad,
+        };
+        entry[12..16].copy_from_slice(&ms_to_half_frames(cue.position_ms).to_le_bytes());
+        if let Some(loop_end_ms) = cue.loop_end_ms {
+            entry[16..20].copy_from_slice(&ms_to_half_frames(loop_end_ms).to_le_bytes());
+        }
+
+        bytes.put_slice(&entry);
+    }
+
+    bytes.freeze()
+}
+
+/// Encode a cue list the way rekordbox returns it for an extended cue list
+/// request, which nxs2 players send instead of the plain one.
+///
+/// Entries follow the PCP2 entries of the EXT analysis file: the little
+/// endian entry size, the hot cue pad, a flag of 1 for cues and 2 for loops,
+/// and the start and loop end positions in milliseconds.
+pub fn encode_extended_cue_list(cues: &[Cue]) -> Bytes {
+    let mut bytes = BytesMut::with_capacity(cues.len() * EXTENDED_CUE_ENTRY_SIZE);
+
+    for cue in cues {
+        let mut entry = [0u8; EXTENDED_CUE_ENTRY_SIZE];
+        entry[0..4].copy_from_slice(&(EXTENDED_CUE_ENTRY_SIZE as u32).to_le_bytes());
+        entry[4] = match cue.kind {
+            CueKind::Memory => 0,
+            CueKind::Hot(pad) => pad,
+        };
+        entry[6] = if cue.loop_end_ms.is_some() { 2 } else { 1 };
+        entry[12..16].copy_from_slice(&cue.position_ms.to_le_bytes());
+        if let Some(loop_end_ms) = cue.loop_end_ms {
+            entry[16..20].copy_from_slice(&loop_end_ms.to_le_bytes());
+        }
+
+        bytes.put_slice(&entry);
+    }
+
+    bytes.freeze()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_encodes_hot_cues_and_loops() {
+        let blob = encode_cue_list(&[
+            Cue::new(CueKind::Hot(1), 1000, None),
+            Cue::new(CueKind::Memory, 2000, Some(4000)),
+        ]);
+
+        assert_eq!(2 * CUE_ENTRY_SIZE, blob.len());
+        assert_eq!(&[0, 1, 1], &blob[0..3]);
+        assert_eq!(&150u32.to_le_bytes(), &blob[12..16]);
+        assert_eq!(&[0, 0, 0, 0], &blob[16..20]);
+        assert_eq!(&[1, 1, 0], &blob[36..39]);
+        assert_eq!(&300u32.to_le_bytes(), &blob[48..52]);
+        assert_eq!(&600u32.to_le_bytes(), &blob[52..56]);
+    }
+
+    #[test]
+    fn it_encodes_extended_cues_in_milliseconds() {
+        let blob = encode_extended_cue_list(&[
+            Cue::new(CueKind::Hot(2), 1000, None),
+            Cue::new(CueKind::Memory, 2000, Some(4000)),
+        ]);
+
+        assert_eq!(2 * EXTENDED_CUE_ENTRY_SIZE, blob.len());
+        assert_eq!(&[0x38, 0, 0, 0, 2, 0, 1], &blob[0..7]);
+        assert_eq!(&1000u32.to_le_bytes(), &blob[12..16]);
+        assert_eq!(&[0, 0, 0, 0], &blob[16..20]);
+        assert_eq!(&[0x38, 0, 0, 0, 0, 0, 2], &blob[56..63]);
+        assert_eq!(&2000u32.to_le_bytes(), &blob[68..72]);
+        assert_eq!(&4000u32.to_le_bytes(), &blob[72..76]);
+    }
+}
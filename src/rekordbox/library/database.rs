@@ -1,14 +1,107 @@
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, RwLockWriteGuard, RwLockReadGuard, Mutex};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
 use std::ops::Add;
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::rekordbox::MetadataTrack;
-use crate::library::scan_folder;
+use crate::rekordbox::{MetadataTrack, Metadata, Cue, CueSource, TrackColor};
+use super::history::{self, Play, PlayEvent, Session};
+use super::playlist::{self, Playlist, PlaylistSource, ROOT_FOLDER};
+use super::smart_playlist::read_smart_playlists;
+use super::related::{similarity, tempo_distance};
+use crate::library::{is_track_file, scan_folder, scan_playlists, normalize_tag, ContentHashes, read_rekordbox_xml, ImportedTrack, Store};
+use crate::library::{path_from_field, path_to_field};
+use crate::library::{is_device, read_device, DevicePlaylist, TrackAnalysis};
+use crate::library::{analyze_file, key_distance, Loudness, Phrase, PhraseKind};
+
+/// Largest file size that fits the 32 bit size fields used by players and NFSv2.
+pub const MAX_WIRE_FILE_SIZE: u64 = u32::MAX as u64;
+
+/// Tracks with equal tags are only considered duplicates when their sizes
+/// differ less than this many percent, to not group an edit with its original.
+const DUPLICATE_SIZE_TOLERANCE: u64 = 2;
+
+/// Store table with the loudness measured for tracks without ReplayGain tags,
+/// a record with only the path marks a track that was silent or couldn't be
+/// decoded.
+const LOUDNESS_TABLE: &str = "loudness";
+
+/// Store table with the phrases detected for tracks, a record with only the
+/// path marks a track that was analyzed without finding any.
+const PHRASES_TABLE: &str = "phrases";
+
+/// Store table with the content hashes of indexed files, so unchanged files
+/// are not read again to find duplicates.
+const CONTENT_HASHES_TABLE: &str = "content_hashes";
 
 #[derive(Debug)]
 pub enum DatabaseError {
     Unknown,
+    TrackNotFound(u32),
+    PlaylistNotFound(u32),
+    /// File and smart playlists follow their definitions and can't be edited.
+    ReadOnlyPlaylist(u32),
+    NotAFolder(u32),
+    InvalidPosition(usize),
+    NotInLibrary(PathBuf),
+    /// The edit was made but could not be written to the store.
+    Store(io::Error),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatabaseError::Unknown => write!(f, "Database unavailable"),
+            DatabaseError::TrackNotFound(id) => write!(f, "No track with id {}", id),
+            DatabaseError::PlaylistNotFound(id) => write!(f, "No playlist with id {}", id),
+            DatabaseError::ReadOnlyPlaylist(id) => write!(f, "Playlist {} can not be edited", id),
+            DatabaseError::NotAFolder(id) => write!(f, "Playlist {} is not a folder", id),
+            DatabaseError::InvalidPosition(position) => write!(f, "No entry at position {}", position),
+            DatabaseError::NotInLibrary(path) => write!(f, "{:?} is not a track in the library", path),
+            DatabaseError::Store(err) => write!(f, "Failed saving to the data dir; {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+/// Decides if tracks that duplicate an earlier indexed track show up in player menus.
+///
+/// Duplicates are identical files, whose audio bytes hash equal, and tracks
+/// with the same artist and title and nearly the same size. Re-encodes of a
+/// song are not detected, that would take an acoustic fingerprint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+    Show,
+    Hide,
+}
+
+pub struct DatabaseOptions {
+    /// Library folders, earlier roots win when a track is found in several of them.
+    pub roots: Vec<PathBuf>,
+    /// rekordbox XML collections with data for tracks found in the roots.
+    pub imports: Vec<PathBuf>,
+    pub duplicate_policy: DuplicatePolicy,
+    /// Where changes made from the players are kept, nothing is kept without one.
+    pub data_dir: Option<PathBuf>,
+    /// File with smart playlist definitions.
+    pub smart_playlists: Option<PathBuf>,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            roots: vec![],
+            imports: vec![],
+            duplicate_policy: DuplicatePolicy::Show,
+            data_dir: None,
+            smart_playlists: None,
+        }
+    }
 }
 
 struct ArtistTable<T: Record> {
@@ -24,9 +117,18 @@ struct TrackTable<T: Record> {
 struct NewTrack {
     artist_id: u32,
     title: String,
+    album: String,
     path: PathBuf,
-    size: u32,
+    size: u64,
     bpm: Option<u32>,
+    rating: u8,
+    color: Option<TrackColor>,
+    genre: String,
+    comment: String,
+    key: Option<String>,
+    loudness: Option<Loudness>,
+    content_hash: Option<u64>,
+    duplicate_of: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,14 +136,56 @@ pub struct Track {
     id: u32,
     pub artist_id: u32,
     title: String,
+    /// Empty when the tags or the export named no album.
+    pub album: String,
     pub path: PathBuf,
-    pub size: u32,
+    pub size: u64,
     pub bpm: Option<u32>,
+    /// Stars from 0, unrated, to 5.
+    pub rating: u8,
+    /// Color label, `None` when no sidecar or import gave the track one.
+    pub color: Option<TrackColor>,
+    pub genre: String,
+    /// From the tags or the export, without the gain shown to players.
+    pub comment: String,
+    /// Camelot notation, "8A".
+    pub key: Option<String>,
+    /// From ReplayGain tags, or measured when the track had none.
+    pub loudness: Option<Loudness>,
+    pub content_hash: Option<u64>,
+    /// Id of the first indexed track this track is a copy of.
+    pub duplicate_of: Option<u32>,
 }
 
 impl Track {
-    pub fn path(&self) -> &str {
-        self.path.to_str().unwrap()
+    /// Path as text for the players, names that aren't valid UTF-8 get
+    /// replacement characters.
+    pub fn path(&self) -> Cow<'_, str> {
+        self.path.to_string_lossy()
+    }
+
+    /// Size as announced to players, which only have room for 32 bits.
+    ///
+    /// Files larger than 4 GiB are clamped, players can only reach the
+    /// first 4 GiB of them over NFSv2 anyway.
+    pub fn wire_size(&self) -> u32 {
+        u32::try_from(self.size).unwrap_or(u32::MAX)
+    }
+
+    pub fn is_oversize(&self) -> bool {
+        self.size > MAX_WIRE_FILE_SIZE
+    }
+
+    /// Comment for players and exports, which have no field for a gain from
+    /// outside their own analysis: the ReplayGain follows the comment, unless
+    /// it carries one already as when read back from an export.
+    pub fn comment_with_replay_gain(&self) -> String {
+        match self.loudness {
+            Some(_loudness) if self.comment.contains("ReplayGain") => self.comment.clone(),
+            Some(loudness) if self.comment.is_empty() => format!("ReplayGain {:+.1} dB", loudness.replay_gain()),
+            Some(loudness) => format!("{}, ReplayGain {:+.1} dB", self.comment, loudness.replay_gain()),
+            None => self.comment.clone(),
+        }
     }
 }
 
@@ -110,8 +254,17 @@ impl Insertable<NewTrack, u32> for TrackTable<Track> {
                     artist_id: document.artist_id,
                     path: document.path,
                     title: document.title,
+                    album: document.album,
                     size: document.size,
                     bpm: document.bpm,
+                    rating: document.rating,
+                    color: document.color,
+                    genre: document.genre,
+                    comment: document.comment,
+                    key: document.key,
+                    loudness: document.loudness,
+                    content_hash: document.content_hash,
+                    duplicate_of: document.duplicate_of,
                 });
                 return id;
             },
@@ -179,148 +332,1788 @@ impl PrimaryKey for u32 {
     fn one() -> Self { 1 }
 }
 
+/// Lookup tables used to find the original of a track while indexing.
+struct DuplicateIndex {
+    content_hashes: HashMap<u64, u32>,
+    tags: HashMap<String, Vec<u32>>,
+}
+
+impl DuplicateIndex {
+    fn new() -> Self {
+        Self {
+            content_hashes: HashMap::new(),
+            tags: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, track_id: u32, content_hash: Option<u64>, tag_key: Option<String>) {
+        if let Some(content_hash) = content_hash {
+            self.content_hashes.entry(content_hash).or_insert(track_id);
+        }
+
+        if let Some(tag_key) = tag_key {
+            self.tags.entry(tag_key).or_default().push(track_id);
+        }
+    }
+}
+
+fn duplicate_tag_key(metadata: &Metadata) -> Option<String> {
+    let title = normalize_tag(&metadata.title);
+    if title.is_empty() {
+        return None;
+    }
+
+    Some(format!("{}\u{0}{}", normalize_tag(&metadata.artist), title))
+}
+
+/// Canonical paths of playlist entries, taken before the entries are
+/// resolved under the lock.
+fn canonical_entries(entries: &[PathBuf]) -> Vec<Option<PathBuf>> {
+    entries.iter().map(|entry| entry.canonicalize().ok()).collect()
+}
+
+fn similar_size(a: u64, b: u64) -> bool {
+    let difference = a.abs_diff(b);
+    difference.saturating_mul(100) <= a.max(b).saturating_mul(DUPLICATE_SIZE_TOLERANCE)
+}
+
 struct InnerDatabase {
     artists: ArtistTable<Artist>,
     tracks: TrackTable<Track>,
+    duplicates: DuplicateIndex,
+    paths: HashMap<PathBuf, u32>,
+    cues: HashMap<u32, (CueSource, Vec<Cue>)>,
+    analysis: HashMap<u32, TrackAnalysis>,
+    phrases: HashMap<u32, Vec<Phrase>>,
+    /// Tracks analyzed without getting a loudness, silent or undecodable.
+    unmeasured: HashSet<u32>,
+    history: Vec<Play>,
+    playlists: Vec<Playlist>,
+}
+
+impl InnerDatabase {
+    /// Replace the cues of a track unless they came from a source with precedence.
+    fn set_cues(&mut self, track_id: u32, source: CueSource, cues: Vec<Cue>) {
+        match self.cues.get(&track_id) {
+            Some((existing, _)) if *existing > source => {},
+            _ => {
+                self.cues.insert(track_id, (source, cues));
+            },
+        }
+    }
+
+    fn playlist(&self, playlist_id: u32) -> Result<&Playlist, DatabaseError> {
+        self.playlists.iter()
+            .find(|playlist| playlist.id == playlist_id)
+            .ok_or(DatabaseError::PlaylistNotFound(playlist_id))
+    }
+
+    fn editable_playlist(&mut self, playlist_id: u32) -> Result<&mut Playlist, DatabaseError> {
+        let playlist = self.playlists.iter_mut()
+            .find(|playlist| playlist.id == playlist_id)
+            .ok_or(DatabaseError::PlaylistNotFound(playlist_id))?;
+
+        match playlist.is_editable() {
+            true => Ok(playlist),
+            false => Err(DatabaseError::ReadOnlyPlaylist(playlist_id)),
+        }
+    }
+
+    fn playlist_entries(&mut self, playlist_id: u32) -> Result<&mut Vec<PathBuf>, DatabaseError> {
+        match &mut self.editable_playlist(playlist_id)?.source {
+            PlaylistSource::Tracks(entries) => Ok(entries),
+            _ => Err(DatabaseError::ReadOnlyPlaylist(playlist_id)),
+        }
+    }
+
+    fn check_folder(&self, folder_id: u32) -> Result<(), DatabaseError> {
+        if folder_id == ROOT_FOLDER {
+            return Ok(());
+        }
+
+        match self.playlist(folder_id)?.is_folder() {
+            true => Ok(()),
+            false => Err(DatabaseError::NotAFolder(folder_id)),
+        }
+    }
+
+    fn next_playlist_id(&self) -> u32 {
+        self.playlists.iter().map(|playlist| playlist.id).max().unwrap_or(ROOT_FOLDER) + 1
+    }
+
+    /// The playlist and everything in it, when it is a folder.
+    fn playlist_subtree(&self, playlist_id: u32) -> Vec<u32> {
+        let mut ids = vec![playlist_id];
+        let mut index = 0;
+        while index < ids.len() {
+            let parent_id = ids[index];
+            ids.extend(self.playlists.iter()
+                .filter(|playlist| playlist.parent_id == parent_id)
+                .map(|playlist| playlist.id));
+            index += 1;
+        }
+        ids
+    }
+
+    /// Put a playlist in a folder before the sibling at `position`, or last.
+    fn insert_playlist(&mut self, playlist: Playlist, position: Option<usize>) {
+        let index = position.and_then(|position| {
+            self.playlists.iter()
+                .enumerate()
+                .filter(|(_index, sibling)| sibling.parent_id == playlist.parent_id)
+                .nth(position)
+                .map(|(index, _sibling)| index)
+        });
+
+        match index {
+            Some(index) => self.playlists.insert(index, playlist),
+            None => self.playlists.push(playlist),
+        }
+    }
+
+    /// Track at a path, or at its canonical form for paths that go through
+    /// symlinks the canonical roots don't. Callers canonicalize before taking
+    /// the lock, it may touch the disk.
+    fn track_id(&self, path: &Path, canonical: Option<&Path>) -> Option<u32> {
+        self.paths.get(path)
+            .or_else(|| canonical.and_then(|canonical| self.paths.get(canonical)))
+            .cloned()
+    }
+
+    /// Entries of a file playlist under the paths their tracks were indexed
+    /// with, entries missing from the library are kept as they are.
+    fn resolve_entries(&self, name: &str, entries: Vec<PathBuf>, canonical: Vec<Option<PathBuf>>) -> Vec<PathBuf> {
+        let mut unresolved = 0;
+        let entries = entries.into_iter()
+            .zip(canonical)
+            .map(|(entry, canonical)| {
+                let track = self.track_id(&entry, canonical.as_deref())
+                    .and_then(|track_id| self.tracks.rows.get(&track_id));
+                match track {
+                    Some(track) => track.path.clone(),
+                    None => {
+                        unresolved += 1;
+                        entry
+                    },
+                }
+            })
+            .collect();
+
+        if unresolved > 0 {
+            eprintln!("Playlist {:?} has {} entries missing from the library", name, unresolved);
+        }
+        entries
+    }
+
+    fn original_of(&self, track_id: u32) -> u32 {
+        match self.tracks.rows.get(&track_id) {
+            Some(track) => track.duplicate_of.unwrap_or(track_id),
+            None => track_id,
+        }
+    }
+
+    /// Content hashes are trusted on their own, equal tags only together with a similar size.
+    fn find_duplicate(&self, content_hash: Option<u64>, tag_key: &Option<String>, size: u64) -> Option<u32> {
+        if let Some(track_id) = content_hash.and_then(|value| self.duplicates.content_hashes.get(&value)) {
+            return Some(self.original_of(*track_id));
+        }
+
+        tag_key.as_ref()
+            .and_then(|key| self.duplicates.tags.get(key))
+            .and_then(|candidates| {
+                candidates.iter().find(|track_id| {
+                    self.tracks.rows.get(track_id)
+                        .map(|track| similar_size(track.size, size))
+                        .unwrap_or(false)
+                })
+            })
+            .map(|track_id| self.original_of(*track_id))
+    }
 }
 
 pub struct Database {
     inner: RwLock<InnerDatabase>,
+    duplicate_policy: DuplicatePolicy,
+    store: Option<Store>,
+    roots: Vec<PathBuf>,
 }
 
 impl Database {
     pub fn new<T: AsRef<Path>>(root_folder: T) -> Self {
+        Self::with_options(DatabaseOptions {
+            roots: vec![root_folder.as_ref().to_path_buf()],
+            ..Default::default()
+        })
+    }
+
+    /// Database without tracks or playlists, with canonical roots.
+    fn empty(options: &DatabaseOptions) -> Self {
         let inner_db = InnerDatabase {
             artists: ArtistTable::new(),
             tracks: TrackTable::new(),
+            duplicates: DuplicateIndex::new(),
+            paths: HashMap::new(),
+            cues: HashMap::new(),
+            analysis: HashMap::new(),
+            phrases: HashMap::new(),
+            unmeasured: HashSet::new(),
+            history: vec![],
+            playlists: vec![],
         };
 
-        let database = Self {
+        // Tracks are found by path, so the roots are made to match the absolute
+        // locations of imports no matter how they were given.
+        let roots: Vec<PathBuf> = options.roots.iter()
+            .map(|root| root.canonicalize().unwrap_or_else(|_err| root.clone()))
+            .collect();
+        Self {
             inner: RwLock::new(inner_db),
-        };
+            duplicate_policy: options.duplicate_policy,
+            store: options.data_dir.clone().map(Store::new),
+            roots,
+        }
+    }
+
+    /// Only the playlists kept in the store, to edit them without scanning the
+    /// libraries. Tracks are checked against the roots when they are added.
+    pub fn playlist_store(options: DatabaseOptions) -> Self {
+        let database = Self::empty(&options);
+        database.load_playlists();
+
+        database
+    }
+
+    pub fn with_options(options: DatabaseOptions) -> Self {
+        let database = Self::empty(&options);
+
+        let mut content_hashes = database.load_content_hashes();
+        let mut device_playlists = vec![];
+        for root_folder in &database.roots {
+            // rekordbox exports are read from their database instead of the tags
+            let tracks = match is_device(root_folder) {
+                true => match read_device(root_folder) {
+                    Ok(device) => {
+                        device_playlists.extend(device.playlists);
+                        device.tracks
+                    },
+                    Err(err) => {
+                        eprintln!("Failed reading export {:?}; error = {}", root_folder, err);
+                        vec![]
+                    },
+                },
+                false => scan_folder(root_folder),
+            };
+            for track in tracks {
+                if let Err(err) = database.index(track, &mut content_hashes) {
+                    eprintln!("Failed indexing track; error = {:?}", err);
+                }
+            }
+        }
+        database.save_content_hashes(&content_hashes);
+
+        database.load_playlists();
+        database.add_device_playlists(device_playlists);
+        let mut playlists: Vec<(String, PlaylistSource)> = vec![];
+        for root_folder in database.roots.iter().filter(|root_folder| !is_device(root_folder)) {
+            for playlist in scan_playlists(root_folder) {
+                playlists.push((playlist.name, PlaylistSource::File(playlist.entries)));
+            }
+        }
+        if let Some(path) = &options.smart_playlists {
+            match read_smart_playlists(path) {
+                Ok(smart_playlists) => playlists.extend(smart_playlists.into_iter()
+                    .map(|playlist| (playlist.name.clone(), PlaylistSource::Smart(playlist)))),
+                Err(err) => eprintln!("Failed loading smart playlists from {:?}; error = {}", path, err),
+            }
+        }
+        database.add_playlists(playlists);
 
-        for track in scan_folder(&root_folder) {
-            database.index(track);
+        for import in &options.imports {
+            match read_rekordbox_xml(import) {
+                Ok(tracks) => database.import(import, tracks),
+                Err(err) => eprintln!("Failed importing {:?}; error = {}", import, err),
+            }
         }
 
+        database.load_loudness();
+        database.load_phrases();
+        database.load_history();
+
         database
     }
 
-    pub fn get_track(&self, track_id: u32) -> Option<Track> {
-        let mut ret = None;
-        self.read(&mut |reader| {
-            match reader.tracks.rows.get(&track_id) {
-                Some(track) => {
-                    ret = Some(track.clone());
+    fn load_content_hashes(&self) -> ContentHashes {
+        match &self.store {
+            Some(store) => match store.read_table(CONTENT_HASHES_TABLE) {
+                Ok(records) => ContentHashes::from_records(&records),
+                Err(err) => {
+                    eprintln!("Failed loading content hashes; error = {}", err);
+                    ContentHashes::default()
                 },
-                _ => {},
+            },
+            None => ContentHashes::default(),
+        }
+    }
+
+    fn save_content_hashes(&self, content_hashes: &ContentHashes) {
+        let store = match &self.store {
+            Some(store) if content_hashes.changed() => store,
+            _ => return,
+        };
+
+        if let Err(err) = store.write_table(CONTENT_HASHES_TABLE, &content_hashes.to_records()) {
+            eprintln!("Failed saving content hashes; error = {}", err);
+        }
+    }
+
+    /// Measured values only fill in for tracks without ReplayGain tags, the latest measurement wins.
+    fn load_loudness(&self) {
+        let records = match &self.store {
+            Some(store) => match store.read_table(LOUDNESS_TABLE) {
+                Ok(records) => records,
+                Err(err) => {
+                    eprintln!("Failed loading loudness; error = {}", err);
+                    return;
+                },
+            },
+            None => return,
+        };
+
+        let result = self.write(|db| {
+            let mut measured = HashMap::new();
+            for record in &records {
+                match record.as_slice() {
+                    [path, lufs, peak] => {
+                        if let (Some(track_id), Ok(lufs), Ok(peak)) = (db.paths.get(&path_from_field(path)), lufs.parse(), peak.parse()) {
+                            measured.insert(*track_id, Some(Loudness { lufs, peak }));
+                        }
+                    },
+                    [path] => {
+                        if let Some(track_id) = db.paths.get(&path_from_field(path)) {
+                            measured.insert(*track_id, None);
+                        }
+                    },
+                    _ => {},
+                }
             }
+
+            for (track_id, loudness) in measured {
+                match (db.tracks.rows.get_mut(&track_id), loudness) {
+                    (Some(track), Some(loudness)) => track.loudness = track.loudness.or(Some(loudness)),
+                    (Some(_track), None) => {
+                        db.unmeasured.insert(track_id);
+                    },
+                    (None, _) => {},
+                }
+            }
+
+            Ok(())
         });
 
-        ret
+        if let Err(err) = result {
+            eprintln!("Failed storing loudness; error = {:?}", err);
+        }
     }
 
-    pub fn artists(&self) -> Vec<Artist> {
-        let mut ret = vec![];
-        self.read(&mut |reader| {
-            for (_id, artist) in &reader.artists.rows {
-                ret.push(artist.clone());
+    /// Phrases found by an earlier analysis, for tracks that didn't bring their own.
+    fn load_phrases(&self) {
+        let records = match &self.store {
+            Some(store) => match store.read_table(PHRASES_TABLE) {
+                Ok(records) => records,
+                Err(err) => {
+                    eprintln!("Failed loading phrases; error = {}", err);
+                    return;
+                },
+            },
+            None => return,
+        };
+
+        let result = self.write(|db| {
+            let mut detected: HashMap<u32, Vec<Phrase>> = HashMap::new();
+            for record in &records {
+                let track_id = match record.first().and_then(|path| db.paths.get(&path_from_field(path))) {
+                    Some(track_id) => *track_id,
+                    None => continue,
+                };
+                let phrases = detected.entry(track_id).or_default();
+                if let [_path, kind, start_beat, end_beat] = record.as_slice() {
+                    if let (Some(kind), Ok(start_beat), Ok(end_beat)) = (PhraseKind::from_name(kind), start_beat.parse(), end_beat.parse()) {
+                        phrases.push(Phrase { kind, start_beat, end_beat });
+                    }
+                }
+            }
+
+            for (track_id, phrases) in detected {
+                db.phrases.entry(track_id).or_insert(phrases);
             }
+
+            Ok(())
         });
 
-        ret
+        if let Err(err) = result {
+            eprintln!("Failed storing phrases; error = {:?}", err);
+        }
     }
 
-    pub fn get_artist(&self, artist_id: u32) -> Option<Artist> {
-        let mut ret = None;
+    /// Write the phrases of all tracks to the store, one record per phrase.
+    fn save_phrases(&self) {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return,
+        };
+
+        let mut records = vec![];
         self.read(&mut |reader| {
-            match reader.artists.rows.get(&artist_id) {
-                Some(artist) => {
-                    ret = Some(artist.clone());
-                },
-                _ => {},
-            };
+            for (track_id, phrases) in &reader.phrases {
+                let path = match reader.tracks.rows.get(track_id) {
+                    Some(track) => path_to_field(&track.path),
+                    None => continue,
+                };
+
+                if phrases.is_empty() {
+                    records.push(vec![path.clone()]);
+                }
+                for phrase in phrases {
+                    records.push(vec![
+                        path.clone(),
+                        phrase.kind.name().to_string(),
+                        phrase.start_beat.to_string(),
+                        phrase.end_beat.to_string(),
+                    ]);
+                }
+            }
         });
+        records.sort();
 
-        ret
+        if let Err(err) = store.write_table(PHRASES_TABLE, &records) {
+            eprintln!("Failed saving phrases; error = {}", err);
+        }
     }
 
-    pub fn title_by_artist(&self, artist_id: u32) -> Vec<Track> {
-        let mut titles: Vec<Track> = vec![];
+    /// Decode the tracks without a loudness value, or with a tempo but no
+    /// phrases yet, measure their loudness after EBU R128 and detect their
+    /// phrases, keeping the results in the store. Tracks that are silent or
+    /// fail to decode are kept as such, to not decode them again. Returns
+    /// the number of tracks analyzed.
+    pub fn analyze_tracks(&self) -> usize {
+        let mut unanalyzed: Vec<Track> = vec![];
         self.read(&mut |reader| {
-            for (_id, track) in &reader.tracks.rows {
-                if track.artist_id != artist_id {
-                    continue
+            for (track_id, track) in &reader.tracks.rows {
+                let unmeasured = track.loudness.is_none() && !reader.unmeasured.contains(track_id);
+                if unmeasured || (track.bpm.is_some() && !reader.phrases.contains_key(track_id)) {
+                    unanalyzed.push(track.clone());
                 }
-                titles.push(track.clone());
             }
         });
-        titles
-    }
+        unanalyzed.sort_by_key(|track| track.id);
 
-    fn index(&self, track: MetadataTrack) -> Result<(), DatabaseError> {
-        self.write(|db| {
-            let artist_id = db.artists.insert(NewArtist {
-                name: track.metadata.artist,
-            });
-            db.tracks.insert(NewTrack {
-                artist_id,
-                path: track.path,
-                title: track.metadata.title,
-                size: track.size,
-                bpm: track.metadata.bpm,
-            });
+        let mut analyzed = 0;
+        let mut phrases_changed = false;
+        for track in unanalyzed {
+            let (loudness, phrases) = match analyze_file(&track.path, track.bpm) {
+                Ok(analysis) => {
+                    analyzed += 1;
+                    (analysis.loudness, analysis.phrases)
+                },
+                Err(err) => {
+                    eprintln!("Failed decoding {:?}; error = {}", track.path, err);
+                    (None, vec![])
+                },
+            };
 
-            Ok(())
-        })
-    }
+            let measure = track.loudness.is_none();
+            let result = self.write(|db| {
+                match loudness {
+                    Some(loudness) if measure => {
+                        if let Some(row) = db.tracks.rows.get_mut(&track.id) {
+                            row.loudness = Some(loudness);
+                        }
+                    },
+                    None if measure => {
+                        db.unmeasured.insert(track.id);
+                    },
+                    _ => {},
+                }
+                if track.bpm.is_some() {
+                    db.phrases.insert(track.id, phrases.clone());
+                }
+                Ok(())
+            });
+            if let Err(err) = result {
+                eprintln!("Failed storing analysis; error = {:?}", err);
+                continue;
+            }
+            phrases_changed |= track.bpm.is_some();
+            if let (Some(store), true) = (&self.store, measure) {
+                let record: Vec<String> = match loudness {
+                    Some(loudness) => vec![path_to_field(&track.path), loudness.lufs.to_string(), loudness.peak.to_string()],
+                    None => vec![path_to_field(&track.path)],
+                };
+                if let Err(err) = store.append_record(LOUDNESS_TABLE, &record) {
+                    eprintln!("Failed saving loudness; error = {}", err);
+                }
+            }
+        }
+        if phrases_changed {
+            self.save_phrases();
+        }
 
-    fn tracks(&self) -> Result<Vec<Track>, DatabaseError> {
-        Ok(vec![])
+        analyzed
     }
 
-    fn read<T>(&self, closure: &mut T)
-    where
-        T: FnMut(RwLockReadGuard<InnerDatabase>)
-    {
-        match self.inner.read() {
-            Ok(reader) => closure(reader),
-            Err(_err) => {},
+    fn load_history(&self) {
+        let plays = match &self.store {
+            Some(store) => match history::read_history(store) {
+                Ok(plays) => plays,
+                Err(err) => {
+                    eprintln!("Failed loading history; error = {}", err);
+                    return;
+                },
+            },
+            None => return,
         };
-    }
 
-    fn write<T>(&self, closure: T) -> Result<(), DatabaseError>
-    where
-        T: FnOnce(&mut RwLockWriteGuard<InnerDatabase>) -> Result<(), DatabaseError>
-    {
-        match self.inner.write() {
-            Ok(mut writer) => closure(&mut writer),
-            Err(_err) => Err(DatabaseError::Unknown),
+        let result = self.write(|db| {
+            db.history = plays;
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            eprintln!("Failed storing history; error = {:?}", err);
         }
     }
-}
 
-#[test]
-fn it_can_insert_artists() {
-    struct SomeModel {
-        name: String,
-        id: u32,
+    /// Add a track loaded or brought on air on a player to the history.
+    pub fn record_play(&self, track_id: u32, player_number: u8, event: PlayEvent) -> Result<(), DatabaseError> {
+        let played_at = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        self.record_play_at(track_id, player_number, event, played_at)
     }
 
-    impl Record for SomeModel {
-        fn name(&self) -> &String {
-            &self.name
-        }
+    fn record_play_at(
+        &self,
+        track_id: u32,
+        player_number: u8,
+        event: PlayEvent,
+        played_at: u64,
+    ) -> Result<(), DatabaseError> {
+        let mut play = None;
+        self.write(|db| {
+            let track = db.tracks.rows.get(&track_id)
+                .ok_or(DatabaseError::TrackNotFound(track_id))?;
+            let artist = db.artists.rows.get(&track.artist_id)
+                .map(|artist| artist.name.clone())
+                .unwrap_or_default();
 
-        fn id(&self) -> &u32 {
-            &0u32
+            let new_play = Play {
+                played_at,
+                player_number,
+                event,
+                path: track.path.clone(),
+                artist,
+                title: track.title.clone(),
+            };
+            db.history.push(new_play.clone());
+            play = Some(new_play);
+
+            Ok(())
+        })?;
+
+        if let (Some(store), Some(play)) = (&self.store, play) {
+            if let Err(err) = store.append_record(history::HISTORY_TABLE, &play.to_record()) {
+                eprintln!("Failed saving history; error = {}", err);
+            }
         }
+
+        Ok(())
     }
 
-    let mut table: ArtistTable<SomeModel> = ArtistTable::new();
+    /// Play history grouped into sessions, the latest session first.
+    pub fn history_sessions(&self) -> Vec<Session> {
+        let mut ret = vec![];
+        self.read(&mut |reader| {
+            ret = history::sessions(&reader.history);
+        });
+        ret.reverse();
 
-    //table.insert(SomeModel);
-    //assert_eq!(2, *table.sequence.counter.clone().lock().unwrap());
-    //table.insert(SomeModel);
-    //assert_eq!(3, *table.sequence.counter.clone().lock().unwrap());
+        ret
+    }
+
+    pub fn history_session(&self, session_id: u32) -> Option<Session> {
+        self.history_sessions().into_iter()
+            .find(|session| session.id == session_id)
+    }
+
+    /// Attach imported data to the tracks found while scanning, unknown tracks are skipped and counted.
+    fn import(&self, import: &Path, tracks: Vec<ImportedTrack>) {
+        let canonical: Vec<Option<PathBuf>> = tracks.iter()
+            .map(|track| track.path.canonicalize().ok())
+            .collect();
+        let mut unmatched = 0;
+        let result = self.write(|db| {
+            for (track, canonical) in tracks.into_iter().zip(canonical) {
+                let track_id = match db.track_id(&track.path, canonical.as_deref()) {
+                    Some(track_id) => track_id,
+                    None => {
+                        unmatched += 1;
+                        continue;
+                    },
+                };
+                if !track.cues.is_empty() {
+                    db.set_cues(track_id, CueSource::Import, track.cues);
+                }
+                if let Some(row) = db.tracks.rows.get_mut(&track_id) {
+                    if let Some(rating) = track.rating {
+                        row.rating = rating;
+                    }
+                    // Colors from sidecars win over imported ones
+                    if row.color.is_none() {
+                        row.color = track.color;
+                    }
+                }
+            }
+
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            eprintln!("Failed storing imported tracks; error = {:?}", err);
+        }
+        if unmatched > 0 {
+            eprintln!("Skipped {} tracks of {:?} not found in the library", unmatched, import);
+        }
+    }
+
+    fn load_playlists(&self) {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return,
+        };
+        let records = store.read_table(playlist::PLAYLISTS_TABLE)
+            .and_then(|playlists| Ok((playlists, store.read_table(playlist::PLAYLIST_TRACKS_TABLE)?)));
+        let (playlist_records, track_records) = match records {
+            Ok(records) => records,
+            Err(err) => {
+                eprintln!("Failed loading playlists; error = {}", err);
+                return;
+            },
+        };
+
+        let mut playlists: Vec<Playlist> = playlist_records.iter()
+            .filter_map(|record| Playlist::from_record(record))
+            .collect();
+        for record in &track_records {
+            let (playlist_id, path) = match record.as_slice() {
+                [playlist_id, path] => (playlist_id.parse::<u32>().ok(), path_from_field(path)),
+                _ => continue,
+            };
+            let playlist = playlists.iter_mut().find(|playlist| Some(playlist.id) == playlist_id);
+            if let Some(Playlist { source: PlaylistSource::Tracks(entries), .. }) = playlist {
+                entries.push(path);
+            }
+        }
+
+        let result = self.write(|db| {
+            db.playlists = playlists;
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            eprintln!("Failed storing playlists; error = {:?}", err);
+        }
+    }
+
+    /// Write the editable playlists to the store, in menu order.
+    fn save_playlists(&self) -> io::Result<()> {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+
+        let mut playlist_records = vec![];
+        let mut track_records = vec![];
+        self.read(&mut |reader| {
+            for playlist in reader.playlists.iter().filter(|playlist| playlist.is_editable()) {
+                playlist_records.push(playlist.to_record());
+                if let PlaylistSource::Tracks(entries) = &playlist.source {
+                    for path in entries {
+                        track_records.push(vec![playlist.id.to_string(), path_to_field(path)]);
+                    }
+                }
+            }
+        });
+
+        store.write_table(playlist::PLAYLISTS_TABLE, &playlist_records)?;
+        store.write_table(playlist::PLAYLIST_TRACKS_TABLE, &track_records)
+    }
+
+    /// Edit the playlists and keep the result in the store when the edit succeeded.
+    fn edit_playlists<T, R>(&self, closure: T) -> Result<R, DatabaseError>
+    where
+        T: FnOnce(&mut InnerDatabase) -> Result<R, DatabaseError>
+    {
+        let mut ret = None;
+        self.write(|db| {
+            ret = Some(closure(db)?);
+            Ok(())
+        })?;
+        self.save_playlists().map_err(DatabaseError::Store)?;
+
+        ret.ok_or(DatabaseError::Unknown)
+    }
+
+    /// File and smart playlists go in the root folder, after the stored ones.
+    fn add_playlists(&self, playlists: Vec<(String, PlaylistSource)>) {
+        let canonical: Vec<_> = playlists.iter()
+            .map(|(_name, source)| match source {
+                PlaylistSource::File(entries) => canonical_entries(entries),
+                _ => vec![],
+            })
+            .collect();
+        let result = self.write(|db| {
+            for ((name, source), canonical) in playlists.into_iter().zip(canonical) {
+                let source = match source {
+                    PlaylistSource::File(entries) => PlaylistSource::File(db.resolve_entries(&name, entries, canonical)),
+                    source => source,
+                };
+                let id = db.next_playlist_id();
+                db.playlists.push(Playlist { id, parent_id: ROOT_FOLDER, name, source });
+            }
+
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            eprintln!("Failed storing playlists; error = {:?}", err);
+        }
+    }
+
+    /// Playlists of rekordbox exports keep their folders, under new ids.
+    fn add_device_playlists(&self, playlists: Vec<DevicePlaylist>) {
+        let canonical: Vec<_> = playlists.iter()
+            .map(|device_playlist| canonical_entries(&device_playlist.entries))
+            .collect();
+        let result = self.write(|db| {
+            let mut ids = HashMap::new();
+            for (device_playlist, canonical) in playlists.into_iter().zip(canonical) {
+                let id = db.next_playlist_id();
+                ids.insert(device_playlist.id, id);
+                let source = match device_playlist.is_folder {
+                    true => PlaylistSource::Folder,
+                    false => PlaylistSource::File(db.resolve_entries(&device_playlist.name, device_playlist.entries, canonical)),
+                };
+                db.playlists.push(Playlist {
+                    id,
+                    parent_id: ids.get(&device_playlist.parent_id).cloned().unwrap_or(ROOT_FOLDER),
+                    name: device_playlist.name,
+                    source,
+                });
+            }
+
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            eprintln!("Failed storing playlists; error = {:?}", err);
+        }
+    }
+
+    /// All folders and playlists, in menu order within each folder.
+    pub fn playlists(&self) -> Vec<Playlist> {
+        let mut ret = vec![];
+        self.read(&mut |reader| {
+            ret = reader.playlists.clone();
+        });
+
+        ret
+    }
+
+    /// Folders and playlists directly inside a folder.
+    pub fn playlist_folder(&self, folder_id: u32) -> Vec<Playlist> {
+        self.playlists().into_iter()
+            .filter(|playlist| playlist.parent_id == folder_id)
+            .collect()
+    }
+
+    pub fn get_playlist(&self, playlist_id: u32) -> Option<Playlist> {
+        self.playlists().into_iter()
+            .find(|playlist| playlist.id == playlist_id)
+    }
+
+    /// Create an empty playlist at the end of a folder.
+    pub fn create_playlist(&self, folder_id: u32, name: &str) -> Result<u32, DatabaseError> {
+        self.create_playlist_node(folder_id, name, PlaylistSource::Tracks(vec![]))
+    }
+
+    pub fn create_playlist_folder(&self, folder_id: u32, name: &str) -> Result<u32, DatabaseError> {
+        self.create_playlist_node(folder_id, name, PlaylistSource::Folder)
+    }
+
+    fn create_playlist_node(&self, folder_id: u32, name: &str, source: PlaylistSource) -> Result<u32, DatabaseError> {
+        self.edit_playlists(|db| {
+            db.check_folder(folder_id)?;
+            let id = db.next_playlist_id();
+            db.insert_playlist(Playlist {
+                id,
+                parent_id: folder_id,
+                name: name.to_string(),
+                source,
+            }, None);
+
+            Ok(id)
+        })
+    }
+
+    pub fn rename_playlist(&self, playlist_id: u32, name: &str) -> Result<(), DatabaseError> {
+        self.edit_playlists(|db| {
+            db.editable_playlist(playlist_id)?.name = name.to_string();
+            Ok(())
+        })
+    }
+
+    /// Delete a playlist, or a folder with everything in it.
+    pub fn delete_playlist(&self, playlist_id: u32) -> Result<(), DatabaseError> {
+        self.edit_playlists(|db| {
+            db.editable_playlist(playlist_id)?;
+            let deleted = db.playlist_subtree(playlist_id);
+            db.playlists.retain(|playlist| !deleted.contains(&playlist.id));
+            Ok(())
+        })
+    }
+
+    /// Move a playlist or folder to `position` within a folder, or to its
+    /// end without a position. Folders can't be moved into themselves.
+    pub fn move_playlist(&self, playlist_id: u32, folder_id: u32, position: Option<usize>) -> Result<(), DatabaseError> {
+        self.edit_playlists(|db| {
+            db.editable_playlist(playlist_id)?;
+            db.check_folder(folder_id)?;
+            if db.playlist_subtree(playlist_id).contains(&folder_id) {
+                return Err(DatabaseError::NotAFolder(folder_id));
+            }
+
+            let index = db.playlists.iter().position(|playlist| playlist.id == playlist_id)
+                .ok_or(DatabaseError::PlaylistNotFound(playlist_id))?;
+            let mut playlist = db.playlists.remove(index);
+            playlist.parent_id = folder_id;
+            db.insert_playlist(playlist, position);
+
+            Ok(())
+        })
+    }
+
+    /// Add a track at the end of a playlist.
+    pub fn add_playlist_track(&self, playlist_id: u32, track_id: u32) -> Result<(), DatabaseError> {
+        self.edit_playlists(|db| {
+            let path = db.tracks.rows.get(&track_id)
+                .map(|track| track.path.clone())
+                .ok_or(DatabaseError::TrackNotFound(track_id))?;
+            db.playlist_entries(playlist_id)?.push(path);
+            Ok(())
+        })
+    }
+
+    /// Add a file of one of the libraries at the end of a playlist, whether
+    /// or not it was indexed.
+    pub fn add_playlist_path<T: AsRef<Path>>(&self, playlist_id: u32, path: T) -> Result<(), DatabaseError> {
+        let not_in_library = || DatabaseError::NotInLibrary(path.as_ref().to_path_buf());
+        let path = path.as_ref().canonicalize().map_err(|_err| not_in_library())?;
+        if !path.is_file() || !is_track_file(&path) || !self.roots.iter().any(|root| path.starts_with(root)) {
+            return Err(not_in_library());
+        }
+
+        self.edit_playlists(|db| {
+            db.playlist_entries(playlist_id)?.push(path);
+            Ok(())
+        })
+    }
+
+    /// Remove the entry at `position`, counting from 0.
+    pub fn remove_playlist_track(&self, playlist_id: u32, position: usize) -> Result<(), DatabaseError> {
+        self.edit_playlists(|db| {
+            let entries = db.playlist_entries(playlist_id)?;
+            if position >= entries.len() {
+                return Err(DatabaseError::InvalidPosition(position));
+            }
+            entries.remove(position);
+            Ok(())
+        })
+    }
+
+    /// Move the entry at `from` so it ends up at `to`, counting from 0.
+    pub fn move_playlist_track(&self, playlist_id: u32, from: usize, to: usize) -> Result<(), DatabaseError> {
+        self.edit_playlists(|db| {
+            let entries = db.playlist_entries(playlist_id)?;
+            if from >= entries.len() || to >= entries.len() {
+                return Err(DatabaseError::InvalidPosition(from.max(to)));
+            }
+            let entry = entries.remove(from);
+            entries.insert(to, entry);
+            Ok(())
+        })
+    }
+
+    /// Paths of the entries of a track or file playlist, also those missing from the library.
+    pub fn playlist_paths(&self, playlist_id: u32) -> Vec<PathBuf> {
+        match self.get_playlist(playlist_id).map(|playlist| playlist.source) {
+            Some(PlaylistSource::Tracks(entries)) | Some(PlaylistSource::File(entries)) => entries,
+            _ => vec![],
+        }
+    }
+
+    /// Visible tracks of a playlist. Track and file playlists keep their order and
+    /// skip entries missing from the library, smart playlists are evaluated on every call.
+    pub fn playlist_tracks(&self, playlist_id: u32) -> Vec<Track> {
+        let mut titles: Vec<Track> = vec![];
+        self.read(&mut |reader| {
+            let playlist = match reader.playlists.iter().find(|playlist| playlist.id == playlist_id) {
+                Some(playlist) => playlist,
+                None => return,
+            };
+
+            match &playlist.source {
+                PlaylistSource::Folder => {},
+                PlaylistSource::Tracks(entries) | PlaylistSource::File(entries) => {
+                    titles = entries.iter()
+                        .filter_map(|path| reader.track_id(path, None))
+                        .filter_map(|track_id| reader.tracks.rows.get(&track_id))
+                        .filter(|track| !self.is_hidden(track))
+                        .cloned()
+                        .collect();
+                },
+                PlaylistSource::Smart(smart_playlist) => {
+                    for track in reader.tracks.rows.values() {
+                        let artist = reader.artists.rows.get(&track.artist_id)
+                            .map(|artist| artist.name.as_str())
+                            .unwrap_or("");
+                        if self.is_hidden(track) || !smart_playlist.matches(track, artist) {
+                            continue
+                        }
+                        titles.push(track.clone());
+                    }
+                    titles.sort_by_key(|track| track.id);
+                },
+            }
+        });
+
+        titles
+    }
+
+    pub fn track_id_by_path<T: AsRef<Path>>(&self, path: T) -> Option<u32> {
+        let canonical = path.as_ref().canonicalize().ok();
+        let mut ret = None;
+        self.read(&mut |reader| {
+            ret = reader.track_id(path.as_ref(), canonical.as_deref());
+        });
+
+        ret
+    }
+
+    /// Library folders the tracks were found in.
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Where the library keeps its own data, if anywhere.
+    pub fn store(&self) -> Option<&Store> {
+        self.store.as_ref()
+    }
+
+    /// Memory points, hot cues and loops of a track, ordered by position.
+    pub fn cues(&self, track_id: u32) -> Vec<Cue> {
+        let mut ret = vec![];
+        self.read(&mut |reader| {
+            if let Some((_source, cues)) = reader.cues.get(&track_id) {
+                ret = cues.clone();
+            }
+        });
+        ret.sort_by_key(|cue| cue.position_ms);
+
+        ret
+    }
+
+    /// Beat grid and waveforms read along with the track, from a rekordbox export.
+    pub fn track_analysis(&self, track_id: u32) -> Option<TrackAnalysis> {
+        let mut ret = None;
+        self.read(&mut |reader| {
+            ret = reader.analysis.get(&track_id).cloned();
+        });
+
+        ret
+    }
+
+    /// Intro, verses, choruses, breakdowns and outro of a track, in order.
+    pub fn phrases(&self, track_id: u32) -> Vec<Phrase> {
+        let mut ret = vec![];
+        self.read(&mut |reader| {
+            if let Some(phrases) = reader.phrases.get(&track_id) {
+                ret = phrases.clone();
+            }
+        });
+
+        ret
+    }
+
+    /// Tracks with a phrase of the kind lasting at least this many bars,
+    /// like the ones with a 32 bar intro to mix into.
+    pub fn tracks_with_phrase(&self, kind: PhraseKind, min_bars: u32) -> Vec<Track> {
+        let mut ret: Vec<Track> = vec![];
+        self.read(&mut |reader| {
+            for (track_id, phrases) in &reader.phrases {
+                if !phrases.iter().any(|phrase| phrase.kind == kind && phrase.bars() >= min_bars) {
+                    continue
+                }
+                match reader.tracks.rows.get(track_id) {
+                    Some(track) if !self.is_hidden(track) => ret.push(track.clone()),
+                    _ => {},
+                }
+            }
+        });
+        ret.sort_by_key(|track| track.id);
+
+        ret
+    }
+
+    /// Whether the track is left out of the menus presented to players.
+    fn is_hidden(&self, track: &Track) -> bool {
+        self.duplicate_policy == DuplicatePolicy::Hide && track.duplicate_of.is_some()
+    }
+
+    /// Groups of tracks holding the same song, the original first.
+    pub fn duplicate_groups(&self) -> Vec<Vec<Track>> {
+        let mut groups: HashMap<u32, Vec<Track>> = HashMap::new();
+        self.read(&mut |reader| {
+            for track in reader.tracks.rows.values() {
+                if let Some(original_id) = track.duplicate_of {
+                    groups.entry(original_id).or_default().push(track.clone());
+                }
+            }
+
+            for (original_id, group) in groups.iter_mut() {
+                if let Some(original) = reader.tracks.rows.get(original_id) {
+                    group.push(original.clone());
+                }
+            }
+        });
+
+        let mut groups: Vec<Vec<Track>> = groups.into_iter()
+            .map(|(original_id, mut group)| {
+                group.sort_by_key(|track| (track.id != original_id, track.id));
+                group
+            })
+            .collect();
+        groups.sort_by_key(|group| group[0].id);
+        groups
+    }
+
+    pub fn get_track(&self, track_id: u32) -> Option<Track> {
+        let mut ret = None;
+        self.read(&mut |reader| {
+            match reader.tracks.rows.get(&track_id) {
+                Some(track) => {
+                    ret = Some(track.clone());
+                },
+                _ => {},
+            }
+        });
+
+        ret
+    }
+
+    pub fn artists(&self) -> Vec<Artist> {
+        let mut ret = vec![];
+        self.read(&mut |reader| {
+            for (_id, artist) in &reader.artists.rows {
+                ret.push(artist.clone());
+            }
+        });
+
+        ret
+    }
+
+    pub fn get_artist(&self, artist_id: u32) -> Option<Artist> {
+        let mut ret = None;
+        self.read(&mut |reader| {
+            match reader.artists.rows.get(&artist_id) {
+                Some(artist) => {
+                    ret = Some(artist.clone());
+                },
+                _ => {},
+            };
+        });
+
+        ret
+    }
+
+    pub fn title_by_artist(&self, artist_id: u32) -> Vec<Track> {
+        let mut titles: Vec<Track> = vec![];
+        self.read(&mut |reader| {
+            for track in reader.tracks.rows.values() {
+                if track.artist_id != artist_id || self.is_hidden(track) {
+                    continue
+                }
+                titles.push(track.clone());
+            }
+        });
+        titles
+    }
+
+    /// Visible tracks with the given number of stars.
+    pub fn title_by_rating(&self, rating: u8) -> Vec<Track> {
+        let mut titles: Vec<Track> = vec![];
+        self.read(&mut |reader| {
+            for track in reader.tracks.rows.values() {
+                if track.rating != rating || self.is_hidden(track) {
+                    continue
+                }
+                titles.push(track.clone());
+            }
+        });
+        titles.sort_by_key(|track| track.id);
+        titles
+    }
+
+    /// Visible tracks with a key at most `distance` steps from the given
+    /// Camelot key on the wheel. Suggestions for the selected track come
+    /// first when there is one: the same artist, a compatible key, a close
+    /// tempo or the same genre, the best matches first. Otherwise the
+    /// closest keys come first.
+    pub fn title_by_key_distance(&self, key: &str, distance: u32, selected: Option<u32>) -> Vec<Track> {
+        let mut titles: Vec<(u32, u32, u32, Track)> = vec![];
+        self.read(&mut |reader| {
+            let selected = selected.and_then(|track_id| reader.tracks.rows.get(&track_id));
+            let original = selected.map(|track| reader.original_of(track.id));
+
+            for candidate in reader.tracks.rows.values() {
+                let key_distance = match candidate.key.as_deref().and_then(|candidate_key| key_distance(key, candidate_key)) {
+                    Some(key_distance) if key_distance <= distance => key_distance,
+                    _ => continue,
+                };
+                if self.is_hidden(candidate) || original == Some(reader.original_of(candidate.id)) {
+                    continue
+                }
+                let (score, tempo_distance) = match selected {
+                    Some(track) => (similarity(track, candidate), tempo_distance(track, candidate)),
+                    None => (0, 0),
+                };
+                titles.push((score, tempo_distance, key_distance, candidate.clone()));
+            }
+        });
+        titles.sort_by(|(score_a, tempo_a, key_a, a), (score_b, tempo_b, key_b, b)| {
+            score_b.cmp(score_a)
+                .then(tempo_a.cmp(tempo_b))
+                .then(key_a.cmp(key_b))
+                .then(a.id.cmp(&b.id))
+        });
+
+        titles.into_iter()
+            .map(|(_score, _tempo_distance, _key_distance, track)| track)
+            .collect()
+    }
+
+    /// Visible tracks with the given color label.
+    pub fn title_by_color(&self, color: TrackColor) -> Vec<Track> {
+        let mut titles: Vec<Track> = vec![];
+        self.read(&mut |reader| {
+            for track in reader.tracks.rows.values() {
+                if track.color.unwrap_or(TrackColor::None) != color || self.is_hidden(track) {
+                    continue
+                }
+                titles.push(track.clone());
+            }
+        });
+        titles.sort_by_key(|track| track.id);
+        titles
+    }
+
+    fn index(&self, track: MetadataTrack, content_hashes: &mut ContentHashes) -> Result<(), DatabaseError> {
+        let content_hash = content_hashes.hash(&track.path).ok();
+        let tag_key = duplicate_tag_key(&track.metadata);
+        let loudness = track.loudness.or_else(|| track.analysis.as_ref().and_then(|analysis| analysis.loudness));
+
+        self.write(|db| {
+            let duplicate_of = db.find_duplicate(content_hash, &tag_key, track.size);
+            let artist_id = db.artists.insert(NewArtist {
+                name: track.metadata.artist,
+            });
+            let track_id = db.tracks.insert(NewTrack {
+                artist_id,
+                path: track.path.clone(),
+                title: track.metadata.title,
+                album: track.metadata.album,
+                size: track.size,
+                bpm: track.metadata.bpm,
+                rating: track.metadata.rating,
+                color: track.color,
+                genre: track.metadata.genre,
+                comment: track.metadata.comment,
+                key: track.metadata.key,
+                loudness,
+                content_hash,
+                duplicate_of,
+            });
+            db.duplicates.add(track_id, content_hash, tag_key);
+            db.paths.insert(track.path, track_id);
+            if !track.cues.is_empty() {
+                db.set_cues(track_id, track.cue_source, track.cues);
+            }
+            if let Some(analysis) = track.analysis {
+                if !analysis.phrases.is_empty() {
+                    db.phrases.insert(track_id, analysis.phrases.clone());
+                }
+                db.analysis.insert(track_id, analysis);
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn tracks(&self) -> Vec<Track> {
+        let mut ret = vec![];
+        self.read(&mut |reader| {
+            for track in reader.tracks.rows.values() {
+                ret.push(track.clone());
+            }
+        });
+        ret.sort_by_key(|track| track.id);
+
+        ret
+    }
+
+    fn read<T>(&self, closure: &mut T)
+    where
+        T: FnMut(RwLockReadGuard<InnerDatabase>)
+    {
+        match self.inner.read() {
+            Ok(reader) => closure(reader),
+            Err(_err) => {},
+        };
+    }
+
+    fn write<T>(&self, closure: T) -> Result<(), DatabaseError>
+    where
+        T: FnOnce(&mut RwLockWriteGuard<InnerDatabase>) -> Result<(), DatabaseError>
+    {
+        match self.inner.write() {
+            Ok(mut writer) => closure(&mut writer),
+            Err(_err) => Err(DatabaseError::Unknown),
+        }
+    }
+}
+
+#[test]
+fn it_can_insert_artists() {
+    struct SomeModel {
+        name: String,
+        id: u32,
+    }
+
+    impl Record for SomeModel {
+        fn name(&self) -> &String {
+            &self.name
+        }
+
+        fn id(&self) -> &u32 {
+            &0u32
+        }
+    }
+
+    let mut table: ArtistTable<SomeModel> = ArtistTable::new();
+
+    //table.insert(SomeModel);
+    //assert_eq!(2, *table.sequence.counter.clone().lock().unwrap());
+    //table.insert(SomeModel);
+    //assert_eq!(3, *table.sequence.counter.clone().lock().unwrap());
+}
+
+#[test]
+fn it_clamps_oversize_tracks_to_wire_size() {
+    let mut track = Track {
+        id: 1,
+        artist_id: 1,
+        title: String::from("Live set"),
+        album: String::new(),
+        path: PathBuf::from("/music/live-set.wav"),
+        size: 1024,
+        bpm: None,
+        rating: 0,
+        color: None,
+        genre: String::new(),
+        comment: String::new(),
+        key: None,
+        loudness: None,
+        content_hash: None,
+        duplicate_of: None,
+    };
+    assert_eq!(1024, track.wire_size());
+    assert!(!track.is_oversize());
+
+    track.size = 6 * 1024 * 1024 * 1024;
+    assert_eq!(u32::MAX, track.wire_size());
+    assert!(track.is_oversize());
+}
+
+#[test]
+fn it_groups_duplicates_across_roots() {
+    let temp = crate::utils::test_dir("duplicates");
+    let base = temp.path().to_path_buf();
+    let roots = vec![base.join("a"), base.join("b")];
+    for root in &roots {
+        std::fs::create_dir_all(root).unwrap();
+    }
+
+    // Same audio under another name, same tags with a slightly different size, and an unrelated track
+    std::fs::write(roots[0].join("Artist - Song.wav"), vec![1u8; 1000]).unwrap();
+    std::fs::write(roots[1].join("Artist - Song (copy).wav"), vec![1u8; 1000]).unwrap();
+    std::fs::write(roots[1].join("Artist - Song.wav"), vec![2u8; 1010]).unwrap();
+    std::fs::write(roots[1].join("Artist - Other.wav"), vec![3u8; 1000]).unwrap();
+
+    let database = Database::with_options(DatabaseOptions {
+        roots: roots.clone(),
+        duplicate_policy: DuplicatePolicy::Hide,
+        ..Default::default()
+    });
+
+    let groups = database.duplicate_groups();
+    assert_eq!(1, groups.len());
+    assert_eq!(3, groups[0].len());
+    assert_eq!(roots[0].join("Artist - Song.wav"), groups[0][0].path);
+
+    let artist_id = groups[0][0].artist_id;
+    let mut visible: Vec<String> = database.title_by_artist(artist_id).iter()
+        .map(|track| track.name().clone())
+        .collect();
+    visible.sort();
+    assert_eq!(vec![String::from("Other"), String::from("Song")], visible);
+}
+
+#[test]
+fn it_applies_imported_cues_ratings_and_colors() {
+    let temp = crate::utils::test_dir("cues");
+    let root = temp.path().to_path_buf();
+    std::fs::create_dir_all(&root).unwrap();
+
+    std::fs::write(root.join("Artist - Imported.wav"), vec![1u8; 100]).unwrap();
+    std::fs::write(root.join("Artist - Sidecar.wav"), vec![2u8; 100]).unwrap();
+    std::fs::write(root.join("Artist - Sidecar.wav.termdj"), "cue hot A 1.5\ncolor blue\n").unwrap();
+
+    let collection = root.join("rekordbox.xml");
+    let mut document = String::from("<COLLECTION>");
+    for name in &["Imported", "Sidecar"] {
+        document.push_str(&format!(
+            r#"<TRACK Rating="204" Colour="0xFF0000" Location="file://localhost{}"><POSITION_MARK Type="0" Start="8.0" Num="-1"/></TRACK>"#,
+            root.join(format!("Artist - {}.wav", name)).display(),
+        ));
+    }
+    document.push_str("</COLLECTION>");
+    std::fs::write(&collection, document).unwrap();
+
+    let database = Database::with_options(DatabaseOptions {
+        roots: vec![root.clone()],
+        imports: vec![collection],
+        ..Default::default()
+    });
+
+    let imported = database.track_id_by_path(root.join("Artist - Imported.wav")).unwrap();
+    let sidecar = database.track_id_by_path(root.join("Artist - Sidecar.wav")).unwrap();
+    assert_eq!(vec![Cue::new(crate::rekordbox::CueKind::Memory, 8000, None)], database.cues(imported));
+    // Sidecar cues win over imported ones
+    assert_eq!(vec![Cue::new(crate::rekordbox::CueKind::Hot(1), 1500, None)], database.cues(sidecar));
+    assert_eq!(2, database.title_by_rating(4).len());
+    assert_eq!(Some(TrackColor::Red), database.get_track(imported).unwrap().color);
+    assert_eq!(Some(TrackColor::Blue), database.get_track(sidecar).unwrap().color);
+}
+
+#[test]
+fn it_matches_imports_to_tracks_under_a_symlinked_root() {
+    let temp = crate::utils::test_dir("symlinked-root");
+    let base = temp.path().to_path_buf();
+    let root = base.join("music");
+    let link = base.join("link");
+    std::fs::create_dir_all(&root).unwrap();
+    std::os::unix::fs::symlink(&root, &link).unwrap();
+    std::fs::write(root.join("Artist - Song.wav"), vec![1u8; 100]).unwrap();
+
+    let collection = base.join("rekordbox.xml");
+    std::fs::write(&collection, format!(
+        r#"<COLLECTION><TRACK Rating="255" Location="file://localhost{}"/></COLLECTION>"#,
+        root.canonicalize().unwrap().join("Artist - Song.wav").display(),
+    )).unwrap();
+
+    let database = Database::with_options(DatabaseOptions {
+        roots: vec![link.clone()],
+        imports: vec![collection],
+        ..Default::default()
+    });
+
+    assert_eq!(vec![root.canonicalize().unwrap()], database.roots());
+    let track_id = database.track_id_by_path(link.join("Artist - Song.wav")).unwrap();
+    assert_eq!(5, database.get_track(track_id).unwrap().rating);
+}
+
+#[test]
+fn it_keeps_the_play_history_across_restarts() {
+    let temp = crate::utils::test_dir("history");
+    let base = temp.path().to_path_buf();
+    let root = base.join("music");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("Artist - Song.wav"), vec![1u8; 100]).unwrap();
+
+    let options = || DatabaseOptions {
+        roots: vec![root.clone()],
+        data_dir: Some(base.join("data")),
+        ..Default::default()
+    };
+
+    let database = Database::with_options(options());
+    let track_id = database.track_id_by_path(root.join("Artist - Song.wav")).unwrap();
+    database.record_play_at(track_id, 2, PlayEvent::Loaded, 1583013600).unwrap();
+    database.record_play_at(track_id, 2, PlayEvent::OnAir, 1583013660).unwrap();
+    assert!(database.record_play_at(track_id + 1, 2, PlayEvent::Loaded, 1583013700).is_err());
+
+    let sessions = Database::with_options(options()).history_sessions();
+    assert_eq!(1, sessions.len());
+    assert_eq!("2020-02-29", sessions[0].name);
+    assert_eq!(2, sessions[0].plays.len());
+    assert_eq!(1, sessions[0].tracks().len());
+    assert_eq!("Song", sessions[0].tracks()[0].title);
+}
+
+#[test]
+fn it_keeps_playlists_and_history_of_tracks_whose_names_are_not_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp = crate::utils::test_dir("non-utf8");
+    let base = temp.path().to_path_buf();
+    let root = base.join("music");
+    let path = root.join(OsStr::from_bytes(b"Artist - Caf\xe9.wav"));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(&path, vec![1u8; 100]).unwrap();
+
+    let options = || DatabaseOptions {
+        roots: vec![root.clone()],
+        data_dir: Some(base.join("data")),
+        ..Default::default()
+    };
+
+    let database = Database::with_options(options());
+    let track_id = database.track_id_by_path(&path).unwrap();
+    let playlist_id = database.create_playlist(ROOT_FOLDER, "Friday").unwrap();
+    database.add_playlist_track(playlist_id, track_id).unwrap();
+    database.record_play_at(track_id, 2, PlayEvent::Loaded, 1583013600).unwrap();
+
+    let database = Database::with_options(options());
+    let paths: Vec<PathBuf> = database.playlist_tracks(playlist_id).iter()
+        .map(|track| track.path.clone())
+        .collect();
+    assert_eq!(vec![path.clone()], paths);
+    assert_eq!(path, database.history_sessions()[0].plays[0].path);
+}
+
+#[test]
+fn it_serves_file_and_smart_playlists() {
+    let temp = crate::utils::test_dir("playlists");
+    let base = temp.path().to_path_buf();
+    let root = base.join("music");
+    std::fs::create_dir_all(root.join("sets")).unwrap();
+    std::fs::write(root.join("Artist - Dub One.wav"), vec![1u8; 100]).unwrap();
+    std::fs::write(root.join("Other - Two.wav"), vec![2u8; 100]).unwrap();
+    std::os::unix::fs::symlink(&root, base.join("alias")).unwrap();
+    std::fs::write(root.join("sets/Friday.m3u"), "#EXTM3U\n../Other - Two.wav\n../Missing.wav\n../Artist - Dub One.wav\n../../alias/Other - Two.wav\n").unwrap();
+    std::fs::write(base.join("smart-playlists.conf"), "[Dubs]\ntitle contains dub\nartist is artist\n").unwrap();
+
+    let database = Database::with_options(DatabaseOptions {
+        roots: vec![root.clone()],
+        smart_playlists: Some(base.join("smart-playlists.conf")),
+        ..Default::default()
+    });
+
+    let playlists = database.playlists();
+    assert_eq!(
+        vec!["Friday", "Dubs"],
+        playlists.iter().map(|playlist| playlist.name.as_str()).collect::<Vec<&str>>(),
+    );
+    let titles = |playlist_id: u32| database.playlist_tracks(playlist_id).iter()
+        .map(|track| track.name().clone())
+        .collect::<Vec<String>>();
+    assert_eq!(vec![String::from("Two"), String::from("Dub One"), String::from("Two")], titles(playlists[0].id));
+    assert_eq!(vec![String::from("Dub One")], titles(playlists[1].id));
+}
+
+#[test]
+fn it_keeps_edited_playlists_across_restarts() {
+    let temp = crate::utils::test_dir("edited-playlists");
+    let base = temp.path().to_path_buf();
+    let root = base.join("music");
+    std::fs::create_dir_all(&root).unwrap();
+    for name in &["One", "Two", "Three"] {
+        std::fs::write(root.join(format!("Artist - {}.wav", name)), name.as_bytes()).unwrap();
+    }
+
+    let options = || DatabaseOptions {
+        roots: vec![root.clone()],
+        data_dir: Some(base.join("data")),
+        ..Default::default()
+    };
+    let track = |database: &Database, name: &str| {
+        database.track_id_by_path(root.join(format!("Artist - {}.wav", name))).unwrap()
+    };
+
+    let database = Database::with_options(options());
+    let gigs = database.create_playlist_folder(ROOT_FOLDER, "Gigs").unwrap();
+    let friday = database.create_playlist(gigs, "Friday").unwrap();
+    let saturday = database.create_playlist(gigs, "Sat").unwrap();
+    for name in &["One", "Two", "Three"] {
+        database.add_playlist_track(friday, track(&database, name)).unwrap();
+    }
+    database.move_playlist_track(friday, 2, 0).unwrap();
+    database.remove_playlist_track(friday, 1).unwrap();
+    database.rename_playlist(saturday, "Saturday").unwrap();
+    database.move_playlist(saturday, gigs, Some(0)).unwrap();
+    assert!(database.move_playlist(gigs, friday, None).is_err());
+    assert!(database.move_playlist(gigs, gigs, None).is_err());
+
+    let database = Database::with_options(options());
+    let names = |folder_id: u32| database.playlist_folder(folder_id).iter()
+        .map(|playlist| playlist.name.clone())
+        .collect::<Vec<String>>();
+    assert_eq!(vec![String::from("Gigs")], names(ROOT_FOLDER));
+    assert_eq!(vec![String::from("Saturday"), String::from("Friday")], names(gigs));
+    let titles: Vec<String> = database.playlist_tracks(friday).iter()
+        .map(|track| track.name().clone())
+        .collect();
+    assert_eq!(vec![String::from("Three"), String::from("Two")], titles);
+
+    database.delete_playlist(gigs).unwrap();
+    assert_eq!(0, Database::with_options(options()).playlists().len());
+}
+
+#[test]
+fn it_edits_stored_playlists_without_scanning() {
+    let temp = crate::utils::test_dir("playlist-store");
+    let base = temp.path().to_path_buf();
+    let roots = vec![base.join("a"), base.join("b")];
+    for root in &roots {
+        std::fs::create_dir_all(root).unwrap();
+    }
+    std::fs::write(roots[0].join("Artist - One.wav"), b"One").unwrap();
+    std::fs::write(roots[1].join("Artist - Two.wav"), b"Two").unwrap();
+    std::fs::write(roots[1].join("notes.txt"), b"").unwrap();
+    std::fs::write(base.join("Artist - Outside.wav"), b"").unwrap();
+
+    let options = || DatabaseOptions {
+        roots: roots.clone(),
+        data_dir: Some(base.join("data")),
+        ..Default::default()
+    };
+    let database = Database::playlist_store(options());
+    assert_eq!(0, database.tracks().len());
+    let friday = database.create_playlist(ROOT_FOLDER, "Friday").unwrap();
+    database.add_playlist_path(friday, roots[1].join("Artist - Two.wav")).unwrap();
+    database.add_playlist_path(friday, roots[0].join("Artist - One.wav")).unwrap();
+    assert!(database.add_playlist_path(friday, roots[1].join("notes.txt")).is_err());
+    assert!(database.add_playlist_path(friday, base.join("Artist - Outside.wav")).is_err());
+    assert!(database.add_playlist_path(friday, roots[0].join("Missing.wav")).is_err());
+
+    let database = Database::with_options(options());
+    let titles: Vec<String> = database.playlist_tracks(friday).iter()
+        .map(|track| track.name().clone())
+        .collect();
+    assert_eq!(vec![String::from("Two"), String::from("One")], titles);
+}
+
+#[test]
+fn it_fails_playlist_edits_it_can_not_store() {
+    let temp = crate::utils::test_dir("playlist-store-unwritable");
+    let base = temp.path().to_path_buf();
+    std::fs::write(base.join("data"), b"").unwrap();
+
+    let database = Database::playlist_store(DatabaseOptions {
+        data_dir: Some(base.join("data")),
+        ..Default::default()
+    });
+    match database.create_playlist(ROOT_FOLDER, "Friday") {
+        Err(DatabaseError::Store(_err)) => {},
+        other => panic!("Expected a store error, got {:?}", other),
+    }
+}
+
+#[test]
+fn it_reads_tracks_cues_and_playlists_from_an_export() {
+    let temp = crate::utils::test_dir("device-export");
+    let base = temp.path().to_path_buf();
+    let root = base.join("music");
+    std::fs::create_dir_all(&root).unwrap();
+    // One second of 16 bit mono silence followed by a second at half scale
+    let mut wave = b"RIFF\x00\x00\x00\x00WAVEfmt \x10\x00\x00\x00\x01\x00\x01\x00\xe8\x03\x00\x00\xd0\x07\x00\x00\x02\x00\x10\x00data\xa0\x0f\x00\x00".to_vec();
+    wave.extend(vec![0u8; 2000]);
+    wave.extend([0x00, 0x40].iter().cycle().take(2000));
+    for name in &["One", "Two"] {
+        std::fs::write(root.join(format!("Artist - {}.wav", name)), &wave).unwrap();
+    }
+    std::fs::write(root.join("Artist - One.wav.termdj"), "loop hot A 1.0 1.5\n").unwrap();
+    std::fs::write(base.join("Three.wav"), &wave).unwrap();
+
+    let database = Database::new(&root);
+    let one = database.track_id_by_path(root.join("Artist - One.wav")).unwrap();
+    let two = database.track_id_by_path(root.join("Artist - Two.wav")).unwrap();
+    let metadata = Metadata {
+        artist: String::from("Other"),
+        title: String::from("Three"),
+        bpm: Some(12000),
+        album: String::from("Album"),
+        rating: 0,
+        genre: String::new(),
+        comment: String::from("Warm up"),
+        key: None,
+    };
+    let mut track = MetadataTrack::new(metadata, base.join("Three.wav"), wave.len() as u64);
+    track.loudness = Some(Loudness::from_replay_gain(-3.0, 1.0));
+    database.index(track, &mut ContentHashes::default()).unwrap();
+    let folder = database.create_playlist_folder(ROOT_FOLDER, "Gigs").unwrap();
+    let friday = database.create_playlist(folder, "Friday").unwrap();
+    database.add_playlist_track(friday, two).unwrap();
+    database.add_playlist_track(friday, one).unwrap();
+    crate::rekordbox::export_device(&database, &base.join("usb")).unwrap();
+
+    let device = Database::new(base.join("usb"));
+    assert_eq!(3, device.tracks().len());
+    let one = device.track_id_by_path(base.join("usb/Contents/Artist/Artist - One.wav")).unwrap();
+    let track = device.get_track(one).unwrap();
+    assert_eq!(String::from("One"), *track.name());
+    assert_eq!(Some(String::from("Artist")), device.get_artist(track.artist_id).map(|artist| artist.name().clone()));
+    assert_eq!(vec![Cue::new(crate::rekordbox::CueKind::Hot(1), 1000, Some(1500))], device.cues(one));
+    let analysis = device.track_analysis(one).unwrap();
+    assert_eq!((2000, 1000, 400), (analysis.duration_ms, analysis.sample_rate, analysis.preview.len()));
+
+    let three = device.track_id_by_path(base.join("usb/Contents/Other/Three.wav")).unwrap();
+    let track = device.get_track(three).unwrap();
+    assert_eq!((String::from("Album"), Some(12000)), (track.album.clone(), track.bpm));
+    assert_eq!("Warm up, ReplayGain -3.0 dB", track.comment_with_replay_gain());
+    let beats = device.track_analysis(three).unwrap().beats;
+    assert!(!beats.is_empty());
+    assert_eq!(crate::library::analyze_file(base.join("Three.wav"), Some(12000)).unwrap().beats, beats);
+
+    let gigs = device.playlist_folder(ROOT_FOLDER);
+    assert_eq!(vec![String::from("Gigs")], gigs.iter().map(|playlist| playlist.name.clone()).collect::<Vec<String>>());
+    let playlists = device.playlist_folder(gigs[0].id);
+    assert_eq!(String::from("Friday"), playlists[0].name);
+    let titles: Vec<String> = device.playlist_tracks(playlists[0].id).iter()
+        .map(|track| track.name().clone())
+        .collect();
+    assert_eq!(vec![String::from("Two"), String::from("One")], titles);
+}
+
+#[test]
+fn it_finds_tracks_by_phrase() {
+    let database = Database::with_options(DatabaseOptions::default());
+    for (title, intro_bars) in [("Short intro", 8), ("Long intro", 32), ("Longer intro", 64)].iter() {
+        let metadata = Metadata {
+            artist: String::from("Artist"),
+            title: title.to_string(),
+            bpm: Some(12800),
+            album: String::new(),
+            rating: 0,
+            genre: String::new(),
+            comment: String::new(),
+            key: None,
+        };
+        let mut track = MetadataTrack::new(metadata, PathBuf::from(format!("/music/{}.mp3", title)), 1000);
+        let intro_end = intro_bars * 4 + 1;
+        track.analysis = Some(TrackAnalysis {
+            duration_ms: 0,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            preview: vec![],
+            detail: vec![],
+            beats: vec![],
+            loudness: None,
+            phrases: vec![
+                Phrase { kind: PhraseKind::Intro, start_beat: 1, end_beat: intro_end },
+                Phrase { kind: PhraseKind::Chorus, start_beat: intro_end, end_beat: intro_end + 64 },
+            ],
+        });
+        database.index(track, &mut ContentHashes::default()).unwrap();
+    }
+
+    let titles: Vec<String> = database.tracks_with_phrase(PhraseKind::Intro, 32).iter()
+        .map(|track| track.name().clone())
+        .collect();
+    assert_eq!(vec!["Long intro", "Longer intro"], titles);
+    assert!(database.tracks_with_phrase(PhraseKind::Outro, 1).is_empty());
+    let short = database.track_id_by_path("/music/Short intro.mp3").unwrap();
+    assert_eq!(2, database.phrases(short).len());
+}
+
+#[test]
+fn it_lists_tracks_by_key_distance_and_ranks_them_for_the_selected_track() {
+    let database = Database::with_options(DatabaseOptions::default());
+    let tracks = [
+        ("A", "Seed", 12400, "8A", "Techno"),
+        ("B", "Key and tempo", 12500, "9A", "House"),
+        ("A", "Same artist", 9000, "2B", "Techno"),
+        ("C", "Genre only", 17000, "3B", "Techno"),
+        ("D", "Unrelated", 17000, "3B", "Jungle"),
+        ("E", "Tempo only", 12000, "4A", "House"),
+    ];
+    for (artist, title, bpm, key, genre) in tracks.iter() {
+        let metadata = Metadata {
+            artist: artist.to_string(),
+            title: title.to_string(),
+            bpm: Some(*bpm),
+            album: String::new(),
+            rating: 0,
+            genre: genre.to_string(),
+            comment: String::new(),
+            key: Some(key.to_string()),
+        };
+        database.index(MetadataTrack::new(metadata, PathBuf::from(format!("/music/{}.mp3", title)), 1000), &mut ContentHashes::default()).unwrap();
+    }
+
+    let titles = |distance, selected| -> Vec<String> {
+        database.title_by_key_distance("8A", distance, selected).iter()
+            .map(|track| track.name().clone())
+            .collect()
+    };
+    assert_eq!(vec!["Seed"], titles(0, None));
+    assert_eq!(vec!["Seed", "Key and tempo"], titles(1, None));
+
+    let seed = database.track_id_by_path("/music/Seed.mp3").unwrap();
+    assert_eq!(vec!["Key and tempo", "Tempo only", "Same artist", "Genre only", "Unrelated"], titles(7, Some(seed)));
+}
+
+#[test]
+fn it_keeps_measured_loudness_across_restarts() {
+    let temp = crate::utils::test_dir("loudness");
+    let base = temp.path().to_path_buf();
+    let root = base.join("music");
+    std::fs::create_dir_all(&root).unwrap();
+    // Half a second of a 16 bit mono square wave at half scale, 8 kHz
+    let mut wave = b"RIFF\x00\x00\x00\x00WAVEfmt \x10\x00\x00\x00\x01\x00\x01\x00\x40\x1f\x00\x00\x80\x3e\x00\x00\x02\x00\x10\x00data\x40\x1f\x00\x00".to_vec();
+    for index in 0..4000 {
+        wave.extend(if index / 8 % 2 == 0 { [0x00, 0x40] } else { [0x00, 0xc0] }.iter());
+    }
+    std::fs::write(root.join("Artist - Tone.wav"), &wave).unwrap();
+    let mut silence = wave[..44].to_vec();
+    silence.resize(44 + 8000, 0);
+    std::fs::write(root.join("Artist - Silence.wav"), &silence).unwrap();
+    std::fs::write(root.join("Artist - Broken.wav"), &wave[..12]).unwrap();
+
+    let options = || DatabaseOptions {
+        roots: vec![root.clone()],
+        data_dir: Some(base.join("data")),
+        ..Default::default()
+    };
+    let database = Database::with_options(options());
+    assert_eq!(2, database.analyze_tracks());
+    assert_eq!(0, database.analyze_tracks());
+
+    let database = Database::with_options(options());
+    assert_eq!(0, database.analyze_tracks());
+    let track = database.get_track(database.track_id_by_path(root.join("Artist - Tone.wav")).unwrap()).unwrap();
+    let loudness = track.loudness.unwrap();
+    assert_eq!(0.5, loudness.peak);
+    assert!(loudness.lufs < -3.0 && loudness.lufs > -12.0, "{}", loudness.lufs);
 }
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
-use crate::rekordbox::{Artist};
+use crate::rekordbox::{Artist, Track, TrackColor};
+use crate::library::MAX_RATING;
 
 type Database = Arc<crate::rekordbox::library::Database>;
 
@@ -12,6 +13,33 @@ pub fn number_of_tracks_by_artist(artist_id: u32, database: &Database) -> u32 {
     database.title_by_artist(artist_id).len() as u32
 }
 
+/// Star ratings that have tracks, with the number of tracks for each.
+pub fn rating_groups(database: &Database) -> Vec<(u8, u32)> {
+    (0..=MAX_RATING)
+        .map(|rating| (rating, database.title_by_rating(rating).len() as u32))
+        .filter(|(_rating, count)| *count > 0)
+        .collect()
+}
+
+/// Color labels that have tracks, with the number of tracks for each.
+pub fn color_groups(database: &Database) -> Vec<(TrackColor, u32)> {
+    TrackColor::ALL.iter()
+        .map(|color| (*color, database.title_by_color(*color).len() as u32))
+        .filter(|(_color, count)| *count > 0)
+        .collect()
+}
+
+/// Tracks of a history session that are still in the library.
+pub fn history_tracks(session_id: u32, database: &Database) -> Vec<Track> {
+    match database.history_session(session_id) {
+        Some(session) => session.tracks().iter()
+            .filter_map(|play| database.track_id_by_path(&play.path))
+            .filter_map(|track_id| database.get_track(track_id))
+            .collect(),
+        None => vec![],
+    }
+}
+
 pub fn find_artist(artist_id: u32, database: &Database) -> Option<Artist> {
     database.get_artist(artist_id)
 }
@@ -0,0 +1,232 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::library::{path_from_field, path_to_field, Store};
+
+/// Store table with every track loaded or played on a player.
+pub const HISTORY_TABLE: &str = "history";
+
+/// Plays further apart than this start a new session.
+const SESSION_GAP_SECONDS: u64 = 6 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayEvent {
+    /// A player loaded the track.
+    Loaded,
+    /// The mixer brought the channel of the player holding the track on air.
+    OnAir,
+}
+
+impl PlayEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlayEvent::Loaded => "loaded",
+            PlayEvent::OnAir => "on-air",
+        }
+    }
+}
+
+/// A single entry of the play history, artist and title are kept so the
+/// history stays readable after the track left the library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Play {
+    /// Seconds since the unix epoch.
+    pub played_at: u64,
+    pub player_number: u8,
+    pub event: PlayEvent,
+    pub path: PathBuf,
+    pub artist: String,
+    pub title: String,
+}
+
+impl Play {
+    pub fn to_record(&self) -> Vec<String> {
+        vec![
+            self.played_at.to_string(),
+            self.player_number.to_string(),
+            self.event.as_str().to_string(),
+            path_to_field(&self.path),
+            self.artist.clone(),
+            self.title.clone(),
+        ]
+    }
+
+    pub fn from_record(record: &[String]) -> Option<Play> {
+        match record {
+            [played_at, player_number, event, path, artist, title] => Some(Play {
+                played_at: played_at.parse().ok()?,
+                player_number: player_number.parse().ok()?,
+                event: match event.as_str() {
+                    "loaded" => PlayEvent::Loaded,
+                    "on-air" => PlayEvent::OnAir,
+                    _ => return None,
+                },
+                path: path_from_field(path),
+                artist: artist.clone(),
+                title: title.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Plays without long breaks in between, named after the date they started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    /// Start of the session, which stays the same while plays are added.
+    pub id: u32,
+    pub name: String,
+    pub plays: Vec<Play>,
+}
+
+impl Session {
+    /// The tracks played in the session. Bringing a freshly loaded track on
+    /// air is the same play, so it is only listed once.
+    pub fn tracks(&self) -> Vec<&Play> {
+        let mut tracks: Vec<&Play> = vec![];
+
+        for play in &self.plays {
+            let previous = tracks.iter().rev()
+                .find(|previous| previous.player_number == play.player_number);
+            let repeated = match previous {
+                Some(previous) => play.event == PlayEvent::OnAir && previous.path == play.path,
+                None => false,
+            };
+
+            if !repeated {
+                tracks.push(play);
+            }
+        }
+
+        tracks
+    }
+}
+
+/// All plays in the store, oldest first.
+pub fn read_history(store: &Store) -> io::Result<Vec<Play>> {
+    let mut plays: Vec<Play> = store.read_table(HISTORY_TABLE)?.iter()
+        .filter_map(|record| Play::from_record(record))
+        .collect();
+    plays.sort_by_key(|play| play.played_at);
+
+    Ok(plays)
+}
+
+/// Civil date (UTC) of a unix timestamp, as "YYYY-MM-DD".
+pub fn format_date(unix_seconds: u64) -> String {
+    // Howard Hinnant's days to civil algorithm
+    let days = (unix_seconds / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Date and time (UTC) of a unix timestamp, as "YYYY-MM-DD HH:MM:SS".
+pub fn format_timestamp(unix_seconds: u64) -> String {
+    let seconds = unix_seconds % 86400;
+
+    format!(
+        "{} {:02}:{:02}:{:02}",
+        format_date(unix_seconds),
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+/// Group plays, ordered by time, into sessions. Sessions starting on the
+/// same date are numbered so they can be told apart on the player.
+pub fn sessions(plays: &[Play]) -> Vec<Session> {
+    let mut sessions: Vec<Session> = vec![];
+
+    for play in plays {
+        let continues = sessions.last()
+            .and_then(|session| session.plays.last())
+            .map(|last| play.played_at.saturating_sub(last.played_at) < SESSION_GAP_SECONDS)
+            .unwrap_or(false);
+
+        if continues {
+            sessions.last_mut().unwrap().plays.push(play.clone());
+            continue;
+        }
+
+        let date = format_date(play.played_at);
+        let same_date = sessions.iter()
+            .filter(|session| session.name.starts_with(&date))
+            .count();
+        let name = match same_date {
+            0 => date,
+            _ => format!("{} ({})", date, same_date + 1),
+        };
+
+        sessions.push(Session {
+            id: play.played_at as u32,
+            name,
+            plays: vec![play.clone()],
+        });
+    }
+
+    sessions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn play(played_at: u64, player_number: u8, event: PlayEvent, name: &str) -> Play {
+        Play {
+            played_at,
+            player_number,
+            event,
+            path: PathBuf::from(format!("/music/{}.mp3", name)),
+            artist: String::from("Artist"),
+            title: String::from(name),
+        }
+    }
+
+    #[test]
+    fn it_formats_dates() {
+        assert_eq!("1970-01-01", format_date(0));
+        assert_eq!("2020-02-29", format_date(1582934400));
+        assert_eq!("2026-10-19", format_date(1792368000));
+        assert_eq!("2020-02-29 22:05:09", format_timestamp(1583013909));
+    }
+
+    #[test]
+    fn it_groups_plays_into_sessions() {
+        // 2020-02-29 22:00, a set running past midnight and a morning session the day after
+        let start = 1583013600;
+        let plays = vec![
+            play(start, 1, PlayEvent::Loaded, "One"),
+            play(start + 60, 1, PlayEvent::OnAir, "One"),
+            play(start + 300, 2, PlayEvent::OnAir, "Two"),
+            play(start + 3 * 3600, 1, PlayEvent::Loaded, "Three"),
+            play(start + 12 * 3600, 1, PlayEvent::OnAir, "Four"),
+        ];
+
+        let sessions = sessions(&plays);
+        assert_eq!(
+            vec!["2020-02-29", "2020-03-01"],
+            sessions.iter().map(|session| session.name.as_str()).collect::<Vec<&str>>(),
+        );
+        assert_eq!(
+            vec!["One", "Two", "Three"],
+            sessions[0].tracks().iter().map(|play| play.title.as_str()).collect::<Vec<&str>>(),
+        );
+        assert_eq!(start as u32, sessions[0].id);
+    }
+
+    #[test]
+    fn it_round_trips_store_records() {
+        let play = play(1583013600, 3, PlayEvent::OnAir, "Tab\tTitle");
+        assert_eq!(Some(play.clone()), Play::from_record(&play.to_record()));
+    }
+}
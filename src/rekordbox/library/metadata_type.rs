@@ -1,3 +1,5 @@
+use crate::rekordbox::TrackColor;
+
 pub type MetadataType = u32;
 
 pub const MOUNT_PATH: MetadataType = 0x00000000;
@@ -13,6 +15,15 @@ pub const BPM: MetadataType = 0x0000000d;
 pub const LABEL: MetadataType = 0x0000000e;
 pub const KEY: MetadataType = 0x0000000f;
 pub const COLOR_NONE: MetadataType = 0x00000013;
+pub const COLOR_PINK: MetadataType = 0x00000014;
+pub const COLOR_RED: MetadataType = 0x00000015;
+pub const COLOR_ORANGE: MetadataType = 0x00000016;
+pub const COLOR_YELLOW: MetadataType = 0x00000017;
+pub const COLOR_GREEN: MetadataType = 0x00000018;
+pub const COLOR_AQUA: MetadataType = 0x00000019;
+pub const COLOR_BLUE: MetadataType = 0x0000001a;
+pub const COLOR_PURPLE: MetadataType = 0x0000001b;
+pub const HISTORY_PLAYLIST: MetadataType = 0x00000024;
 pub const UNKNOWN1: MetadataType = 0x0000002f;
 
 pub const COMMENT: MetadataType = 0x00000023;
@@ -22,6 +33,7 @@ pub const ROOT_TRACK: MetadataType = 0x00000083;
 pub const ROOT_PLAYLIST: MetadataType = 0x00000084;
 pub const ROOT_RATING: MetadataType = 0x00000086;
 pub const ROOT_KEY: MetadataType = 0x0000008b;
+pub const ROOT_COLOR: MetadataType = 0x0000008e;
 pub const ROOT_FOLDER: MetadataType = 0x00000090;
 pub const ROOT_SEARCH: MetadataType = 0x00000091;
 pub const ROOT_HISTORY: MetadataType = 0x00000095;
@@ -40,6 +52,15 @@ pub enum ArgumentType {
     Label,
     Key,
     ColorNone,
+    ColorPink,
+    ColorRed,
+    ColorOrange,
+    ColorYellow,
+    ColorGreen,
+    ColorAqua,
+    ColorBlue,
+    ColorPurple,
+    HistoryPlaylist,
     Unknown1,
     Comment,
     RootArtist,
@@ -48,7 +69,13 @@ pub enum ArgumentType {
     RootPlaylist,
     RootRating,
     RootKey,
+    RootColor,
     RootFolder,
     RootSearch,
     RootHistory,
 }
+
+/// Menu item type for a color label, the colors follow COLOR_NONE in player order.
+pub fn color_type(color: TrackColor) -> MetadataType {
+    COLOR_NONE + color.id() as MetadataType
+}
@@ -1,26 +1,141 @@
 use std::path::PathBuf;
 
+use crate::library::{Loudness, TrackAnalysis};
+
 #[derive(Debug)]
 pub struct Metadata {
     pub artist: String,
     pub title: String,
     pub bpm: Option<u32>,
     pub album: String,
+    /// Stars from 0, unrated, to 5.
+    pub rating: u8,
+    pub genre: String,
+    pub comment: String,
+    /// Camelot notation, "8A", when the key could be read.
+    pub key: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct MetadataTrack {
     pub metadata: Metadata,
     pub path: PathBuf,
-    pub size: u32,
+    pub size: u64,
+    pub cues: Vec<Cue>,
+    pub cue_source: CueSource,
+    pub color: Option<TrackColor>,
+    /// Beat grid and waveforms when the track comes with an analysis, as on rekordbox exports.
+    pub analysis: Option<TrackAnalysis>,
+    /// From ReplayGain tags.
+    pub loudness: Option<Loudness>,
 }
 
 impl MetadataTrack {
-    pub fn new(metadata: Metadata, path: PathBuf, size: u32) -> Self {
+    pub fn new(metadata: Metadata, path: PathBuf, size: u64) -> Self {
         Self {
             metadata,
             path,
             size,
+            cues: vec![],
+            cue_source: CueSource::Tags,
+            color: None,
+            analysis: None,
+            loudness: None,
+        }
+    }
+}
+
+/// Whether a cue is a memory point or sits on one of the hot cue pads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CueKind {
+    Memory,
+    /// Hot cue pad, 1 is pad A.
+    Hot(u8),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub kind: CueKind,
+    pub position_ms: u32,
+    /// End of the loop when the cue is a loop.
+    pub loop_end_ms: Option<u32>,
+}
+
+impl Cue {
+    pub fn new(kind: CueKind, position_ms: u32, loop_end_ms: Option<u32>) -> Self {
+        Self {
+            kind,
+            position_ms,
+            loop_end_ms,
         }
     }
 }
+
+/// Where cues of a track came from, later sources take precedence over earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum CueSource {
+    Tags,
+    Import,
+    Sidecar,
+}
+
+/// Color label of a track, in the order players number them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackColor {
+    None,
+    Pink,
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Aqua,
+    Blue,
+    Purple,
+}
+
+impl TrackColor {
+    pub const ALL: [TrackColor; 9] = [
+        TrackColor::None,
+        TrackColor::Pink,
+        TrackColor::Red,
+        TrackColor::Orange,
+        TrackColor::Yellow,
+        TrackColor::Green,
+        TrackColor::Aqua,
+        TrackColor::Blue,
+        TrackColor::Purple,
+    ];
+
+    /// Color id used by players, 0 for no color and 1 to 8 for the colors.
+    pub fn id(&self) -> u8 {
+        TrackColor::ALL.iter().position(|color| color == self).unwrap_or(0) as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        TrackColor::ALL.get(id as usize).cloned()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrackColor::None => "No Color",
+            TrackColor::Pink => "Pink",
+            TrackColor::Red => "Red",
+            TrackColor::Orange => "Orange",
+            TrackColor::Yellow => "Yellow",
+            TrackColor::Green => "Green",
+            TrackColor::Aqua => "Aqua",
+            TrackColor::Blue => "Blue",
+            TrackColor::Purple => "Purple",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("none") {
+            return Some(TrackColor::None);
+        }
+
+        TrackColor::ALL.iter()
+            .find(|color| color.name().eq_ignore_ascii_case(name))
+            .cloned()
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use super::smart_playlist::SmartPlaylist;
+
+/// Store table with the folders and playlists made from the command line or library API.
+pub const PLAYLISTS_TABLE: &str = "playlists";
+
+/// Store table with the tracks of those playlists, in playlist order.
+pub const PLAYLIST_TRACKS_TABLE: &str = "playlist_tracks";
+
+/// Id of the folder every playlist tree starts at.
+pub const ROOT_FOLDER: u32 = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaylistSource {
+    Folder,
+    /// Tracks added from the command line or library API.
+    Tracks(Vec<PathBuf>),
+    /// Tracks listed in a playlist file in one of the library folders.
+    File(Vec<PathBuf>),
+    /// Tracks matching the rules, evaluated whenever the playlist is opened.
+    Smart(SmartPlaylist),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playlist {
+    pub id: u32,
+    /// Folder holding the playlist, `ROOT_FOLDER` at the top.
+    pub parent_id: u32,
+    pub name: String,
+    pub source: PlaylistSource,
+}
+
+impl Playlist {
+    pub fn is_folder(&self) -> bool {
+        self.source == PlaylistSource::Folder
+    }
+
+    /// Folders and track playlists can be edited and are kept in the store,
+    /// file and smart playlists follow their definitions.
+    pub fn is_editable(&self) -> bool {
+        match self.source {
+            PlaylistSource::Folder | PlaylistSource::Tracks(_) => true,
+            PlaylistSource::File(_) | PlaylistSource::Smart(_) => false,
+        }
+    }
+
+    pub fn to_record(&self) -> Vec<String> {
+        let kind = match self.source {
+            PlaylistSource::Folder => "folder",
+            _ => "playlist",
+        };
+
+        vec![
+            self.id.to_string(),
+            self.parent_id.to_string(),
+            kind.to_string(),
+            self.name.clone(),
+        ]
+    }
+
+    /// A playlist without tracks, they are kept in a table of their own.
+    pub fn from_record(record: &[String]) -> Option<Playlist> {
+        match record {
+            [id, parent_id, kind, name] => Some(Playlist {
+                id: id.parse().ok()?,
+                parent_id: parent_id.parse().ok()?,
+                name: name.clone(),
+                source: match kind.as_str() {
+                    "folder" => PlaylistSource::Folder,
+                    "playlist" => PlaylistSource::Tracks(vec![]),
+                    _ => return None,
+                },
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_round_trips_store_records() {
+        let folder = Playlist {
+            id: 3,
+            parent_id: ROOT_FOLDER,
+            name: String::from("Gigs\t2020"),
+            source: PlaylistSource::Folder,
+        };
+        assert_eq!(Some(folder.clone()), Playlist::from_record(&folder.to_record()));
+
+        let playlist = Playlist {
+            id: 4,
+            parent_id: 3,
+            name: String::from("Friday"),
+            source: PlaylistSource::Tracks(vec![]),
+        };
+        assert_eq!(Some(playlist.clone()), Playlist::from_record(&playlist.to_record()));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::library::compatible_keys;
+use super::database::Track;
+
+/// Tempo difference in percent that still counts as mixable.
+pub const TEMPO_TOLERANCE: u32 = 6;
+
+const KEY_SCORE: u32 = 3;
+const TEMPO_SCORE: u32 = 3;
+const ARTIST_SCORE: u32 = 2;
+const GENRE_SCORE: u32 = 1;
+
+fn similar_tempo(a: Option<u32>, b: Option<u32>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) if a > 0 => (a as i64 - b as i64).unsigned_abs() * 100 <= a as u64 * TEMPO_TOLERANCE as u64,
+        _ => false,
+    }
+}
+
+/// How well a candidate fits a track. A key that mixes and a close tempo
+/// weigh more than the same artist, the genre counts least, 0 is no match.
+pub fn similarity(track: &Track, candidate: &Track) -> u32 {
+    let mut score = 0;
+    if let (Some(key), Some(candidate_key)) = (&track.key, &candidate.key) {
+        if compatible_keys(key, candidate_key) {
+            score += KEY_SCORE;
+        }
+    }
+    if similar_tempo(track.bpm, candidate.bpm) {
+        score += TEMPO_SCORE;
+    }
+    if track.artist_id == candidate.artist_id {
+        score += ARTIST_SCORE;
+    }
+    if !track.genre.is_empty() && track.genre.eq_ignore_ascii_case(&candidate.genre) {
+        score += GENRE_SCORE;
+    }
+
+    score
+}
+
+/// Distance of a candidate's tempo to the track's, for ordering equal scores.
+pub fn tempo_distance(track: &Track, candidate: &Track) -> u32 {
+    match (track.bpm, candidate.bpm) {
+        (Some(a), Some(b)) => (a as i64 - b as i64).unsigned_abs() as u32,
+        _ => u32::MAX,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_accepts_tempos_within_the_tolerance() {
+        assert!(similar_tempo(Some(12000), Some(12700)));
+        assert!(similar_tempo(Some(12000), Some(11300)));
+        assert!(!similar_tempo(Some(12000), Some(12800)));
+        assert!(!similar_tempo(Some(12000), None));
+    }
+}
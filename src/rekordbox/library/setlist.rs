@@ -0,0 +1,214 @@
+use super::history::{format_timestamp, Play, Session};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetlistFormat {
+    Csv,
+    Tracklist,
+    CueSheet,
+    M3u,
+}
+
+impl SetlistFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(SetlistFormat::Csv),
+            "tracklist" => Some(SetlistFormat::Tracklist),
+            "cue" => Some(SetlistFormat::CueSheet),
+            "m3u" => Some(SetlistFormat::M3u),
+            _ => None,
+        }
+    }
+}
+
+pub struct SetlistOptions {
+    /// Audio file the CUE sheet refers to.
+    pub recording: String,
+    /// Seconds the recording was running when the first track of the session played.
+    pub recording_offset: u64,
+}
+
+impl Default for SetlistOptions {
+    fn default() -> Self {
+        Self {
+            recording: String::from("recording.wav"),
+            recording_offset: 0,
+        }
+    }
+}
+
+/// Time since the start of the session as "H:MM:SS".
+fn format_elapsed(seconds: u64) -> String {
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// CUE sheets quote strings without any way to escape a quote.
+fn cue_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "'"))
+}
+
+fn display_name(play: &Play) -> String {
+    match play.artist.is_empty() {
+        true => play.title.clone(),
+        false => format!("{} - {}", play.artist, play.title),
+    }
+}
+
+fn render_csv(tracks: &[&Play], start: u64) -> String {
+    let mut output = String::from("played_at,elapsed,deck,event,artist,title,path\n");
+
+    for play in tracks {
+        let fields = [
+            format_timestamp(play.played_at),
+            format_elapsed(play.played_at - start),
+            play.player_number.to_string(),
+            play.event.as_str().to_string(),
+            play.artist.clone(),
+            play.title.clone(),
+            play.path.to_string_lossy().into_owned(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        output.push_str(&fields.join(","));
+        output.push('\n');
+    }
+
+    output
+}
+
+fn render_tracklist(session: &Session, tracks: &[&Play], start: u64) -> String {
+    let mut output = format!("{}\n\n", session.name);
+
+    for (index, play) in tracks.iter().enumerate() {
+        output.push_str(&format!(
+            "{:>2}. {} [Deck {}] {}\n",
+            index + 1,
+            format_elapsed(play.played_at - start),
+            play.player_number,
+            display_name(play),
+        ));
+    }
+
+    output
+}
+
+fn render_cue_sheet(session: &Session, tracks: &[&Play], start: u64, options: &SetlistOptions) -> String {
+    let mut output = format!(
+        "TITLE {}\nFILE {} WAVE\n",
+        cue_string(&session.name),
+        cue_string(&options.recording),
+    );
+
+    // Track numbers only go up to 99 in a CUE sheet
+    for (index, play) in tracks.iter().take(99).enumerate() {
+        let position = play.played_at - start + options.recording_offset;
+        output.push_str(&format!("  TRACK {:02} AUDIO\n", index + 1));
+        output.push_str(&format!("    TITLE {}\n", cue_string(&play.title)));
+        output.push_str(&format!("    PERFORMER {}\n", cue_string(&play.artist)));
+        output.push_str(&format!("    INDEX 01 {:02}:{:02}:00\n", position / 60, position % 60));
+    }
+
+    output
+}
+
+fn render_m3u(tracks: &[&Play]) -> String {
+    let mut output = String::from("#EXTM3U\n");
+
+    for play in tracks {
+        output.push_str(&format!("#EXTINF:-1,{}\n", display_name(play)));
+        output.push_str(&format!("{}\n", play.path.to_string_lossy()));
+    }
+
+    output
+}
+
+/// Render the tracks of a session, timed from its first play.
+pub fn render_setlist(session: &Session, format: SetlistFormat, options: &SetlistOptions) -> String {
+    let tracks = session.tracks();
+    let start = tracks.first().map(|play| play.played_at).unwrap_or(0);
+
+    match format {
+        SetlistFormat::Csv => render_csv(&tracks, start),
+        SetlistFormat::Tracklist => render_tracklist(session, &tracks, start),
+        SetlistFormat::CueSheet => render_cue_sheet(session, &tracks, start, options),
+        SetlistFormat::M3u => render_m3u(&tracks),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::history::PlayEvent;
+    use std::path::PathBuf;
+    use pretty_assertions::assert_eq;
+
+    fn session() -> Session {
+        let play = |played_at: u64, player_number: u8, artist: &str, title: &str| Play {
+            played_at,
+            player_number,
+            event: PlayEvent::OnAir,
+            path: PathBuf::from(format!("/music/{}.mp3", title)),
+            artist: artist.to_string(),
+            title: title.to_string(),
+        };
+
+        Session {
+            id: 1583013600,
+            name: String::from("2020-02-29"),
+            plays: vec![
+                play(1583013600, 1, "Artist", "One"),
+                play(1583013600 + 245, 2, "Other, Artist", "Two \"Dub\""),
+            ],
+        }
+    }
+
+    #[test]
+    fn it_renders_csv() {
+        assert_eq!(
+            "played_at,elapsed,deck,event,artist,title,path\n\
+             2020-02-29 22:00:00,0:00:00,1,on-air,Artist,One,/music/One.mp3\n\
+             2020-02-29 22:04:05,0:04:05,2,on-air,\"Other, Artist\",\"Two \"\"Dub\"\"\",\"/music/Two \"\"Dub\"\".mp3\"\n",
+            render_setlist(&session(), SetlistFormat::Csv, &SetlistOptions::default()),
+        );
+    }
+
+    #[test]
+    fn it_renders_tracklists() {
+        assert_eq!(
+            "2020-02-29\n\n \
+             1. 0:00:00 [Deck 1] Artist - One\n \
+             2. 0:04:05 [Deck 2] Other, Artist - Two \"Dub\"\n",
+            render_setlist(&session(), SetlistFormat::Tracklist, &SetlistOptions::default()),
+        );
+    }
+
+    #[test]
+    fn it_renders_cue_sheets_aligned_to_the_recording() {
+        let options = SetlistOptions {
+            recording: String::from("set.flac"),
+            recording_offset: 30,
+        };
+
+        assert_eq!(
+            "TITLE \"2020-02-29\"\nFILE \"set.flac\" WAVE\n\
+             \x20 TRACK 01 AUDIO\n    TITLE \"One\"\n    PERFORMER \"Artist\"\n    INDEX 01 00:30:00\n\
+             \x20 TRACK 02 AUDIO\n    TITLE \"Two 'Dub'\"\n    PERFORMER \"Other, Artist\"\n    INDEX 01 04:35:00\n",
+            render_setlist(&session(), SetlistFormat::CueSheet, &options),
+        );
+    }
+
+    #[test]
+    fn it_renders_m3u_playlists() {
+        assert_eq!(
+            "#EXTM3U\n#EXTINF:-1,Artist - One\n/music/One.mp3\n\
+             #EXTINF:-1,Other, Artist - Two \"Dub\"\n/music/Two \"Dub\".mp3\n",
+            render_setlist(&session(), SetlistFormat::M3u, &SetlistOptions::default()),
+        );
+    }
+}
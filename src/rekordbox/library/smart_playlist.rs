@@ -0,0 +1,258 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::rekordbox::{Record, Track, TrackColor};
+use crate::library::camelot_key;
+
+/// Track fields a rule can test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Bpm,
+    Rating,
+    Key,
+    Genre,
+    Artist,
+    Title,
+    Color,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bpm" => Some(Field::Bpm),
+            "rating" => Some(Field::Rating),
+            "key" => Some(Field::Key),
+            "genre" => Some(Field::Genre),
+            "artist" => Some(Field::Artist),
+            "title" => Some(Field::Title),
+            "color" => Some(Field::Color),
+            _ => None,
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        *self == Field::Bpm || *self == Field::Rating
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// Equal to one of the values, ignoring case.
+    OneOf(Vec<String>),
+    Contains(String),
+    /// Inclusive range.
+    Between(f64, f64),
+    Above(f64),
+    Below(f64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub field: Field,
+    pub condition: Condition,
+}
+
+/// A playlist holding every track that matches all of its rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmartPlaylist {
+    pub name: String,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SmartPlaylistError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SmartPlaylistError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Rule {
+    fn text<'a>(&self, track: &'a Track, artist: &'a str) -> Option<&'a str> {
+        match self.field {
+            Field::Key => track.key.as_deref(),
+            Field::Genre => Some(&track.genre),
+            Field::Artist => Some(artist),
+            Field::Title => Some(track.name()),
+            Field::Color => Some(track.color.unwrap_or(TrackColor::None).name()),
+            Field::Bpm | Field::Rating => None,
+        }
+    }
+
+    fn number(&self, track: &Track) -> Option<f64> {
+        match self.field {
+            Field::Bpm => track.bpm.map(|bpm| bpm as f64 / 100.0),
+            Field::Rating => Some(track.rating as f64),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, track: &Track, artist: &str) -> bool {
+        match &self.condition {
+            Condition::OneOf(values) => self.text(track, artist)
+                .map(|text| values.iter().any(|value| value.eq_ignore_ascii_case(text)))
+                .unwrap_or(false),
+            Condition::Contains(value) => self.text(track, artist)
+                .map(|text| text.to_lowercase().contains(value.as_str()))
+                .unwrap_or(false),
+            Condition::Between(low, high) => self.number(track)
+                .map(|number| number >= *low && number <= *high)
+                .unwrap_or(false),
+            Condition::Above(low) => self.number(track)
+                .map(|number| number > *low)
+                .unwrap_or(false),
+            Condition::Below(high) => self.number(track)
+                .map(|number| number < *high)
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl SmartPlaylist {
+    pub fn matches(&self, track: &Track, artist: &str) -> bool {
+        self.rules.iter().all(|rule| rule.matches(track, artist))
+    }
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    value.trim().parse::<f64>().map_err(|_| format!("\"{}\" is not a number", value.trim()))
+}
+
+/// Values of an is or in rule, keys are compared in Camelot notation and colors by name.
+fn parse_values(field: Field, value: &str) -> Result<Vec<String>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| match field {
+            Field::Key => camelot_key(value).ok_or_else(|| format!("\"{}\" is not a key", value)),
+            Field::Color => TrackColor::from_name(value)
+                .map(|color| color.name().to_string())
+                .ok_or_else(|| format!("\"{}\" is not a color", value)),
+            _ => Ok(value.to_string()),
+        })
+        .collect()
+}
+
+fn parse_condition(field: Field, operator: &str, value: &str) -> Result<Condition, String> {
+    match (operator, field.is_numeric()) {
+        ("is", true) => parse_number(value).map(|number| Condition::Between(number, number)),
+        ("is", false) | ("in", false) => parse_values(field, value).map(Condition::OneOf),
+        ("contains", false) => Ok(Condition::Contains(value.to_lowercase())),
+        ("between", true) => {
+            let mut bounds = value.splitn(2, '-');
+            let low = parse_number(bounds.next().unwrap_or(""))?;
+            let high = parse_number(bounds.next().ok_or("between needs a range like 122-128")?)?;
+            Ok(Condition::Between(low, high))
+        },
+        (">=", true) => parse_number(value).map(|number| Condition::Between(number, f64::MAX)),
+        ("<=", true) => parse_number(value).map(|number| Condition::Between(f64::MIN, number)),
+        (">", true) => parse_number(value).map(Condition::Above),
+        ("<", true) => parse_number(value).map(Condition::Below),
+        _ => Err(format!("\"{}\" can not be used on this field", operator)),
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let mut words = line.splitn(3, char::is_whitespace);
+    let name = words.next().unwrap_or("");
+    let field = Field::from_name(&name.to_lowercase())
+        .ok_or_else(|| format!("Unknown field \"{}\"", name))?;
+    let operator = words.next().ok_or("Missing operator")?;
+    let value = words.next().map(str::trim).unwrap_or("");
+    if value.is_empty() {
+        return Err(String::from("Missing value"));
+    }
+
+    Ok(Rule {
+        field,
+        condition: parse_condition(field, &operator.to_lowercase(), value)?,
+    })
+}
+
+/// Parse smart playlist definitions. Every playlist starts with its name in
+/// brackets followed by one rule per line, e.g. "bpm between 122-128",
+/// "key in 8A, 9A", "genre is Techno" or "rating >= 4". Lines starting
+/// with # are comments.
+pub fn parse_smart_playlists(document: &str) -> Result<Vec<SmartPlaylist>, SmartPlaylistError> {
+    let mut playlists: Vec<SmartPlaylist> = vec![];
+
+    for (index, line) in document.lines().enumerate() {
+        let line = line.trim();
+        let error = |message: String| SmartPlaylistError { line: index + 1, message };
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            let name = line.strip_prefix('[').and_then(|line| line.strip_suffix(']'))
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| error(String::from("Playlist names look like [Name]")))?;
+            playlists.push(SmartPlaylist {
+                name: name.to_string(),
+                rules: vec![],
+            });
+            continue;
+        }
+
+        let rule = parse_rule(line).map_err(error)?;
+        match playlists.last_mut() {
+            Some(playlist) => playlist.rules.push(rule),
+            None => return Err(error(String::from("Rule outside of a playlist"))),
+        }
+    }
+
+    Ok(playlists)
+}
+
+pub fn read_smart_playlists<T: AsRef<Path>>(path: T) -> io::Result<Vec<SmartPlaylist>> {
+    let document = fs::read_to_string(path)?;
+
+    parse_smart_playlists(&document)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_parses_smart_playlists() {
+        let document = "# Sets\n[Peak time]\nbpm between 122-128\nkey in 8A, Em\ngenre is Techno\nrating >= 4\n\n[Warm up]\ntitle contains Dub\n";
+
+        assert_eq!(Ok(vec![
+            SmartPlaylist {
+                name: String::from("Peak time"),
+                rules: vec![
+                    Rule { field: Field::Bpm, condition: Condition::Between(122.0, 128.0) },
+                    Rule { field: Field::Key, condition: Condition::OneOf(vec![String::from("8A"), String::from("9A")]) },
+                    Rule { field: Field::Genre, condition: Condition::OneOf(vec![String::from("Techno")]) },
+                    Rule { field: Field::Rating, condition: Condition::Between(4.0, f64::MAX) },
+                ],
+            },
+            SmartPlaylist {
+                name: String::from("Warm up"),
+                rules: vec![
+                    Rule { field: Field::Title, condition: Condition::Contains(String::from("dub")) },
+                ],
+            },
+        ]), parse_smart_playlists(document));
+    }
+
+    #[test]
+    fn it_reports_the_line_of_invalid_rules() {
+        assert_eq!(
+            Err(SmartPlaylistError { line: 3, message: String::from("\"contains\" can not be used on this field") }),
+            parse_smart_playlists("[Fast]\nbpm > 130\nbpm contains 1\n"),
+        );
+        assert_eq!(3, parse_smart_playlists("[Keys]\n\nkey is X\n").unwrap_err().line);
+    }
+}
@@ -23,6 +23,7 @@ mod db_message_argument;
 mod rpc;
 mod status_event_server;
 mod keepalive;
+mod export;
 
 // tests
 #[cfg(test)]
@@ -38,6 +39,10 @@ pub use server::ApplicationEvent as Event;
 use rpc::server as rpc_server;
 use library::DBLibraryServer;
 pub use packets::DBMessage;
-pub use library::model::{MetadataTrack, Metadata};
+pub use library::model::{MetadataTrack, Metadata, Cue, CueKind, CueSource, TrackColor};
 pub use library::database::{Track, Artist, Record};
-pub use library::database::Database;
+pub use library::database::{Database, DatabaseError, DatabaseOptions, DuplicatePolicy, MAX_WIRE_FILE_SIZE};
+pub use library::playlist::{PlaylistSource, ROOT_FOLDER};
+pub use library::history::{read_history, sessions, Session};
+pub use library::setlist::{render_setlist, SetlistFormat, SetlistOptions};
+pub use export::export_device;
@@ -353,6 +353,10 @@ impl StatusPacket {
     pub fn kind(&self) -> &StatusPacketType {
         &self.kind
     }
+
+    pub fn content(&self) -> &StatusContentType {
+        &self.content
+    }
 }
 
 impl From<StatusPacket> for Bytes {
@@ -523,14 +527,40 @@ impl Decode for TrackAnalyzeType {
     }
 }
 
+/// Bit of the state flags set while the mixer has the channel of the player on air.
+const CDJ_ON_AIR_FLAG: u8 = 0x08;
+
+/// Offset of the state flags from the start of the CDJ status content.
+const CDJ_FLAGS_OFFSET: usize = 0x67;
+
 #[derive(Debug, PartialEq)]
 pub struct Cdj {
-    activity: u16,
-    loaded_player_number: u16,
+    activity: u8,
+    loaded_player_number: u8,
     loaded_slot: PlayerSlot,
     track_analyze_type: TrackAnalyzeType,
     track_id: u32,
     track_number: u32,
+    flags: u8,
+}
+
+impl Cdj {
+    /// Player the loaded track was loaded from, 17 for tracks served by us.
+    pub fn loaded_player_number(&self) -> u8 {
+        self.loaded_player_number
+    }
+
+    pub fn loaded_slot(&self) -> &PlayerSlot {
+        &self.loaded_slot
+    }
+
+    pub fn track_id(&self) -> u32 {
+        self.track_id
+    }
+
+    pub fn is_on_air(&self) -> bool {
+        self.flags & CDJ_ON_AIR_FLAG != 0
+    }
 }
 
 trait Decode {
@@ -542,15 +572,20 @@ trait Decode {
 impl Decode for Cdj {
     type Item = StatusContentType;
 
-    fn decode(input: &[u8]) -> IResult<&[u8], Self::Item> {
-        let (input, activity) = be_u16(input)?;
-        let (input, loaded_player_number) = be_u16(input)?;
+    fn decode(content: &[u8]) -> IResult<&[u8], Self::Item> {
+        // Length, player number and two unknown bytes
+        let (input, _header) = take(5u8)(content)?;
+        let (input, activity) = be_u8(input)?;
+        let (input, loaded_player_number) = be_u8(input)?;
         let (input, loaded_slot) = PlayerSlot::decode(input)?;
         let (input, track_analyze_type) = TrackAnalyzeType::decode(input)?;
         let (input, _padding) = take(1u8)(input)?;
         let (input, track_id) = be_u32(input)?;
         let (input, track_number) = be_u32(input)?;
 
+        // Older players send shorter packets without the state flags
+        let flags = content.get(CDJ_FLAGS_OFFSET).cloned().unwrap_or(0);
+
         Ok((
             input,
             StatusContentType::Cdj(Cdj {
@@ -560,6 +595,7 @@ impl Decode for Cdj {
                 track_analyze_type,
                 track_id,
                 track_number,
+                flags,
             })
         ))
     }
@@ -785,6 +821,30 @@ mod test {
         );
     }
 
+    #[test]
+    fn it_decodes_on_air_cdj_status() {
+        let mut packet = UDP_MAGIC.to_vec();
+        packet.push(0x0a);
+        packet.extend(b"CDJ-2000nexus\0\0\0\0\0\0\0");
+        packet.extend(&[0x01, 0x03, 0x02]);
+        packet.extend(vec![0u8; 0xd4 - packet.len()]);
+        packet[0x28] = 0x11;
+        packet[0x29] = 0x04;
+        packet[0x2c..0x30].copy_from_slice(&5u32.to_be_bytes());
+        packet[0x89] = 0x48;
+
+        let packet = StatusPacket::try_from(&packet[..]).unwrap();
+        match packet.content() {
+            StatusContentType::Cdj(cdj) => {
+                assert_eq!(0x11, cdj.loaded_player_number());
+                assert_eq!(&PlayerSlot::Rekordbox, cdj.loaded_slot());
+                assert_eq!(5, cdj.track_id());
+                assert_eq!(true, cdj.is_on_air());
+            },
+            content => panic!("Unexpected content {:?}", content),
+        }
+    }
+
     #[test]
     fn verify_title_request_parsing() {
         assert_eq!(
@@ -1,22 +1,33 @@
 use std::io::{Error, ErrorKind};
 use crate::rpc::events::{EventHandler as RpcEventHandler, RpcResult};
-use crate::rpc::PortmapServer;
+use crate::rpc::{HostAccess, NfsExport, PortmapServer};
 use crate::rpc::packets::*;
 use crate::rekordbox::ServerState;
 use std::net::{SocketAddr, IpAddr, Ipv4Addr};
 use std::sync::{Arc, Mutex};
+use ipnetwork::IpNetwork;
 
 struct Context<'a> {
     state: &'a Arc<Mutex<ServerState>>,
     call: &'a RpcCall,
 }
 
-pub async fn server(state_ref: Arc<Mutex<ServerState>>) -> Result<(), std::io::Error> {
+/// Serve the export to `allowed_hosts`, or to the network the players are found on when none are given.
+pub async fn server(
+    state_ref: Arc<Mutex<ServerState>>,
+    export: Arc<NfsExport>,
+    allowed_hosts: Vec<IpNetwork>,
+) -> Result<(), std::io::Error> {
     let portmap_server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 50111);
     let event_handler = EventHandler::new(state_ref.clone());
+    let players_state = state_ref.clone();
+    let access = HostAccess::new(allowed_hosts, move || {
+        let state = players_state.lock().ok()?;
+        state.address().as_ref().map(|address| IpNetwork::V4(address.network()))
+    });
 
     let join = tokio::task::spawn(async move {
-        let server = PortmapServer::new(portmap_server_addr);
+        let server = PortmapServer::new(portmap_server_addr, export, access);
         // Start RPC server
         dbg!("Starting portmap server");
         match server.run(Arc::new(event_handler)).await {
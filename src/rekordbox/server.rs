@@ -12,6 +12,8 @@ use crate::rekordbox::StatusEventServer;
 use crate::rekordbox::DBLibraryServer;
 use crate::rekordbox::rpc_server;
 use crate::rekordbox::Database;
+use crate::rpc::NfsExport;
+use ipnetwork::IpNetwork;
 use super::keepalive::{
     Event as KeepAliveEvent,
     KeepAliveContentType,
@@ -58,10 +60,12 @@ pub struct Server {
     state: Arc<Mutex<ServerState>>,
     tx: Sender<ApplicationEvent>,
     broadcast_sleep_time: Duration,
+    /// Hosts allowed to mount the library, the players' network when empty.
+    allowed_hosts: Vec<IpNetwork>,
 }
 
 impl Server {
-    pub fn new(database: Database, tx: Sender<ApplicationEvent>) -> Self {
+    pub fn new(database: Database, tx: Sender<ApplicationEvent>, allowed_hosts: Vec<IpNetwork>) -> Self {
         let state = Arc::new(Mutex::new(ServerState::default()));
         let database = Arc::new(database);
 
@@ -70,6 +74,7 @@ impl Server {
             state: state,
             tx: tx,
             broadcast_sleep_time: Duration::from_millis(50),
+            allowed_hosts,
         }
     }
 
@@ -78,11 +83,12 @@ impl Server {
 
         broadcast_sender_handler(&self.state);
         keepalive_server(&self.tx, &self.state);
-        let rpc_future = rpc_server(self.state.clone())
+        let export = Arc::new(NfsExport::new(self.database.roots(), self.database.store().cloned()));
+        let rpc_future = rpc_server(self.state.clone(), export.clone(), self.allowed_hosts.clone())
             .map_err(|_| "Unable to start RPC Server".to_string());
-        let db_library_future = DBLibraryServer::run(self.state.clone(), self.database.clone())
+        let db_library_future = DBLibraryServer::run(self.state.clone(), self.database.clone(), export)
             .map_err(|_| "Unable to start DBLibraryServer".to_string());
-        match status_event_server(&self.tx, &self.state) {
+        match status_event_server(&self.tx, &self.state, &self.database) {
             Err(err) => {
                 dbg!(err);
             },
@@ -158,11 +164,12 @@ impl Server {
 fn status_event_server(
     tx: &Sender<ApplicationEvent>,
     state: &Arc<Mutex<ServerState>>,
+    database: &Arc<Database>,
 ) -> Result<(), &'static str> {
     let _tx = tx.clone();
     let _state = state.clone();
 
-    let status_event_server = StatusEventServer::bind()?;
+    let status_event_server = StatusEventServer::bind(database.clone())?;
 
     thread::spawn(move || status_event_server.run());
 
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::net::{UdpSocket, SocketAddr};
 use std::sync::{Arc, Mutex};
@@ -14,32 +15,82 @@ use super::packets::{
     LinkReply,
     Utf16FixedString,
     PlayerSlot,
+    Cdj,
 };
+use super::library::database::Database;
+use super::library::history::PlayEvent;
 
-#[derive(Debug)]
 pub struct StatusEventServer {
     pub socket: Arc<Mutex<UdpSocket>>,
+    database: Arc<Database>,
+    /// Track id each player last reported loaded from us.
+    loaded: Mutex<HashMap<u8, u32>>,
+    /// Track id each player last reported on air with one of our tracks.
+    on_air: Mutex<HashMap<u8, u32>>,
 }
 
 const STATUS_EVENT_SERVER_PORT: u16 = 50002;
 
+/// Player number we announce ourselves with, players loading our tracks report it.
+const REKORDBOX_PLAYER_NUMBER: u8 = 17;
+
 impl StatusEventServer {
     ///
     /// Create a UdpSocket and bind it to port 50002
     /// for the StatusEventServer. This socket will both send and receive
     /// data.
-    pub fn bind() -> Result<Self, &'static str> {
+    pub fn bind(database: Arc<Database>) -> Result<Self, &'static str> {
         let socket = UdpSocket::bind(("0.0.0.0", STATUS_EVENT_SERVER_PORT))
             .expect("Failed to bind status event server socket");
 
         Ok(Self::new(
             Arc::new(Mutex::new(socket)),
+            database,
         ))
     }
 
-    pub fn new(socket: Arc<Mutex<UdpSocket>>) -> StatusEventServer {
+    pub fn new(socket: Arc<Mutex<UdpSocket>>, database: Arc<Database>) -> StatusEventServer {
         StatusEventServer {
             socket,
+            database,
+            loaded: Mutex::new(HashMap::new()),
+            on_air: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record plays to the history when a player loads one of our tracks and
+    /// when it goes on air with it.
+    fn track_status(&self, player_number: u8, cdj: &Cdj) {
+        let serving = cdj.loaded_slot() == &PlayerSlot::Rekordbox
+            && cdj.loaded_player_number() == REKORDBOX_PLAYER_NUMBER;
+        let loaded = Some(cdj.track_id()).filter(|_track_id| serving);
+        let on_air = loaded.filter(|_track_id| cdj.is_on_air());
+
+        self.track_change(&self.loaded, player_number, loaded, PlayEvent::Loaded);
+        self.track_change(&self.on_air, player_number, on_air, PlayEvent::OnAir);
+    }
+
+    /// Status arrives several times a second, so only changes count.
+    fn track_change(&self, tracks: &Mutex<HashMap<u8, u32>>, player_number: u8, track_id: Option<u32>, event: PlayEvent) {
+        let mut tracks = match tracks.lock() {
+            Ok(tracks) => tracks,
+            Err(_err) => return,
+        };
+        let previous = tracks.get(&player_number).cloned();
+        if previous == track_id {
+            return;
+        }
+
+        match track_id {
+            Some(track_id) => {
+                tracks.insert(player_number, track_id);
+                if let Err(err) = self.database.record_play(track_id, player_number, event) {
+                    eprintln!("Failed recording {:?} track; track_id = {}, error = {:?}", event, track_id, err);
+                }
+            },
+            None => {
+                tracks.remove(&player_number);
+            },
         }
     }
 
@@ -48,7 +99,8 @@ impl StatusEventServer {
     }
 
     fn process_packet(&self, packet: StatusPacket) -> Option<StatusPacket> {
-        if packet.kind() == &StatusPacketType::Cdj {
+        if let StatusContentType::Cdj(cdj) = packet.content() {
+            self.track_status(packet.player_number, cdj);
             return None;
         }
 
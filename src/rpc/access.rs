@@ -0,0 +1,59 @@
+use std::net::IpAddr;
+use ipnetwork::IpNetwork;
+
+/// Hosts allowed to call MOUNT and NFS. The machine itself always is, it can
+/// read the library anyway.
+pub struct HostAccess {
+    allowed: Vec<IpNetwork>,
+    /// Network to allow when no hosts are given, looked up on every call
+    /// since the players are found after the server starts.
+    discovered: Box<dyn Fn() -> Option<IpNetwork> + Send + Sync>,
+}
+
+impl Default for HostAccess {
+    fn default() -> Self {
+        Self::new(vec![], || None)
+    }
+}
+
+impl HostAccess {
+    pub fn new<F>(allowed: Vec<IpNetwork>, discovered: F) -> Self
+        where F: Fn() -> Option<IpNetwork> + Send + Sync + 'static,
+    {
+        Self {
+            allowed,
+            discovered: Box::new(discovered),
+        }
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if ip.is_loopback() {
+            return true;
+        }
+
+        match self.allowed.is_empty() {
+            true => (self.discovered)().map(|network| network.contains(ip)).unwrap_or(false),
+            false => self.allowed.iter().any(|network| network.contains(ip)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_allows_the_players_network_unless_hosts_are_given() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let discovered = HostAccess::new(vec![], || Some("192.168.1.0/24".parse().unwrap()));
+        let configured = HostAccess::new(vec!["10.0.0.5".parse().unwrap()], || Some("192.168.1.0/24".parse().unwrap()));
+
+        assert!(discovered.allows(ip("192.168.1.20")));
+        assert!(!discovered.allows(ip("192.168.2.20")));
+        assert!(configured.allows(ip("10.0.0.5")));
+        assert!(!configured.allows(ip("10.0.0.6")));
+        assert!(!configured.allows(ip("192.168.1.20")));
+        assert!(HostAccess::default().allows(ip("127.0.0.1")));
+        assert!(!HostAccess::default().allows(ip("192.168.1.20")));
+    }
+}
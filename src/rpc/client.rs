@@ -0,0 +1,256 @@
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use super::codec::{LAST_FRAGMENT, MAX_RECORD_SIZE};
+use super::packets::*;
+
+/// How long to wait for a reply before sending a call again.
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+const ATTEMPTS: usize = 4;
+const MAX_DATAGRAM_SIZE: usize = 65_536;
+
+/// How calls reach the server.
+enum Transport {
+    /// A datagram per message, calls are sent again when no reply comes.
+    Udp(UdpSocket),
+    /// Records over a connection, which doesn't lose calls.
+    Tcp(TcpStream),
+}
+
+/// Calls the programs of a server one call at a time, the way players do
+/// over UDP and Linux does over TCP: ask the portmapper for a port, mount,
+/// then look up and read files.
+pub struct RpcClient {
+    transport: Transport,
+    server: SocketAddr,
+    xid: u32,
+    /// Encoding of the names sent, UTF-16LE like players unless told otherwise.
+    encoding: NameEncoding,
+}
+
+impl RpcClient {
+    /// Client for the program served at `server` over UDP.
+    pub async fn new(server: SocketAddr) -> Result<RpcClient, Error> {
+        let local = match server {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+
+        Ok(RpcClient {
+            transport: Transport::Udp(UdpSocket::bind(&local).await?),
+            server,
+            xid: 1,
+            encoding: NameEncoding::default(),
+        })
+    }
+
+    /// Client for the program served at `server` over TCP.
+    pub async fn tcp(server: SocketAddr) -> Result<RpcClient, Error> {
+        Ok(RpcClient {
+            transport: Transport::Tcp(TcpStream::connect(&server).await?),
+            server,
+            xid: 1,
+            encoding: NameEncoding::default(),
+        })
+    }
+
+    /// Send names in `encoding`.
+    pub fn with_names(mut self, encoding: NameEncoding) -> RpcClient {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Call a procedure and wait for its results. Replies to earlier calls are skipped.
+    pub async fn call(&mut self, procedure: RpcProcedure) -> Result<RpcReplyMessage, Error> {
+        let program = procedure.program();
+        let number = procedure.number();
+        let xid = self.xid;
+        self.xid = self.xid.wrapping_add(1);
+
+        let call = RpcCall::new(procedure, RpcAuth::Unix(RpcUnixAuth {
+            stamp: 0,
+            machine_name: String::from("termdj"),
+            uid: 0,
+            gid: 0,
+            gids: vec![],
+        }));
+        let message = Bytes::try_from(RpcMessage::new(xid, RpcMessageType::Call(call)))
+            .map_err(|_err| Error::new(ErrorKind::InvalidInput, "Failed encoding RPC call"))?;
+
+        let reply = match &mut self.transport {
+            Transport::Udp(socket) => call_udp(socket, &self.server, &message, xid, &program, number).await?,
+            Transport::Tcp(stream) => call_tcp(stream, &message, xid, &program, number).await?,
+        };
+        match reply {
+            Some(reply) => results(reply),
+            None => Err(Error::new(ErrorKind::TimedOut, format!("No reply from {} to {:?} call", self.server, program))),
+        }
+    }
+
+    /// Port a program is served on, as the portmapper of the server has it.
+    pub async fn getport(&mut self, program: RpcProgram, protocol: PortmapProtocol) -> Result<u16, Error> {
+        let mapping = PortmapMapping {
+            program: program.number(),
+            version: program.versions().1,
+            protocol: protocol.number(),
+            port: 0,
+        };
+        match self.call(RpcProcedure::PortmapGetport(mapping)).await? {
+            RpcReplyMessage::PortmapGetport(PortmapGetportReply { port: 0 }) => {
+                Err(Error::new(ErrorKind::NotFound, format!("{:?} is not registered", program)))
+            },
+            RpcReplyMessage::PortmapGetport(reply) => Ok(reply.port as u16),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Handle of the root of an exported directory.
+    pub async fn mount(&mut self, path: &str) -> Result<FileHandle, Error> {
+        match self.call(RpcProcedure::MountMnt(MountMnt::new(path, self.encoding))).await? {
+            RpcReplyMessage::MountMnt(reply) if reply.status() == 0 => Ok(reply.fhandle().clone()),
+            RpcReplyMessage::MountMnt(reply) => {
+                Err(Error::new(ErrorKind::PermissionDenied, format!("Mount refused; status = {}", reply.status())))
+            },
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub async fn lookup(&mut self, directory: &FileHandle, name: &str) -> Result<NfsLookupReply, Error> {
+        let lookup = NfsLookup {
+            filename: PathBuf::from(name),
+            fhandle: directory.clone(),
+            encoding: self.encoding,
+        };
+        match self.call(RpcProcedure::NfsLookup(lookup)).await? {
+            RpcReplyMessage::NfsLookup(reply) => Ok(reply),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Up to `count` bytes of a file starting at `offset`.
+    pub async fn read(&mut self, file: &FileHandle, offset: u32, count: u32) -> Result<Vec<u8>, Error> {
+        let read = NfsRead {
+            fhandle: file.clone(),
+            offset,
+            count,
+            total_count: count,
+        };
+        match self.call(RpcProcedure::NfsRead(read)).await? {
+            RpcReplyMessage::NfsRead(reply) => Ok(reply.data.data),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Entries of a directory from `cookie` on, as many as fit in `count` bytes.
+    pub async fn readdir(&mut self, directory: &FileHandle, cookie: u32, count: u32) -> Result<NfsReaddirReply, Error> {
+        let readdir = NfsReaddir {
+            fhandle: directory.clone(),
+            cookie,
+            count,
+        };
+        match self.call(RpcProcedure::NfsReaddir(readdir)).await? {
+            RpcReplyMessage::NfsReaddir(reply) => Ok(reply),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+/// Send a call as a datagram until a reply to it comes, `None` if none comes.
+async fn call_udp(
+    socket: &mut UdpSocket,
+    server: &SocketAddr,
+    message: &[u8],
+    xid: u32,
+    program: &RpcProgram,
+    number: u32,
+) -> Result<Option<RpcReply>, Error> {
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    for _attempt in 0..ATTEMPTS {
+        socket.send_to(message, server).await?;
+        loop {
+            let length = match timeout(REPLY_TIMEOUT, socket.recv_from(&mut buffer)).await {
+                Ok(received) => received?.0,
+                Err(_elapsed) => break,
+            };
+            match RpcMessage::decode_reply(&buffer[..length], program, number) {
+                Ok((reply_xid, reply)) if reply_xid == xid => return Ok(Some(reply)),
+                Ok(_) => continue,
+                Err(err) => return Err(Error::new(ErrorKind::InvalidData, err)),
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Send a call as a record and read records until the reply to it, `None`
+/// if it doesn't come in as long as the attempts over UDP take.
+async fn call_tcp(
+    stream: &mut TcpStream,
+    message: &[u8],
+    xid: u32,
+    program: &RpcProgram,
+    number: u32,
+) -> Result<Option<RpcReply>, Error> {
+    stream.write_all(&(LAST_FRAGMENT | message.len() as u32).to_be_bytes()).await?;
+    stream.write_all(message).await?;
+
+    let replies = async {
+        loop {
+            let record = read_record(stream).await?;
+            match RpcMessage::decode_reply(&record, program, number) {
+                Ok((reply_xid, reply)) if reply_xid == xid => return Ok(reply),
+                Ok(_) => continue,
+                Err(err) => return Err(Error::new(ErrorKind::InvalidData, err)),
+            }
+        }
+    };
+    match timeout(REPLY_TIMEOUT * ATTEMPTS as u32, replies).await {
+        Ok(reply) => reply.map(Some),
+        Err(_elapsed) => Ok(None),
+    }
+}
+
+/// The fragments of the next record on a connection.
+async fn read_record(stream: &mut TcpStream) -> Result<Vec<u8>, Error> {
+    let mut record = vec![];
+    loop {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        let header = u32::from_be_bytes(header);
+        let length = (header & !LAST_FRAGMENT) as usize;
+        if record.len() + length > MAX_RECORD_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "RPC record too large"));
+        }
+
+        let start = record.len();
+        record.resize(start + length, 0);
+        stream.read_exact(&mut record[start..]).await?;
+        if header & LAST_FRAGMENT != 0 {
+            return Ok(record);
+        }
+    }
+}
+
+/// Results of a reply, or why the call wasn't served.
+fn results(reply: RpcReply) -> Result<RpcReplyMessage, Error> {
+    match (reply.reply_state, reply.accept_state) {
+        (RpcReplyState::Accepted, RpcAcceptState::Success) => Ok(reply.data),
+        (RpcReplyState::Accepted, state) => Err(Error::other(format!("Call not served; state = {:?}", state))),
+        (RpcReplyState::Denied(reason), _) => Err(Error::new(ErrorKind::PermissionDenied, format!("Call denied; reason = {:?}", reason))),
+    }
+}
+
+fn unexpected(reply: RpcReplyMessage) -> Error {
+    match reply {
+        RpcReplyMessage::NfsError(status) => Error::other(format!("NFS error {:?}", status)),
+        reply => Error::new(ErrorKind::InvalidData, format!("Unexpected reply {:?}", reply)),
+    }
+}
@@ -1,4 +1,4 @@
-use bytes::{Bytes, BytesMut, BufMut};
+use bytes::{Buf, Bytes, BytesMut, BufMut};
 use tokio_util::codec::{Encoder, Decoder};
 use super::packets::RpcMessage;
 use std::io::{Error, ErrorKind};
@@ -17,15 +17,12 @@ impl Decoder for RpcBytesCodec {
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        if buf.len() > 0 {
-            let len = buf.len();
-
-            match RpcMessage::try_from(Bytes::from(buf.split_to(len))) {
-                Ok(message) => Ok(Some(message)),
-                Err(err) => Err(Error::new(ErrorKind::InvalidInput, err)),
-            }
-        } else {
-            Ok(None)
+        // Every datagram is a message, an empty one is as bad as a broken one
+        // and must not end the stream of datagrams.
+        let len = buf.len();
+        match RpcMessage::try_from(Bytes::from(buf.split_to(len))) {
+            Ok(message) => Ok(Some(message)),
+            Err(err) => Err(Error::new(ErrorKind::InvalidInput, err)),
         }
     }
 }
@@ -58,3 +55,112 @@ impl Encoder<RpcMessage> for RpcBytesCodec {
         }
     }
 }
+
+/// Largest record accepted over TCP, far more than any call sent to the programs served.
+pub const MAX_RECORD_SIZE: usize = 1 << 20;
+
+/// Marks the last fragment of a record in its header, the other bits hold the fragment length.
+pub const LAST_FRAGMENT: u32 = 0x8000_0000;
+
+/// Record marking for RPC over TCP: messages are sent as records of one or
+/// more fragments, each preceded by a 4 byte header with its length.
+#[derive(Clone, Debug, Default)]
+pub struct RpcRecordCodec {
+    /// Fragments of the record read so far.
+    record: BytesMut,
+}
+
+impl RpcRecordCodec {
+    pub fn new() -> RpcRecordCodec { RpcRecordCodec::default() }
+}
+
+impl Decoder for RpcRecordCodec {
+    type Item = RpcMessage;
+    type Error = Error;
+
+    /// Records that don't hold a message are skipped, so one bad call doesn't end the connection.
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            if buf.len() < 4 {
+                return Ok(None);
+            }
+            let header = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+            let length = (header & !LAST_FRAGMENT) as usize;
+            if self.record.len() + length > MAX_RECORD_SIZE {
+                return Err(Error::new(ErrorKind::InvalidData, "RPC record too large"));
+            }
+            if buf.len() < 4 + length {
+                buf.reserve(4 + length - buf.len());
+                return Ok(None);
+            }
+
+            buf.advance(4);
+            self.record.extend_from_slice(&buf.split_to(length));
+            if header & LAST_FRAGMENT == 0 {
+                continue;
+            }
+
+            let record = self.record.split().freeze();
+            match RpcMessage::try_from(record) {
+                Ok(message) => return Ok(Some(message)),
+                Err(err) => eprintln!("Skipping RPC record; error = {}", err),
+            }
+        }
+    }
+}
+
+impl Encoder<RpcMessage> for RpcRecordCodec {
+    type Error = Error;
+
+    /// Replies are sent as a single fragment.
+    fn encode(&mut self, data: RpcMessage, buf: &mut BytesMut) -> Result<(), Error> {
+        let mut message = BytesMut::new();
+        RpcBytesCodec::new().encode(data, &mut message)?;
+
+        buf.reserve(4 + message.len());
+        buf.put_u32(LAST_FRAGMENT | message.len() as u32);
+        buf.put(message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::packets::{RpcMessageType, RpcReply, RpcReplyMessage};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_reassembles_fragmented_records() {
+        // A NULL call to NFS, split into two fragments and followed by a record that isn't a message
+        let call = [
+            &[0u8, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0x86, 0xa3, 0, 0, 0, 2, 0, 0, 0, 0][..],
+            &[0u8, 0, 0, 1, 0, 0, 0, 20][..],
+            &[0u8; 28][..],
+        ].concat();
+        let mut buf = BytesMut::new();
+        buf.put_u32(10);
+        buf.extend_from_slice(&call[..10]);
+        buf.put_u32(LAST_FRAGMENT | (call.len() - 10) as u32);
+        buf.extend_from_slice(&call[10..]);
+        buf.put_u32(LAST_FRAGMENT | 2);
+        buf.extend_from_slice(&[1, 2]);
+
+        let mut codec = RpcRecordCodec::new();
+        let mut partial = buf.split_to(20);
+        assert_eq!(None, codec.decode(&mut partial).unwrap());
+        partial.unsplit(buf);
+        let message = codec.decode(&mut partial).unwrap().unwrap();
+        assert_eq!(1, message.xid);
+        assert_eq!(None, codec.decode(&mut partial).unwrap());
+        assert!(partial.is_empty());
+
+        let mut reply = BytesMut::new();
+        codec.encode(
+            RpcMessage::new(1, RpcMessageType::Reply(RpcReply::success(RpcReplyMessage::Void))),
+            &mut reply,
+        ).unwrap();
+        assert_eq!(&[0x80, 0, 0, 24, 0, 0, 0, 1][..], &reply[..8]);
+        assert_eq!(28, reply.len());
+    }
+}
@@ -1,42 +1,385 @@
 use std::io;
 use std::io::prelude::*;
 use std::fs::File;
-use std::io::SeekFrom;
-use std::path::Path;
+use std::io::{ErrorKind, SeekFrom};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use crate::library::{Store, TranscodeCache, TranscodedAudio};
+use crate::rpc::handles::{decode_handle, encode_handle, HandleKey, HandleTable, OpenFiles};
 use crate::rpc::packets::NfsDataWrapper;
 
+/// The part of the filesystem served over NFS: the library folders and the
+/// directories leading down to them, so players can walk the absolute paths
+/// announced in the mount info. Everything else is out of reach.
+///
+/// Tracks players can't decode are served as WAV files next to them, see `TranscodedAudio`.
 #[derive(Debug)]
-pub struct FileWrapper {
-    pub inode: u64,
-    pub encoded: [u8; 32],
-    pub file: File,
-}
-
-pub fn get_fhandle<T: AsRef<Path>>(path: T, inode: u64) -> Result<FileWrapper, std::io::Error> {
-    let file = File::open(path)?;
-    let encoded_fhandle = encode_file_handler(&inode);
-    Ok(FileWrapper {
-        file,
-        encoded: encoded_fhandle,
-        inode,
+pub struct NfsExport {
+    roots: Vec<PathBuf>,
+    /// Tells handles of this export from handles of another set of libraries.
+    generation: u32,
+    handles: Mutex<HandleTable>,
+    /// Where transcoded files are kept.
+    cache: TranscodeCache,
+    /// Probes of tracks by the handle of their WAV file, so tracks aren't
+    /// read again on every call about them.
+    probes: Mutex<OpenFiles<Probe>>,
+}
+
+/// A path as it is served, a track transcoded to WAV has the metadata of the track.
+#[derive(Debug)]
+pub struct ExportedFile {
+    pub key: HandleKey,
+    pub metadata: std::fs::Metadata,
+    pub transcoded: Option<TranscodedAudio>,
+}
+
+/// Whether a track is transcoded, as it was when last modified.
+#[derive(Debug)]
+struct Probe {
+    modified: Option<SystemTime>,
+    size: u64,
+    audio: Option<TranscodedAudio>,
+}
+
+/// Tracks whose probe is kept, a few directories full.
+const MAX_PROBES: usize = 4096;
+
+/// Disk space the WAV files of transcoded tracks may take up, a few hours of CD audio.
+const MAX_TRANSCODED_SIZE: u64 = 4 << 30;
+
+/// FNV-1a of the library folders, never 0 so no handle looks like the root handle.
+fn generation(roots: &[PathBuf]) -> u32 {
+    let hash = roots.iter()
+        .flat_map(|root| root.as_os_str().as_bytes().iter().chain(&[0u8]))
+        .fold(0x811c9dc5u32, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x01000193));
+    hash.max(1)
+}
+
+impl NfsExport {
+    /// Handles are kept in the store, when given, to outlive the process.
+    pub fn new(roots: &[PathBuf], store: Option<Store>) -> Self {
+        let cache = match &store {
+            Some(store) => store.path().join("transcoded"),
+            None => std::env::temp_dir().join("termdj-transcoded"),
+        };
+        let cache = TranscodeCache::new(cache, MAX_TRANSCODED_SIZE);
+        let roots: Vec<PathBuf> = roots.iter()
+            .filter_map(|root| match root.canonicalize() {
+                Ok(root) => Some(root),
+                Err(err) => {
+                    eprintln!("Not exporting {:?} over NFS; error = {}", root, err);
+                    None
+                },
+            })
+            .collect();
+
+        Self {
+            generation: generation(&roots),
+            roots,
+            handles: Mutex::new(HandleTable::load(store)),
+            cache,
+            probes: Mutex::new(OpenFiles::new(MAX_PROBES)),
+        }
+    }
+
+    /// How a track is transcoded, probed again only when it's modified.
+    fn probe(&self, source: &Path, metadata: &std::fs::Metadata) -> Option<TranscodedAudio> {
+        if !TranscodedAudio::may_transcode(source) {
+            return None;
+        }
+        let modified = metadata.modified().ok();
+        let probe = || Ok(Probe { modified, size: metadata.len(), audio: TranscodedAudio::probe(source) });
+
+        let mut probes = self.probes.lock().ok()?;
+        let cached = probes.get(HandleKey::transcoded(metadata), probe).ok()?;
+        let source_moved = cached.audio.as_ref().map(|audio| audio.source() != source).unwrap_or(false);
+        if cached.modified != modified || cached.size != metadata.len() || source_moved {
+            *cached = probe().ok()?;
+        }
+        cached.audio.clone()
+    }
+
+    /// How a track is served when players can't decode it, `None` when it's
+    /// served as it is. Shares the probes of the files served over NFS.
+    pub fn transcoding(&self, track: &Path) -> Option<TranscodedAudio> {
+        let metadata = std::fs::metadata(track).ok()?;
+        self.probe(track, &metadata)
+    }
+
+    /// The track a transcoded path is served from and its metadata, `None` for other paths.
+    fn transcoded(&self, path: &Path) -> Option<(std::fs::Metadata, TranscodedAudio)> {
+        let source = TranscodedAudio::source_of(path)?;
+        let metadata = std::fs::metadata(&source).ok()?;
+        let audio = self.probe(&source, &metadata)?;
+        Some((metadata, audio))
+    }
+
+    /// A path as it is served.
+    pub fn file(&self, path: &Path) -> io::Result<ExportedFile> {
+        if let Some((metadata, audio)) = self.transcoded(path) {
+            return Ok(ExportedFile {
+                key: HandleKey::transcoded(&metadata),
+                metadata,
+                transcoded: Some(audio),
+            });
+        }
+
+        let metadata = std::fs::metadata(path)?;
+        Ok(ExportedFile {
+            key: HandleKey::of(&metadata),
+            metadata,
+            transcoded: None,
+        })
+    }
+
+    /// Handle for a resolved path, remembered so it can be resolved again.
+    pub fn handle(&self, path: &Path, key: HandleKey) -> [u8; 32] {
+        if let Ok(mut handles) = self.handles.lock() {
+            handles.issue(key, path);
+        }
+        encode_handle(self.generation, key)
+    }
+
+    /// Path a handle stands for, `None` for stale handles: handles of another
+    /// export, unknown ones and those whose file was removed or replaced.
+    pub fn path_of(&self, handle: &[u8]) -> Option<(HandleKey, PathBuf)> {
+        if handle.iter().all(|byte| *byte == 0) {
+            return Some((HandleKey { device: 0, inode: 0, transcoded: false }, self.root()));
+        }
+
+        let (generation, key) = decode_handle(handle)?;
+        if generation != self.generation {
+            return None;
+        }
+        let path = self.handles.lock().ok()?.path(&key)?.clone();
+        match self.file(&path) {
+            Ok(file) if file.key == key && self.is_visible(&path) => Some((key, path)),
+            _ => None,
+        }
+    }
+
+    pub fn root(&self) -> PathBuf {
+        PathBuf::from("/")
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    pub fn cache(&self) -> &TranscodeCache {
+        &self.cache
+    }
+
+    /// Whether a resolved path is inside a library folder.
+    pub fn is_exported(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Whether a resolved path is inside a library folder or on the way to one.
+    pub fn is_visible(&self, path: &Path) -> bool {
+        self.is_exported(path) || self.roots.iter().any(|root| root.starts_with(path))
+    }
+
+    /// Resolve a name looked up in a directory. Names must be a single path
+    /// component, and symlinks are followed to check where they end up.
+    /// Transcoded files resolve next to where their track resolves. Hidden
+    /// files aren't served, like they aren't indexed, unless they lead to a library.
+    pub fn resolve(&self, directory: &Path, name: &Path) -> io::Result<PathBuf> {
+        let mut components = name.components();
+        let hidden = match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => name.as_bytes().starts_with(b"."),
+            _ => return Err(io::Error::new(ErrorKind::PermissionDenied, format!("Invalid file name {:?}", name))),
+        };
+
+        let path = match directory.join(name).canonicalize() {
+            Err(err) if err.kind() == ErrorKind::NotFound => match self.transcoded(&directory.join(name)) {
+                Some((_metadata, audio)) => audio.canonicalize()?.path(),
+                None => return Err(err),
+            },
+            path => path?,
+        };
+        if hidden && !self.roots.iter().any(|root| root.starts_with(&path)) {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("{:?} is hidden", name)));
+        }
+        match self.is_visible(&path) {
+            true => Ok(path),
+            false => Err(io::Error::new(ErrorKind::PermissionDenied, format!("{:?} is outside the library", path))),
+        }
+    }
+
+    /// Names and inodes of what a directory holds that can be looked up, ordered
+    /// by name. Tracks players can't decode are listed as their WAV files.
+    pub fn list(&self, directory: &Path) -> io::Result<Vec<(String, u64)>> {
+        let mut entries: Vec<(String, u64)> = std::fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let path = self.resolve(directory, Path::new(&name)).ok()?;
+                let metadata = std::fs::metadata(&path).ok()?;
+                let name = match metadata.is_file() && self.probe(&path, &metadata).is_some() {
+                    true => TranscodedAudio::served_name(&name.to_string_lossy()),
+                    false => name.to_string_lossy().into_owned(),
+                };
+                Some((name, metadata.ino()))
+            })
+            .collect();
+        entries.sort();
+
+        Ok(entries)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FilesystemUsage {
+    pub block_size: u64,
+    pub blocks: u64,
+    pub free_blocks: u64,
+    /// Free blocks available to unprivileged users.
+    pub available_blocks: u64,
+}
+
+/// Size and free space of the filesystem a path is on.
+pub fn filesystem_usage(path: &Path) -> io::Result<FilesystemUsage> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(FilesystemUsage {
+        block_size: stats.f_frsize as u64,
+        blocks: stats.f_blocks as u64,
+        free_blocks: stats.f_bfree as u64,
+        available_blocks: stats.f_bavail as u64,
     })
 }
 
-pub fn read_file_range(mut file: &File, start: u32, count: u32) -> Result<NfsDataWrapper, io::Error> {
-    let mut buf: Vec<u8> = vec![0x00; count as usize];
+/// Read up to `count` bytes starting at `start`.
+///
+/// Reads past the end of the file return the bytes that exist, never padding.
+pub fn read_file_range(mut file: &File, start: u64, count: u32) -> Result<NfsDataWrapper, io::Error> {
+    let mut buf: Vec<u8> = Vec::with_capacity(count as usize);
 
-    file.seek(SeekFrom::Start(start as u64))?;
-    file.take(count as u64).read(&mut buf)?;
+    file.seek(SeekFrom::Start(start))?;
+    file.take(count as u64).read_to_end(&mut buf)?;
 
     Ok(NfsDataWrapper {
         data: buf,
     })
 }
 
-fn encode_file_handler(inode: &u64) -> [u8; 32] {
-    let mut data = [0u8; 32];
-    for (index, value) in inode.to_le_bytes().iter().enumerate() {
-        data[index] = *value;
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn it_keeps_lookups_inside_the_library() {
+        let temp = crate::utils::test_dir("nfs-export");
+        let base = temp.path().to_path_buf();
+        let root = base.join("music");
+        std::fs::create_dir_all(root.join("Artist")).unwrap();
+        std::fs::write(root.join("Artist/Track.mp3"), b"").unwrap();
+        std::fs::write(base.join("secret.txt"), b"").unwrap();
+        std::fs::create_dir_all(root.join(".termdj")).unwrap();
+        let _ = std::os::unix::fs::symlink(base.join("secret.txt"), root.join("escape"));
+        let _ = std::os::unix::fs::symlink(root.join("Artist"), root.join("inside"));
+
+        let export = NfsExport::new(std::slice::from_ref(&root), None);
+        let base = base.canonicalize().unwrap();
+        let root = root.canonicalize().unwrap();
+        let kind = |result: io::Result<PathBuf>| result.map_err(|err| err.kind());
+
+        assert_eq!(Ok(root.join("Artist/Track.mp3")), kind(export.resolve(&root.join("Artist"), Path::new("Track.mp3"))));
+        assert_eq!(Ok(root.join("Artist")), kind(export.resolve(&root, Path::new("inside"))));
+        assert_eq!(Ok(base.clone()), kind(export.resolve(base.parent().unwrap(), base.file_name().unwrap().as_ref())));
+        assert_eq!(Err(ErrorKind::PermissionDenied), kind(export.resolve(&base, Path::new("secret.txt"))));
+        assert_eq!(Err(ErrorKind::PermissionDenied), kind(export.resolve(&root, Path::new("escape"))));
+        assert_eq!(Err(ErrorKind::PermissionDenied), kind(export.resolve(&root, Path::new(".."))));
+        assert_eq!(Err(ErrorKind::PermissionDenied), kind(export.resolve(&root, Path::new("/etc"))));
+        assert_eq!(Err(ErrorKind::PermissionDenied), kind(export.resolve(&root, Path::new("Artist/Track.mp3"))));
+        assert_eq!(Err(ErrorKind::NotFound), kind(export.resolve(&root, Path::new("Missing.mp3"))));
+        assert_eq!(Err(ErrorKind::NotFound), kind(export.resolve(&root, Path::new(".termdj"))));
+
+        let names = |directory: &Path| -> Vec<String> {
+            export.list(directory).unwrap().into_iter().map(|(name, _inode)| name).collect()
+        };
+        assert_eq!(vec!["Artist", "inside"], names(&root));
+        assert_eq!(vec!["music"], names(&base));
+    }
+
+    #[test]
+    fn it_resolves_handles_issued_before_a_restart() {
+        let temp = crate::utils::test_dir("nfs-restart");
+        let base = temp.path().to_path_buf();
+        let root = base.join("music");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("Track.mp3"), b"").unwrap();
+        let track = root.canonicalize().unwrap().join("Track.mp3");
+        let store = Store::new(base.join("data"));
+
+        let handle = NfsExport::new(std::slice::from_ref(&root), Some(store.clone()))
+            .handle(&track, HandleKey::of(&std::fs::metadata(&track).unwrap()));
+
+        let export = NfsExport::new(std::slice::from_ref(&root), Some(store.clone()));
+        assert_eq!(Some(track.clone()), export.path_of(&handle).map(|(_key, path)| path));
+        let other = NfsExport::new(std::slice::from_ref(&base), Some(store));
+        assert_eq!(None, other.path_of(&handle));
+
+        // A file that was replaced is another file
+        std::fs::remove_file(&track).unwrap();
+        std::fs::write(base.join("New.mp3"), b"").unwrap();
+        std::fs::write(&track, b"").unwrap();
+        std::fs::remove_file(base.join("New.mp3")).unwrap();
+        let replaced = std::fs::metadata(&track).unwrap();
+        if HandleKey::of(&replaced) != decode_handle(&handle).unwrap().1 {
+            assert_eq!(None, export.path_of(&handle));
+        }
+    }
+
+    #[test]
+    fn it_probes_tracks_again_only_when_they_are_modified() {
+        let temp = crate::utils::test_dir("nfs-probes");
+        let root = temp.path().canonicalize().unwrap();
+        let track = root.join("Track.aifc");
+        // Mono AIFC file with two little endian 16 bit samples
+        let aifc = [
+            &b"FORM\0\0\0\0AIFCCOMM\0\0\0\x16\0\x01\0\0\0\x02\0\x10"[..],
+            &[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0],
+            &b"sowtSSND\0\0\0\x0c\0\0\0\0\0\0\0\0\x01\x02\x03\x04"[..],
+        ].concat();
+        std::fs::write(&track, &aifc).unwrap();
+        let modified = std::fs::metadata(&track).unwrap().modified().unwrap();
+
+        let export = NfsExport::new(std::slice::from_ref(&root), None);
+        let wave = root.join("Track.aifc.wav");
+        assert_eq!(Some(44 + 4), export.file(&wave).unwrap().transcoded.map(|audio| audio.size()));
+
+        // Unreadable as long as it isn't modified, the probe is kept
+        std::fs::write(&track, vec![0u8; aifc.len()]).unwrap();
+        OpenOptions::new().write(true).open(&track).unwrap().set_modified(modified).unwrap();
+        assert_eq!(Some(44 + 4), export.file(&wave).unwrap().transcoded.map(|audio| audio.size()));
+        assert_eq!(Some(wave.clone()), export.transcoding(&track).map(|audio| audio.path()));
+
+        OpenOptions::new().write(true).open(&track).unwrap().set_modified(modified + std::time::Duration::from_secs(1)).unwrap();
+        assert_eq!(ErrorKind::NotFound, export.file(&wave).unwrap_err().kind());
+    }
+
+    #[test]
+    fn it_does_not_pad_reads_past_end_of_file() {
+        let temp = crate::utils::test_dir("read-file-range");
+        let path = temp.path().join("file");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let file = OpenOptions::new().read(true).open(&path).unwrap();
+
+        assert_eq!(b"2345".to_vec(), read_file_range(&file, 2, 4).unwrap().data);
+        assert_eq!(b"89".to_vec(), read_file_range(&file, 8, 4).unwrap().data);
+        assert_eq!(Vec::<u8>::new(), read_file_range(&file, 20, 4).unwrap().data);
     }
-    data
 }
@@ -0,0 +1,228 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, Metadata};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::library::{path_from_field, path_to_field, Store};
+
+/// Store table with the paths handles were issued for, so the handles
+/// players hold on to keep working after a restart.
+const HANDLES_TABLE: &str = "nfs-handles";
+
+/// Marks the records of handles of transcoded files in the store.
+const TRANSCODED_RECORD: &str = "transcoded";
+
+/// Files kept open for reading, the least recently read one is closed first.
+pub const MAX_OPEN_FILES: usize = 32;
+
+/// A file or directory as file handles identify it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandleKey {
+    pub device: u64,
+    pub inode: u64,
+    /// The WAV file served for a track, rather than the track itself.
+    pub transcoded: bool,
+}
+
+impl HandleKey {
+    pub fn of(metadata: &Metadata) -> Self {
+        Self {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            transcoded: false,
+        }
+    }
+
+    /// Key of the WAV file a track is served as.
+    pub fn transcoded(metadata: &Metadata) -> Self {
+        Self {
+            transcoded: true,
+            ..Self::of(metadata)
+        }
+    }
+}
+
+/// Handles carry the inode, the device, the generation of the export and
+/// whether they are transcoded, little endian and padded with zeros to 32 bytes.
+pub fn encode_handle(generation: u32, key: HandleKey) -> [u8; 32] {
+    let mut data = [0u8; 32];
+    data[0..8].copy_from_slice(&key.inode.to_le_bytes());
+    data[8..16].copy_from_slice(&key.device.to_le_bytes());
+    data[16..20].copy_from_slice(&generation.to_le_bytes());
+    data[20] = key.transcoded as u8;
+    data
+}
+
+pub fn decode_handle(data: &[u8]) -> Option<(u32, HandleKey)> {
+    let field = |range: std::ops::Range<usize>| data.get(range);
+    let mut inode = [0u8; 8];
+    let mut device = [0u8; 8];
+    let mut generation = [0u8; 4];
+    inode.copy_from_slice(field(0..8)?);
+    device.copy_from_slice(field(8..16)?);
+    generation.copy_from_slice(field(16..20)?);
+
+    Some((u32::from_le_bytes(generation), HandleKey {
+        device: u64::from_le_bytes(device),
+        inode: u64::from_le_bytes(inode),
+        transcoded: data.get(20).map(|flag| *flag != 0).unwrap_or(false),
+    }))
+}
+
+/// Paths of the handles that were handed out, kept in the store when there is one.
+#[derive(Debug, Default)]
+pub struct HandleTable {
+    store: Option<Store>,
+    paths: HashMap<HandleKey, PathBuf>,
+}
+
+impl HandleTable {
+    /// Handles issued in earlier runs, the latest path of a handle wins.
+    pub fn load(store: Option<Store>) -> Self {
+        let records = match &store {
+            Some(store) => store.read_table(HANDLES_TABLE).unwrap_or_else(|err| {
+                eprintln!("Failed loading NFS handles; error = {}", err);
+                vec![]
+            }),
+            None => vec![],
+        };
+
+        let mut paths = HashMap::new();
+        for record in records {
+            let (device, inode, path, transcoded) = match record.as_slice() {
+                [device, inode, path] => (device, inode, path, false),
+                [device, inode, path, kind] => (device, inode, path, kind == TRANSCODED_RECORD),
+                _ => continue,
+            };
+            if let (Ok(device), Ok(inode)) = (device.parse(), inode.parse()) {
+                paths.insert(HandleKey { device, inode, transcoded }, path_from_field(path));
+            }
+        }
+
+        Self { store, paths }
+    }
+
+    pub fn issue(&mut self, key: HandleKey, path: &Path) {
+        if self.paths.get(&key).map(|known| known == path).unwrap_or(false) {
+            return;
+        }
+        self.paths.insert(key, path.to_path_buf());
+
+        if let Some(store) = &self.store {
+            let mut record = vec![key.device.to_string(), key.inode.to_string(), path_to_field(path)];
+            if key.transcoded {
+                record.push(TRANSCODED_RECORD.to_string());
+            }
+            if let Err(err) = store.append_record(HANDLES_TABLE, &record) {
+                eprintln!("Failed saving NFS handle; error = {}", err);
+            }
+        }
+    }
+
+    pub fn path(&self, key: &HandleKey) -> Option<&PathBuf> {
+        self.paths.get(key)
+    }
+}
+
+/// Files open for reading, at most `capacity` of them.
+#[derive(Debug)]
+pub struct OpenFiles<T = File> {
+    capacity: usize,
+    files: HashMap<HandleKey, T>,
+    /// Least recently used first.
+    order: VecDeque<HandleKey>,
+}
+
+impl<T> OpenFiles<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            files: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// The open file of a handle, opening it and closing the least recently used one when needed.
+    pub fn get<F: FnOnce() -> io::Result<T>>(&mut self, key: HandleKey, open: F) -> io::Result<&mut T> {
+        match self.order.iter().position(|open| *open == key) {
+            Some(index) => {
+                self.order.remove(index);
+            },
+            None => {
+                let file = open()?;
+                if self.files.len() >= self.capacity {
+                    if let Some(oldest) = self.order.pop_front() {
+                        self.files.remove(&oldest);
+                    }
+                }
+                self.files.insert(key, file);
+            },
+        }
+        self.order.push_back(key);
+
+        Ok(self.files.get_mut(&key).expect("Open files and their order are kept in step"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_encodes_handles() {
+        let key = HandleKey { device: 0x801, inode: 1234567, transcoded: false };
+        let transcoded = HandleKey { transcoded: true, ..key };
+
+        assert_eq!(Some((7, key)), decode_handle(&encode_handle(7, key)));
+        assert_eq!(Some((7, transcoded)), decode_handle(&encode_handle(7, transcoded)));
+        assert_eq!(None, decode_handle(&[0u8; 12]));
+    }
+
+    #[test]
+    fn it_keeps_handles_across_restarts() {
+        let temp = crate::utils::test_dir("nfs-handles");
+        let base = temp.path().to_path_buf();
+        let key = HandleKey { device: 1, inode: 2, transcoded: false };
+        let transcoded = HandleKey { transcoded: true, ..key };
+        let mut handles = HandleTable::load(Some(Store::new(&base)));
+        handles.issue(key, Path::new("/music/Old.mp3"));
+        handles.issue(key, Path::new("/music/New.mp3"));
+        handles.issue(key, Path::new("/music/New.mp3"));
+        handles.issue(transcoded, Path::new("/music/New.mp3.wav"));
+
+        let handles = HandleTable::load(Some(Store::new(&base)));
+        assert_eq!(Some(&PathBuf::from("/music/New.mp3")), handles.path(&key));
+        assert_eq!(Some(&PathBuf::from("/music/New.mp3.wav")), handles.path(&transcoded));
+        assert_eq!(3, Store::new(&base).read_table(HANDLES_TABLE).unwrap().len());
+    }
+
+    #[test]
+    fn it_closes_the_least_recently_used_file() {
+        let temp = crate::utils::test_dir("open-files");
+        let base = temp.path().to_path_buf();
+        std::fs::create_dir_all(&base).unwrap();
+        let keys: Vec<HandleKey> = (1..=3).map(|inode| HandleKey { device: 0, inode, transcoded: false }).collect();
+        for key in &keys {
+            std::fs::write(base.join(key.inode.to_string()), b"").unwrap();
+        }
+        let open = |key: &HandleKey| {
+            let path = base.join(key.inode.to_string());
+            move || File::open(path)
+        };
+
+        let mut files = OpenFiles::new(2);
+        files.get(keys[0], open(&keys[0])).unwrap();
+        files.get(keys[1], open(&keys[1])).unwrap();
+        files.get(keys[0], open(&keys[0])).unwrap();
+        files.get(keys[2], open(&keys[2])).unwrap();
+
+        assert_eq!(2, files.len());
+        assert_eq!(vec![keys[0], keys[2]], files.order.iter().cloned().collect::<Vec<HandleKey>>());
+    }
+}
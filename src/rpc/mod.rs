@@ -3,9 +3,14 @@
 
 pub mod server;
 pub mod packets;
+pub mod client;
+mod access;
 mod codec;
 mod fs;
+mod handles;
+mod names;
 mod nfs_program;
+mod portmap;
 
 pub mod events {
     use super::packets::{
@@ -28,3 +33,5 @@ pub mod events {
 }
 
 pub use server::PortmapServer;
+pub use fs::NfsExport;
+pub use access::HostAccess;
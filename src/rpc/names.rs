@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use super::packets::{NameEncoding, RpcMessage, RpcMessageType, RpcProcedure};
+
+/// Encoding of the names each host sends in MOUNT and LOOKUP calls, so the
+/// names sent back to it are encoded the same way. Hosts that sent none yet
+/// are taken for players.
+#[derive(Debug, Default)]
+pub struct ClientNames {
+    encodings: Mutex<HashMap<IpAddr, NameEncoding>>,
+}
+
+impl ClientNames {
+    /// Remember the encoding of the name a call carries, if it carries one,
+    /// and return the encoding to reply to the host with.
+    pub fn learn(&self, message: &RpcMessage, ip: IpAddr) -> NameEncoding {
+        let encoding = match message.message() {
+            RpcMessageType::Call(call) => match call.procedure() {
+                RpcProcedure::MountMnt(arguments) => Some(arguments.encoding()),
+                RpcProcedure::NfsLookup(arguments) => Some(arguments.encoding),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let mut encodings = match self.encodings.lock() {
+            Ok(encodings) => encodings,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(encoding) = encoding {
+            encodings.insert(ip, encoding);
+        }
+        encodings.get(&ip).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::packets::{MountMnt, RpcAuth, RpcCall};
+
+    #[test]
+    fn it_replies_to_hosts_in_the_encoding_they_mounted_with() {
+        let names = ClientNames::default();
+        let call = |procedure| RpcMessage::new(1, RpcMessageType::Call(RpcCall::new(procedure, RpcAuth::Null)));
+        let linux = "10.0.0.5".parse().unwrap();
+        let player = "10.0.0.6".parse().unwrap();
+
+        assert_eq!(NameEncoding::Utf8, names.learn(&call(RpcProcedure::MountMnt(MountMnt::new("/", NameEncoding::Utf8))), linux));
+        assert_eq!(NameEncoding::Utf8, names.learn(&call(RpcProcedure::NfsNull), linux));
+        assert_eq!(NameEncoding::Utf16Le, names.learn(&call(RpcProcedure::NfsNull), player));
+    }
+}
@@ -1,13 +1,16 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
 use std::fs::File;
-use std::os::unix::fs::MetadataExt;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use tokio_util::udp::UdpFramed;
-use tokio::stream::StreamExt;
-use futures::{SinkExt};
+use std::convert::TryFrom;
 
-use crate::rpc::fs::get_fhandle;
+use crate::library::Transcoder;
+use crate::rpc::fs::{filesystem_usage, ExportedFile, NfsExport};
+use crate::rpc::handles::{HandleKey, OpenFiles, MAX_OPEN_FILES};
+use crate::rpc::names::ClientNames;
 use crate::rpc::packets::{
     *,
     self as rpc_packages,
@@ -15,139 +18,459 @@ use crate::rpc::packets::{
     NfsFileAttributes,
     NfsStatus,
 };
-use crate::rpc::codec::RpcBytesCodec;
+use crate::rpc::server::RpcService;
 use crate::rpc::fs::read_file_range;
 
+/// Largest transfer NFSv2 allows, announced by STATFS.
+const TRANSFER_SIZE: u32 = 8192;
+
+/// Bytes of a READDIR reply besides its entries: status, end of list and eof flag.
+const READDIR_REPLY_SIZE: usize = 12;
+
+/// Directory listings kept for READDIR calls continuing them.
+const MAX_LISTINGS: usize = 16;
+
+/// Data of a READ reply, or the transcoder to read it from once decoded.
+enum ReadData {
+    Read(NfsDataWrapper),
+    Transcoded(Arc<Transcoder>),
+}
+
+/// Names and inodes of a directory as it was when last modified.
+struct Listing {
+    modified: SystemTime,
+    entries: Vec<(String, u64)>,
+}
+
 pub struct RpcNfsProgramHandler {
-    path: PathBuf,
-    file_handlers: HashMap<u64, File>,
+    export: Arc<NfsExport>,
+    open_files: OpenFiles,
+    transcoders: OpenFiles<Arc<Transcoder>>,
+    listings: OpenFiles<Listing>,
+    names: Arc<ClientNames>,
 }
 
 #[derive(Debug)]
 pub enum NfsProcedureError {
     FileDoesNotExist,
     StaleFileHandle,
+    NotADirectory,
+    IsADirectory,
+    AccessDenied,
+    IOError,
     NotImplemented,
 }
 
 impl From<std::io::Error> for NfsProcedureError {
-    fn from(_error: std::io::Error) -> NfsProcedureError {
-        NfsProcedureError::FileDoesNotExist
+    fn from(error: std::io::Error) -> NfsProcedureError {
+        match error.kind() {
+            ErrorKind::NotFound => NfsProcedureError::FileDoesNotExist,
+            ErrorKind::PermissionDenied => NfsProcedureError::AccessDenied,
+            _ => NfsProcedureError::IOError,
+        }
+    }
+}
+
+impl NfsProcedureError {
+    /// Status answered to the client, procedures that don't exist get no NFS status.
+    fn status(&self) -> Option<NfsStatus> {
+        match self {
+            NfsProcedureError::FileDoesNotExist => Some(NfsStatus::NoEnt),
+            NfsProcedureError::StaleFileHandle => Some(NfsStatus::Stale),
+            NfsProcedureError::NotADirectory => Some(NfsStatus::NotDir),
+            NfsProcedureError::IsADirectory => Some(NfsStatus::IsDir),
+            NfsProcedureError::AccessDenied => Some(NfsStatus::Acces),
+            NfsProcedureError::IOError => Some(NfsStatus::Io),
+            NfsProcedureError::NotImplemented => None,
+        }
     }
 }
 
 impl RpcNfsProgramHandler {
-    pub fn new() -> Self {
+    /// Handler of the calls to `export`, answering hosts in the encoding `names` has for them.
+    pub fn new(export: Arc<NfsExport>, names: Arc<ClientNames>) -> Self {
         Self {
-            path: PathBuf::from("/"),
-            file_handlers: HashMap::new(),
+            export,
+            open_files: OpenFiles::new(MAX_OPEN_FILES),
+            transcoders: OpenFiles::new(MAX_OPEN_FILES),
+            listings: OpenFiles::new(MAX_LISTINGS),
+            names,
         }
     }
 
-    fn reset_path(&mut self) {
-        self.path = PathBuf::from("/");
+    /// Attributes of a path as it is served, transcoded files have the size of the WAV file.
+    fn attributes(file: ExportedFile) -> NfsFileAttributes {
+        match file.transcoded {
+            Some(audio) => NfsFileAttributes::from(file.metadata).with_size(audio.size()),
+            None => NfsFileAttributes::from(file.metadata),
+        }
     }
 
-    pub fn lookup(&mut self, lookup: &rpc_packages::NfsLookup) -> Result<NfsLookupReply, NfsProcedureError> {
-        let mut temp_path = self.path.clone();
-        temp_path.push(lookup.filename());
+    /// What a handle stands for, handles the export doesn't know are stale.
+    fn path(&self, fhandle: &FileHandle) -> Result<(HandleKey, PathBuf), NfsProcedureError> {
+        self.export.path_of(fhandle.data()).ok_or(NfsProcedureError::StaleFileHandle)
+    }
 
-        match std::fs::metadata(temp_path.as_path()) {
-            Ok(metadata) => {
-                self.path = temp_path;
-                let fwrapper = get_fhandle(self.path.as_path(), metadata.ino())?;
+    /// Directory a handle was issued for.
+    fn directory(&self, fhandle: &FileHandle) -> Result<PathBuf, NfsProcedureError> {
+        let (_key, path) = self.path(fhandle)?;
+        match path.is_dir() {
+            true => Ok(path),
+            false => Err(NfsProcedureError::NotADirectory),
+        }
+    }
 
-                if metadata.is_file() {
-                    self.file_handlers.insert(fwrapper.inode, fwrapper.file);
-                    self.reset_path();
+    /// Look a name up in the directory of the handle. The handle of what is
+    /// found stays valid as long as the file does, across restarts too.
+    pub fn lookup(&mut self, lookup: &rpc_packages::NfsLookup) -> Result<NfsLookupReply, NfsProcedureError> {
+        let directory = self.directory(&lookup.fhandle)?;
+        let path = self.export.resolve(&directory, lookup.filename())
+            .map_err(|err| {
+                if err.kind() == ErrorKind::PermissionDenied {
+                    eprintln!("Refused NFS lookup; error = {}", err);
                 }
+                err
+            })?;
+        let file = self.export.file(&path)?;
 
-                Ok(NfsLookupReply {
-                    attributes: NfsFileAttributes::from(metadata),
-                    fhandle: FileHandle::new(fwrapper.encoded),
-                    status: NfsStatus::Ok,
-                })
-            },
-            Err(err) => {
-                self.reset_path();
-                Err(err.into())
-            },
-        }
+        Ok(NfsLookupReply {
+            fhandle: FileHandle::new(self.export.handle(&path, file.key)),
+            attributes: Self::attributes(file),
+            status: NfsStatus::Ok,
+        })
     }
 
     pub fn getattr(&mut self, arguments: &NfsGetAttr) -> Result<NfsGetAttrReply, NfsProcedureError> {
-        let inode = arguments.fhandle.ino();
-        match self.file_handlers.get(&inode) {
-            Some(file) => {
-                let metadata = file.metadata()?;
-                Ok(NfsGetAttrReply {
-                    status: NfsStatus::Ok,
-                    attributes: NfsFileAttributes::from(metadata),
-                })
-            },
-            None => Err(NfsProcedureError::StaleFileHandle),
-        }
+        let (_key, path) = self.path(&arguments.fhandle)?;
+
+        Ok(NfsGetAttrReply {
+            status: NfsStatus::Ok,
+            attributes: Self::attributes(self.export.file(&path)?),
+        })
     }
 
+    /// Transcoded files are read once the range asked for is decoded.
     pub fn read(&mut self, arguments: &NfsRead) -> Result<NfsReadReply, NfsProcedureError> {
-        let inode = arguments.fhandle.ino();
-        match self.file_handlers.get_mut(&inode) {
-            Some(mut file) => {
-                let data = read_file_range(&mut file, arguments.offset, arguments.count)?;
-                let metadata = file.metadata()?;
-
-                Ok(NfsReadReply {
-                    status: NfsStatus::Ok,
-                    attributes: NfsFileAttributes::from(metadata),
-                    data,
-                })
+        let (data, attributes) = self.start_read(arguments)?;
+        Self::finish_read(arguments, data, attributes)
+    }
+
+    /// Data of a read when it's at hand, the transcoder to wait for otherwise.
+    fn start_read(&mut self, arguments: &NfsRead) -> Result<(ReadData, NfsFileAttributes), NfsProcedureError> {
+        let (key, path) = self.path(&arguments.fhandle)?;
+        if path.is_dir() {
+            return Err(NfsProcedureError::IsADirectory);
+        }
+        let file = self.export.file(&path)?;
+        let transcoded = file.transcoded.clone();
+        let attributes = Self::attributes(file);
+
+        let data = match key.transcoded {
+            true => {
+                let cache = self.export.cache();
+                let transcoder = self.transcoders.get(key, || match transcoded {
+                    Some(audio) => cache.open(audio).map(Arc::new),
+                    None => Err(std::io::Error::new(ErrorKind::NotFound, format!("{:?} is not transcoded", path))),
+                })?;
+                ReadData::Transcoded(transcoder.clone())
             },
-            None => Err(NfsProcedureError::StaleFileHandle),
+            false => {
+                let file = self.open_files.get(key, || File::open(&path))?;
+                ReadData::Read(read_file_range(file, arguments.offset as u64, arguments.count)?)
+            },
+        };
+
+        Ok((data, attributes))
+    }
+
+    /// Reply to a read, waiting for a transcoder to decode the range asked for.
+    fn finish_read(arguments: &NfsRead, data: ReadData, attributes: NfsFileAttributes) -> Result<NfsReadReply, NfsProcedureError> {
+        let data = match data {
+            ReadData::Read(data) => data,
+            ReadData::Transcoded(transcoder) => NfsDataWrapper {
+                data: transcoder.read_at(arguments.offset as u64, arguments.count)?,
+            },
+        };
+
+        Ok(NfsReadReply {
+            status: NfsStatus::Ok,
+            attributes,
+            data,
+        })
+    }
+
+    /// Entries of a directory from the cookie on, as many as fit the count
+    /// asked for. The cookie of an entry is its position in the listing, which
+    /// is kept until the directory is modified so continuing it is cheap.
+    pub fn readdir(&mut self, arguments: &NfsReaddir, encoding: NameEncoding) -> Result<NfsReaddirReply, NfsProcedureError> {
+        let (key, directory) = self.path(&arguments.fhandle)?;
+        if !directory.is_dir() {
+            return Err(NfsProcedureError::NotADirectory);
+        }
+        let modified = std::fs::metadata(&directory)?.modified()?;
+        let export = &self.export;
+        let list = || Ok(Listing { modified, entries: export.list(&directory)? });
+        let cached = self.listings.get(key, list)?;
+        if cached.modified != modified {
+            *cached = list()?;
+        }
+        let listing = &cached.entries;
+
+        let mut size = READDIR_REPLY_SIZE;
+        let mut entries = vec![];
+        for (index, (name, inode)) in listing.iter().enumerate().skip(arguments.cookie as usize) {
+            let entry = NfsDirectoryEntry {
+                file_id: *inode as u32,
+                name: name.clone(),
+                cookie: index as u32 + 1,
+            };
+            size += entry.encoded_len(encoding);
+            // Always answer with an entry, a client could not make progress otherwise
+            if size > arguments.count as usize && !entries.is_empty() {
+                break;
+            }
+            entries.push(entry);
         }
+
+        Ok(NfsReaddirReply {
+            status: NfsStatus::Ok,
+            eof: arguments.cookie as usize + entries.len() >= listing.len(),
+            entries,
+            encoding,
+        })
+    }
+
+    /// Size and free space of the filesystem holding the handle. Files and
+    /// the directories leading to the library count as the first library folder.
+    pub fn statfs(&mut self, arguments: &NfsStatfs) -> Result<NfsStatfsReply, NfsProcedureError> {
+        let directory = match self.directory(&arguments.fhandle) {
+            Err(NfsProcedureError::NotADirectory) => self.export.root(),
+            directory => directory?,
+        };
+        let path = match (self.export.is_exported(&directory), self.export.roots().first()) {
+            (false, Some(root)) => root.clone(),
+            _ => directory,
+        };
+        let usage = filesystem_usage(&path)?;
+        let blocks = |count: u64| u32::try_from(count).unwrap_or(u32::MAX);
+
+        Ok(NfsStatfsReply {
+            status: NfsStatus::Ok,
+            transfer_size: TRANSFER_SIZE,
+            block_size: blocks(usage.block_size),
+            blocks: blocks(usage.blocks),
+            free_blocks: blocks(usage.free_blocks),
+            available_blocks: blocks(usage.available_blocks),
+        })
     }
 
-    fn call_procedure(&mut self, call: &RpcCall) -> Result<RpcReplyMessage, NfsProcedureError> {
+    fn call_procedure(&mut self, call: &RpcCall, encoding: NameEncoding) -> Result<RpcReplyMessage, NfsProcedureError> {
         match call.procedure() {
             RpcProcedure::NfsLookup(args) => Ok(RpcReplyMessage::NfsLookup(self.lookup(args)?)),
             RpcProcedure::NfsGetAttr(args) => Ok(RpcReplyMessage::NfsGetAttr(self.getattr(args)?)),
             RpcProcedure::NfsRead(args) => Ok(RpcReplyMessage::NfsRead(self.read(args)?)),
+            RpcProcedure::NfsReaddir(args) => Ok(RpcReplyMessage::NfsReaddir(self.readdir(args, encoding)?)),
+            RpcProcedure::NfsStatfs(args) => Ok(RpcReplyMessage::NfsStatfs(self.statfs(args)?)),
             _ => Err(NfsProcedureError::NotImplemented),
         }
     }
 
-    pub async fn run(&mut self, mut socket: UdpFramed<RpcBytesCodec>) {
-        while let Some(package) = socket.next().await {
-            match package {
-                Ok((rpc_message, address)) => {
-                    match rpc_message.message() {
-                        RpcMessageType::Call(call) => {
-                            match self.call_procedure(&call) {
-                                Ok(rpc_reply) => {
-                                    let package = (
-                                        RpcMessage::new(
-                                            rpc_message.transaction_id(),
-                                            RpcMessageType::Reply(RpcReply {
-                                                verifier: RpcAuth::Null,
-                                                reply_state: RpcReplyState::Accepted,
-                                                accept_state: RpcAcceptState::Success,
-                                                data: rpc_reply,
-                                            }),
-                                        ),
-                                        address,
-                                    );
-                                    socket.send(package).await;
-                                },
-                                Err(err) => {
-                                    eprintln!("{:?}", err);
-                                }
-                            };
-                        },
-                        _ => {},
-                    }
-                },
-                Err(_err) => {},
-            }
+    /// Reply to a message: results, an NFS error status for failed
+    /// procedures or the RPC state for calls that can't be served. Names are
+    /// sent in `encoding`.
+    fn reply(&mut self, message: &RpcMessage, encoding: NameEncoding) -> Option<RpcReply> {
+        let call = match message.message() {
+            RpcMessageType::Call(call) => call,
+            RpcMessageType::InvalidCall(err) => return Some(RpcReply::from(*err)),
+            RpcMessageType::Reply(_) => return None,
+        };
+        if *call.program() != RpcProgram::Nfs {
+            return Some(RpcReply::from(RpcCallError::ProgramUnavailable));
+        }
+        if let RpcProcedure::NfsNull = call.procedure() {
+            return Some(RpcReply::success(RpcReplyMessage::Void));
         }
+
+        Some(answer(self.call_procedure(call, encoding)))
     }
 }
 
+/// Results of a procedure, an NFS error status when it failed or the RPC
+/// state for procedures that don't exist.
+fn answer(results: Result<RpcReplyMessage, NfsProcedureError>) -> RpcReply {
+    match results {
+        Ok(reply) => RpcReply::success(reply),
+        Err(err) => match err.status() {
+            Some(status) => RpcReply::success(RpcReplyMessage::NfsError(status)),
+            None => RpcReply::from(RpcCallError::ProcedureUnavailable),
+        },
+    }
+}
+
+impl RpcService for Mutex<RpcNfsProgramHandler> {
+    /// One handler answers all clients, calls wait for each other. Reads of
+    /// transcoded files wait for the track to be decoded after letting go of
+    /// the handler, so the calls of others don't wait for that too.
+    fn reply(&self, message: &RpcMessage, address: SocketAddr) -> Option<RpcReply> {
+        let mut handler = match self.lock() {
+            Ok(handler) => handler,
+            Err(_err) => return Some(RpcReply::from(RpcCallError::SystemError)),
+        };
+        let encoding = handler.names.learn(message, address.ip());
+        let arguments = match message.message() {
+            RpcMessageType::Call(call) if *call.program() == RpcProgram::Nfs => match call.procedure() {
+                RpcProcedure::NfsRead(arguments) => arguments,
+                _ => return handler.reply(message, encoding),
+            },
+            _ => return handler.reply(message, encoding),
+        };
+
+        let started = handler.start_read(arguments);
+        drop(handler);
+        let results = started
+            .and_then(|(data, attributes)| RpcNfsProgramHandler::finish_read(arguments, data, attributes))
+            .map(RpcReplyMessage::NfsRead);
+        Some(answer(results))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+    use crate::library::Store;
+
+    fn lookup(handler: &mut RpcNfsProgramHandler, fhandle: &FileHandle, name: &str) -> Result<NfsLookupReply, NfsProcedureError> {
+        handler.lookup(&NfsLookup {
+            filename: PathBuf::from(name),
+            fhandle: fhandle.clone(),
+            encoding: NameEncoding::Utf16Le,
+        })
+    }
+
+    #[test]
+    fn it_looks_names_up_in_the_directory_of_the_handle() {
+        let temp = crate::utils::test_dir("nfs-lookup");
+        let base = temp.path().to_path_buf();
+        let root = base.join("music");
+        std::fs::create_dir_all(root.join("A")).unwrap();
+        std::fs::create_dir_all(root.join("B")).unwrap();
+        std::fs::write(root.join("A/One.mp3"), b"1").unwrap();
+        std::fs::write(root.join("B/Two.mp3"), b"22").unwrap();
+
+        let mut handler = RpcNfsProgramHandler::new(Arc::new(NfsExport::new(std::slice::from_ref(&root), None)), Arc::default());
+        let mut directory = FileHandle::new([0u8; 32]);
+        for component in root.canonicalize().unwrap().iter().skip(1) {
+            directory = lookup(&mut handler, &directory, &component.to_string_lossy()).unwrap().fhandle;
+        }
+        let a = lookup(&mut handler, &directory, "A").unwrap().fhandle;
+        let b = lookup(&mut handler, &directory, "B").unwrap().fhandle;
+
+        // A failed lookup and a lookup in another directory leave the handles alone
+        assert!(lookup(&mut handler, &a, "Missing.mp3").is_err());
+        assert!(lookup(&mut handler, &b, "Two.mp3").is_ok());
+        let one = lookup(&mut handler, &a, "One.mp3").unwrap();
+        assert!(lookup(&mut handler, &b, "One.mp3").is_err());
+        assert!(matches!(lookup(&mut handler, &one.fhandle, "One.mp3"), Err(NfsProcedureError::NotADirectory)));
+        assert!(matches!(
+            lookup(&mut handler, &FileHandle::new([9u8; 32]), "One.mp3"),
+            Err(NfsProcedureError::StaleFileHandle),
+        ));
+    }
+
+    #[test]
+    fn it_answers_failed_procedures_with_their_status() {
+        let mut handler = RpcNfsProgramHandler::new(Arc::new(NfsExport::new(&[], None)), Arc::default());
+        let stale = RpcMessage::try_from(Bytes::from(
+            [
+                &[0u8, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0x86, 0xa3, 0, 0, 0, 2, 0, 0, 0, 1][..],
+                &[0, 0, 0, 1, 0, 0, 0, 20][..],
+                &[0; 28][..],
+                &[0xff; 32][..],
+            ].concat(),
+        )).unwrap();
+        let write = RpcMessage::try_from(Bytes::from(
+            [&[0u8, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0x86, 0xa3, 0, 0, 0, 2, 0, 0, 0, 8][..], &[0; 36][..]].concat(),
+        )).unwrap();
+
+        assert_eq!(Some(RpcReply::success(RpcReplyMessage::NfsError(NfsStatus::Stale))), handler.reply(&stale, NameEncoding::Utf16Le));
+        assert_eq!(Some(RpcReply::from(RpcCallError::ProcedureUnavailable)), handler.reply(&write, NameEncoding::Utf16Le));
+    }
+
+    #[test]
+    fn it_continues_directory_listings_from_the_cookie() {
+        let temp = crate::utils::test_dir("nfs-readdir");
+        let root = temp.path().to_path_buf();
+        std::fs::create_dir_all(&root).unwrap();
+        for name in &["A.mp3", "B.mp3", "C.mp3"] {
+            std::fs::write(root.join(name), b"").unwrap();
+        }
+
+        let mut handler = RpcNfsProgramHandler::new(Arc::new(NfsExport::new(std::slice::from_ref(&root), None)), Arc::default());
+        let mut directory = FileHandle::new([0u8; 32]);
+        for component in root.canonicalize().unwrap().iter().skip(1) {
+            directory = lookup(&mut handler, &directory, &component.to_string_lossy()).unwrap().fhandle;
+        }
+
+        // Room for two entries with 5 character names
+        let first = handler.readdir(&NfsReaddir { fhandle: directory.clone(), cookie: 0, count: 12 + 2 * 28 }, NameEncoding::Utf16Le).unwrap();
+        let names: Vec<&str> = first.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!((vec!["A.mp3", "B.mp3"], false), (names, first.eof));
+
+        let cookie = first.entries.last().unwrap().cookie;
+        let rest = handler.readdir(&NfsReaddir { fhandle: directory.clone(), cookie, count: 8192 }, NameEncoding::Utf16Le).unwrap();
+        let names: Vec<&str> = rest.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!((vec!["C.mp3"], true), (names, rest.eof));
+
+        // Listings are kept until the directory is modified
+        let modified = std::fs::metadata(&root).unwrap().modified().unwrap();
+        std::fs::write(root.join("D.mp3"), b"").unwrap();
+        File::open(&root).unwrap().set_modified(modified).unwrap();
+        let names = |handler: &mut RpcNfsProgramHandler| -> Vec<String> {
+            handler.readdir(&NfsReaddir { fhandle: directory.clone(), cookie: 0, count: 8192 }, NameEncoding::Utf16Le).unwrap()
+                .entries.into_iter().map(|entry| entry.name).collect()
+        };
+        assert_eq!(vec!["A.mp3", "B.mp3", "C.mp3"], names(&mut handler));
+        File::open(&root).unwrap().set_modified(modified + std::time::Duration::from_secs(1)).unwrap();
+        assert_eq!(vec!["A.mp3", "B.mp3", "C.mp3", "D.mp3"], names(&mut handler));
+
+        let statfs = handler.statfs(&NfsStatfs { fhandle: directory }).unwrap();
+        assert!(statfs.blocks > 0 && statfs.free_blocks <= statfs.blocks);
+    }
+
+    #[test]
+    fn it_serves_tracks_players_cannot_decode_as_wave_files() {
+        let temp = crate::utils::test_dir("nfs-transcode");
+        let root = temp.path().join("music");
+        std::fs::create_dir_all(&root).unwrap();
+        // Mono AIFC file with two little endian 16 bit samples
+        std::fs::write(root.join("Track.aifc"), [
+            &b"FORM\0\0\0\0AIFCCOMM\0\0\0\x16\0\x01\0\0\0\x02\0\x10"[..],
+            &[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0],
+            &b"sowtSSND\0\0\0\x0c\0\0\0\0\0\0\0\0\x01\x02\x03\x04"[..],
+        ].concat()).unwrap();
+
+        let export = NfsExport::new(std::slice::from_ref(&root), Some(Store::new(temp.path().join("data"))));
+        let mut handler = RpcNfsProgramHandler::new(Arc::new(export), Arc::default());
+        let mut directory = FileHandle::new([0u8; 32]);
+        for component in root.canonicalize().unwrap().iter().skip(1) {
+            directory = lookup(&mut handler, &directory, &component.to_string_lossy()).unwrap().fhandle;
+        }
+
+        let listing = handler.readdir(&NfsReaddir { fhandle: directory.clone(), cookie: 0, count: 8192 }, NameEncoding::Utf16Le).unwrap();
+        let names: Vec<&str> = listing.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(vec!["Track.aifc.wav"], names);
+
+        let track = lookup(&mut handler, &directory, "Track.aifc").unwrap();
+        let wave = lookup(&mut handler, &directory, "Track.aifc.wav").unwrap();
+        assert_ne!(track.fhandle, wave.fhandle);
+        assert_eq!(44 + 4, wave.attributes.size());
+
+        let read = |handler: &mut RpcNfsProgramHandler, offset: u32, count: u32| {
+            handler.read(&NfsRead { fhandle: wave.fhandle.clone(), offset, count, total_count: count }).unwrap().data.data
+        };
+        assert_eq!(vec![0x01, 0x02, 0x03, 0x04], read(&mut handler, 44, 8192));
+        assert_eq!(b"RIFF".to_vec(), read(&mut handler, 0, 4));
+        assert_eq!(44 + 4, handler.getattr(&NfsGetAttr { fhandle: wave.fhandle.clone() }).unwrap().attributes.size());
+    }
+}
@@ -1,11 +1,12 @@
-use nom::number::complete::{be_u32, be_u16, le_u16, be_u8};
+use nom::number::complete::{be_u32, be_u8};
 use nom::multi::count;
+use nom::bytes::complete::take;
 use nom::IResult;
 use nom::error::ErrorKind::{Switch, MapRes};
 use bytes::{BytesMut, Bytes, BufMut};
 use std::convert::TryFrom;
-use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 use std::fs::Metadata;
 use std::os::unix::fs::MetadataExt;
 
@@ -43,6 +44,23 @@ impl RpcMessage {
         }))
     }
 
+    /// Decode a reply to a call of `procedure` of `program`, results included,
+    /// along with its transaction id.
+    pub fn decode_reply(input: &[u8], program: &RpcProgram, procedure: u32) -> Result<(u32, RpcReply), &'static str> {
+        let (input, message) = RpcMessage::decode(input).map_err(|_err| "Failed decoding RPC reply")?;
+        let mut reply = match message.message {
+            RpcMessageType::Reply(reply) => reply,
+            _ => return Err("Expected an RPC reply"),
+        };
+        if reply.reply_state == RpcReplyState::Accepted && reply.accept_state == RpcAcceptState::Success {
+            let (_input, data) = RpcReplyMessage::decode(input, program, procedure)
+                .map_err(|_err| "Failed decoding RPC results")?;
+            reply.data = data;
+        }
+
+        Ok((message.xid, reply))
+    }
+
     pub fn transaction_id(self) -> u32 {
         self.xid
     }
@@ -82,21 +100,34 @@ impl TryFrom<RpcMessage> for Bytes {
 #[derive(Debug, PartialEq)]
 pub enum RpcAuth {
     Null,
-    Unix,
+    Unix(RpcUnixAuth),
     Short,
     Des,
 }
 
+/// Opaque auth bodies are at most 400 bytes.
+const MAX_AUTH_LENGTH: u32 = 400;
+
 impl Decoder for RpcAuth {
     type Output = Self;
 
     fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
         let (input, flavor) = be_u32(input)?;
-        let (input, _length) = be_u32(input)?;
+        let (input, length) = be_u32(input)?;
+        if length > MAX_AUTH_LENGTH {
+            return Err(nom::Err::Error((input, MapRes)));
+        }
+        let (input, body) = take(padded_length(length as usize))(input)?;
+        let body = &body[..length as usize];
 
         match flavor {
             0u32 => Ok((input, RpcAuth::Null)),
-            1u32 => Ok((input, RpcAuth::Unix)),
+            1u32 => {
+                let (_body, auth) = RpcUnixAuth::decode(body)?;
+                Ok((input, RpcAuth::Unix(auth)))
+            },
+            2u32 => Ok((input, RpcAuth::Short)),
+            3u32 => Ok((input, RpcAuth::Des)),
             _ => Err(nom::Err::Error((input, Switch)))
         }
     }
@@ -106,25 +137,92 @@ impl From<RpcAuth> for Bytes {
     fn from(auth: RpcAuth) -> Bytes {
         let mut buffer = BytesMut::new();
 
-        let rpc_auth = match auth {
-            RpcAuth::Null => 0u32,
-            _             => 16u32,
+        let (flavor, body) = match auth {
+            RpcAuth::Null => (0u32, Bytes::new()),
+            RpcAuth::Unix(auth) => (1u32, Bytes::from(auth)),
+            RpcAuth::Short => (2u32, Bytes::new()),
+            RpcAuth::Des => (3u32, Bytes::new()),
         };
 
-        buffer.extend(rpc_auth.to_be_bytes().as_ref());
-        buffer.extend(0u32.to_be_bytes().as_ref());
+        buffer.extend(flavor.to_be_bytes().as_ref());
+        buffer.extend((body.len() as u32).to_be_bytes().as_ref());
+        buffer.extend(body);
 
         Bytes::from(buffer)
     }
 }
 
+impl std::fmt::Display for RpcAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RpcAuth::Null => write!(f, "no credentials"),
+            RpcAuth::Unix(auth) => write!(f, "machine {:?} uid {} gid {}", auth.machine_name, auth.uid, auth.gid),
+            RpcAuth::Short => write!(f, "short credentials"),
+            RpcAuth::Des => write!(f, "DES credentials"),
+        }
+    }
+}
+
+/// Length of XDR opaque data with its padding to 4 bytes.
+fn padded_length(length: usize) -> usize {
+    length.div_ceil(4) * 4
+}
+
+/// Zeros padding opaque data of `length` bytes to 4 bytes.
+fn fill_bytes(length: usize) -> Vec<u8> {
+    vec![0u8; padded_length(length) - length]
+}
+
+/// AUTH_UNIX credentials: who the caller says it is.
 #[derive(Debug, PartialEq)]
-pub struct RpcUnixAuth<'a> {
-    stamp: u32,
-    machine_name: &'a str,
-    uid: u32,
-    gid: u32,
-    gids: u32,
+pub struct RpcUnixAuth {
+    pub stamp: u32,
+    pub machine_name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub gids: Vec<u32>,
+}
+
+impl Decoder for RpcUnixAuth {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, stamp) = be_u32(input)?;
+        let (input, name_length) = be_u32(input)?;
+        let (input, machine_name) = take(padded_length(name_length as usize))(input)?;
+        let machine_name = machine_name.get(..name_length as usize).unwrap_or(machine_name);
+        let (input, uid) = be_u32(input)?;
+        let (input, gid) = be_u32(input)?;
+        let (input, gid_count) = be_u32(input)?;
+        let (input, gids) = count(be_u32, gid_count.min(16) as usize)(input)?;
+
+        Ok((input, RpcUnixAuth {
+            stamp,
+            machine_name: String::from_utf8_lossy(machine_name).into_owned(),
+            uid,
+            gid,
+            gids,
+        }))
+    }
+}
+
+impl From<RpcUnixAuth> for Bytes {
+    fn from(auth: RpcUnixAuth) -> Bytes {
+        let mut buffer = BytesMut::new();
+
+        buffer.put_u32(auth.stamp);
+        buffer.put_u32(auth.machine_name.len() as u32);
+        buffer.extend(auth.machine_name.as_bytes());
+        buffer.extend(fill_bytes(auth.machine_name.len()));
+        buffer.put_u32(auth.uid);
+        buffer.put_u32(auth.gid);
+        buffer.put_u32(auth.gids.len() as u32);
+        for gid in auth.gids {
+            buffer.put_u32(gid);
+        }
+
+        buffer.freeze()
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -133,11 +231,77 @@ pub struct RpcCall {
     program: RpcProgram,
     program_version: u32,
     procedure: RpcProcedure,
-    credentials: RpcCredentials,
+    credentials: RpcAuth,
     verifier: RpcAuth,
 }
 
+/// Version of the RPC protocol itself, the only one spoken.
+pub const RPC_VERSION: u32 = 2;
+
+/// Why a call can't be handed to its procedure, answered with the matching
+/// reject or accept state instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RpcCallError {
+    RpcMismatch,
+    ProgramUnavailable,
+    ProgramMismatch { low: u32, high: u32 },
+    ProcedureUnavailable,
+    GarbageArguments,
+    SystemError,
+    /// The caller isn't allowed to call the program.
+    Unauthorized,
+}
+
+impl From<RpcCallError> for RpcReply {
+    fn from(error: RpcCallError) -> RpcReply {
+        let accept_state = match error {
+            RpcCallError::RpcMismatch => return RpcReply::denied(RpcRejectState::RpcMismatch {
+                low: RPC_VERSION,
+                high: RPC_VERSION,
+            }),
+            RpcCallError::ProgramUnavailable => RpcAcceptState::ProgramUnavailable,
+            RpcCallError::ProgramMismatch { low, high } => RpcAcceptState::ProgramMismatch { low, high },
+            RpcCallError::ProcedureUnavailable => RpcAcceptState::ProcedureUnavailable,
+            RpcCallError::GarbageArguments => RpcAcceptState::GarbageArguments,
+            RpcCallError::SystemError => RpcAcceptState::SystemError,
+            RpcCallError::Unauthorized => return RpcReply::denied(RpcRejectState::AuthError(RpcAuthState::TooWeak)),
+        };
+
+        RpcReply {
+            verifier: RpcAuth::Null,
+            reply_state: RpcReplyState::Accepted,
+            accept_state,
+            data: RpcReplyMessage::Void,
+        }
+    }
+}
+
 impl RpcCall {
+    /// Call of a procedure at the highest version of its program that is served.
+    pub fn new(procedure: RpcProcedure, credentials: RpcAuth) -> RpcCall {
+        let program = procedure.program();
+        RpcCall {
+            version: RPC_VERSION,
+            program_version: program.versions().1,
+            program,
+            procedure,
+            credentials,
+            verifier: RpcAuth::Null,
+        }
+    }
+
+    fn decode_arguments<'a>(
+        input: &'a [u8],
+        program: &RpcProgram,
+        procedure: u32,
+    ) -> IResult<&'a [u8], (RpcAuth, RpcAuth, Option<RpcProcedure>)> {
+        let (input, credentials) = RpcAuth::decode(input)?;
+        let (input, verifier) = RpcAuth::decode(input)?;
+        let (input, procedure) = RpcProcedure::decode(input, program, procedure)?;
+
+        Ok((input, (credentials, verifier, procedure)))
+    }
+
     pub fn procedure(&self) -> &RpcProcedure {
         &self.procedure
     }
@@ -145,27 +309,60 @@ impl RpcCall {
     pub fn program(&self) -> &RpcProgram {
         &self.program
     }
+
+    pub fn credentials(&self) -> &RpcAuth {
+        &self.credentials
+    }
 }
 
 impl Decoder for RpcCall {
-    type Output = Self;
+    type Output = Result<Self, RpcCallError>;
 
+    /// Calls that can't be served still decode, into the reason why, so they can be answered.
     fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
         let (input, version) = be_u32(input)?;
-        let (input, (program, program_version)) = RpcProgram::decode(input)?;
+        let (input, program) = be_u32(input)?;
+        let (input, program_version) = be_u32(input)?;
         let (input, procedure) = be_u32(input)?;
-        let (input, credentials) = RpcCredentials::decode(input)?;
-        let (input, verifier) = RpcAuth::decode(input)?;
-        let (input, procedure) = RpcProcedure::decode(input, &program, procedure)?;
 
-        Ok((input, RpcCall {
-            version,
-            program,
-            program_version,
-            procedure,
-            credentials,
-            verifier,
-        }))
+        let program = match RpcProgram::from_number(program) {
+            _ if version != RPC_VERSION => return Ok((input, Err(RpcCallError::RpcMismatch))),
+            Some(program) => program,
+            None => return Ok((input, Err(RpcCallError::ProgramUnavailable))),
+        };
+        let (low, high) = program.versions();
+        if program_version < low || program_version > high {
+            return Ok((input, Err(RpcCallError::ProgramMismatch { low, high })));
+        }
+
+        match RpcCall::decode_arguments(input, &program, procedure) {
+            Ok((input, (credentials, verifier, Some(procedure)))) => Ok((input, Ok(RpcCall {
+                version,
+                program,
+                program_version,
+                procedure,
+                credentials,
+                verifier,
+            }))),
+            Ok((input, (_credentials, _verifier, None))) => Ok((input, Err(RpcCallError::ProcedureUnavailable))),
+            Err(_err) => Ok((input, Err(RpcCallError::GarbageArguments))),
+        }
+    }
+}
+
+impl From<RpcCall> for Bytes {
+    fn from(call: RpcCall) -> Bytes {
+        let mut buffer = BytesMut::new();
+
+        buffer.put_u32(call.version);
+        buffer.put_u32(call.program.number());
+        buffer.put_u32(call.program_version);
+        buffer.put_u32(call.procedure.number());
+        buffer.extend(Bytes::from(call.credentials));
+        buffer.extend(Bytes::from(call.verifier));
+        buffer.extend(Bytes::from(call.procedure));
+
+        buffer.freeze()
     }
 }
 
@@ -180,19 +377,102 @@ pub struct RpcReply {
 impl Decoder for RpcReply {
     type Output = Self;
 
-    fn decode(_input: &[u8]) -> IResult<&[u8], Self::Output> {
-        unimplemented!()
+    /// Everything but the results, which depend on the procedure called, see
+    /// `RpcMessage::decode_reply`. The results are left in the input.
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, reply_state) = be_u32(input)?;
+        if reply_state == 1 {
+            let (input, reject_state) = be_u32(input)?;
+            let (input, reason) = match reject_state {
+                0 => {
+                    let (input, low) = be_u32(input)?;
+                    let (input, high) = be_u32(input)?;
+                    (input, RpcRejectState::RpcMismatch { low, high })
+                },
+                1 => {
+                    let (input, auth_state) = be_u32(input)?;
+                    let auth_state = match auth_state {
+                        1 => RpcAuthState::BadCredentials,
+                        2 => RpcAuthState::RejectedCredentials,
+                        3 => RpcAuthState::BadVerifier,
+                        4 => RpcAuthState::RejectedVerifier,
+                        5 => RpcAuthState::TooWeak,
+                        _ => return Err(nom::Err::Error((input, Switch))),
+                    };
+                    (input, RpcRejectState::AuthError(auth_state))
+                },
+                _ => return Err(nom::Err::Error((input, Switch))),
+            };
+            return Ok((input, RpcReply::denied(reason)));
+        }
+        if reply_state != 0 {
+            return Err(nom::Err::Error((input, Switch)));
+        }
+
+        let (input, verifier) = RpcAuth::decode(input)?;
+        let (input, accept_state) = be_u32(input)?;
+        let (input, accept_state) = match accept_state {
+            0 => (input, RpcAcceptState::Success),
+            1 => (input, RpcAcceptState::ProgramUnavailable),
+            2 => {
+                let (input, low) = be_u32(input)?;
+                let (input, high) = be_u32(input)?;
+                (input, RpcAcceptState::ProgramMismatch { low, high })
+            },
+            3 => (input, RpcAcceptState::ProcedureUnavailable),
+            4 => (input, RpcAcceptState::GarbageArguments),
+            5 => (input, RpcAcceptState::SystemError),
+            _ => return Err(nom::Err::Error((input, Switch))),
+        };
+
+        Ok((input, RpcReply {
+            verifier,
+            reply_state: RpcReplyState::Accepted,
+            accept_state,
+            data: RpcReplyMessage::Void,
+        }))
+    }
+}
+
+impl RpcReply {
+    pub fn success(data: RpcReplyMessage) -> RpcReply {
+        RpcReply {
+            verifier: RpcAuth::Null,
+            reply_state: RpcReplyState::Accepted,
+            accept_state: RpcAcceptState::Success,
+            data,
+        }
+    }
+
+    pub fn denied(reason: RpcRejectState) -> RpcReply {
+        RpcReply {
+            verifier: RpcAuth::Null,
+            reply_state: RpcReplyState::Denied(reason),
+            accept_state: RpcAcceptState::Success,
+            data: RpcReplyMessage::Void,
+        }
     }
 }
 
 impl From<RpcReply> for Bytes {
+    /// Denied replies only carry the reason, accepted ones the verifier, the
+    /// accept state and, when successful, the results.
     fn from(reply: RpcReply) -> Bytes {
         let mut buffer = BytesMut::new();
 
-        buffer.extend(Bytes::from(reply.verifier));
+        let denied = match reply.reply_state {
+            RpcReplyState::Denied(_) => true,
+            RpcReplyState::Accepted => false,
+        };
+        let success = reply.accept_state == RpcAcceptState::Success;
         buffer.extend(Bytes::from(reply.reply_state));
-        buffer.extend(Bytes::from(reply.accept_state));
-        buffer.extend(Bytes::from(reply.data));
+        if !denied {
+            buffer.extend(Bytes::from(reply.verifier));
+            buffer.extend(Bytes::from(reply.accept_state));
+            if success {
+                buffer.extend(Bytes::from(reply.data));
+            }
+        }
 
         Bytes::from(buffer)
     }
@@ -217,11 +497,158 @@ impl From<NfsReadReply> for Bytes {
     }
 }
 
+/// How a client encodes file names: players send UTF-16LE, other clients
+/// such as the Linux kernel send UTF-8.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NameEncoding {
+    #[default]
+    Utf16Le,
+    Utf8,
+}
+
+impl NameEncoding {
+    /// Encoding of a name as a client sent it. UTF-8 names never hold a NUL
+    /// byte, while UTF-16LE names of ASCII characters always do.
+    pub fn detect(name: &[u8]) -> NameEncoding {
+        if !name.is_empty() && !name.contains(&0) && std::str::from_utf8(name).is_ok() {
+            NameEncoding::Utf8
+        } else {
+            NameEncoding::Utf16Le
+        }
+    }
+
+    pub fn encode(self, name: &str) -> Vec<u8> {
+        match self {
+            NameEncoding::Utf16Le => name.encode_utf16().flat_map(|unit| unit.to_le_bytes().to_vec()).collect(),
+            NameEncoding::Utf8 => name.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn decode(self, name: &[u8]) -> Option<String> {
+        match self {
+            NameEncoding::Utf16Le if name.len().is_multiple_of(2) => {
+                let units: Vec<u16> = name.chunks(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+                String::from_utf16(&units).ok()
+            },
+            NameEncoding::Utf16Le => None,
+            NameEncoding::Utf8 => String::from_utf8(name.to_vec()).ok(),
+        }
+    }
+}
+
+/// File name in the encoding of the client, padded to a multiple of 4 bytes.
+fn nfs_filename(name: &str, encoding: NameEncoding) -> Bytes {
+    let mut buffer = BytesMut::new();
+    let content = encoding.encode(name);
+
+    buffer.put_u32(content.len() as u32);
+    buffer.extend(&content);
+    buffer.extend(fill_bytes(content.len()));
+
+    buffer.freeze()
+}
+
+/// File name and the encoding the client sent it in.
+fn decode_nfs_filename(input: &[u8]) -> IResult<&[u8], (String, NameEncoding)> {
+    let (input, length) = be_u32(input)?;
+    let (input, contents) = take(length as usize)(input)?;
+    let (input, _fill_bytes) = take(padded_length(length as usize) - length as usize)(input)?;
+
+    let encoding = NameEncoding::detect(contents);
+    match encoding.decode(contents) {
+        Some(name) => Ok((input, (name, encoding))),
+        None => Err(nom::Err::Error((input, MapRes))),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NfsDirectoryEntry {
+    pub file_id: u32,
+    pub name: String,
+    /// Cookie to continue reading the directory after this entry.
+    pub cookie: u32,
+}
+
+impl NfsDirectoryEntry {
+    /// Bytes the entry takes up in a reply with names in `encoding`.
+    pub fn encoded_len(&self, encoding: NameEncoding) -> usize {
+        12 + nfs_filename(&self.name, encoding).len()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NfsReaddirReply {
+    pub status: NfsStatus,
+    pub entries: Vec<NfsDirectoryEntry>,
+    pub eof: bool,
+    /// Encoding of the names, the one the client sends.
+    pub encoding: NameEncoding,
+}
+
+impl From<NfsReaddirReply> for Bytes {
+    fn from(reply: NfsReaddirReply) -> Self {
+        let mut buffer = BytesMut::new();
+
+        buffer.extend(Bytes::from(reply.status));
+        for entry in reply.entries {
+            buffer.extend(VALUE_FOLLOWS.to_vec());
+            buffer.put_u32(entry.file_id);
+            buffer.extend(nfs_filename(&entry.name, reply.encoding));
+            buffer.put_u32(entry.cookie);
+        }
+        buffer.extend(NO_VALUE_FOLLOWS.to_vec());
+        buffer.put_u32(reply.eof as u32);
+
+        buffer.freeze()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NfsStatfsReply {
+    pub status: NfsStatus,
+    /// Largest READ or WRITE the server handles, in bytes.
+    pub transfer_size: u32,
+    pub block_size: u32,
+    pub blocks: u32,
+    pub free_blocks: u32,
+    /// Free blocks available to unprivileged users.
+    pub available_blocks: u32,
+}
+
+impl From<NfsStatfsReply> for Bytes {
+    fn from(reply: NfsStatfsReply) -> Self {
+        let mut buffer = BytesMut::new();
+
+        buffer.extend(Bytes::from(reply.status));
+        buffer.put_u32(reply.transfer_size);
+        buffer.put_u32(reply.block_size);
+        buffer.put_u32(reply.blocks);
+        buffer.put_u32(reply.free_blocks);
+        buffer.put_u32(reply.available_blocks);
+
+        buffer.freeze()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct NfsDataWrapper {
     pub data: Vec<u8>,
 }
 
+impl Decoder for NfsDataWrapper {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, length) = be_u32(input)?;
+        let (input, data) = take(length as usize)(input)?;
+        // Data ends the results, the padding may be left to the transport
+        let fill: IResult<&[u8], &[u8]> = take(padded_length(length as usize) - length as usize)(input);
+        let input = fill.map(|(input, _fill_bytes)| input).unwrap_or(input);
+
+        Ok((input, NfsDataWrapper { data: data.to_vec() }))
+    }
+}
+
 impl From<NfsDataWrapper> for Bytes {
     fn from(data: NfsDataWrapper) -> Self {
         let mut buffer = BytesMut::new();
@@ -235,23 +662,145 @@ impl From<NfsDataWrapper> for Bytes {
 
 #[derive(Debug, PartialEq)]
 pub enum RpcReplyMessage {
+    /// Whether SET and UNSET changed the registry.
+    PortmapBool(bool),
     PortmapGetport(PortmapGetportReply),
+    PortmapDump(Vec<PortmapMapping>),
     MountExport(MountExportReply),
     MountMnt(MountMntReply),
     NfsLookup(NfsLookupReply),
     NfsGetAttr(NfsGetAttrReply),
     NfsRead(NfsReadReply),
+    NfsReaddir(NfsReaddirReply),
+    NfsStatfs(NfsStatfsReply),
+    /// Failed NFS procedures only answer with their status.
+    NfsError(NfsStatus),
+    /// Procedures without results, like NULL, and unsuccessful replies.
+    Void,
+}
+
+impl RpcReplyMessage {
+    /// Results of a successful call, laid out as the procedure called returns them.
+    fn decode<'a>(input: &'a [u8], program: &RpcProgram, procedure: u32) -> IResult<&'a [u8], RpcReplyMessage> {
+        match (program, procedure) {
+            (_, 0) => Ok((input, RpcReplyMessage::Void)),
+            (RpcProgram::Portmap, 1) | (RpcProgram::Portmap, 2) => {
+                let (input, value) = be_u32(input)?;
+                Ok((input, RpcReplyMessage::PortmapBool(value != 0)))
+            },
+            (RpcProgram::Portmap, 3) => {
+                let (input, port) = be_u32(input)?;
+                Ok((input, RpcReplyMessage::PortmapGetport(PortmapGetportReply { port })))
+            },
+            (RpcProgram::Portmap, 4) => {
+                let mut mappings = vec![];
+                let (mut input, mut follows) = be_u32(input)?;
+                while follows != 0 {
+                    let (rest, mapping) = PortmapMapping::decode(input)?;
+                    let (rest, next) = be_u32(rest)?;
+                    mappings.push(mapping);
+                    input = rest;
+                    follows = next;
+                }
+                Ok((input, RpcReplyMessage::PortmapDump(mappings)))
+            },
+            (RpcProgram::Mount, 1) => {
+                let (input, reply) = MountMntReply::decode(input)?;
+                Ok((input, RpcReplyMessage::MountMnt(reply)))
+            },
+            (RpcProgram::Mount, 5) => {
+                let (input, reply) = MountExportReply::decode(input)?;
+                Ok((input, RpcReplyMessage::MountExport(reply)))
+            },
+            (RpcProgram::Nfs, _) => {
+                let (input, status) = NfsStatus::decode(input)?;
+                if status != NfsStatus::Ok {
+                    return Ok((input, RpcReplyMessage::NfsError(status)));
+                }
+                match procedure {
+                    1 => {
+                        let (input, attributes) = NfsFileAttributes::decode(input)?;
+                        Ok((input, RpcReplyMessage::NfsGetAttr(NfsGetAttrReply { status, attributes })))
+                    },
+                    4 => {
+                        let (input, fhandle) = FileHandle::decode(input)?;
+                        let (input, attributes) = NfsFileAttributes::decode(input)?;
+                        Ok((input, RpcReplyMessage::NfsLookup(NfsLookupReply { status, fhandle, attributes })))
+                    },
+                    6 => {
+                        let (input, attributes) = NfsFileAttributes::decode(input)?;
+                        let (input, data) = NfsDataWrapper::decode(input)?;
+                        Ok((input, RpcReplyMessage::NfsRead(NfsReadReply { status, attributes, data })))
+                    },
+                    16 => {
+                        let mut entries = vec![];
+                        let mut encoding = None;
+                        let (mut input, mut follows) = be_u32(input)?;
+                        while follows != 0 {
+                            let (rest, file_id) = be_u32(input)?;
+                            let (rest, (name, name_encoding)) = decode_nfs_filename(rest)?;
+                            encoding = encoding.or(Some(name_encoding));
+                            let (rest, cookie) = be_u32(rest)?;
+                            let (rest, next) = be_u32(rest)?;
+                            entries.push(NfsDirectoryEntry { file_id, name, cookie });
+                            input = rest;
+                            follows = next;
+                        }
+                        let (input, eof) = be_u32(input)?;
+                        Ok((input, RpcReplyMessage::NfsReaddir(NfsReaddirReply {
+                            status,
+                            entries,
+                            eof: eof != 0,
+                            encoding: encoding.unwrap_or_default(),
+                        })))
+                    },
+                    17 => {
+                        let (input, transfer_size) = be_u32(input)?;
+                        let (input, block_size) = be_u32(input)?;
+                        let (input, blocks) = be_u32(input)?;
+                        let (input, free_blocks) = be_u32(input)?;
+                        let (input, available_blocks) = be_u32(input)?;
+                        Ok((input, RpcReplyMessage::NfsStatfs(NfsStatfsReply {
+                            status,
+                            transfer_size,
+                            block_size,
+                            blocks,
+                            free_blocks,
+                            available_blocks,
+                        })))
+                    },
+                    _ => Err(nom::Err::Error((input, Switch))),
+                }
+            },
+            _ => Err(nom::Err::Error((input, Switch))),
+        }
+    }
 }
 
 impl From<RpcReplyMessage> for Bytes {
     fn from(reply_message: RpcReplyMessage) -> Bytes {
         match reply_message {
+            RpcReplyMessage::PortmapBool(value)    => Bytes::from((value as u32).to_be_bytes().to_vec()),
             RpcReplyMessage::PortmapGetport(reply) => Bytes::from(reply),
+            RpcReplyMessage::PortmapDump(mappings) => {
+                // A list of optional entries, every entry is preceded by a 1 and the end by a 0
+                let mut buffer = BytesMut::new();
+                for mapping in mappings {
+                    buffer.extend(1u32.to_be_bytes().as_ref());
+                    buffer.extend(Bytes::from(mapping));
+                }
+                buffer.extend(0u32.to_be_bytes().as_ref());
+                buffer.freeze()
+            },
             RpcReplyMessage::MountExport(reply)    => Bytes::from(reply),
             RpcReplyMessage::MountMnt(reply)       => Bytes::from(reply),
             RpcReplyMessage::NfsLookup(reply)      => Bytes::from(reply),
             RpcReplyMessage::NfsGetAttr(reply)     => Bytes::from(reply),
             RpcReplyMessage::NfsRead(reply)        => Bytes::from(reply),
+            RpcReplyMessage::NfsReaddir(reply)     => Bytes::from(reply),
+            RpcReplyMessage::NfsStatfs(reply)      => Bytes::from(reply),
+            RpcReplyMessage::NfsError(status)      => Bytes::from(status),
+            RpcReplyMessage::Void                  => Bytes::new(),
         }
     }
 }
@@ -272,22 +821,13 @@ impl From<ExportListEntry> for Bytes {
     fn from(entry: ExportListEntry) -> Bytes {
         let mut buf = BytesMut::new();
 
-        let content = entry.directory.encode_utf16()
-            .into_iter()
-            .flat_map(|item| { item.to_le_bytes().to_vec() })
-            .collect::<Bytes>();
-        buf.extend((content.len() as u32).to_be_bytes().to_vec());
-        buf.put(content);
-        buf.extend(OPAQUE_DATA.to_vec());
+        buf.extend(nfs_filename(&entry.directory, entry.encoding));
 
-        if entry.groups.len() > 0 {
+        for group in entry.groups {
             buf.extend(VALUE_FOLLOWS.to_vec());
-
-            for group in entry.groups {
-                buf.extend((group.len() as u32).to_be_bytes().to_vec());
-                buf.extend(group.as_bytes());
-                buf.extend(OPAQUE_DATA.to_vec());
-            }
+            buf.extend((group.len() as u32).to_be_bytes().to_vec());
+            buf.extend(group.as_bytes());
+            buf.extend(fill_bytes(group.len()));
         }
 
         buf.extend(NO_VALUE_FOLLOWS.to_vec());
@@ -303,18 +843,32 @@ pub struct MountExportReply {
 
 const VALUE_FOLLOWS: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
 const NO_VALUE_FOLLOWS: [u8; 4] = [0x00, 0x00, 0x00, 0x00];
-const OPAQUE_DATA: [u8; 2] = [0x00, 0x00];
+
+impl Decoder for MountExportReply {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let mut export_list_entries = vec![];
+        let (mut input, mut follows) = be_u32(input)?;
+        while follows != 0 {
+            let (rest, entry) = ExportListEntry::decode(input)?;
+            let (rest, next) = be_u32(rest)?;
+            export_list_entries.push(entry);
+            input = rest;
+            follows = next;
+        }
+
+        Ok((input, MountExportReply { export_list_entries }))
+    }
+}
 
 impl From<MountExportReply> for Bytes {
     fn from(reply: MountExportReply) -> Bytes {
         let mut buf = BytesMut::new();
 
-        if reply.export_list_entries.len() > 0 {
+        for entry in reply.export_list_entries {
             buf.extend(VALUE_FOLLOWS.to_vec());
-
-            for entry in reply.export_list_entries {
-                buf.extend(Bytes::from(entry));
-            }
+            buf.extend(Bytes::from(entry));
         }
 
         buf.extend(NO_VALUE_FOLLOWS.to_vec());
@@ -326,6 +880,7 @@ impl From<MountExportReply> for Bytes {
 pub struct ExportListEntry {
     directory: String,
     groups: Vec<String>,
+    encoding: NameEncoding,
 }
 
 impl ExportListEntry {
@@ -333,13 +888,75 @@ impl ExportListEntry {
         ExportListEntry {
             directory,
             groups,
+            encoding: NameEncoding::default(),
         }
     }
+
+    pub fn directory(&self) -> &str {
+        &self.directory
+    }
+
+    /// Send the directory in the encoding of the client.
+    pub fn set_encoding(&mut self, encoding: NameEncoding) {
+        self.encoding = encoding;
+    }
 }
 
-#[derive(Debug, PartialEq)]
+impl Decoder for ExportListEntry {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, (directory, encoding)) = decode_nfs_filename(input)?;
+
+        let mut groups = vec![];
+        let (mut input, mut follows) = be_u32(input)?;
+        while follows != 0 {
+            let (rest, length) = be_u32(input)?;
+            let (rest, group) = take(length as usize)(rest)?;
+            let (rest, _fill_bytes) = take(padded_length(length as usize) - length as usize)(rest)?;
+            let (rest, next) = be_u32(rest)?;
+            groups.push(String::from_utf8_lossy(group).into_owned());
+            input = rest;
+            follows = next;
+        }
+
+        Ok((input, ExportListEntry { directory, groups, encoding }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NfsStatus {
     Ok,
+    Perm,
+    NoEnt,
+    Io,
+    Acces,
+    NotDir,
+    IsDir,
+    NameTooLong,
+    Stale,
+}
+
+impl Decoder for NfsStatus {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, status) = be_u32(input)?;
+        let status = match status {
+            0 => NfsStatus::Ok,
+            1 => NfsStatus::Perm,
+            2 => NfsStatus::NoEnt,
+            5 => NfsStatus::Io,
+            13 => NfsStatus::Acces,
+            20 => NfsStatus::NotDir,
+            21 => NfsStatus::IsDir,
+            63 => NfsStatus::NameTooLong,
+            70 => NfsStatus::Stale,
+            _ => return Err(nom::Err::Error((input, Switch))),
+        };
+
+        Ok((input, status))
+    }
 }
 
 impl From<NfsStatus> for Bytes {
@@ -347,6 +964,14 @@ impl From<NfsStatus> for Bytes {
         let mut buffer = BytesMut::new();
         buffer.put_u32(match status {
             NfsStatus::Ok => 0u32,
+            NfsStatus::Perm => 1,
+            NfsStatus::NoEnt => 2,
+            NfsStatus::Io => 5,
+            NfsStatus::Acces => 13,
+            NfsStatus::NotDir => 20,
+            NfsStatus::IsDir => 21,
+            NfsStatus::NameTooLong => 63,
+            NfsStatus::Stale => 70,
         });
         buffer.freeze()
     }
@@ -365,6 +990,29 @@ impl MountMntReply {
             fhandle,
         }
     }
+
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    pub fn fhandle(&self) -> &FileHandle {
+        &self.fhandle
+    }
+}
+
+impl Decoder for MountMntReply {
+    type Output = Self;
+
+    /// Failed mounts carry no handle, they get an empty one.
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, status) = be_u32(input)?;
+        if status != 0 {
+            return Ok((input, MountMntReply::new(status, FileHandle::new([0u8; 32]))));
+        }
+        let (input, fhandle) = FileHandle::decode(input)?;
+
+        Ok((input, MountMntReply { status, fhandle }))
+    }
 }
 
 impl From<MountMntReply> for Bytes {
@@ -394,10 +1042,11 @@ impl From<Metadata> for NfsFileAttributes {
             nlink: metadata.nlink() as u32,
             uid: metadata.uid(),
             gid: metadata.gid(),
-            size: metadata.size() as u32,
+            // NFSv2 sizes are 32 bits, larger files are clamped rather than wrapped
+            size: u32::try_from(metadata.size()).unwrap_or(u32::MAX),
             blocksize: metadata.blksize() as u32,
             rdev: metadata.rdev() as u32,
-            blocks: metadata.blocks() as u32,
+            blocks: u32::try_from(metadata.blocks()).unwrap_or(u32::MAX),
             fsid: 0,
             file_id: 0,
             atime: metadata.accessed().unwrap(),
@@ -468,6 +1117,14 @@ fn system_time_to_bytes(time: SystemTime) -> Bytes {
     }
 }
 
+/// A timeval as RFC 1094 defines it, seconds and microseconds.
+fn decode_time(input: &[u8]) -> IResult<&[u8], SystemTime> {
+    let (input, secs) = be_u32(input)?;
+    let (input, usecs) = be_u32(input)?;
+
+    Ok((input, SystemTime::UNIX_EPOCH + Duration::new(secs as u64, usecs.saturating_mul(1000))))
+}
+
 impl From<NfsLookupReply> for Bytes {
     fn from(reply: NfsLookupReply) -> Bytes {
         let mut buffer = BytesMut::with_capacity(104);
@@ -480,6 +1137,44 @@ impl From<NfsLookupReply> for Bytes {
     }
 }
 
+impl Decoder for NfsFileAttributes {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, _type) = FileType::decode(input)?;
+        let (input, mode) = FileMode::decode(input)?;
+        let (input, nlink) = be_u32(input)?;
+        let (input, uid) = be_u32(input)?;
+        let (input, gid) = be_u32(input)?;
+        let (input, size) = be_u32(input)?;
+        let (input, blocksize) = be_u32(input)?;
+        let (input, rdev) = be_u32(input)?;
+        let (input, blocks) = be_u32(input)?;
+        let (input, fsid) = be_u32(input)?;
+        let (input, file_id) = be_u32(input)?;
+        let (input, atime) = decode_time(input)?;
+        let (input, mtime) = decode_time(input)?;
+        let (input, ctime) = decode_time(input)?;
+
+        Ok((input, NfsFileAttributes {
+            _type,
+            mode,
+            nlink,
+            uid,
+            gid,
+            size,
+            blocksize,
+            rdev,
+            blocks,
+            fsid,
+            file_id,
+            atime,
+            mtime,
+            ctime,
+        }))
+    }
+}
+
 impl From<NfsFileAttributes> for Bytes {
     fn from(attributes: NfsFileAttributes) -> Bytes {
         let mut buffer = BytesMut::new();
@@ -511,6 +1206,19 @@ pub struct FileMode {
     other: u8,
 }
 
+impl Decoder for FileMode {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, name) = be_u8(input)?;
+        let (input, user) = be_u8(input)?;
+        let (input, group) = be_u8(input)?;
+        let (input, other) = be_u8(input)?;
+
+        Ok((input, FileMode { name, user, group, other }))
+    }
+}
+
 impl From<FileMode> for Bytes {
     fn from(file_mode: FileMode) -> Bytes {
         let mut buffer = BytesMut::with_capacity(4);
@@ -528,6 +1236,19 @@ enum FileType {
     Directory, // 2u32
 }
 
+impl Decoder for FileType {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, file_type) = be_u32(input)?;
+        match file_type {
+            1 => Ok((input, FileType::File)),
+            2 => Ok((input, FileType::Directory)),
+            _ => Err(nom::Err::Error((input, Switch))),
+        }
+    }
+}
+
 impl From<FileType> for Bytes {
     fn from(file_type: FileType) -> Self {
         let mut buffer = BytesMut::with_capacity(4);
@@ -539,39 +1260,110 @@ impl From<FileType> for Bytes {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RpcAcceptState {
     Success,
+    ProgramUnavailable,
+    /// Lowest and highest version of the program that are served.
+    ProgramMismatch { low: u32, high: u32 },
+    ProcedureUnavailable,
+    GarbageArguments,
+    SystemError,
 }
 
 impl From<RpcAcceptState> for Bytes {
     fn from(state: RpcAcceptState) -> Bytes {
-        let reply_state_value = match state {
+        let mut buffer = BytesMut::new();
+
+        let accept_state_value = match state {
             RpcAcceptState::Success => 0u32,
+            RpcAcceptState::ProgramUnavailable => 1u32,
+            RpcAcceptState::ProgramMismatch { .. } => 2u32,
+            RpcAcceptState::ProcedureUnavailable => 3u32,
+            RpcAcceptState::GarbageArguments => 4u32,
+            RpcAcceptState::SystemError => 5u32,
         };
+        buffer.extend(accept_state_value.to_be_bytes().as_ref());
+        if let RpcAcceptState::ProgramMismatch { low, high } = state {
+            buffer.extend(low.to_be_bytes().as_ref());
+            buffer.extend(high.to_be_bytes().as_ref());
+        }
 
-        Bytes::from(reply_state_value.to_be_bytes().to_vec())
+        buffer.freeze()
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RpcAuthState {
+    BadCredentials,
+    RejectedCredentials,
+    BadVerifier,
+    RejectedVerifier,
+    /// Refused for security reasons.
+    TooWeak,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RpcRejectState {
+    /// Lowest and highest version of the RPC protocol that are spoken.
+    RpcMismatch { low: u32, high: u32 },
+    AuthError(RpcAuthState),
+}
+
+impl From<RpcRejectState> for Bytes {
+    fn from(state: RpcRejectState) -> Bytes {
+        let mut buffer = BytesMut::new();
+
+        match state {
+            RpcRejectState::RpcMismatch { low, high } => {
+                buffer.extend(0u32.to_be_bytes().as_ref());
+                buffer.extend(low.to_be_bytes().as_ref());
+                buffer.extend(high.to_be_bytes().as_ref());
+            },
+            RpcRejectState::AuthError(state) => {
+                let auth_state_value = match state {
+                    RpcAuthState::BadCredentials => 1u32,
+                    RpcAuthState::RejectedCredentials => 2u32,
+                    RpcAuthState::BadVerifier => 3u32,
+                    RpcAuthState::RejectedVerifier => 4u32,
+                    RpcAuthState::TooWeak => 5u32,
+                };
+                buffer.extend(1u32.to_be_bytes().as_ref());
+                buffer.extend(auth_state_value.to_be_bytes().as_ref());
+            },
+        }
+
+        buffer.freeze()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RpcReplyState {
     Accepted,
+    Denied(RpcRejectState),
 }
 
 impl From<RpcReplyState> for Bytes {
     fn from(state: RpcReplyState) -> Bytes {
-        let reply_state_value = match state {
-            RpcReplyState::Accepted => 0u32,
-        };
+        let mut buffer = BytesMut::new();
+
+        match state {
+            RpcReplyState::Accepted => buffer.extend(0u32.to_be_bytes().as_ref()),
+            RpcReplyState::Denied(reason) => {
+                buffer.extend(1u32.to_be_bytes().as_ref());
+                buffer.extend(Bytes::from(reason));
+            },
+        }
 
-        Bytes::from(reply_state_value.to_be_bytes().to_vec())
+        buffer.freeze()
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum RpcMessageType {
     Call(RpcCall),
+    /// A call that can't be served, see `RpcCallError`.
+    InvalidCall(RpcCallError),
     Reply(RpcReply),
 }
 
@@ -583,8 +1375,10 @@ impl Decoder for RpcMessageType {
 
         match message_type {
             0u32 => {
-                let (input, rpc_call) = RpcCall::decode(input)?;
-                Ok((input, RpcMessageType::Call(rpc_call)))
+                match RpcCall::decode(input)? {
+                    (input, Ok(rpc_call)) => Ok((input, RpcMessageType::Call(rpc_call))),
+                    (input, Err(err)) => Ok((input, RpcMessageType::InvalidCall(err))),
+                }
             },
             1u32 => {
                 let (input, rpc_reply) = RpcReply::decode(input)?;
@@ -600,11 +1394,15 @@ impl From<RpcMessageType> for Bytes {
         let mut buffer = BytesMut::new();
 
         match message {
+            RpcMessageType::Call(call) => {
+                buffer.extend(0u32.to_be_bytes().as_ref());
+                buffer.extend(Bytes::from(call));
+            },
             RpcMessageType::Reply(reply) => {
                 buffer.extend(1u32.to_be_bytes().as_ref());
                 buffer.extend(Bytes::from(reply));
             },
-            _ => {},
+            RpcMessageType::InvalidCall(_) => {},
         };
 
         Bytes::from(buffer)
@@ -618,6 +1416,34 @@ pub enum RpcProgram {
     Mount,
 }
 
+impl RpcProgram {
+    pub fn number(&self) -> u32 {
+        match self {
+            RpcProgram::Portmap => 100000,
+            RpcProgram::Nfs => 100003,
+            RpcProgram::Mount => 100005,
+        }
+    }
+
+    pub fn from_number(program: u32) -> Option<Self> {
+        match program {
+            100000u32 => Some(RpcProgram::Portmap),
+            100003u32 => Some(RpcProgram::Nfs),
+            100005u32 => Some(RpcProgram::Mount),
+            _ => None,
+        }
+    }
+
+    /// Lowest and highest version served: portmap 2, NFS 2 and MOUNT 1 as the players use them.
+    pub fn versions(&self) -> (u32, u32) {
+        match self {
+            RpcProgram::Portmap => (2, 2),
+            RpcProgram::Nfs => (2, 2),
+            RpcProgram::Mount => (1, 1),
+        }
+    }
+}
+
 impl Decoder for RpcProgram {
     type Output = (RpcProgram, u32);
 
@@ -625,11 +1451,9 @@ impl Decoder for RpcProgram {
         let (input, program) = be_u32(input)?;
         let (input, program_version) = be_u32(input)?;
 
-        match program {
-            100000u32 => Ok((input, (RpcProgram::Portmap, program_version))),
-            100003u32 => Ok((input, (RpcProgram::Nfs, program_version))),
-            100005u32 => Ok((input, (RpcProgram::Mount, program_version))),
-            _ => Err(nom::Err::Error((input, Switch))),
+        match RpcProgram::from_number(program) {
+            Some(program) => Ok((input, (program, program_version))),
+            None => Err(nom::Err::Error((input, Switch))),
         }
     }
 }
@@ -664,52 +1488,153 @@ impl Decoder for PortmapProcedure {
 #[derive(Debug, PartialEq)]
 pub enum RpcProcedure {
     PortmapNull,
-    PortmapSet,
-    PortmapUnset,
-    PortmapGetport(PortmapGetport),
+    PortmapSet(PortmapMapping),
+    PortmapUnset(PortmapMapping),
+    PortmapGetport(PortmapMapping),
     PortmapDump,
     PortmapCallResult,
     NfsNull,
     NfsGetAttr(NfsGetAttr),
     NfsLookup(NfsLookup),
     NfsRead(NfsRead),
+    NfsReaddir(NfsReaddir),
+    NfsStatfs(NfsStatfs),
     MountMnt(MountMnt),
     MountExport,
     MountNull,
 }
 
 impl RpcProcedure {
-    fn decode<'a>(input: &'a [u8], program: &RpcProgram, procedure: u32) -> IResult<&'a [u8], RpcProcedure> {
+    pub fn program(&self) -> RpcProgram {
+        match self {
+            RpcProcedure::PortmapNull
+            | RpcProcedure::PortmapSet(_)
+            | RpcProcedure::PortmapUnset(_)
+            | RpcProcedure::PortmapGetport(_)
+            | RpcProcedure::PortmapDump
+            | RpcProcedure::PortmapCallResult => RpcProgram::Portmap,
+            RpcProcedure::NfsNull
+            | RpcProcedure::NfsGetAttr(_)
+            | RpcProcedure::NfsLookup(_)
+            | RpcProcedure::NfsRead(_)
+            | RpcProcedure::NfsReaddir(_)
+            | RpcProcedure::NfsStatfs(_) => RpcProgram::Nfs,
+            RpcProcedure::MountNull
+            | RpcProcedure::MountMnt(_)
+            | RpcProcedure::MountExport => RpcProgram::Mount,
+        }
+    }
+
+    /// Number of the procedure within its program.
+    pub fn number(&self) -> u32 {
+        match self {
+            RpcProcedure::PortmapNull => 0,
+            RpcProcedure::PortmapSet(_) => 1,
+            RpcProcedure::PortmapUnset(_) => 2,
+            RpcProcedure::PortmapGetport(_) => 3,
+            RpcProcedure::PortmapDump => 4,
+            RpcProcedure::PortmapCallResult => 5,
+            RpcProcedure::NfsNull => 0,
+            RpcProcedure::NfsGetAttr(_) => 1,
+            RpcProcedure::NfsLookup(_) => 4,
+            RpcProcedure::NfsRead(_) => 6,
+            RpcProcedure::NfsReaddir(_) => 16,
+            RpcProcedure::NfsStatfs(_) => 17,
+            RpcProcedure::MountNull => 0,
+            RpcProcedure::MountMnt(_) => 1,
+            RpcProcedure::MountExport => 5,
+        }
+    }
+
+    /// `None` for procedures the program doesn't have.
+    fn decode<'a>(input: &'a [u8], program: &RpcProgram, procedure: u32) -> IResult<&'a [u8], Option<RpcProcedure>> {
         match (program, procedure) {
-            (RpcProgram::Portmap, 0u32) => Ok((input, RpcProcedure::PortmapNull)),
-            (RpcProgram::Portmap, 1u32) => Ok((input, RpcProcedure::PortmapSet)),
-            (RpcProgram::Portmap, 2u32) => Ok((input, RpcProcedure::PortmapUnset)),
+            (RpcProgram::Portmap, 0u32) => Ok((input, Some(RpcProcedure::PortmapNull))),
+            (RpcProgram::Portmap, 1u32) => {
+                let (input, data) = PortmapMapping::decode(input)?;
+                Ok((input, Some(RpcProcedure::PortmapSet(data))))
+            },
+            (RpcProgram::Portmap, 2u32) => {
+                let (input, data) = PortmapMapping::decode(input)?;
+                Ok((input, Some(RpcProcedure::PortmapUnset(data))))
+            },
             (RpcProgram::Portmap, 3u32) => {
-                let (input, data) = PortmapGetport::decode(&input)?;
-                Ok((input, RpcProcedure::PortmapGetport(data)))
+                let (input, data) = PortmapMapping::decode(input)?;
+                Ok((input, Some(RpcProcedure::PortmapGetport(data))))
             },
-            (RpcProgram::Portmap, 4u32) => Ok((input, RpcProcedure::PortmapDump)),
-            (RpcProgram::Portmap, 5u32) => Ok((input, RpcProcedure::PortmapCallResult)),
-            (RpcProgram::Portmap, _)    => Err(nom::Err::Error((input, Switch))),
+            (RpcProgram::Portmap, 4u32) => Ok((input, Some(RpcProcedure::PortmapDump))),
+            (RpcProgram::Portmap, 5u32) => Ok((input, Some(RpcProcedure::PortmapCallResult))),
+            (RpcProgram::Portmap, _)    => Ok((input, None)),
+            (RpcProgram::Nfs, 0) => Ok((input, Some(RpcProcedure::NfsNull))),
             (RpcProgram::Nfs, 1) => {
                 let (input, data) = NfsGetAttr::decode(input)?;
-                Ok((input, RpcProcedure::NfsGetAttr(data)))
+                Ok((input, Some(RpcProcedure::NfsGetAttr(data))))
             },
             (RpcProgram::Nfs, 4) => {
                 let (input, data) = NfsLookup::decode(&input)?;
-                Ok((input, RpcProcedure::NfsLookup(data)))
+                Ok((input, Some(RpcProcedure::NfsLookup(data))))
             },
             (RpcProgram::Nfs, 6) => {
                 let (input, data) = NfsRead::decode(&input)?;
-                Ok((input, RpcProcedure::NfsRead(data)))
+                Ok((input, Some(RpcProcedure::NfsRead(data))))
             }
-            (RpcProgram::Nfs, _)        => Err(nom::Err::Error((input, Switch))),
-            (RpcProgram::Mount, 5u32)   => Ok((input, RpcProcedure::MountExport)),
+            (RpcProgram::Nfs, 16) => {
+                let (input, data) = NfsReaddir::decode(input)?;
+                Ok((input, Some(RpcProcedure::NfsReaddir(data))))
+            },
+            (RpcProgram::Nfs, 17) => {
+                let (input, data) = NfsStatfs::decode(input)?;
+                Ok((input, Some(RpcProcedure::NfsStatfs(data))))
+            },
+            (RpcProgram::Nfs, _)        => Ok((input, None)),
+            (RpcProgram::Mount, 0u32)   => Ok((input, Some(RpcProcedure::MountNull))),
+            (RpcProgram::Mount, 5u32)   => Ok((input, Some(RpcProcedure::MountExport))),
             (RpcProgram::Mount, 1u32)   => {
                 let (input, data) = MountMnt::decode(&input)?;
-                Ok((input, RpcProcedure::MountMnt(data)))
+                Ok((input, Some(RpcProcedure::MountMnt(data))))
             },
-            (RpcProgram::Mount, _)      => Err(nom::Err::Error((input, Switch))),
+            (RpcProgram::Mount, _)      => Ok((input, None)),
+        }
+    }
+}
+
+/// Arguments are encoded as `RpcCall` sends them.
+impl From<RpcProcedure> for Bytes {
+    fn from(procedure: RpcProcedure) -> Bytes {
+        match procedure {
+            RpcProcedure::PortmapSet(mapping)
+            | RpcProcedure::PortmapUnset(mapping)
+            | RpcProcedure::PortmapGetport(mapping) => Bytes::from(mapping),
+            RpcProcedure::NfsGetAttr(arguments) => Bytes::from(arguments.fhandle),
+            RpcProcedure::NfsStatfs(arguments) => Bytes::from(arguments.fhandle),
+            RpcProcedure::NfsLookup(arguments) => {
+                let mut buffer = BytesMut::new();
+                buffer.extend(Bytes::from(arguments.fhandle));
+                buffer.extend(nfs_filename(&arguments.filename.to_string_lossy(), arguments.encoding));
+                buffer.freeze()
+            },
+            RpcProcedure::NfsRead(arguments) => {
+                let mut buffer = BytesMut::new();
+                buffer.extend(Bytes::from(arguments.fhandle));
+                buffer.put_u32(arguments.offset);
+                buffer.put_u32(arguments.count);
+                buffer.put_u32(arguments.total_count);
+                buffer.freeze()
+            },
+            RpcProcedure::NfsReaddir(arguments) => {
+                let mut buffer = BytesMut::new();
+                buffer.extend(Bytes::from(arguments.fhandle));
+                buffer.put_u32(arguments.cookie);
+                buffer.put_u32(arguments.count);
+                buffer.freeze()
+            },
+            RpcProcedure::MountMnt(arguments) => nfs_filename(arguments.path(), arguments.encoding()),
+            RpcProcedure::PortmapNull
+            | RpcProcedure::PortmapDump
+            | RpcProcedure::PortmapCallResult
+            | RpcProcedure::NfsNull
+            | RpcProcedure::MountNull
+            | RpcProcedure::MountExport => Bytes::new(),
         }
     }
 }
@@ -717,31 +1642,41 @@ impl RpcProcedure {
 #[derive(Debug, PartialEq)]
 pub struct MountMnt {
     paths: Vec<String>,
+    encoding: NameEncoding,
+}
+
+impl MountMnt {
+    pub fn new(path: &str, encoding: NameEncoding) -> MountMnt {
+        MountMnt {
+            paths: vec![path.to_string()],
+            encoding,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        self.paths.first().map(String::as_str).unwrap_or("")
+    }
+
+    /// Encoding the client sent the path in.
+    pub fn encoding(&self) -> NameEncoding {
+        self.encoding
+    }
 }
 
 impl Decoder for MountMnt {
     type Output = Self;
 
     fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
-        let (input, length) = be_u32(input)?;
-        let (input, contents) = count(le_u16, length as usize / 2)(input)?;
-        let (input, _fill_bytes) = be_u16(input)?;
-
-        match String::from_utf16(&contents) {
-            Ok(contents) => {
-                Ok((input, MountMnt {
-                    paths: vec![
-                        contents,
-                    ]
-                }))
-            },
-            // TODO: change this ErrorTag to something relevant.
-            Err(_err) => Err(nom::Err::Error((input, Switch))),
-        }
+        let (input, (path, encoding)) = decode_nfs_filename(input)?;
+
+        Ok((input, MountMnt {
+            paths: vec![path],
+            encoding,
+        }))
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FileHandle {
     data: Vec<u8>,
 }
@@ -753,6 +1688,10 @@ impl FileHandle {
         }
     }
 
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn ino(&self) -> u64 {
         let mut data = [0u8; 8];
         for (index, value) in self.data[0..=7].into_iter().enumerate() {
@@ -804,6 +1743,48 @@ impl Decoder for NfsRead {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub struct NfsReaddir {
+    pub fhandle: FileHandle,
+    /// 0 to start at the beginning, or the cookie of the last entry read.
+    pub cookie: u32,
+    /// Most bytes of directory entries to answer with.
+    pub count: u32,
+}
+
+impl Decoder for NfsReaddir {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, fhandle) = FileHandle::decode(input)?;
+        let (input, cookie) = be_u32(input)?;
+        let (input, count) = be_u32(input)?;
+
+        Ok((input, NfsReaddir {
+            fhandle,
+            cookie,
+            count,
+        }))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NfsStatfs {
+    pub fhandle: FileHandle,
+}
+
+impl Decoder for NfsStatfs {
+    type Output = Self;
+
+    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
+        let (input, fhandle) = FileHandle::decode(input)?;
+
+        Ok((input, NfsStatfs {
+            fhandle,
+        }))
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct NfsGetAttr {
     pub fhandle: FileHandle,
@@ -839,6 +1820,25 @@ pub struct NfsFileAttributes {
     ctime: SystemTime,
 }
 
+impl NfsFileAttributes {
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The same attributes for a file of another size, in blocks of 512 bytes like `st_blocks`.
+    pub fn with_size(self, size: u64) -> Self {
+        Self {
+            size: u32::try_from(size).unwrap_or(u32::MAX),
+            blocks: u32::try_from(size.div_ceil(512)).unwrap_or(u32::MAX),
+            ..self
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self._type == FileType::Directory
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct NfsGetAttrReply {
     pub status: NfsStatus,
@@ -869,6 +1869,8 @@ impl From<Metadata> for NfsGetAttrReply {
 pub struct NfsLookup {
     pub filename: PathBuf,
     pub fhandle: FileHandle,
+    /// Encoding the client sent the name in.
+    pub encoding: NameEncoding,
 }
 
 impl NfsLookup {
@@ -882,66 +1884,76 @@ impl Decoder for NfsLookup {
 
     fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
         let (input, file_handle) = FileHandle::decode(input)?;
-
-        let (input, length) = be_u32(input)?;
-        let (input, contents) = count(le_u16, length as usize / 2)(input)?;
-
-        let contents = String::from_utf16(&contents)
-            .map_err(|_| nom::Err::Error((input, MapRes)))?;
+        let (input, (name, encoding)) = decode_nfs_filename(input)?;
 
         Ok((input, NfsLookup {
-            filename: Path::new(&contents).to_path_buf(),
+            filename: PathBuf::from(name),
             fhandle: file_handle,
+            encoding,
         }))
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub struct PortmapGetport {
-    version: u32,
-    program: RpcProgram,
-    protocol: PortmapProtocol,
-    port: u32,
+/// A program version served on a port, as SET, UNSET and GETPORT carry it
+/// and DUMP lists it. Program and protocol stay numbers, any program can be registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PortmapMapping {
+    pub program: u32,
+    pub version: u32,
+    pub protocol: u32,
+    pub port: u32,
 }
 
-impl PortmapGetport {
-    pub fn program(&self) -> &RpcProgram {
-        &self.program
+impl PortmapMapping {
+    pub fn program(&self) -> Option<RpcProgram> {
+        RpcProgram::from_number(self.program)
     }
 }
 
-impl Decoder for PortmapGetport {
+impl Decoder for PortmapMapping {
     type Output = Self;
 
     fn decode(input: &[u8]) -> IResult<&[u8], Self::Output>{
-        let (input, (program, version)) = RpcProgram::decode(input)?;
-        let (input, protocol) = PortmapProtocol::decode(input)?;
+        let (input, program) = be_u32(input)?;
+        let (input, version) = be_u32(input)?;
+        let (input, protocol) = be_u32(input)?;
         let (input, port) = be_u32(input)?;
 
-        Ok((input, PortmapGetport {
-            version,
+        Ok((input, PortmapMapping {
             program,
+            version,
             protocol,
             port,
         }))
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+impl From<PortmapMapping> for Bytes {
+    fn from(mapping: PortmapMapping) -> Bytes {
+        let mut buffer = BytesMut::new();
+
+        buffer.extend(mapping.program.to_be_bytes().as_ref());
+        buffer.extend(mapping.version.to_be_bytes().as_ref());
+        buffer.extend(mapping.protocol.to_be_bytes().as_ref());
+        buffer.extend(mapping.port.to_be_bytes().as_ref());
+
+        buffer.freeze()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PortmapProtocol {
     Ip,
+    Tcp,
     Udp,
 }
 
-impl Decoder for PortmapProtocol {
-    type Output = Self;
-
-    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
-        let (input, protocol) = be_u32(input)?;
-
-        match protocol {
-            17u32 => Ok((input, PortmapProtocol::Udp)),
-            _ => Err(nom::Err::Error((input, Switch))),
+impl PortmapProtocol {
+    pub fn number(&self) -> u32 {
+        match self {
+            PortmapProtocol::Ip => 0,
+            PortmapProtocol::Tcp => 6,
+            PortmapProtocol::Udp => 17,
         }
     }
 }
@@ -997,41 +2009,6 @@ impl Decoder for NfsProcedure {
     }
 }
 
-#[derive(Debug, PartialEq)]
-struct RpcCredentials {
-    flavor: u32,
-    length: u32,
-    stamp: u32,
-    machine_name: u32,
-    uid: u32,
-    gid: u32,
-    aux_gid: u32,
-}
-
-impl Decoder for RpcCredentials {
-    type Output = Self;
-
-    fn decode(input: &[u8]) -> IResult<&[u8], Self::Output> {
-        let (input, flavor) = be_u32(input)?;
-        let (input, length) = be_u32(input)?;
-        let (input, stamp) = be_u32(input)?;
-        let (input, machine_name) = be_u32(input)?;
-        let (input, uid) = be_u32(input)?;
-        let (input, gid) = be_u32(input)?;
-        let (input, aux_gid) = be_u32(input)?;
-
-        Ok((input, RpcCredentials {
-            flavor,
-            length,
-            stamp,
-            machine_name,
-            uid,
-            gid,
-            aux_gid,
-        }))
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1060,21 +2037,19 @@ mod test {
                     version: 2,
                     program: RpcProgram::Portmap,
                     program_version: 2,
-                    procedure: RpcProcedure::PortmapGetport(PortmapGetport {
+                    procedure: RpcProcedure::PortmapGetport(PortmapMapping {
+                        program: 100003,
                         version: 2,
-                        program: RpcProgram::Nfs,
-                        protocol: PortmapProtocol::Udp,
+                        protocol: 17,
                         port: 0,
                     }),
-                    credentials: RpcCredentials {
-                        flavor: 1,
-                        length: 20,
+                    credentials: RpcAuth::Unix(RpcUnixAuth {
                         stamp: 624398636,
-                        machine_name: 0u32,
-                        uid: 0u32,
-                        gid: 0u32,
-                        aux_gid: 0u32,
-                    },
+                        machine_name: String::new(),
+                        uid: 0,
+                        gid: 0,
+                        gids: vec![],
+                    }),
                     verifier: RpcAuth::Null,
                 }),
             })),
@@ -1104,21 +2079,19 @@ mod test {
                     version: 2,
                     program: RpcProgram::Portmap,
                     program_version: 2,
-                    procedure: RpcProcedure::PortmapGetport(PortmapGetport {
+                    procedure: RpcProcedure::PortmapGetport(PortmapMapping {
+                        program: 100005,
                         version: 1,
-                        program: RpcProgram::Mount,
-                        protocol: PortmapProtocol::Udp,
+                        protocol: 17,
                         port: 0,
                     }),
-                    credentials: RpcCredentials {
-                        flavor: 1,
-                        length: 20,
+                    credentials: RpcAuth::Unix(RpcUnixAuth {
                         stamp: 4274624529,
-                        machine_name: 0u32,
-                        uid: 0u32,
-                        gid: 0u32,
-                        aux_gid: 0u32,
-                    },
+                        machine_name: String::new(),
+                        uid: 0,
+                        gid: 0,
+                        gids: vec![],
+                    }),
                     verifier: RpcAuth::Null,
                 }),
             })),
@@ -1162,15 +2135,13 @@ mod test {
                     program: RpcProgram::Mount,
                     program_version: 1,
                     procedure: RpcProcedure::MountExport,
-                    credentials: RpcCredentials {
-                        flavor: 1,
-                        length: 20,
+                    credentials: RpcAuth::Unix(RpcUnixAuth {
                         stamp: 2964730132,
-                        machine_name: 0u32,
-                        uid: 0u32,
-                        gid: 0u32,
-                        aux_gid: 0u32,
-                    },
+                        machine_name: String::new(),
+                        uid: 0,
+                        gid: 0,
+                        gids: vec![],
+                    }),
                     verifier: RpcAuth::Null,
                 }),
             })),
@@ -1209,6 +2180,7 @@ mod test {
                                 groups: vec![
                                     String::from("192.168.10.5/255.255.255.0"),
                                 ],
+                                encoding: NameEncoding::Utf16Le,
                             },
                         ],
                     },
@@ -1231,17 +2203,16 @@ mod test {
                     procedure: RpcProcedure::MountMnt(
                         MountMnt {
                             paths: vec![String::from("/C/")],
+                            encoding: NameEncoding::Utf16Le,
                         },
                     ),
-                    credentials: RpcCredentials {
-                        flavor: 1,
-                        length: 20,
+                    credentials: RpcAuth::Unix(RpcUnixAuth {
                         stamp: 2962022660,
-                        machine_name: 0u32,
-                        uid: 0u32,
-                        gid: 0u32,
-                        aux_gid: 0u32,
-                    },
+                        machine_name: String::new(),
+                        uid: 0,
+                        gid: 0,
+                        gids: vec![],
+                    }),
                     verifier: RpcAuth::Null,
                 }),
             })),
@@ -1278,6 +2249,72 @@ mod test {
         }));
     }
 
+    #[test]
+    fn it_decodes_calls_that_cannot_be_served() {
+        let call = |program: u32, version: u32, procedure: u32, arguments: &[u8]| {
+            let mut call = vec![0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 2];
+            for number in &[program, version, procedure, 1, 20, 0, 0, 0, 0, 0, 0, 0] {
+                call.extend(&number.to_be_bytes());
+            }
+            call.extend(arguments);
+            RpcMessage::decode(&call).ok().map(|(_input, message)| message.message)
+        };
+
+        assert_eq!(Some(RpcMessageType::InvalidCall(RpcCallError::ProgramUnavailable)), call(100099, 1, 0, &[]));
+        assert_eq!(
+            Some(RpcMessageType::InvalidCall(RpcCallError::ProgramMismatch { low: 2, high: 2 })),
+            call(100003, 3, 0, &[]),
+        );
+        assert_eq!(Some(RpcMessageType::InvalidCall(RpcCallError::ProcedureUnavailable)), call(100003, 2, 8, &[]));
+        assert_eq!(Some(RpcMessageType::InvalidCall(RpcCallError::GarbageArguments)), call(100003, 2, 6, &[0; 12]));
+    }
+
+    #[test]
+    fn it_encodes_rejected_and_unsuccessful_replies() {
+        let reply = |reply: RpcReply| Bytes::try_from(RpcMessage::new(7, RpcMessageType::Reply(reply)));
+
+        assert_eq!(Ok(Bytes::from(vec![
+            0, 0, 0, 7, 0, 0, 0, 1,
+            0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 2,
+            0, 0, 0, 1, 0, 0, 0, 1,
+        ])), reply(RpcReply::from(RpcCallError::ProgramMismatch { low: 1, high: 1 })));
+        assert_eq!(Ok(Bytes::from(vec![
+            0, 0, 0, 7, 0, 0, 0, 1,
+            0, 0, 0, 1, 0, 0, 0, 0,
+            0, 0, 0, 2, 0, 0, 0, 2,
+        ])), reply(RpcReply::from(RpcCallError::RpcMismatch)));
+    }
+
+    #[test]
+    fn it_decodes_unix_credentials() {
+        let credentials = [
+            &[0u8, 0, 0, 1, 0, 0, 0, 32, 0, 0, 0, 9][..],
+            &[0, 0, 0, 5][..], &b"CDJ-2"[..], &[0, 0, 0][..],
+            &[0, 0, 3, 0xe8, 0, 0, 0, 100, 0, 0, 0, 1, 0, 0, 0, 20][..],
+        ].concat();
+
+        assert_eq!(Ok((&[][..], RpcAuth::Unix(RpcUnixAuth {
+            stamp: 9,
+            machine_name: String::from("CDJ-2"),
+            uid: 1000,
+            gid: 100,
+            gids: vec![20],
+        }))), RpcAuth::decode(&credentials));
+        assert_eq!(Ok((&[][..], RpcAuth::Short)), RpcAuth::decode(&[0, 0, 0, 2, 0, 0, 0, 4, 1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn it_encodes_portmap_dump_reply() {
+        let mapping = PortmapMapping { program: 100003, version: 2, protocol: 17, port: 2049 };
+
+        assert_eq!(Bytes::from(vec![
+            0, 0, 0, 1,
+            0, 1, 0x86, 0xa3, 0, 0, 0, 2, 0, 0, 0, 17, 0, 0, 0x08, 0x01,
+            0, 0, 0, 0,
+        ]), Bytes::from(RpcReplyMessage::PortmapDump(vec![mapping])));
+    }
+
     #[test]
     fn it_can_decode_lookup_call() {
         let call = Bytes::from(b"\0\0\0\"\0\0\0\0\0\0\0\x02\0\x01\x86\xa3\0\0\0\x02\0\0\0\x04\0\0\0\x01\0\0\0\x14\xf0\xbcq\x07\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x03\x01\0\0\0\0\x1bX\0\0\0\0\x11\x04\x01\0\0\0\0\x05\0\0\0\nU\0s\0e\0r\0s\0\0\0".to_vec());
@@ -1285,6 +2322,21 @@ mod test {
         assert_eq!(nfs_lookup.is_ok(), true);
     }
 
+    #[test]
+    fn it_can_encode_nfs_readdir_reply() {
+        let reply = NfsReaddirReply {
+            status: NfsStatus::Ok,
+            entries: vec![NfsDirectoryEntry { file_id: 7, name: String::from("A"), cookie: 1 }],
+            eof: true,
+            encoding: NameEncoding::Utf16Le,
+        };
+
+        assert_eq!(
+            Bytes::from(b"\0\0\0\0\0\0\0\x01\0\0\0\x07\0\0\0\x02A\0\0\0\0\0\0\x01\0\0\0\0\0\0\0\x01".to_vec()),
+            Bytes::from(reply),
+        );
+    }
+
     #[test]
     fn it_can_encode_nfs_lookup_reply() {
         let reply = NfsLookupReply {
@@ -1297,5 +2349,124 @@ mod test {
 
         assert_eq!(104, Bytes::from(reply).len());
     }
-}
 
+    #[test]
+    fn it_decodes_the_replies_it_encodes() {
+        let encode = |reply: RpcReply| Bytes::try_from(RpcMessage::new(7, RpcMessageType::Reply(reply))).unwrap();
+        let decode = |program: RpcProgram, procedure: u32, reply: RpcReply| {
+            RpcMessage::decode_reply(&encode(reply), &program, procedure).map(|(_xid, reply)| reply.data)
+        };
+
+        let readdir = || NfsReaddirReply {
+            status: NfsStatus::Ok,
+            entries: vec![
+                NfsDirectoryEntry { file_id: 7, name: String::from("A"), cookie: 1 },
+                NfsDirectoryEntry { file_id: 8, name: String::from("Ünïcode.mp3"), cookie: 2 },
+            ],
+            eof: true,
+            encoding: NameEncoding::Utf16Le,
+        };
+        assert_eq!(
+            Ok(RpcReplyMessage::NfsReaddir(readdir())),
+            decode(RpcProgram::Nfs, 16, RpcReply::success(RpcReplyMessage::NfsReaddir(readdir()))),
+        );
+
+        let export = || MountExportReply {
+            export_list_entries: vec![
+                ExportListEntry::new(String::from("/C/"), vec![String::from("everyone")]),
+                ExportListEntry::new(String::from("/Music"), vec![]),
+            ],
+        };
+        assert_eq!(
+            Ok(RpcReplyMessage::MountExport(export())),
+            decode(RpcProgram::Mount, 5, RpcReply::success(RpcReplyMessage::MountExport(export()))),
+        );
+
+        let read = || NfsReadReply {
+            status: NfsStatus::Ok,
+            attributes: NfsFileAttributes {
+                _type: FileType::File,
+                atime: SystemTime::UNIX_EPOCH,
+                mtime: SystemTime::UNIX_EPOCH,
+                ctime: SystemTime::UNIX_EPOCH,
+                ..Default::default()
+            },
+            data: NfsDataWrapper { data: b"abcde".to_vec() },
+        };
+        assert_eq!(
+            Ok(RpcReplyMessage::NfsRead(read())),
+            decode(RpcProgram::Nfs, 6, RpcReply::success(RpcReplyMessage::NfsRead(read()))),
+        );
+
+        assert_eq!(
+            Ok(RpcReplyMessage::NfsError(NfsStatus::Stale)),
+            decode(RpcProgram::Nfs, 4, RpcReply::success(RpcReplyMessage::NfsError(NfsStatus::Stale))),
+        );
+        assert_eq!(
+            Ok(RpcReplyMessage::PortmapGetport(PortmapGetportReply { port: 2049 })),
+            decode(RpcProgram::Portmap, 3, RpcReply::success(RpcReplyMessage::PortmapGetport(PortmapGetportReply { port: 2049 }))),
+        );
+
+        let denied = RpcReply::from(RpcCallError::Unauthorized);
+        assert_eq!(
+            Ok((7, RpcReply::from(RpcCallError::Unauthorized))),
+            RpcMessage::decode_reply(&encode(denied), &RpcProgram::Mount, 1),
+        );
+        let mismatch = RpcReply::from(RpcCallError::ProgramMismatch { low: 2, high: 2 });
+        assert_eq!(
+            Ok((7, RpcReply::from(RpcCallError::ProgramMismatch { low: 2, high: 2 }))),
+            RpcMessage::decode_reply(&encode(mismatch), &RpcProgram::Nfs, 1),
+        );
+    }
+
+    #[test]
+    fn it_encodes_calls_it_decodes() {
+        let call = RpcCall::new(
+            RpcProcedure::NfsLookup(NfsLookup {
+                filename: PathBuf::from("Track.mp3"),
+                fhandle: FileHandle::new([3u8; 32]),
+                encoding: NameEncoding::Utf16Le,
+            }),
+            RpcAuth::Unix(RpcUnixAuth { stamp: 1, machine_name: String::from("cdj"), uid: 0, gid: 0, gids: vec![0] }),
+        );
+        let expected = RpcCall::new(
+            RpcProcedure::NfsLookup(NfsLookup {
+                filename: PathBuf::from("Track.mp3"),
+                fhandle: FileHandle::new([3u8; 32]),
+                encoding: NameEncoding::Utf16Le,
+            }),
+            RpcAuth::Unix(RpcUnixAuth { stamp: 1, machine_name: String::from("cdj"), uid: 0, gid: 0, gids: vec![0] }),
+        );
+        let bytes = Bytes::try_from(RpcMessage::new(9, RpcMessageType::Call(call))).unwrap();
+
+        assert_eq!(Ok(RpcMessage::new(9, RpcMessageType::Call(expected))), RpcMessage::try_from(bytes));
+    }
+
+    #[test]
+    fn it_keeps_names_in_the_encoding_of_the_client() {
+        assert_eq!(NameEncoding::Utf16Le, NameEncoding::detect(b"/\0C\0/\0"));
+        assert_eq!(NameEncoding::Utf16Le, NameEncoding::detect(&NameEncoding::Utf16Le.encode("音楽")));
+        assert_eq!(NameEncoding::Utf8, NameEncoding::detect("/Ünïcode".as_bytes()));
+
+        for &encoding in &[NameEncoding::Utf16Le, NameEncoding::Utf8] {
+            let lookup = || RpcMessage::new(9, RpcMessageType::Call(RpcCall::new(
+                RpcProcedure::NfsLookup(NfsLookup {
+                    filename: PathBuf::from("Ünïcode.mp3"),
+                    fhandle: FileHandle::new([3u8; 32]),
+                    encoding,
+                }),
+                RpcAuth::Null,
+            )));
+            assert_eq!(Ok(lookup()), RpcMessage::try_from(Bytes::try_from(lookup()).unwrap()));
+
+            let readdir = || RpcReply::success(RpcReplyMessage::NfsReaddir(NfsReaddirReply {
+                status: NfsStatus::Ok,
+                entries: vec![NfsDirectoryEntry { file_id: 8, name: String::from("Ünïcode.mp3"), cookie: 1 }],
+                eof: true,
+                encoding,
+            }));
+            let reply = Bytes::try_from(RpcMessage::new(7, RpcMessageType::Reply(readdir()))).unwrap();
+            assert_eq!(Ok((7, readdir())), RpcMessage::decode_reply(&reply, &RpcProgram::Nfs, 16));
+        }
+    }
+}
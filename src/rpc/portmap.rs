@@ -0,0 +1,127 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use super::packets::*;
+use super::server::RpcService;
+
+/// Ports programs are served on, as the portmapper hands them out.
+#[derive(Debug, Default)]
+pub struct PortmapRegistry {
+    mappings: Vec<PortmapMapping>,
+}
+
+impl PortmapRegistry {
+    /// Register a program version on a port, refused while the version is
+    /// registered for the protocol already.
+    pub fn set(&mut self, mapping: PortmapMapping) -> bool {
+        if self.getport(&mapping) != 0 {
+            return false;
+        }
+        self.mappings.push(mapping);
+        true
+    }
+
+    /// Drop a program version for all protocols, protocol and port are ignored.
+    pub fn unset(&mut self, mapping: &PortmapMapping) -> bool {
+        let count = self.mappings.len();
+        self.mappings.retain(|known| (known.program, known.version) != (mapping.program, mapping.version));
+        self.mappings.len() != count
+    }
+
+    /// Port of a program version for the protocol, 0 when it isn't registered.
+    pub fn getport(&self, mapping: &PortmapMapping) -> u32 {
+        self.mappings.iter()
+            .find(|known| {
+                (known.program, known.version, known.protocol) == (mapping.program, mapping.version, mapping.protocol)
+            })
+            .map(|known| known.port)
+            .unwrap_or(0)
+    }
+
+    pub fn dump(&self) -> Vec<PortmapMapping> {
+        self.mappings.clone()
+    }
+}
+
+/// The portmapper program. Only the machine itself may change the registry,
+/// anyone may read it.
+#[derive(Debug, Default)]
+pub struct Portmapper {
+    registry: Mutex<PortmapRegistry>,
+}
+
+impl Portmapper {
+    /// Register every version of a program served on a port.
+    pub fn register(&self, program: RpcProgram, protocol: PortmapProtocol, port: u16) {
+        if let Ok(mut registry) = self.registry.lock() {
+            let (low, high) = program.versions();
+            for version in low..=high {
+                registry.set(PortmapMapping {
+                    program: program.number(),
+                    version,
+                    protocol: protocol.number(),
+                    port: port as u32,
+                });
+            }
+        }
+    }
+}
+
+impl RpcService for Portmapper {
+    fn reply(&self, message: &RpcMessage, address: SocketAddr) -> Option<RpcReply> {
+        let call = match message.message() {
+            RpcMessageType::Call(call) => call,
+            RpcMessageType::InvalidCall(err) => return Some(RpcReply::from(*err)),
+            RpcMessageType::Reply(_) => return None,
+        };
+        let mut registry = match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(_err) => return Some(RpcReply::from(RpcCallError::SystemError)),
+        };
+        let local = address.ip().is_loopback();
+
+        Some(match call.procedure() {
+            RpcProcedure::PortmapNull => RpcReply::success(RpcReplyMessage::Void),
+            RpcProcedure::PortmapSet(mapping) => RpcReply::success(RpcReplyMessage::PortmapBool(
+                local && registry.set(*mapping),
+            )),
+            RpcProcedure::PortmapUnset(mapping) => RpcReply::success(RpcReplyMessage::PortmapBool(
+                local && registry.unset(mapping),
+            )),
+            RpcProcedure::PortmapGetport(mapping) => RpcReply::success(RpcReplyMessage::PortmapGetport(
+                PortmapGetportReply {
+                    port: registry.getport(mapping),
+                },
+            )),
+            RpcProcedure::PortmapDump => RpcReply::success(RpcReplyMessage::PortmapDump(registry.dump())),
+            _ if *call.program() != RpcProgram::Portmap => RpcReply::from(RpcCallError::ProgramUnavailable),
+            _ => RpcReply::from(RpcCallError::ProcedureUnavailable),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn mapping(program: u32, version: u32, port: u32) -> PortmapMapping {
+        PortmapMapping { program, version, protocol: 17, port }
+    }
+
+    #[test]
+    fn it_registers_programs_once() {
+        let mut registry = PortmapRegistry::default();
+
+        assert!(registry.set(mapping(100005, 1, 2000)));
+        assert!(!registry.set(mapping(100005, 1, 3000)));
+        assert!(registry.set(mapping(100003, 2, 2049)));
+        assert_eq!(2000, registry.getport(&mapping(100005, 1, 0)));
+        assert_eq!(0, registry.getport(&mapping(100005, 3, 0)));
+        assert_eq!(0, registry.getport(&PortmapMapping { protocol: 6, ..mapping(100005, 1, 0) }));
+
+        assert!(registry.unset(&mapping(100005, 1, 0)));
+        assert!(!registry.unset(&mapping(100005, 1, 0)));
+        assert_eq!(vec![mapping(100003, 2, 2049)], registry.dump());
+    }
+}
@@ -1,162 +1,252 @@
 use std::net::{SocketAddr, IpAddr, Ipv4Addr};
-use std::sync::Arc;
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use tokio_util::udp::UdpFramed;
-use tokio::net::UdpSocket;
-use tokio::stream::StreamExt;
-use futures::{SinkExt};
+use tokio_util::codec::Framed;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc;
+use futures::{SinkExt, StreamExt};
 
 use super::packets::*;
-use super::codec::RpcBytesCodec;
+use super::codec::{RpcBytesCodec, RpcRecordCodec};
 use super::events::{EventHandler};
 use crate::rpc::nfs_program::RpcNfsProgramHandler;
+use crate::rpc::fs::NfsExport;
+use crate::rpc::portmap::Portmapper;
+use crate::rpc::access::HostAccess;
+use crate::rpc::names::ClientNames;
 
-struct RpcProcedureRouter<T>
-    where T: EventHandler,
-{
-    request: RpcMessage,
-    peer: SocketAddr,
-    handler: Arc<T>,
+/// A program as the servers see it, whatever transport its messages come over.
+pub trait RpcService: Send + Sync + 'static {
+    /// Reply to a message from `address`, `None` for messages that get no reply.
+    fn reply(&self, message: &RpcMessage, address: SocketAddr) -> Option<RpcReply>;
 }
 
-#[derive(Debug)]
-enum RpcServerError {
-    ProgramNotImplemented,
-    ReplyNotAllowed,
-    IOError(std::io::Error),
+/// Programs whose procedures are answered by an `EventHandler`.
+struct EventService<T: EventHandler> {
+    handler: Arc<T>,
+    names: Arc<ClientNames>,
 }
 
-fn serialize_rpc_reply_message(reply: RpcReplyMessage, transaction_id: u32) -> RpcMessage {
-    RpcMessage::new(
-        transaction_id,
-        RpcMessageType::Reply(RpcReply {
-            verifier: RpcAuth::Null,
-            reply_state: RpcReplyState::Accepted,
-            accept_state: RpcAcceptState::Success,
-            data: reply,
+impl<T: EventHandler> RpcService for EventService<T> {
+    /// Answer a call with what its handler returns. NULL procedures are answered
+    /// right away, procedures the handler doesn't know with PROC_UNAVAIL and
+    /// handler errors with SYSTEM_ERR. Exported directories are sent in the
+    /// encoding of the client.
+    fn reply(&self, message: &RpcMessage, address: SocketAddr) -> Option<RpcReply> {
+        let encoding = self.names.learn(message, address.ip());
+        Some(match message.message() {
+            RpcMessageType::Call(call) => match call.procedure() {
+                RpcProcedure::PortmapNull | RpcProcedure::MountNull | RpcProcedure::NfsNull => {
+                    RpcReply::success(RpcReplyMessage::Void)
+                },
+                _ => match self.handler.handle_event(call) {
+                    Some(Ok(RpcReplyMessage::MountExport(mut reply))) => {
+                        for entry in &mut reply.export_list_entries {
+                            entry.set_encoding(encoding);
+                        }
+                        RpcReply::success(RpcReplyMessage::MountExport(reply))
+                    },
+                    Some(Ok(reply)) => RpcReply::success(reply),
+                    Some(Err(err)) => {
+                        eprintln!("Failed handling RPC call {:?}; error = {}", call.procedure(), err);
+                        RpcReply::from(RpcCallError::SystemError)
+                    },
+                    None => RpcReply::from(RpcCallError::ProcedureUnavailable),
+                },
+            },
+            RpcMessageType::InvalidCall(err) => RpcReply::from(*err),
+            RpcMessageType::Reply(_) => return None,
         })
-    )
+    }
 }
 
-fn rpc_procedure_router<T: EventHandler>(
-    request: RpcMessage,
-    address: SocketAddr,
-    handler: Arc<T>,
-) -> Result<(RpcMessage, SocketAddr), RpcServerError> {
-    let transaction_id = request.xid;
-    match request.message() {
-        RpcMessageType::Call(call) => {
-            match handler.handle_event(&call) {
-                Some(Ok(reply)) => Ok((serialize_rpc_reply_message(reply, transaction_id), address)),
-                Some(Err(e)) => Err(RpcServerError::IOError(e)),
-                None => Err(RpcServerError::ProgramNotImplemented),
+/// Programs only the hosts of the access list may call. Calls from other
+/// hosts are logged with the credentials they carry and refused: NFS
+/// procedures with NFSERR_ACCES, everything else with AUTH_ERROR.
+struct Restricted<S: RpcService> {
+    service: S,
+    access: Arc<HostAccess>,
+}
+
+impl<S: RpcService> RpcService for Restricted<S> {
+    fn reply(&self, message: &RpcMessage, address: SocketAddr) -> Option<RpcReply> {
+        if self.access.allows(address.ip()) {
+            if let RpcMessageType::Call(call) = message.message() {
+                if let RpcProcedure::MountMnt(_) = call.procedure() {
+                    eprintln!("Mount from {}; credentials = {}", address.ip(), call.credentials());
+                }
             }
+            return self.service.reply(message, address);
+        }
+
+        match message.message() {
+            RpcMessageType::Call(call) => {
+                eprintln!("Refused {:?} call from {}; credentials = {}", call.program(), address.ip(), call.credentials());
+                Some(match call.procedure() {
+                    RpcProcedure::NfsNull => RpcReply::from(RpcCallError::Unauthorized),
+                    _ if *call.program() == RpcProgram::Nfs => RpcReply::success(RpcReplyMessage::NfsError(NfsStatus::Acces)),
+                    _ => RpcReply::from(RpcCallError::Unauthorized),
+                })
+            },
+            RpcMessageType::InvalidCall(_) => Some(RpcReply::from(RpcCallError::Unauthorized)),
+            RpcMessageType::Reply(_) => None,
+        }
+    }
+}
+
+/// Reply to a message on a thread of the blocking pool, as services may wait
+/// for files, while the calls that come in meanwhile are answered too.
+async fn answer<S: RpcService>(service: Arc<S>, request: RpcMessage, address: SocketAddr) -> Option<RpcMessage> {
+    let xid = request.xid;
+    match tokio::task::spawn_blocking(move || service.reply(&request, address)).await {
+        Ok(reply) => reply.map(|reply| RpcMessage::new(xid, RpcMessageType::Reply(reply))),
+        Err(err) => {
+            eprintln!("Failed answering RPC call from {}; error = {}", address, err);
+            None
         },
-        RpcMessageType::Reply(_) => Err(RpcServerError::ReplyNotAllowed),
     }
 }
 
-/// Make this server handle generic program handlers.
+/// Serve a program on a UDP socket, one message per datagram.
 ///
-/// This server will crash directly it is unable to process a message in either direction.
-async fn rpc_program_server<T: EventHandler>(
-    mut socket: UdpFramed<RpcBytesCodec>,
-    handler: Arc<T>,
-) -> Result<(), String> {
-
-    while let Some(package) = socket.next().await {
-        let handler = handler.clone();
+/// Messages that can't be answered are logged and skipped, the server keeps running.
+async fn serve_udp<S: RpcService>(socket: UdpFramed<RpcBytesCodec>, service: Arc<S>) {
+    let (mut replies, mut requests) = socket.split();
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(reply) = receiver.recv().await {
+            if let Err(err) = replies.send(reply).await {
+                eprintln!("Failed sending RPC reply; error = {}", err);
+            }
+        }
+    });
 
+    while let Some(package) = requests.next().await {
         match package {
             Ok((request, address)) => {
-                let message = rpc_procedure_router(request, address, handler.clone())
-                    .map_err(|err| format!("failed processing RPC Message into reply; error = {:?}", err))?;
-                socket.send(message).await
-                    .map_err(|_| String::from("Failed sending on socket"))?;
+                let (service, sender) = (service.clone(), sender.clone());
+                tokio::spawn(async move {
+                    if let Some(reply) = answer(service, request, address).await {
+                        let _ = sender.send((reply, address));
+                    }
+                });
             },
             Err(err) => eprintln!("error decoding bytes into RPC Message; err = {}", err),
         }
     }
+}
+
+/// Serve a program on a TCP listener, every connection in a task of its own.
+/// A connection ends when the client closes it or sends something that isn't
+/// a record. Replies are sent as they are ready, not in the order of the calls.
+async fn serve_tcp<S: RpcService>(mut listener: TcpListener, service: Arc<S>) {
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                eprintln!("Failed accepting RPC connection; error = {}", err);
+                continue;
+            },
+        };
+
+        let service = service.clone();
+        tokio::spawn(async move {
+            let (mut replies, mut requests) = Framed::new(stream, RpcRecordCodec::new()).split();
+            let (sender, mut receiver) = mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                while let Some(reply) = receiver.recv().await {
+                    if let Err(err) = replies.send(reply).await {
+                        eprintln!("Failed sending RPC reply; error = {}", err);
+                        break;
+                    }
+                }
+            });
 
-    Ok(())
+            while let Some(request) = requests.next().await {
+                let request = match request {
+                    Ok(request) => request,
+                    Err(err) => {
+                        eprintln!("Closing RPC connection from {}; error = {}", address, err);
+                        break;
+                    },
+                };
+                let (service, sender) = (service.clone(), sender.clone());
+                tokio::spawn(async move {
+                    if let Some(reply) = answer(service, request, address).await {
+                        let _ = sender.send(reply);
+                    }
+                });
+            }
+        });
+    }
+}
+
+/// Serve a program on a UDP socket and a TCP listener, on ports the system picks.
+async fn serve<S: RpcService>(service: Arc<S>) -> Result<(u16, u16), std::io::Error> {
+    let udp_socket = UdpSocket::bind(&get_ipv4_socket_addr(0)).await?;
+    let udp_port = udp_socket.local_addr()?.port();
+    let listener = TcpListener::bind(&get_ipv4_socket_addr(0)).await?;
+    let tcp_port = listener.local_addr()?.port();
+
+    tokio::spawn(serve_udp(UdpFramed::new(udp_socket, RpcBytesCodec::new()), service.clone()));
+    tokio::spawn(serve_tcp(listener, service));
+
+    Ok((udp_port, tcp_port))
 }
 
 pub struct PortmapServer {
     socket_addr: SocketAddr,
-    programs: HashMap<(
-        RpcProgram,
-        u32,
-        PortmapProtocol,
-    ), u16>,
+    export: Arc<NfsExport>,
+    access: Arc<HostAccess>,
+    portmapper: Arc<Portmapper>,
+    names: Arc<ClientNames>,
 }
 
 /// This is the Portmap server
 impl PortmapServer {
-    pub fn new(addr: SocketAddr) -> Self {
+    /// Serves `export` over NFS to the hosts `access` allows.
+    pub fn new(addr: SocketAddr, export: Arc<NfsExport>, access: HostAccess) -> Self {
         Self {
             socket_addr: addr,
-            programs: HashMap::new(),
+            export,
+            access: Arc::new(access),
+            portmapper: Arc::new(Portmapper::default()),
+            names: Arc::new(ClientNames::default()),
         }
     }
 
+    /// Serve MOUNT and NFS over UDP and TCP, for the lifetime of the server,
+    /// and register them along with the portmapper itself. Both programs
+    /// share the name encodings of the clients, as READDIR follows MNT.
+    async fn start_programs<T: EventHandler>(&self, handler: Arc<T>) -> Result<(), std::io::Error> {
+        let (mount_udp, mount_tcp) = serve(Arc::new(Restricted {
+            service: EventService { handler, names: self.names.clone() },
+            access: self.access.clone(),
+        })).await?;
+        let (nfs_udp, nfs_tcp) = serve(Arc::new(Restricted {
+            service: Mutex::new(RpcNfsProgramHandler::new(self.export.clone(), self.names.clone())),
+            access: self.access.clone(),
+        })).await?;
+
+        let portmap_port = self.socket_addr.port();
+        self.portmapper.register(RpcProgram::Portmap, PortmapProtocol::Udp, portmap_port);
+        self.portmapper.register(RpcProgram::Portmap, PortmapProtocol::Tcp, portmap_port);
+        self.portmapper.register(RpcProgram::Mount, PortmapProtocol::Udp, mount_udp);
+        self.portmapper.register(RpcProgram::Mount, PortmapProtocol::Tcp, mount_tcp);
+        self.portmapper.register(RpcProgram::Nfs, PortmapProtocol::Udp, nfs_udp);
+        self.portmapper.register(RpcProgram::Nfs, PortmapProtocol::Tcp, nfs_tcp);
+
+        Ok(())
+    }
+
+    /// Serve the portmapper on the address of the server, over UDP and TCP on the same port.
     pub async fn run<T: EventHandler>(&self, handler: Arc<T>) -> Result<(), std::io::Error> {
         let socket = UdpSocket::bind(&self.socket_addr).await?;
-        let mut socket = UdpFramed::new(socket, RpcBytesCodec::new());
-
-        while let Some(result) = socket.next().await {
-            match result {
-                Ok((rpc_message, address)) => {
-                    let handler = handler.clone();
-                    let allocated_rpc_socket = UdpSocket::bind(&get_ipv4_socket_addr(0)).await?;
-                    let local_addr = allocated_rpc_socket.local_addr()?;
-                    let allocated_rpc_socket = UdpFramed::new(allocated_rpc_socket, RpcBytesCodec::new());
-
-                    match rpc_message.message() {
-                        RpcMessageType::Call(call) => {
-                            match call.procedure() {
-                                RpcProcedure::PortmapGetport(getport) => {
-                                    match getport.program() {
-                                        RpcProgram::Nfs => {
-                                            tokio::spawn(async move {
-                                                let mut program_handler = RpcNfsProgramHandler::new();
-                                                program_handler.run(allocated_rpc_socket).await;
-                                            });
-                                        },
-                                        _ => {
-                                            // Spawn RPC Program in thread to handle multiple concurrent clients
-                                            tokio::spawn(async move {
-                                                rpc_program_server(allocated_rpc_socket, handler).await
-                                            });
-                                        },
-                                    }
-                                },
-                                _ => {},
-                            }
-                        },
-                        _ => {},
-                    };
-
-
-                    // Prepare portmap response
-                    let portmap_response = RpcMessage::new(
-                        rpc_message.transaction_id(),
-                        RpcMessageType::Reply(RpcReply {
-                            verifier: RpcAuth::Null,
-                            reply_state: RpcReplyState::Accepted,
-                            accept_state: RpcAcceptState::Success,
-                            data: RpcReplyMessage::PortmapGetport(
-                                PortmapGetportReply {
-                                    port: local_addr.port() as u32,
-                                },
-                            ),
-                        }),
-                    );
-
-                    socket.send((portmap_response, address)).await?
-                },
-                _ => {},
-            };
-        }
+        let listener = TcpListener::bind(&self.socket_addr).await?;
+        self.start_programs(handler).await?;
+
+        tokio::spawn(serve_tcp(listener, self.portmapper.clone()));
+        serve_udp(UdpFramed::new(socket, RpcBytesCodec::new()), self.portmapper.clone()).await;
 
         Ok(())
     }
@@ -169,11 +259,11 @@ fn get_ipv4_socket_addr(port: u16) -> SocketAddr {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::net::{IpAddr, Ipv4Addr, UdpSocket};
-    use std::path::Path;
-    use bytes::Bytes;
     use std::io::{Error, ErrorKind};
+    use std::path::Path;
+    use pretty_assertions::assert_eq;
     use super::super::events::RpcResult;
+    use crate::rpc::client::RpcClient;
 
     struct Context;
     struct MockEventHandler;
@@ -188,6 +278,9 @@ mod test {
                         Err(err) => Err(err),
                     })
                 },
+                RpcProcedure::MountMnt(_) => {
+                    Some(Ok(RpcReplyMessage::MountMnt(MountMntReply::new(0, FileHandle::new([0u8; 32])))))
+                },
                 _ => Some(Err(Error::new(ErrorKind::InvalidInput, "failed"))),
             }
         }
@@ -206,43 +299,91 @@ mod test {
         })
     }
 
-    fn portmap_server_address<'a>() -> SocketAddr {
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 50111)
+    /// Serve a library on loopback, as the app does on the network.
+    fn start_server(port: u16, root: &Path) -> SocketAddr {
+        let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+        let server = PortmapServer::new(address, Arc::new(NfsExport::new(&[root.to_path_buf()], None)), HostAccess::default());
+        tokio::spawn(async move { server.run(Arc::new(MockEventHandler)).await });
+        address
+    }
+
+    /// Connect over TCP once the server started listening.
+    async fn connect(address: SocketAddr) -> RpcClient {
+        for _attempt in 0..20 {
+            if let Ok(client) = RpcClient::tcp(address).await {
+                return client;
+            }
+            tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("Server at {} doesn't listen", address)
     }
 
-    fn rpc_allocated_server_address(port: u16) -> SocketAddr {
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
+    #[tokio::test]
+    async fn it_serves_files_from_portmap_to_read() {
+        let temp = crate::utils::test_dir("rpc-loopback");
+        let root = temp.path().to_path_buf();
+        std::fs::create_dir_all(root.join("Artist")).unwrap();
+        std::fs::write(root.join("Artist/Track.mp3"), b"0123456789").unwrap();
+        let portmap = start_server(50411, &root);
+
+        let mut client = RpcClient::new(portmap).await.unwrap();
+        let mount_port = client.getport(RpcProgram::Mount, PortmapProtocol::Udp).await.unwrap();
+        let nfs_port = client.getport(RpcProgram::Nfs, PortmapProtocol::Udp).await.unwrap();
+        match client.call(RpcProcedure::PortmapDump).await.unwrap() {
+            RpcReplyMessage::PortmapDump(mappings) => assert_eq!(6, mappings.len()),
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+
+        let mut mount = RpcClient::new(SocketAddr::new(portmap.ip(), mount_port)).await.unwrap();
+        match mount.call(RpcProcedure::MountExport).await.unwrap() {
+            RpcReplyMessage::MountExport(reply) => assert_eq!("/C/", reply.export_list_entries[0].directory()),
+            reply => panic!("Unexpected reply {:?}", reply),
+        }
+        let mut handle = mount.mount("/C/").await.unwrap();
+
+        let mut nfs = RpcClient::new(SocketAddr::new(portmap.ip(), nfs_port)).await.unwrap();
+        let track = root.canonicalize().unwrap().join("Artist/Track.mp3");
+        for name in track.parent().unwrap().iter().skip(1) {
+            handle = nfs.lookup(&handle, &name.to_string_lossy()).await.unwrap().fhandle;
+        }
+        let directory = handle;
+        let reply = nfs.lookup(&directory, "Track.mp3").await.unwrap();
+        assert_eq!(10, reply.attributes.size());
+        assert_eq!(b"2345".to_vec(), nfs.read(&reply.fhandle, 2, 4).await.unwrap());
+
+        let missing = nfs.lookup(&directory, "Missing.mp3").await.unwrap_err();
+        assert_eq!("NFS error NoEnt", missing.to_string());
+        let not_a_directory = nfs.lookup(&reply.fhandle, "Missing.mp3").await.unwrap_err();
+        assert_eq!("NFS error NotDir", not_a_directory.to_string());
     }
 
-    //#[test]
-    fn export_mount_service() {
-        let client_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 50222);
-        let rpc_client = UdpSocket::bind(&client_address).expect("Failed to bind RPC Mock Client socket");
-        let portmap_getport_call = Bytes::from(vec![
-            0x00, 0x00, 0x00, 0x15, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x86, 0xa0,
-            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03,
-            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x14,
-            0xfe, 0xc9, 0x98, 0x11, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x86, 0xa5,
-            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x11,
-            0x00, 0x00, 0x00, 0x00
-        ]);
-        let mount_export_call    = b"\0\0\0\x0c\0\0\0\0\0\0\0\x02\0\x01\x86\xa5\0\0\0\x01\0\0\0\x05\0\0\0\x01\0\0\0\x14\xb0\xb61\x14\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
-
-        assert_eq!(76, rpc_client.send_to(&portmap_getport_call, &portmap_server_address()).unwrap());
-        let mut buffer = [0; 512];
-        let response = rpc_client.recv_from(&mut buffer);
-        assert_eq!((28, portmap_server_address()), response.unwrap());
-
-        // TODO: Implment RpcReplyMessage::decode
-        // Extract allocated port from portmap reply
-        let allocated_port: u16 = u16::from_be_bytes([buffer[26], buffer[27]]);
-        assert_eq!(60, rpc_client.send_to(mount_export_call, &rpc_allocated_server_address(allocated_port.clone())).unwrap());
-        let mut buffer = [0; 512];
-        let response = rpc_client.recv_from(&mut buffer);
-        assert_eq!((70, rpc_allocated_server_address(allocated_port.clone())), response.unwrap());
+    #[tokio::test]
+    async fn it_serves_linux_over_tcp_in_utf8() {
+        let temp = crate::utils::test_dir("rpc-tcp");
+        let root = temp.path().to_path_buf();
+        std::fs::create_dir_all(root.join("Ärtist")).unwrap();
+        std::fs::write(root.join("Ärtist/Träck.mp3"), b"0123456789").unwrap();
+        let portmap = start_server(50412, &root);
+
+        let mut client = connect(portmap).await;
+        let mount_port = client.getport(RpcProgram::Mount, PortmapProtocol::Tcp).await.unwrap();
+        let nfs_port = client.getport(RpcProgram::Nfs, PortmapProtocol::Tcp).await.unwrap();
+
+        let mut mount = connect(SocketAddr::new(portmap.ip(), mount_port)).await.with_names(NameEncoding::Utf8);
+        let mut handle = mount.mount("/").await.unwrap();
+
+        let mut nfs = connect(SocketAddr::new(portmap.ip(), nfs_port)).await.with_names(NameEncoding::Utf8);
+        let artist = root.canonicalize().unwrap().join("Ärtist");
+        for name in artist.iter().skip(1) {
+            let listing = nfs.readdir(&handle, 0, 8192).await.unwrap();
+            assert_eq!(NameEncoding::Utf8, listing.encoding);
+            assert!(listing.entries.iter().any(|entry| entry.name == name.to_string_lossy()));
+            handle = nfs.lookup(&handle, &name.to_string_lossy()).await.unwrap().fhandle;
+        }
+        let listing = nfs.readdir(&handle, 0, 8192).await.unwrap();
+        assert_eq!(vec!["Träck.mp3"], listing.entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>());
+
+        let track = nfs.lookup(&handle, "Träck.mp3").await.unwrap().fhandle;
+        assert_eq!(b"2345".to_vec(), nfs.read(&track, 2, 4).await.unwrap());
     }
 }
@@ -1 +1,10 @@
 pub mod network;
+
+/// Empty directory of its own for a test, removed when dropped, also when the test fails.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix(&format!("termdj-{}-", name))
+        .tempdir()
+        .expect("Failed creating a test directory")
+}
@@ -19,6 +19,10 @@ impl PioneerNetwork {
         self.network.contains(ip)
     }
 
+    pub fn network(&self) -> Ipv4Network {
+        self.network
+    }
+
     pub fn ip(&self) -> Ipv4Addr {
         self.network.ip()
     }